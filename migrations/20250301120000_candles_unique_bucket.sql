@@ -0,0 +1,14 @@
+-- 20250301120000_candles_unique_bucket.sql
+-- Убираем дубли свечей, оставляя последнюю запись корзины, и запрещаем новые
+DELETE FROM candles a
+USING candles b
+WHERE a.pair = b.pair
+  AND a.time_frame = b.time_frame
+  AND a.utc_begin = b.utc_begin
+  AND a.id < b.id;
+
+CREATE UNIQUE INDEX candles_pair_time_frame_utc_begin_idx
+    ON candles (pair, time_frame, utc_begin);
+
+CREATE INDEX IF NOT EXISTS trades_pair_time_stamp_idx
+    ON trades (pair, time_stamp);
@@ -0,0 +1,30 @@
+-- Источник свечи и признак закрытого интервала.
+-- Свечи разных источников за одну корзину хранятся рядом, выбор делается при чтении.
+ALTER TABLE candles
+    ADD COLUMN source TEXT NOT NULL DEFAULT 'rest',
+    ADD COLUMN is_final BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- Свечи с короткими именами таймфреймов строились из трейдов; незакрытые интервалы - не окончательные
+UPDATE candles
+SET source = CASE WHEN time_frame IN ('1m', '15m', '1h', '1d') THEN 'aggregated' ELSE 'rest' END,
+    is_final = utc_begin + CASE time_frame
+        WHEN '1m' THEN 60000
+        WHEN 'MINUTE_1' THEN 60000
+        WHEN '15m' THEN 900000
+        WHEN 'MINUTE_15' THEN 900000
+        WHEN '1h' THEN 3600000
+        WHEN 'HOUR_1' THEN 3600000
+        WHEN '1d' THEN 86400000
+        WHEN 'DAY_1' THEN 86400000
+        ELSE 0
+    END <= (extract(epoch FROM now()) * 1000)::BIGINT;
+
+-- Дальше источник и окончательность всегда задаёт тот, кто пишет свечу
+ALTER TABLE candles
+    ALTER COLUMN source DROP DEFAULT,
+    ALTER COLUMN is_final DROP DEFAULT,
+    ADD CONSTRAINT candles_source_check CHECK (source IN ('rest', 'ws', 'aggregated', 'imported'));
+
+DROP INDEX candles_pair_time_frame_utc_begin_idx;
+CREATE UNIQUE INDEX candles_pair_time_frame_utc_begin_source_idx
+    ON candles (pair, time_frame, utc_begin, source);
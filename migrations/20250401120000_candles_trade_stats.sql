@@ -0,0 +1,26 @@
+-- Конец интервала, число трейдов и VWAP свечи.
+-- Для уже записанных свечей число трейдов неизвестно и остаётся 0, VWAP считается по объёмам.
+ALTER TABLE candles
+    ADD COLUMN close_time BIGINT,
+    ADD COLUMN trade_count BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN vwap DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+UPDATE candles
+SET close_time = utc_begin + CASE time_frame
+        WHEN '1m' THEN 60000
+        WHEN 'MINUTE_1' THEN 60000
+        WHEN '15m' THEN 900000
+        WHEN 'MINUTE_15' THEN 900000
+        WHEN '1h' THEN 3600000
+        WHEN 'HOUR_1' THEN 3600000
+        WHEN '1d' THEN 86400000
+        WHEN 'DAY_1' THEN 86400000
+        ELSE 1
+    END - 1,
+    vwap = CASE WHEN buy_base + sell_base > 0
+        THEN (buy_quote + sell_quote) / (buy_base + sell_base) ELSE 0 END;
+
+ALTER TABLE candles
+    ALTER COLUMN close_time SET NOT NULL,
+    ALTER COLUMN trade_count DROP DEFAULT,
+    ALTER COLUMN vwap DROP DEFAULT;
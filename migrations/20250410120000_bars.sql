@@ -0,0 +1,28 @@
+-- Бары по потоку трейдов: тиковые, по объёму, по обороту и по дисбалансу.
+-- Ряд - (pair, bar_type, threshold), бар определяется своим первым трейдом.
+CREATE TABLE IF NOT EXISTS bars (
+    id BIGSERIAL PRIMARY KEY,
+    pair TEXT NOT NULL,
+    bar_type TEXT NOT NULL,
+    threshold DOUBLE PRECISION NOT NULL,
+    open DOUBLE PRECISION NOT NULL,
+    high DOUBLE PRECISION NOT NULL,
+    low DOUBLE PRECISION NOT NULL,
+    close DOUBLE PRECISION NOT NULL,
+    buy_base DOUBLE PRECISION NOT NULL,
+    sell_base DOUBLE PRECISION NOT NULL,
+    buy_quote DOUBLE PRECISION NOT NULL,
+    sell_quote DOUBLE PRECISION NOT NULL,
+    utc_begin BIGINT NOT NULL,
+    utc_end BIGINT NOT NULL,
+    first_tid TEXT NOT NULL,
+    last_tid TEXT NOT NULL,
+    trade_count BIGINT NOT NULL,
+    vwap DOUBLE PRECISION NOT NULL,
+    imbalance DOUBLE PRECISION NOT NULL,
+    CONSTRAINT bars_bar_type_check
+        CHECK (bar_type IN ('tick', 'volume', 'dollar', 'tick_imbalance', 'volume_imbalance'))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS bars_pair_bar_type_threshold_utc_begin_first_tid_idx
+    ON bars (pair, bar_type, threshold, utc_begin, first_tid);
@@ -0,0 +1,4 @@
+-- Сколько раз закрытую свечу пересчитали после публикации из-за опоздавших трейдов.
+-- Уже записанные свечи считаются не пересчитанными.
+ALTER TABLE candles ADD COLUMN revision BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE candles ALTER COLUMN revision DROP DEFAULT;
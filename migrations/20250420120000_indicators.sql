@@ -0,0 +1,17 @@
+-- Значения технических индикаторов по закрытым свечам.
+-- Выходы индикатора по порядку: value_1 - основной (sma, rsi, macd, middle у Боллинджера, k у стохастика, adx),
+-- value_2/value_3 - дополнительные (signal/histogram, upper/lower, d, plus_di/minus_di).
+CREATE TABLE IF NOT EXISTS indicators (
+    id BIGSERIAL PRIMARY KEY,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    utc_begin BIGINT NOT NULL,
+    indicator TEXT NOT NULL,
+    params TEXT NOT NULL,
+    value_1 DOUBLE PRECISION NOT NULL,
+    value_2 DOUBLE PRECISION,
+    value_3 DOUBLE PRECISION
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS indicators_pair_time_frame_utc_begin_indicator_params_idx
+    ON indicators (pair, time_frame, utc_begin, indicator, params);
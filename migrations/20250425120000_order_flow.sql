@@ -0,0 +1,21 @@
+-- Метрики потока ордеров по свечам: дельта, накопленная дельта (CVD), перекос покупок/продаж,
+-- расхождение дельты с ценой и z-оценки агрессивных объёмов за окно window_size свечей.
+CREATE TABLE IF NOT EXISTS order_flow (
+    id BIGSERIAL PRIMARY KEY,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    utc_begin BIGINT NOT NULL,
+    buy_volume DOUBLE PRECISION NOT NULL,
+    sell_volume DOUBLE PRECISION NOT NULL,
+    delta DOUBLE PRECISION NOT NULL,
+    cvd DOUBLE PRECISION NOT NULL,
+    imbalance DOUBLE PRECISION NOT NULL,
+    divergence SMALLINT NOT NULL,
+    buy_z DOUBLE PRECISION,
+    sell_z DOUBLE PRECISION,
+    window_size BIGINT NOT NULL,
+    CONSTRAINT order_flow_divergence_check CHECK (divergence IN (-1, 0, 1))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS order_flow_pair_time_frame_utc_begin_idx
+    ON order_flow (pair, time_frame, utc_begin);
@@ -0,0 +1,21 @@
+-- Профили объёма по периодам: строка на ценовой уровень, поля профиля (POC, зона стоимости) повторяются.
+-- Профиль - (pair, period, utc_begin, bins) и перезаписывается целиком.
+CREATE TABLE IF NOT EXISTS volume_profiles (
+    id BIGSERIAL PRIMARY KEY,
+    pair TEXT NOT NULL,
+    period TEXT NOT NULL,
+    utc_begin BIGINT NOT NULL,
+    utc_end BIGINT NOT NULL,
+    bins TEXT NOT NULL,
+    bin_width DOUBLE PRECISION NOT NULL,
+    price_low DOUBLE PRECISION NOT NULL,
+    buy_volume DOUBLE PRECISION NOT NULL,
+    sell_volume DOUBLE PRECISION NOT NULL,
+    trade_count BIGINT NOT NULL,
+    poc DOUBLE PRECISION NOT NULL,
+    value_area_high DOUBLE PRECISION NOT NULL,
+    value_area_low DOUBLE PRECISION NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS volume_profiles_pair_period_utc_begin_bins_price_low_idx
+    ON volume_profiles (pair, period, utc_begin, bins, price_low);
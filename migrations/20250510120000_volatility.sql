@@ -0,0 +1,16 @@
+-- Оценки волатильности по свечам: скользящие по окну window_size свечей (close_to_close, parkinson,
+-- garman_klass, rogers_satchell, yang_zhang) и дневная реализованная по минутным доходностям (realized).
+-- variance - за один интервал таймфрейма, volatility - годовая.
+CREATE TABLE IF NOT EXISTS volatility (
+    id BIGSERIAL PRIMARY KEY,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    utc_begin BIGINT NOT NULL,
+    estimator TEXT NOT NULL,
+    window_size BIGINT NOT NULL,
+    variance DOUBLE PRECISION NOT NULL,
+    volatility DOUBLE PRECISION NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS volatility_pair_time_frame_estimator_window_size_utc_begin_idx
+    ON volatility (pair, time_frame, estimator, window_size, utc_begin);
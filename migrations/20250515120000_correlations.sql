@@ -0,0 +1,16 @@
+-- Скользящие корреляции и беты доходностей между парами: строка на ячейку матрицы (pair против other_pair),
+-- матрица - все строки с одними (time_frame, window_size, utc_begin). beta - pair к other_pair.
+CREATE TABLE IF NOT EXISTS correlations (
+    id BIGSERIAL PRIMARY KEY,
+    time_frame TEXT NOT NULL,
+    utc_begin BIGINT NOT NULL,
+    window_size BIGINT NOT NULL,
+    pair TEXT NOT NULL,
+    other_pair TEXT NOT NULL,
+    observations BIGINT NOT NULL,
+    correlation DOUBLE PRECISION,
+    beta DOUBLE PRECISION
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS correlations_time_frame_window_size_utc_begin_pair_other_pair_idx
+    ON correlations (time_frame, window_size, utc_begin, pair, other_pair);
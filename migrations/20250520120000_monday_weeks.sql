@@ -0,0 +1,9 @@
+-- Недельные корзины теперь начинаются с понедельника 00:00 UTC (как WEEK_1 в REST API), а не с четверга эпохи.
+-- Удаляем построенные по старым границам недельные ряды и их отметки, чтобы задачи пересчитали их заново.
+DELETE FROM candles WHERE time_frame = '1w' AND (utc_begin - 345600000) % 604800000 <> 0;
+DELETE FROM indicators WHERE time_frame = '1w' AND (utc_begin - 345600000) % 604800000 <> 0;
+DELETE FROM order_flow WHERE time_frame = '1w' AND (utc_begin - 345600000) % 604800000 <> 0;
+DELETE FROM volatility WHERE time_frame = '1w' AND (utc_begin - 345600000) % 604800000 <> 0;
+DELETE FROM correlations WHERE time_frame = '1w' AND (utc_begin - 345600000) % 604800000 <> 0;
+DELETE FROM volume_profiles WHERE period = '1w' AND (utc_begin - 345600000) % 604800000 <> 0;
+DELETE FROM job_watermarks WHERE name LIKE '%:1w:%' OR name LIKE '%:1w';
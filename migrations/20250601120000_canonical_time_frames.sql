@@ -0,0 +1,23 @@
+-- Свечи всех источников хранятся под короткими именами таймфреймов, чтобы источники одной корзины
+-- попадали в один ряд. REST-свечи, записанные как MINUTE_1, HOUR_1 и т.п., переименовываются;
+-- если свеча того же источника под коротким именем уже есть, остаётся она.
+CREATE TEMPORARY TABLE time_frame_names (rest TEXT PRIMARY KEY, name TEXT NOT NULL);
+INSERT INTO time_frame_names (rest, name) VALUES
+    ('MINUTE_1', '1m'), ('MINUTE_5', '5m'), ('MINUTE_10', '10m'), ('MINUTE_15', '15m'), ('MINUTE_30', '30m'),
+    ('HOUR_1', '1h'), ('HOUR_2', '2h'), ('HOUR_4', '4h'), ('HOUR_6', '6h'), ('HOUR_12', '12h'),
+    ('DAY_1', '1d'), ('DAY_3', '3d'), ('WEEK_1', '1w');
+
+DELETE FROM candles c
+USING time_frame_names n
+WHERE c.time_frame = n.rest
+  AND EXISTS (
+      SELECT 1 FROM candles d
+      WHERE d.pair = c.pair AND d.time_frame = n.name AND d.utc_begin = c.utc_begin AND d.source = c.source
+  );
+
+UPDATE candles c
+SET time_frame = n.name
+FROM time_frame_names n
+WHERE c.time_frame = n.rest;
+
+DROP TABLE time_frame_names;
@@ -0,0 +1,29 @@
+-- 20250301120000_create_candles_and_trades.sql
+CREATE TABLE IF NOT EXISTS candles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    buy_base REAL NOT NULL,
+    sell_base REAL NOT NULL,
+    buy_quote REAL NOT NULL,
+    sell_quote REAL NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    UNIQUE (pair, time_frame, utc_begin)
+);
+
+CREATE TABLE IF NOT EXISTS trades (
+    tid TEXT PRIMARY KEY,
+    pair TEXT,
+    amount TEXT,
+    side TEXT,
+    quantity TEXT,
+    create_time INTEGER,
+    price TEXT,
+    time_stamp INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS trades_pair_time_stamp_idx ON trades (pair, time_stamp);
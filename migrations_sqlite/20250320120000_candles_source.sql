@@ -0,0 +1,40 @@
+-- Источник свечи и признак закрытого интервала. Уникальность в SQLite не поменять
+-- без пересоздания таблицы, поэтому переносим данные в новую.
+CREATE TABLE candles_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    buy_base REAL NOT NULL,
+    sell_base REAL NOT NULL,
+    buy_quote REAL NOT NULL,
+    sell_quote REAL NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    source TEXT NOT NULL CHECK (source IN ('rest', 'ws', 'aggregated', 'imported')),
+    is_final BOOLEAN NOT NULL,
+    UNIQUE (pair, time_frame, utc_begin, source)
+);
+
+INSERT INTO candles_new (pair, time_frame, open, high, low, close,
+                         buy_base, sell_base, buy_quote, sell_quote, utc_begin, source, is_final)
+SELECT pair, time_frame, open, high, low, close,
+       buy_base, sell_base, buy_quote, sell_quote, utc_begin,
+       CASE WHEN time_frame IN ('1m', '15m', '1h', '1d') THEN 'aggregated' ELSE 'rest' END,
+       utc_begin + CASE time_frame
+           WHEN '1m' THEN 60000
+           WHEN 'MINUTE_1' THEN 60000
+           WHEN '15m' THEN 900000
+           WHEN 'MINUTE_15' THEN 900000
+           WHEN '1h' THEN 3600000
+           WHEN 'HOUR_1' THEN 3600000
+           WHEN '1d' THEN 86400000
+           WHEN 'DAY_1' THEN 86400000
+           ELSE 0
+       END <= CAST(strftime('%s', 'now') AS INTEGER) * 1000
+FROM candles;
+
+DROP TABLE candles;
+ALTER TABLE candles_new RENAME TO candles;
@@ -0,0 +1,6 @@
+-- Отметки фоновых задач: до какого момента данные уже обработаны
+CREATE TABLE IF NOT EXISTS job_watermarks (
+    name TEXT PRIMARY KEY,
+    value INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
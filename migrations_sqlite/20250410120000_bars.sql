@@ -0,0 +1,25 @@
+-- Бары по потоку трейдов: тиковые, по объёму, по обороту и по дисбалансу.
+-- Ряд - (pair, bar_type, threshold), бар определяется своим первым трейдом.
+CREATE TABLE IF NOT EXISTS bars (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    bar_type TEXT NOT NULL,
+    threshold REAL NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    buy_base REAL NOT NULL,
+    sell_base REAL NOT NULL,
+    buy_quote REAL NOT NULL,
+    sell_quote REAL NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    utc_end INTEGER NOT NULL,
+    first_tid TEXT NOT NULL,
+    last_tid TEXT NOT NULL,
+    trade_count INTEGER NOT NULL,
+    vwap REAL NOT NULL,
+    imbalance REAL NOT NULL,
+    CHECK (bar_type IN ('tick', 'volume', 'dollar', 'tick_imbalance', 'volume_imbalance')),
+    UNIQUE (pair, bar_type, threshold, utc_begin, first_tid)
+);
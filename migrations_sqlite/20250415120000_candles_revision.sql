@@ -0,0 +1,3 @@
+-- Сколько раз закрытую свечу пересчитали после публикации из-за опоздавших трейдов.
+-- Уже записанные свечи считаются не пересчитанными.
+ALTER TABLE candles ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
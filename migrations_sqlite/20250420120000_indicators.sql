@@ -0,0 +1,15 @@
+-- Значения технических индикаторов по закрытым свечам.
+-- Выходы индикатора по порядку: value_1 - основной (sma, rsi, macd, middle у Боллинджера, k у стохастика, adx),
+-- value_2/value_3 - дополнительные (signal/histogram, upper/lower, d, plus_di/minus_di).
+CREATE TABLE IF NOT EXISTS indicators (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    indicator TEXT NOT NULL,
+    params TEXT NOT NULL,
+    value_1 REAL NOT NULL,
+    value_2 REAL,
+    value_3 REAL,
+    UNIQUE (pair, time_frame, utc_begin, indicator, params)
+);
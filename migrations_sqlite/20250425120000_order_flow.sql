@@ -0,0 +1,19 @@
+-- Метрики потока ордеров по свечам: дельта, накопленная дельта (CVD), перекос покупок/продаж,
+-- расхождение дельты с ценой и z-оценки агрессивных объёмов за окно window_size свечей.
+CREATE TABLE IF NOT EXISTS order_flow (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    buy_volume REAL NOT NULL,
+    sell_volume REAL NOT NULL,
+    delta REAL NOT NULL,
+    cvd REAL NOT NULL,
+    imbalance REAL NOT NULL,
+    divergence INTEGER NOT NULL,
+    buy_z REAL,
+    sell_z REAL,
+    window_size INTEGER NOT NULL,
+    CHECK (divergence IN (-1, 0, 1)),
+    UNIQUE (pair, time_frame, utc_begin)
+);
@@ -0,0 +1,19 @@
+-- Профили объёма по периодам: строка на ценовой уровень, поля профиля (POC, зона стоимости) повторяются.
+-- Профиль - (pair, period, utc_begin, bins) и перезаписывается целиком.
+CREATE TABLE IF NOT EXISTS volume_profiles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    period TEXT NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    utc_end INTEGER NOT NULL,
+    bins TEXT NOT NULL,
+    bin_width REAL NOT NULL,
+    price_low REAL NOT NULL,
+    buy_volume REAL NOT NULL,
+    sell_volume REAL NOT NULL,
+    trade_count INTEGER NOT NULL,
+    poc REAL NOT NULL,
+    value_area_high REAL NOT NULL,
+    value_area_low REAL NOT NULL,
+    UNIQUE (pair, period, utc_begin, bins, price_low)
+);
@@ -0,0 +1,14 @@
+-- Оценки волатильности по свечам: скользящие по окну window_size свечей (close_to_close, parkinson,
+-- garman_klass, rogers_satchell, yang_zhang) и дневная реализованная по минутным доходностям (realized).
+-- variance - за один интервал таймфрейма, volatility - годовая.
+CREATE TABLE IF NOT EXISTS volatility (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pair TEXT NOT NULL,
+    time_frame TEXT NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    estimator TEXT NOT NULL,
+    window_size INTEGER NOT NULL,
+    variance REAL NOT NULL,
+    volatility REAL NOT NULL,
+    UNIQUE (pair, time_frame, estimator, window_size, utc_begin)
+);
@@ -0,0 +1,14 @@
+-- Скользящие корреляции и беты доходностей между парами: строка на ячейку матрицы (pair против other_pair),
+-- матрица - все строки с одними (time_frame, window_size, utc_begin). beta - pair к other_pair.
+CREATE TABLE IF NOT EXISTS correlations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time_frame TEXT NOT NULL,
+    utc_begin INTEGER NOT NULL,
+    window_size INTEGER NOT NULL,
+    pair TEXT NOT NULL,
+    other_pair TEXT NOT NULL,
+    observations INTEGER NOT NULL,
+    correlation REAL,
+    beta REAL,
+    UNIQUE (time_frame, window_size, utc_begin, pair, other_pair)
+);
@@ -0,0 +1,22 @@
+-- Свечи всех источников хранятся под короткими именами таймфреймов, чтобы источники одной корзины
+-- попадали в один ряд. REST-свечи, записанные как MINUTE_1, HOUR_1 и т.п., переименовываются;
+-- если свеча того же источника под коротким именем уже есть, остаётся она.
+CREATE TEMPORARY TABLE time_frame_names (rest TEXT PRIMARY KEY, name TEXT NOT NULL);
+INSERT INTO time_frame_names (rest, name) VALUES
+    ('MINUTE_1', '1m'), ('MINUTE_5', '5m'), ('MINUTE_10', '10m'), ('MINUTE_15', '15m'), ('MINUTE_30', '30m'),
+    ('HOUR_1', '1h'), ('HOUR_2', '2h'), ('HOUR_4', '4h'), ('HOUR_6', '6h'), ('HOUR_12', '12h'),
+    ('DAY_1', '1d'), ('DAY_3', '3d'), ('WEEK_1', '1w');
+
+DELETE FROM candles
+WHERE EXISTS (
+    SELECT 1 FROM candles d
+    JOIN time_frame_names n ON n.name = d.time_frame
+    WHERE n.rest = candles.time_frame
+      AND d.pair = candles.pair AND d.utc_begin = candles.utc_begin AND d.source = candles.source
+);
+
+UPDATE candles
+SET time_frame = (SELECT name FROM time_frame_names WHERE rest = candles.time_frame)
+WHERE time_frame IN (SELECT rest FROM time_frame_names);
+
+DROP TABLE time_frame_names;
@@ -0,0 +1,37 @@
+-- 20250310120000_hypertables.sql
+-- Применяется только при сборке с feature `timescale` и установленном TimescaleDB
+CREATE EXTENSION IF NOT EXISTS timescaledb;
+
+-- Время в таблицах хранится в миллисекундах эпохи, Timescale нужна функция "текущего момента"
+CREATE OR REPLACE FUNCTION unix_now_ms() RETURNS BIGINT
+    LANGUAGE SQL STABLE AS $$ SELECT (extract(epoch FROM now()) * 1000)::BIGINT $$;
+
+-- Уникальные индексы гипертаблицы обязаны включать колонку времени
+ALTER TABLE trades DROP CONSTRAINT IF EXISTS trades_pkey;
+CREATE UNIQUE INDEX IF NOT EXISTS trades_tid_time_stamp_idx ON trades (tid, time_stamp);
+
+ALTER TABLE candles DROP CONSTRAINT IF EXISTS candles_pkey;
+
+SELECT create_hypertable('trades', 'time_stamp',
+    chunk_time_interval => BIGINT '86400000',   -- сутки
+    migrate_data => true);
+SELECT set_integer_now_func('trades', 'unix_now_ms');
+
+SELECT create_hypertable('candles', 'utc_begin',
+    chunk_time_interval => BIGINT '2592000000', -- 30 суток
+    migrate_data => true);
+SELECT set_integer_now_func('candles', 'unix_now_ms');
+
+ALTER TABLE trades SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'pair',
+    timescaledb.compress_orderby = 'time_stamp, tid'
+);
+SELECT add_compression_policy('trades', BIGINT '604800000');  -- старше 7 суток
+
+ALTER TABLE candles SET (
+    timescaledb.compress,
+    timescaledb.compress_segmentby = 'pair, time_frame',
+    timescaledb.compress_orderby = 'utc_begin'
+);
+SELECT add_compression_policy('candles', BIGINT '2592000000'); -- старше 30 суток
@@ -0,0 +1,72 @@
+-- 20250310120100_continuous_aggregates.sql
+-- Свечи старших таймфреймов как непрерывные агрегаты над `trades`.
+-- Корзины выровнены по границам от UTC-эпохи.
+
+CREATE MATERIALIZED VIEW candles_15m
+WITH (timescaledb.continuous) AS
+SELECT
+    pair,
+    '15m'::TEXT AS time_frame,
+    time_bucket(BIGINT '900000', time_stamp) AS utc_begin,
+    first(price::NUMERIC, time_stamp)::FLOAT8 AS open,
+    MAX(price::NUMERIC)::FLOAT8 AS high,
+    MIN(price::NUMERIC)::FLOAT8 AS low,
+    last(price::NUMERIC, time_stamp)::FLOAT8 AS close,
+    SUM(CASE WHEN side = 'buy' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS buy_base,
+    SUM(CASE WHEN side = 'sell' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS sell_base,
+    SUM(CASE WHEN side = 'buy' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS buy_quote,
+    SUM(CASE WHEN side = 'sell' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS sell_quote
+FROM trades
+GROUP BY pair, time_bucket(BIGINT '900000', time_stamp)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_15m',
+    start_offset => BIGINT '86400000',
+    end_offset => BIGINT '60000',
+    schedule_interval => INTERVAL '60 seconds');
+
+CREATE MATERIALIZED VIEW candles_1h
+WITH (timescaledb.continuous) AS
+SELECT
+    pair,
+    '1h'::TEXT AS time_frame,
+    time_bucket(BIGINT '3600000', time_stamp) AS utc_begin,
+    first(price::NUMERIC, time_stamp)::FLOAT8 AS open,
+    MAX(price::NUMERIC)::FLOAT8 AS high,
+    MIN(price::NUMERIC)::FLOAT8 AS low,
+    last(price::NUMERIC, time_stamp)::FLOAT8 AS close,
+    SUM(CASE WHEN side = 'buy' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS buy_base,
+    SUM(CASE WHEN side = 'sell' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS sell_base,
+    SUM(CASE WHEN side = 'buy' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS buy_quote,
+    SUM(CASE WHEN side = 'sell' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS sell_quote
+FROM trades
+GROUP BY pair, time_bucket(BIGINT '3600000', time_stamp)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_1h',
+    start_offset => BIGINT '259200000',
+    end_offset => BIGINT '60000',
+    schedule_interval => INTERVAL '300 seconds');
+
+CREATE MATERIALIZED VIEW candles_1d
+WITH (timescaledb.continuous) AS
+SELECT
+    pair,
+    '1d'::TEXT AS time_frame,
+    time_bucket(BIGINT '86400000', time_stamp) AS utc_begin,
+    first(price::NUMERIC, time_stamp)::FLOAT8 AS open,
+    MAX(price::NUMERIC)::FLOAT8 AS high,
+    MIN(price::NUMERIC)::FLOAT8 AS low,
+    last(price::NUMERIC, time_stamp)::FLOAT8 AS close,
+    SUM(CASE WHEN side = 'buy' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS buy_base,
+    SUM(CASE WHEN side = 'sell' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS sell_base,
+    SUM(CASE WHEN side = 'buy' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS buy_quote,
+    SUM(CASE WHEN side = 'sell' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS sell_quote
+FROM trades
+GROUP BY pair, time_bucket(BIGINT '86400000', time_stamp)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_1d',
+    start_offset => BIGINT '2592000000',
+    end_offset => BIGINT '60000',
+    schedule_interval => INTERVAL '3600 seconds');
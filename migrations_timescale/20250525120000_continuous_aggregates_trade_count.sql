@@ -0,0 +1,92 @@
+-- Пересоздаём непрерывные агрегаты: число трейдов в корзине и open/close с тем же порядком трейдов,
+-- что у агрегации по таблице (время, затем id: числовые id по значению и раньше остальных, остальные - по байтам).
+-- Материализация после пересоздания пуста, историю заново считает PgStore::connect.
+
+-- Ключ порядка трейда для first()/last(): time_stamp * 10^30 + ранг id.
+-- Ранг точен для числовых id до 29 значащих цифр и прочих id до 12 байт; id биржи укладываются в это с запасом.
+CREATE OR REPLACE FUNCTION trade_order_key(time_stamp BIGINT, tid TEXT) RETURNS NUMERIC
+    LANGUAGE SQL IMMUTABLE PARALLEL SAFE AS $$
+    SELECT time_stamp::NUMERIC * 1e30 + CASE
+        WHEN tid !~ '^[0-9]+$' THEN 1e29 + (
+            SELECT coalesce(sum(get_byte(b, i) * 256::NUMERIC ^ (11 - i)), 0)::NUMERIC(30, 0)
+            FROM (SELECT convert_to(tid, 'UTF8') AS b) bytes, generate_series(0, least(length(b), 12) - 1) i)
+        WHEN length(ltrim(tid, '0')) <= 29 THEN coalesce(nullif(ltrim(tid, '0'), '')::NUMERIC, 0)
+        ELSE 1e29 - 1
+    END
+$$;
+
+DROP MATERIALIZED VIEW IF EXISTS candles_15m;
+DROP MATERIALIZED VIEW IF EXISTS candles_1h;
+DROP MATERIALIZED VIEW IF EXISTS candles_1d;
+
+CREATE MATERIALIZED VIEW candles_15m
+WITH (timescaledb.continuous) AS
+SELECT
+    pair,
+    '15m'::TEXT AS time_frame,
+    time_bucket(BIGINT '900000', time_stamp) AS utc_begin,
+    first(price::NUMERIC, trade_order_key(time_stamp, tid))::FLOAT8 AS open,
+    MAX(price::NUMERIC)::FLOAT8 AS high,
+    MIN(price::NUMERIC)::FLOAT8 AS low,
+    last(price::NUMERIC, trade_order_key(time_stamp, tid))::FLOAT8 AS close,
+    SUM(CASE WHEN side = 'buy' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS buy_base,
+    SUM(CASE WHEN side = 'sell' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS sell_base,
+    SUM(CASE WHEN side = 'buy' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS buy_quote,
+    SUM(CASE WHEN side = 'sell' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS sell_quote,
+    COUNT(*) AS trade_count
+FROM trades
+GROUP BY pair, time_bucket(BIGINT '900000', time_stamp)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_15m',
+    start_offset => BIGINT '86400000',
+    end_offset => BIGINT '60000',
+    schedule_interval => INTERVAL '60 seconds');
+
+CREATE MATERIALIZED VIEW candles_1h
+WITH (timescaledb.continuous) AS
+SELECT
+    pair,
+    '1h'::TEXT AS time_frame,
+    time_bucket(BIGINT '3600000', time_stamp) AS utc_begin,
+    first(price::NUMERIC, trade_order_key(time_stamp, tid))::FLOAT8 AS open,
+    MAX(price::NUMERIC)::FLOAT8 AS high,
+    MIN(price::NUMERIC)::FLOAT8 AS low,
+    last(price::NUMERIC, trade_order_key(time_stamp, tid))::FLOAT8 AS close,
+    SUM(CASE WHEN side = 'buy' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS buy_base,
+    SUM(CASE WHEN side = 'sell' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS sell_base,
+    SUM(CASE WHEN side = 'buy' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS buy_quote,
+    SUM(CASE WHEN side = 'sell' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS sell_quote,
+    COUNT(*) AS trade_count
+FROM trades
+GROUP BY pair, time_bucket(BIGINT '3600000', time_stamp)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_1h',
+    start_offset => BIGINT '259200000',
+    end_offset => BIGINT '60000',
+    schedule_interval => INTERVAL '300 seconds');
+
+CREATE MATERIALIZED VIEW candles_1d
+WITH (timescaledb.continuous) AS
+SELECT
+    pair,
+    '1d'::TEXT AS time_frame,
+    time_bucket(BIGINT '86400000', time_stamp) AS utc_begin,
+    first(price::NUMERIC, trade_order_key(time_stamp, tid))::FLOAT8 AS open,
+    MAX(price::NUMERIC)::FLOAT8 AS high,
+    MIN(price::NUMERIC)::FLOAT8 AS low,
+    last(price::NUMERIC, trade_order_key(time_stamp, tid))::FLOAT8 AS close,
+    SUM(CASE WHEN side = 'buy' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS buy_base,
+    SUM(CASE WHEN side = 'sell' THEN quantity::NUMERIC ELSE 0 END)::FLOAT8 AS sell_base,
+    SUM(CASE WHEN side = 'buy' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS buy_quote,
+    SUM(CASE WHEN side = 'sell' THEN amount::NUMERIC ELSE 0 END)::FLOAT8 AS sell_quote,
+    COUNT(*) AS trade_count
+FROM trades
+GROUP BY pair, time_bucket(BIGINT '86400000', time_stamp)
+WITH NO DATA;
+
+SELECT add_continuous_aggregate_policy('candles_1d',
+    start_offset => BIGINT '2592000000',
+    end_offset => BIGINT '60000',
+    schedule_interval => INTERVAL '3600 seconds');
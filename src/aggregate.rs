@@ -0,0 +1,320 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use chrono::Utc;
+use crate::data_structs::{Bar, CandleSource, Kline, RecentTrade, VBS};
+
+// Таймфреймы: имя, под которым хранятся свечи всех источников, имя REST API и длительность в минутах
+const TIME_FRAMES: [(&str, &str, i64); 13] = [
+    ("1m", "MINUTE_1", 1),
+    ("5m", "MINUTE_5", 5),
+    ("10m", "MINUTE_10", 10),
+    ("15m", "MINUTE_15", 15),
+    ("30m", "MINUTE_30", 30),
+    ("1h", "HOUR_1", 60),
+    ("2h", "HOUR_2", 2 * 60),
+    ("4h", "HOUR_4", 4 * 60),
+    ("6h", "HOUR_6", 6 * 60),
+    ("12h", "HOUR_12", 12 * 60),
+    ("1d", "DAY_1", 24 * 60),
+    ("3d", "DAY_3", 3 * 24 * 60),
+    ("1w", "WEEK_1", 7 * 24 * 60),
+];
+
+fn find_time_frame(time_frame: &str) -> Option<&'static (&'static str, &'static str, i64)> {
+    TIME_FRAMES.iter().find(|(name, rest, _)| *name == time_frame || *rest == time_frame)
+}
+
+// Длительность таймфрейма в миллисекундах.
+// Понимает и короткие имена агрегатора ("1m", "15m", "1h", "1d"), и имена REST API ("MINUTE_1", "HOUR_1", ...).
+pub fn time_frame_ms(time_frame: &str) -> Option<i64> {
+    find_time_frame(time_frame).map(|(_, _, minutes)| minutes * 60_000)
+}
+
+// Имя, под которым хранится таймфрейм: "MINUTE_1" -> "1m"; неизвестное имя не меняется
+pub fn canonical_time_frame(time_frame: &str) -> String {
+    find_time_frame(time_frame).map_or(time_frame, |(name, _, _)| name).to_string()
+}
+
+// Имя таймфрейма в REST API: "1m" -> "MINUTE_1"
+pub fn rest_interval(time_frame: &str) -> Option<&'static str> {
+    find_time_frame(time_frame).map(|(_, rest, _)| *rest)
+}
+
+const WEEK_MS: i64 = 7 * 24 * 60 * 60_000;
+// Эпоха пришлась на четверг; недели, как WEEK_1 в REST API, начинаются с понедельника 00:00 UTC
+const WEEK_OFFSET_MS: i64 = 4 * 24 * 60 * 60_000;
+
+// Сдвиг границ корзин относительно UTC-эпохи: ненулевой только у недельных корзин
+pub fn bucket_offset(bucket_ms: i64) -> i64 {
+    if bucket_ms == WEEK_MS {
+        WEEK_OFFSET_MS
+    } else {
+        0
+    }
+}
+
+// Начало корзины: границы идут от UTC-эпохи, недельные - с понедельника
+pub fn bucket_start(ts: i64, bucket_ms: i64) -> i64 {
+    let offset = bucket_offset(bucket_ms);
+    (ts - offset).div_euclid(bucket_ms) * bucket_ms + offset
+}
+
+// Ошибка для таймфрейма, который не знает time_frame_ms
+pub fn unknown_time_frame(time_frame: &str) -> sqlx::Error {
+    sqlx::Error::Configuration(format!("неизвестный таймфрейм для агрегации: {}", time_frame).into())
+}
+
+// Последняя миллисекунда интервала, начавшегося в utc_begin
+pub fn close_time(utc_begin: i64, time_frame: &str) -> i64 {
+    time_frame_ms(time_frame).map_or(utc_begin, |ms| utc_begin + ms - 1)
+}
+
+// Интервал, начавшийся в utc_begin, уже закончился. Для неизвестного таймфрейма - false.
+pub fn bucket_closed(utc_begin: i64, time_frame: &str) -> bool {
+    time_frame_ms(time_frame).is_some_and(|ms| utc_begin + ms <= Utc::now().timestamp_millis())
+}
+
+// Порядок трейдов внутри корзины: по времени биржи, затем по id трейда
+pub fn trade_order(a: &RecentTrade, b: &RecentTrade) -> Ordering {
+    trade_key_order((a.timestamp, &a.tid), (b.timestamp, &b.tid))
+}
+
+// То же для пар (timestamp, tid)
+pub fn trade_key_order(a: (i64, &str), b: (i64, &str)) -> Ordering {
+    a.0.cmp(&b.0).then_with(|| tid_order(a.1, b.1))
+}
+
+// Полный порядок id трейдов, как в SQL "tid_num NULLS LAST, tid": сначала числовые id (только цифры)
+// по значению, затем остальные лексикографически; при равном значении ("01" и "1") - по строке
+pub fn tid_order(a: &str, b: &str) -> Ordering {
+    match (numeric_tid(a), numeric_tid(b)) {
+        (Some(x), Some(y)) => x.len().cmp(&y.len()).then_with(|| x.cmp(y)).then_with(|| a.cmp(b)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+// Значащие цифры числового id без ведущих нулей; None, если id не из одних цифр
+fn numeric_tid(tid: &str) -> Option<&str> {
+    if tid.is_empty() || !tid.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(tid.trim_start_matches('0'))
+}
+
+// Порядок баров ряда: по первому трейду
+pub fn bar_order(a: &Bar, b: &Bar) -> Ordering {
+    trade_key_order((a.utc_begin, &a.first_tid), (b.utc_begin, &b.first_tid))
+}
+
+// Строит свечи из трейдов одной пары. Трейды других пар и с непарсящимися числами пропускаются.
+// None - неизвестный таймфрейм.
+pub fn aggregate_trades(pair: &str, time_frame: &str, trades: &[RecentTrade]) -> Option<Vec<Kline>> {
+    let bucket_ms = time_frame_ms(time_frame)?;
+
+    let now = Utc::now().timestamp_millis();
+    let mut sorted: Vec<&RecentTrade> = trades.iter().filter(|t| t.pair == pair).collect();
+    sorted.sort_by(|a, b| trade_order(a, b));
+
+    let mut buckets: BTreeMap<i64, Kline> = BTreeMap::new();
+    for trade in sorted {
+        let (price, quantity, amount) = match (
+            trade.price.parse::<f64>(),
+            trade.quantity.parse::<f64>(),
+            trade.amount.parse::<f64>(),
+        ) {
+            (Ok(p), Ok(q), Ok(a)) => (p, q, a),
+            _ => continue,
+        };
+
+        let begin = bucket_start(trade.timestamp, bucket_ms);
+        let candle = buckets.entry(begin).or_insert_with(|| Kline {
+            pair: pair.to_string(),
+            time_frame: time_frame.to_string(),
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume_bs: VBS {
+                buy_base: 0.0,
+                sell_base: 0.0,
+                buy_quote: 0.0,
+                sell_quote: 0.0,
+            },
+            utc_begin: begin,
+            close_time: begin + bucket_ms - 1,
+            trade_count: 0,
+            vwap: 0.0,
+            source: CandleSource::Aggregated,
+            is_final: begin + bucket_ms <= now,
+            revision: 0,
+        });
+
+        candle.high = candle.high.max(price);
+        candle.low = candle.low.min(price);
+        candle.close = price;
+        candle.trade_count += 1;
+        match trade.side.as_str() {
+            "buy" => {
+                candle.volume_bs.buy_base += quantity;
+                candle.volume_bs.buy_quote += amount;
+            }
+            "sell" => {
+                candle.volume_bs.sell_base += quantity;
+                candle.volume_bs.sell_quote += amount;
+            }
+            _ => {}
+        }
+    }
+
+    Some(
+        buckets
+            .into_values()
+            .map(|mut c| {
+                c.vwap = c.volume_bs.vwap();
+                c
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tid_order_is_total() {
+        // Раньше "2" < "10" < "1a" < "2" давало цикл
+        let mut tids = vec!["1a", "10", "2", "b", "002", "2", "0", "99999999999999999999"];
+        tids.sort_by(|a, b| tid_order(a, b));
+        assert_eq!(tids, vec!["0", "002", "2", "2", "10", "99999999999999999999", "1a", "b"]);
+
+        let all = ["2", "10", "1a", "-1", "+3", "", "007", "7"];
+        for a in all {
+            for b in all {
+                assert_eq!(tid_order(a, b), tid_order(b, a).reverse(), "{} vs {}", a, b);
+                for c in all {
+                    if tid_order(a, b).is_le() && tid_order(b, c).is_le() {
+                        assert!(tid_order(a, c).is_le(), "{} <= {} <= {}", a, b, c);
+                    }
+                }
+            }
+        }
+    }
+
+    // 2024-01-01 00:00 UTC, понедельник
+    const T0: i64 = 1_704_067_200_000;
+    const MIN: i64 = 60_000;
+
+    fn trade(pair: &str, ts: i64, tid: &str, price: &str, quantity: f64, side: &str) -> RecentTrade {
+        let amount = price.parse::<f64>().map(|p| (p * quantity).to_string()).unwrap_or_default();
+        RecentTrade {
+            tid: tid.to_string(),
+            pair: pair.to_string(),
+            price: price.to_string(),
+            amount,
+            quantity: quantity.to_string(),
+            side: side.to_string(),
+            create_time: ts,
+            timestamp: ts,
+        }
+    }
+
+    fn sample() -> Vec<RecentTrade> {
+        // Намеренно не по порядку
+        vec![
+            trade("BTC_USDT", T0 + 70 * MIN, "5", "130", 1.0, "sell"),
+            trade("BTC_USDT", T0 + 5 * MIN, "2", "110", 2.0, "sell"),
+            trade("BTC_USDT", T0 + 1000, "1", "100", 1.0, "buy"),
+            trade("ETH_USDT", T0 + 1000, "1", "5", 1.0, "buy"),
+            trade("BTC_USDT", T0 + 14 * MIN, "3", "90", 1.0, "buy"),
+            trade("BTC_USDT", T0 + 14 * MIN, "x", "bad", 1.0, "buy"),
+            trade("BTC_USDT", T0 + 20 * MIN, "4", "120", 0.5, "buy"),
+        ]
+    }
+
+    // (utc_begin, open, high, low, close, trade_count, buy_base, sell_base, buy_quote, sell_quote, vwap)
+    type Expected = (i64, f64, f64, f64, f64, i64, f64, f64, f64, f64, f64);
+
+    fn check(time_frame: &str, expected: &[Expected]) {
+        let candles = aggregate_trades("BTC_USDT", time_frame, &sample()).unwrap();
+        assert_eq!(candles.len(), expected.len(), "{}", time_frame);
+        let ms = time_frame_ms(time_frame).unwrap();
+        for (c, e) in candles.iter().zip(expected) {
+            assert_eq!((c.pair.as_str(), c.time_frame.as_str()), ("BTC_USDT", time_frame));
+            assert_eq!((c.utc_begin, c.close_time), (e.0, e.0 + ms - 1));
+            assert_eq!((c.open, c.high, c.low, c.close), (e.1, e.2, e.3, e.4), "{} {}", time_frame, e.0);
+            assert_eq!(c.trade_count, e.5);
+            let v = &c.volume_bs;
+            assert_eq!((v.buy_base, v.sell_base, v.buy_quote, v.sell_quote), (e.6, e.7, e.8, e.9));
+            assert!((c.vwap - e.10).abs() < 1e-9, "{} vwap {} != {}", time_frame, c.vwap, e.10);
+            assert_eq!(c.source, CandleSource::Aggregated);
+            assert!(c.is_final);
+        }
+    }
+
+    #[test]
+    fn aggregates_15m() {
+        check(
+            "15m",
+            &[
+                (T0, 100.0, 110.0, 90.0, 90.0, 3, 2.0, 2.0, 190.0, 220.0, 410.0 / 4.0),
+                (T0 + 15 * MIN, 120.0, 120.0, 120.0, 120.0, 1, 0.5, 0.0, 60.0, 0.0, 120.0),
+                (T0 + 60 * MIN, 130.0, 130.0, 130.0, 130.0, 1, 0.0, 1.0, 0.0, 130.0, 130.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn aggregates_1h() {
+        check(
+            "1h",
+            &[
+                (T0, 100.0, 120.0, 90.0, 120.0, 4, 2.5, 2.0, 250.0, 220.0, 470.0 / 4.5),
+                (T0 + 60 * MIN, 130.0, 130.0, 130.0, 130.0, 1, 0.0, 1.0, 0.0, 130.0, 130.0),
+            ],
+        );
+    }
+
+    #[test]
+    fn aggregates_1d() {
+        check("1d", &[(T0, 100.0, 130.0, 90.0, 130.0, 5, 2.5, 3.0, 250.0, 350.0, 600.0 / 5.5)]);
+    }
+
+    #[test]
+    fn unknown_time_frame_is_none() {
+        assert!(aggregate_trades("BTC_USDT", "7m", &sample()).is_none());
+        assert!(aggregate_trades("BTC_USDT", "1h", &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rest_names_map_to_stored_names() {
+        assert_eq!(canonical_time_frame("MINUTE_1"), "1m");
+        assert_eq!(canonical_time_frame("DAY_1"), "1d");
+        assert_eq!(canonical_time_frame("1h"), "1h");
+        assert_eq!(canonical_time_frame("7m"), "7m");
+        assert_eq!(rest_interval("15m"), Some("MINUTE_15"));
+        assert_eq!(rest_interval("HOUR_1"), Some("HOUR_1"));
+        assert_eq!(time_frame_ms("WEEK_1"), time_frame_ms("1w"));
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let week = time_frame_ms("1w").unwrap();
+        // Воскресенье 2023-12-31 23:59:59.999 - ещё прошлая неделя, с понедельника 2023-12-25
+        assert_eq!(bucket_start(T0 - 1, week), T0 - week);
+        assert_eq!(bucket_start(T0, week), T0);
+        assert_eq!(bucket_start(T0 + week - 1, week), T0);
+        // Остальные корзины по-прежнему от эпохи
+        assert_eq!(bucket_start(T0 + 61 * MIN, 60 * MIN), T0 + 60 * MIN);
+        assert_eq!(bucket_start(-1, 60 * MIN), -60 * MIN);
+    }
+
+    #[test]
+    fn trade_key_order_compares_time_first() {
+        assert_eq!(trade_key_order((1, "10"), (2, "2")), Ordering::Less);
+        assert_eq!(trade_key_order((2, "10"), (2, "9")), Ordering::Greater);
+        assert_eq!(trade_key_order((2, "x"), (2, "9")), Ordering::Greater);
+    }
+}
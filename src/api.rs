@@ -1,5 +1,51 @@
 use chrono::{Utc, TimeZone};
-use crate::data_structs::{Kline, VBS};                    
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, VBS};                    
+
+// Свеча в терминах REST API Poloniex, до разложения объёма на покупки и продажи
+#[derive(Debug, Clone)]
+pub struct RestCandle {
+    pub low: f64,
+    pub high: f64,
+    pub open: f64,
+    pub close: f64,
+    pub amount: f64,             // объём в котируемой валюте
+    pub quantity: f64,           // объём в базовой валюте
+    pub buy_taker_amount: f64,   // покупки тейкеров в котируемой валюте
+    pub buy_taker_quantity: f64, // покупки тейкеров в базовой валюте
+    pub trade_count: i64,
+    pub weighted_average: f64,   // VWAP интервала
+    pub start_time: i64,
+    pub close_time: i64,
+}
+
+impl RestCandle {
+    pub fn into_kline(self, symbol: &str, interval: &str) -> Kline {
+        let volume_bs = VBS {
+            buy_base: self.buy_taker_quantity,
+            sell_base: self.quantity - self.buy_taker_quantity,
+            buy_quote: self.buy_taker_amount,
+            sell_quote: self.amount - self.buy_taker_amount,
+        };
+
+        Kline {
+            pair: symbol.to_string(),
+            time_frame: aggregate::canonical_time_frame(interval),
+            open: self.open,
+            high: self.high,
+            low: self.low,
+            close: self.close,
+            volume_bs,
+            utc_begin: self.start_time,
+            close_time: self.close_time,
+            trade_count: self.trade_count,
+            vwap: self.weighted_average,
+            source: CandleSource::Rest,
+            is_final: aggregate::bucket_closed(self.start_time, interval),
+            revision: 0,
+        }
+    }
+}
 
 pub fn get_time_range() -> (i64, i64) {
     let start_time = Utc.with_ymd_and_hms(2024, 12, 1, 0, 0, 0)
@@ -11,6 +57,15 @@ pub fn get_time_range() -> (i64, i64) {
 
 pub async fn get_candles(symbol: &str, interval: &str) -> Result<Vec<Kline>, Box<dyn std::error::Error>> {
     let (start_time, end_time) = get_time_range();
+    get_candles_between(symbol, interval, start_time, end_time).await
+}
+
+pub async fn get_candles_between(
+    symbol: &str,
+    interval: &str,
+    start_time: i64,
+    end_time: i64,
+) -> Result<Vec<Kline>, Box<dyn std::error::Error>> {
 
     let url = format!(
         "https://api.poloniex.com/markets/{}/candles?interval={}&startTime={}&endTime={}",
@@ -40,14 +95,14 @@ pub async fn get_candles(symbol: &str, interval: &str) -> Result<Vec<Kline>, Box
         // index 5: quantity (base volume, string)
         // index 6: buyTakerAmount (string)
         // index 7: buyTakerQuantity (string)
-        // index 8: tradeCount (integer) – не используем
+        // index 8: tradeCount (integer)
         // index 9: ts (integer) – не используем
-        // index 10: weightedAverage (string) – не используем
+        // index 10: weightedAverage (string) – vwap
         // index 11: interval (string) – не используем, берём из аргумента
         // index 12: startTime (integer) – используем как utc_begin
-        // index 13: closeTime (integer) – не используем
+        // index 13: closeTime (integer)
 
-        let low = row.get(0)
+        let low = row.first()
             .and_then(|v| v.as_str())
             .and_then(|s| s.parse::<f64>().ok())
             .unwrap_or_default();
@@ -80,27 +135,35 @@ pub async fn get_candles(symbol: &str, interval: &str) -> Result<Vec<Kline>, Box
             .and_then(|v| v.as_str())
             .and_then(|s| s.parse::<f64>().ok())
             .unwrap_or_default();
+        let trade_count = row.get(8)
+            .and_then(|v| v.as_i64())
+            .unwrap_or_default();
+        let weighted_average = row.get(10)
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or_default();
         let utc_begin = row.get(12)
             .and_then(|v| v.as_i64())
             .unwrap_or_default();
+        let close_time = row.get(13)
+            .and_then(|v| v.as_i64())
+            .unwrap_or_else(|| aggregate::close_time(utc_begin, interval));
 
-        let volume_bs = VBS {
-            buy_base: buy_taker_quantity,
-            sell_base: quantity - buy_taker_quantity,
-            buy_quote: buy_taker_amount,
-            sell_quote: amount - buy_taker_amount,
-        };
-
-        Kline {
-            pair: symbol.to_string(),
-            time_frame: interval.to_string(),
-            open,
-            high,
+        RestCandle {
             low,
+            high,
+            open,
             close,
-            volume_bs,
-            utc_begin,
+            amount,
+            quantity,
+            buy_taker_amount,
+            buy_taker_quantity,
+            trade_count,
+            weighted_average,
+            start_time: utc_begin,
+            close_time,
         }
+        .into_kline(symbol, interval)
     }).collect();
 
     println!("Преобразованные свечи: {:?}", candles);
@@ -0,0 +1,340 @@
+use chrono::Utc;
+use futures_util::StreamExt;
+use sqlx::{PgPool, Row};
+use crate::aggregate;
+use crate::api;
+use crate::data_structs::{CandleSource, Kline, AGG_TIME_FRAMES, INTERVALS};
+use crate::db::{self, SortOrder};
+use crate::storage::Storage;
+
+// Допустимое относительное расхождение объёма свечи и суммы трейдов
+const VOLUME_TOLERANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IssueKind {
+    Missing,                      // нет свечи за интервал
+    Duplicate { count: i64 },     // несколько свечей одного источника за один интервал
+    Misaligned,                   // utc_begin не на границе таймфрейма
+    NonPositivePrice,             // нулевая или отрицательная цена (например, от unwrap_or_default)
+    HighBelowLow,
+    OpenCloseOutOfRange,
+    NegativeVolume,
+    VolumeMismatch { candle: f64, trades: f64 }, // buy_base + sell_base не равно сумме трейдов
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditIssue {
+    pub pair: String,
+    pub time_frame: String,
+    pub utc_begin: i64,
+    pub kind: IssueKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AuditReport {
+    pub candles_checked: u64,
+    pub issues: Vec<AuditIssue>,
+}
+
+// Проверяет один ряд (pair, time_frame) за [start_ts, end_ts]
+pub async fn audit_series(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+) -> Result<AuditReport, sqlx::Error> {
+    let mut report = AuditReport::default();
+    let bucket_ms = match aggregate::time_frame_ms(time_frame) {
+        Some(ms) => ms,
+        None => {
+            eprintln!("Аудит: неизвестный таймфрейм {}, ряд пропущен", time_frame);
+            return Ok(report);
+        }
+    };
+    // Текущий, ещё не закрытый интервал не проверяем
+    let last_closed = Utc::now().timestamp_millis() - bucket_ms;
+    let end_ts = Some(end_ts.map_or(last_closed, |e| e.min(last_closed)));
+    let from_trades = AGG_TIME_FRAMES.contains(&time_frame);
+
+    let mut issue = |utc_begin: i64, kind: IssueKind| {
+        report.issues.push(AuditIssue {
+            pair: pair.to_string(),
+            time_frame: time_frame.to_string(),
+            utc_begin,
+            kind,
+        })
+    };
+
+    let mut prev: Option<i64> = None;
+    let mut stream = db::stream_candles(
+        pool,
+        pair,
+        time_frame,
+        start_ts,
+        end_ts,
+        SortOrder::Asc,
+        &CandleSource::DEFAULT_PREFERENCE,
+    );
+    let mut checked = 0;
+    while let Some(candle) = stream.next().await {
+        let candle = candle?;
+        checked += 1;
+        for kind in check_candle(&candle, bucket_ms) {
+            issue(candle.utc_begin, kind);
+        }
+        // У свечей из своих трейдов пропуск законен, если трейдов не было: их проверяем ниже по `trades`
+        if let (Some(prev), false) = (prev, from_trades) {
+            for missing in missing_between(prev, candle.utc_begin, bucket_ms) {
+                issue(missing, IssueKind::Missing);
+            }
+        }
+        prev = Some(candle.utc_begin);
+    }
+    drop(stream);
+
+    // Биржа отдаёт свечу на каждый интервал, поэтому ряд REST проверяем по сетке отдельно:
+    // в общем ряду свеча из трейдов закрыла бы недостающую свечу биржи
+    if from_trades && aggregate::rest_interval(time_frame).is_some_and(|i| INTERVALS.contains(&i)) {
+        let mut prev: Option<i64> = None;
+        let mut stream = db::stream_candles(pool, pair, time_frame, start_ts, end_ts, SortOrder::Asc, &[CandleSource::Rest]);
+        while let Some(candle) = stream.next().await {
+            let candle = candle?;
+            if candle.source != CandleSource::Rest {
+                continue;
+            }
+            if let Some(prev) = prev {
+                for missing in missing_between(prev, candle.utc_begin, bucket_ms) {
+                    issue(missing, IssueKind::Missing);
+                }
+            }
+            prev = Some(candle.utc_begin);
+        }
+    }
+
+    for (utc_begin, count) in duplicate_buckets(pool, pair, time_frame, start_ts, end_ts).await? {
+        issue(utc_begin, IssueKind::Duplicate { count });
+    }
+
+    if from_trades {
+        for utc_begin in buckets_without_candle(pool, pair, time_frame, bucket_ms, start_ts, end_ts).await? {
+            issue(utc_begin, IssueKind::Missing);
+        }
+        for (utc_begin, candle, trades) in volume_mismatches(pool, pair, time_frame, bucket_ms, start_ts, end_ts).await? {
+            issue(utc_begin, IssueKind::VolumeMismatch { candle, trades });
+        }
+    }
+
+    report.candles_checked = checked;
+    report.issues.sort_by_key(|i| i.utc_begin);
+    Ok(report)
+}
+
+// Начала интервалов сетки строго между двумя свечами
+fn missing_between(prev: i64, next: i64, bucket_ms: i64) -> impl Iterator<Item = i64> {
+    (1..).map(move |k| prev + k * bucket_ms).take_while(move |ts| *ts < next)
+}
+
+pub fn check_candle(candle: &Kline, bucket_ms: i64) -> Vec<IssueKind> {
+    let mut kinds = Vec::new();
+    if aggregate::bucket_start(candle.utc_begin, bucket_ms) != candle.utc_begin {
+        kinds.push(IssueKind::Misaligned);
+    }
+    if candle.open <= 0.0 || candle.high <= 0.0 || candle.low <= 0.0 || candle.close <= 0.0 {
+        kinds.push(IssueKind::NonPositivePrice);
+    }
+    if candle.high < candle.low {
+        kinds.push(IssueKind::HighBelowLow);
+    }
+    let in_range = |p: f64| p >= candle.low && p <= candle.high;
+    if !in_range(candle.open) || !in_range(candle.close) {
+        kinds.push(IssueKind::OpenCloseOutOfRange);
+    }
+    let v = &candle.volume_bs;
+    if v.buy_base < 0.0 || v.sell_base < 0.0 || v.buy_quote < 0.0 || v.sell_quote < 0.0 {
+        kinds.push(IssueKind::NegativeVolume);
+    }
+    kinds
+}
+
+async fn duplicate_buckets(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT utc_begin, COUNT(*) AS cnt FROM candles
+        WHERE pair = $1 AND time_frame = $2
+          AND ($3::BIGINT IS NULL OR utc_begin >= $3)
+          AND ($4::BIGINT IS NULL OR utc_begin <= $4)
+        GROUP BY utc_begin, source
+        HAVING COUNT(*) > 1",
+    )
+    .bind(pair)
+    .bind(time_frame)
+    .bind(start_ts)
+    .bind(end_ts)
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|r| Ok((r.try_get("utc_begin")?, r.try_get("cnt")?)))
+        .collect()
+}
+
+// Интервалы, где трейды есть, а свечи из них нет
+async fn buckets_without_candle(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    bucket_ms: i64,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT DISTINCT time_stamp - mod(time_stamp - $6, $3) AS bucket FROM trades
+        WHERE pair = $1
+          AND ($4::BIGINT IS NULL OR time_stamp >= $4)
+          AND ($5::BIGINT IS NULL OR time_stamp < $5 + $3)
+        EXCEPT
+        SELECT utc_begin FROM candles WHERE pair = $1 AND time_frame = $2 AND source = 'aggregated'
+        ORDER BY bucket",
+    )
+    .bind(pair)
+    .bind(time_frame)
+    .bind(bucket_ms)
+    .bind(start_ts)
+    .bind(end_ts)
+    .bind(aggregate::bucket_offset(bucket_ms))
+    .fetch_all(pool)
+    .await
+}
+
+// Свечи из трейдов, у которых buy_base + sell_base расходится с суммой quantity трейдов интервала
+async fn volume_mismatches(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    bucket_ms: i64,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+) -> Result<Vec<(i64, f64, f64)>, sqlx::Error> {
+    let rows = sqlx::query(
+        "WITH t AS (
+            SELECT time_stamp - mod(time_stamp - $7, $3) AS bucket, SUM(quantity::numeric)::float8 AS qty
+            FROM trades
+            WHERE pair = $1
+              AND ($4::BIGINT IS NULL OR time_stamp >= $4)
+              AND ($5::BIGINT IS NULL OR time_stamp < $5 + $3)
+            GROUP BY 1
+        )
+        SELECT c.utc_begin, (c.buy_base + c.sell_base) AS candle_qty, t.qty AS trades_qty
+        FROM candles c
+        JOIN t ON t.bucket = c.utc_begin
+        WHERE c.pair = $1 AND c.time_frame = $2 AND c.source = 'aggregated'
+          AND abs((c.buy_base + c.sell_base) - t.qty) > $6 * GREATEST(abs(t.qty), 1e-12)",
+    )
+    .bind(pair)
+    .bind(time_frame)
+    .bind(bucket_ms)
+    .bind(start_ts)
+    .bind(end_ts)
+    .bind(VOLUME_TOLERANCE)
+    .bind(aggregate::bucket_offset(bucket_ms))
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|r| Ok((r.try_get("utc_begin")?, r.try_get("candle_qty")?, r.try_get("trades_qty")?)))
+        .collect()
+}
+
+// Склеивает проблемные интервалы в непрерывные диапазоны [start, end)
+fn issue_ranges(issues: &[AuditIssue], bucket_ms: i64) -> Vec<(i64, i64)> {
+    let mut starts: Vec<i64> = issues.iter().map(|i| aggregate::bucket_start(i.utc_begin, bucket_ms)).collect();
+    starts.sort_unstable();
+    starts.dedup();
+    let mut ranges: Vec<(i64, i64)> = Vec::new();
+    for start in starts {
+        match ranges.last_mut() {
+            Some((_, end)) if *end == start => *end = start + bucket_ms,
+            _ => ranges.push((start, start + bucket_ms)),
+        }
+    }
+    ranges
+}
+
+// Точечно перезапрашивает проблемные интервалы: интервалы биржи - у биржи, свои таймфреймы -
+// повторной агрегацией трейдов, общие - обоими путями. Возвращает число перезаписанных свечей.
+pub async fn refetch(store: &dyn Storage, pair: &str, time_frame: &str, issues: &[AuditIssue]) -> Result<usize, Box<dyn std::error::Error>> {
+    let bucket_ms = match aggregate::time_frame_ms(time_frame) {
+        Some(ms) => ms,
+        None => return Ok(0),
+    };
+    let interval = aggregate::rest_interval(time_frame).filter(|i| INTERVALS.contains(i));
+    let own = AGG_TIME_FRAMES.contains(&time_frame);
+    if interval.is_none() && !own {
+        return Ok(0);
+    }
+    let mut written = 0;
+    for (start, end) in issue_ranges(issues, bucket_ms) {
+        let mut candles = Vec::new();
+        if let Some(interval) = interval {
+            candles.extend(api::get_candles_between(pair, interval, start, end - 1).await?);
+        }
+        if own {
+            candles.extend(store.aggregate_candles(pair, time_frame, start, end - 1).await?);
+        }
+        written += candles.len();
+        store.upsert_candles(candles).await?;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN, T0};
+
+    fn candle(utc_begin: i64, open: f64, high: f64, low: f64, close: f64) -> Kline {
+        testing::candle("1m", utc_begin, close).with_ohlc(open, high, low, close)
+    }
+
+    #[test]
+    fn valid_candle_has_no_issues() {
+        assert!(check_candle(&candle(T0, 10.0, 12.0, 9.0, 11.0), MIN).is_empty());
+    }
+
+    #[test]
+    fn detects_broken_candles() {
+        assert_eq!(check_candle(&candle(T0 + 1, 10.0, 12.0, 9.0, 11.0), MIN), vec![IssueKind::Misaligned]);
+        assert_eq!(
+            check_candle(&candle(T0, 10.0, 9.0, 12.0, 11.0), MIN),
+            vec![IssueKind::HighBelowLow, IssueKind::OpenCloseOutOfRange]
+        );
+        assert_eq!(check_candle(&candle(T0, 13.0, 12.0, 9.0, 11.0), MIN), vec![IssueKind::OpenCloseOutOfRange]);
+        assert_eq!(
+            check_candle(&candle(T0, 0.0, 12.0, 0.0, 11.0), MIN),
+            vec![IssueKind::NonPositivePrice]
+        );
+        let mut negative = candle(T0, 10.0, 12.0, 9.0, 11.0);
+        negative.volume_bs.sell_quote = -1.0;
+        assert_eq!(check_candle(&negative, MIN), vec![IssueKind::NegativeVolume]);
+    }
+
+    #[test]
+    fn issues_merge_into_refetch_ranges() {
+        let issue = |utc_begin: i64| AuditIssue {
+            pair: "BTC_USDT".to_string(),
+            time_frame: "1m".to_string(),
+            utc_begin,
+            kind: IssueKind::Missing,
+        };
+        // Соседние интервалы склеиваются, повтор и смещённое начало - в тот же интервал
+        let issues = [issue(T0 + 5 * MIN), issue(T0), issue(T0 + MIN), issue(T0 + MIN + 7), issue(T0 + 3 * MIN)];
+        assert_eq!(
+            issue_ranges(&issues, MIN),
+            vec![(T0, T0 + 2 * MIN), (T0 + 3 * MIN, T0 + 4 * MIN), (T0 + 5 * MIN, T0 + 6 * MIN)]
+        );
+    }
+}
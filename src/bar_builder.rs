@@ -0,0 +1,465 @@
+use std::env;
+use std::sync::Arc;
+use chrono::Utc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use crate::aggregate;
+use crate::data_structs::{Bar, BarKind, BarSpec, RecentTrade, PAIRS, VBS};
+use crate::storage::Storage;
+
+// Ёмкость канала между WS-читателем и построителем
+const CHANNEL_CAPACITY: usize = 10_000;
+// Сколько трейдов по времени читается из БД за один запрос при построении по истории
+const HISTORY_WINDOW_MS: i64 = 6 * 60 * 60_000;
+
+// Ряд баров одной пары: открытый бар и последний учтённый трейд.
+// Бар закрывается трейдом, на котором достигнут порог; этот трейд целиком входит в бар.
+pub struct BarSeries {
+    pub pair: String,
+    pub spec: BarSpec,
+    open: Option<Bar>,
+    last: Option<(i64, String)>, // (timestamp, tid) последнего учтённого трейда
+}
+
+impl BarSeries {
+    pub fn new(pair: &str, spec: BarSpec) -> Self {
+        BarSeries {
+            pair: pair.to_string(),
+            spec,
+            open: None,
+            last: None,
+        }
+    }
+
+    // Продолжение ряда после уже записанного бара
+    pub fn after(bar: &Bar) -> Self {
+        BarSeries {
+            pair: bar.pair.clone(),
+            spec: bar.spec,
+            open: None,
+            last: Some((bar.utc_end, bar.last_tid.clone())),
+        }
+    }
+
+    // Незакрытый бар: порог ещё не достигнут
+    pub fn open_bar(&self) -> Option<&Bar> {
+        self.open.as_ref()
+    }
+
+    // Трейд уже учтён или пришёл раньше последнего учтённого
+    pub fn is_behind(&self, trade: &RecentTrade) -> bool {
+        self.last.as_ref().is_some_and(|(ts, tid)| {
+            aggregate::trade_key_order((trade.timestamp, &trade.tid), (*ts, tid)).is_le()
+        })
+    }
+
+    // Учитывает трейд и возвращает бар, если трейд его закрыл.
+    // Трейды других пар, опоздавшие и с непарсящимися числами пропускаются.
+    pub fn push(&mut self, trade: &RecentTrade) -> Option<Bar> {
+        if trade.pair != self.pair || self.is_behind(trade) {
+            return None;
+        }
+        let (price, quantity, amount) = match (
+            trade.price.parse::<f64>(),
+            trade.quantity.parse::<f64>(),
+            trade.amount.parse::<f64>(),
+        ) {
+            (Ok(p), Ok(q), Ok(a)) => (p, q, a),
+            _ => return None,
+        };
+        self.last = Some((trade.timestamp, trade.tid.clone()));
+
+        let spec = self.spec;
+        let bar = self.open.get_or_insert_with(|| Bar {
+            pair: trade.pair.clone(),
+            spec,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume_bs: VBS {
+                buy_base: 0.0,
+                sell_base: 0.0,
+                buy_quote: 0.0,
+                sell_quote: 0.0,
+            },
+            utc_begin: trade.timestamp,
+            utc_end: trade.timestamp,
+            first_tid: trade.tid.clone(),
+            last_tid: trade.tid.clone(),
+            trade_count: 0,
+            vwap: 0.0,
+            imbalance: 0.0,
+        });
+
+        bar.high = bar.high.max(price);
+        bar.low = bar.low.min(price);
+        bar.close = price;
+        bar.utc_end = trade.timestamp;
+        bar.last_tid = trade.tid.clone();
+        bar.trade_count += 1;
+        let sign = match trade.side.as_str() {
+            "buy" => {
+                bar.volume_bs.buy_base += quantity;
+                bar.volume_bs.buy_quote += amount;
+                1.0
+            }
+            "sell" => {
+                bar.volume_bs.sell_base += quantity;
+                bar.volume_bs.sell_quote += amount;
+                -1.0
+            }
+            _ => 0.0,
+        };
+        bar.imbalance += match spec.kind {
+            BarKind::VolumeImbalance => sign * quantity,
+            _ => sign,
+        };
+
+        if !threshold_reached(bar) {
+            return None;
+        }
+        let mut bar = self.open.take()?;
+        bar.vwap = bar.volume_bs.vwap();
+        Some(bar)
+    }
+}
+
+fn threshold_reached(bar: &Bar) -> bool {
+    let progress = match bar.spec.kind {
+        BarKind::Tick => bar.trade_count as f64,
+        BarKind::Volume => bar.volume_bs.buy_base + bar.volume_bs.sell_base,
+        BarKind::Dollar => bar.volume_bs.buy_quote + bar.volume_bs.sell_quote,
+        BarKind::TickImbalance | BarKind::VolumeImbalance => bar.imbalance.abs(),
+    };
+    progress >= bar.spec.threshold
+}
+
+// Закрытые бары из трейдов одной пары; хвост, не добравший порога, не возвращается
+pub fn build_bars(pair: &str, spec: BarSpec, trades: &[RecentTrade]) -> Vec<Bar> {
+    let mut sorted: Vec<&RecentTrade> = trades.iter().collect();
+    sorted.sort_by(|a, b| aggregate::trade_order(a, b));
+    let mut series = BarSeries::new(pair, spec);
+    sorted.into_iter().filter_map(|t| series.push(t)).collect()
+}
+
+// Бары из потока трейдов по нескольким рядам сразу
+pub struct BarBuilder {
+    series: Vec<BarSeries>,
+    late_trades: u64,
+}
+
+impl BarBuilder {
+    pub fn new(series: Vec<BarSeries>) -> Self {
+        BarBuilder { series, late_trades: 0 }
+    }
+
+    // Трейды, пришедшие позже уже учтённых: в бары не попали
+    pub fn late_trades(&self) -> u64 {
+        self.late_trades
+    }
+
+    // Учитывает трейд и возвращает бары, которые он закрыл
+    pub fn push(&mut self, trade: &RecentTrade) -> Vec<Bar> {
+        let mut closed = Vec::new();
+        let mut late = false;
+        for series in self.series.iter_mut().filter(|s| s.pair == trade.pair) {
+            if series.is_behind(trade) {
+                late = true;
+                continue;
+            }
+            closed.extend(series.push(trade));
+        }
+        if late {
+            self.late_trades += 1;
+        }
+        closed
+    }
+}
+
+// Ряды из списка вида "tick:1000,dollar:1000000,BTC_USDT:volume:5":
+// ряд без пары строится для каждой из `pairs`, с префиксом пары - только для неё
+pub fn parse_bar_specs(list: &str, pairs: &[String]) -> Result<Vec<(String, BarSpec)>, String> {
+    let mut specs = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.split_once(':') {
+            Some((pair, rest)) if rest.contains(':') => {
+                specs.push((pair.to_string(), BarSpec::parse(rest)?));
+            }
+            _ => {
+                let spec = BarSpec::parse(entry)?;
+                specs.extend(pairs.iter().map(|pair| (pair.clone(), spec)));
+            }
+        }
+    }
+    Ok(specs)
+}
+
+// BAR_SPECS; пусто - бары по живому потоку не строятся
+pub fn bar_specs_from_env() -> Result<Vec<(String, BarSpec)>, String> {
+    let pairs: Vec<String> = PAIRS.iter().map(|p| p.to_string()).collect();
+    parse_bar_specs(&env::var("BAR_SPECS").unwrap_or_default(), &pairs)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BarReport {
+    pub trades: usize,
+    pub written: usize,
+}
+
+// Продолжает ряд трейдами из БД за [start_ts, end_ts] и записывает закрытые бары
+pub async fn build_history(
+    store: &dyn Storage,
+    series: &mut BarSeries,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<BarReport, sqlx::Error> {
+    let mut report = BarReport::default();
+    // Дальше последнего трейда пары читать нечего
+    let end_ts = match store.latest_trade(&series.pair).await? {
+        Some(trade) => end_ts.min(trade.timestamp),
+        None => return Ok(report),
+    };
+    let mut from = start_ts;
+    while from <= end_ts {
+        let to = from.saturating_add(HISTORY_WINDOW_MS - 1).min(end_ts);
+        let mut trades = store.trades_range(&series.pair, from, to).await?;
+        trades.sort_by(aggregate::trade_order);
+        let bars: Vec<Bar> = trades.iter().filter_map(|t| series.push(t)).collect();
+        report.trades += trades.len();
+        report.written += bars.len();
+        store.upsert_bars(&bars).await?;
+        if to == end_ts {
+            break;
+        }
+        from = to + 1;
+    }
+    Ok(report)
+}
+
+// Ряд с последнего записанного бара (без баров - с start_ts), догнанный по трейдам до end_ts
+pub async fn resume_series(
+    store: &dyn Storage,
+    pair: &str,
+    spec: BarSpec,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<(BarSeries, BarReport), sqlx::Error> {
+    let (mut series, from) = match store.latest_bar(pair, &spec).await? {
+        Some(bar) => (BarSeries::after(&bar), bar.utc_end),
+        None => (BarSeries::new(pair, spec), start_ts),
+    };
+    let report = build_history(store, &mut series, from, end_ts).await?;
+    Ok((series, report))
+}
+
+// Запускает построитель в фоне: сначала догоняет ряды по трейдам из БД,
+// потом строит бары из живого потока и пишет закрытые в хранилище
+pub fn spawn(store: Arc<dyn Storage>, specs: Vec<(String, BarSpec)>) -> (mpsc::Sender<RecentTrade>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let handle = tokio::spawn(run_builder(store, specs, rx));
+    (tx, handle)
+}
+
+async fn run_builder(store: Arc<dyn Storage>, specs: Vec<(String, BarSpec)>, mut rx: mpsc::Receiver<RecentTrade>) {
+    // Ряд без записанных баров начинается с живого потока
+    let now = Utc::now().timestamp_millis();
+    let mut series = Vec::with_capacity(specs.len());
+    for (pair, spec) in specs {
+        match resume_series(&*store, &pair, spec, now, now).await {
+            Ok((resumed, report)) => {
+                if report.trades > 0 {
+                    println!(
+                        "Бары {} {}: догнано по {} трейдам, записано {}",
+                        pair,
+                        spec.name(),
+                        report.trades,
+                        report.written
+                    );
+                }
+                series.push(resumed);
+            }
+            Err(e) => {
+                eprintln!("Ошибка догона баров {} {}: {}", pair, spec.name(), e);
+                series.push(BarSeries::new(&pair, spec));
+            }
+        }
+    }
+
+    let mut builder = BarBuilder::new(series);
+    // Бары, которые не удалось записать; повторяем со следующими
+    let mut pending: Vec<Bar> = Vec::new();
+    while let Some(trade) = rx.recv().await {
+        let closed = builder.push(&trade);
+        if !closed.is_empty() {
+            pending.extend(closed);
+            write(&*store, &mut pending).await;
+        }
+    }
+    write(&*store, &mut pending).await;
+    println!("Построитель баров остановлен, опоздавших трейдов: {}", builder.late_trades());
+}
+
+async fn write(store: &dyn Storage, pending: &mut Vec<Bar>) {
+    if pending.is_empty() {
+        return;
+    }
+    match store.upsert_bars(pending).await {
+        Ok(()) => {
+            println!("Построитель баров: записано {} баров", pending.len());
+            pending.clear();
+        }
+        Err(e) => eprintln!("Ошибка записи {} баров: {}", pending.len(), e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStore, SeriesStore, TradeStore};
+
+    // 2024-01-01 00:00 UTC
+    const T0: i64 = 1_704_067_200_000;
+
+    fn trade(i: i64, price: f64, quantity: f64, side: &str) -> RecentTrade {
+        RecentTrade {
+            tid: i.to_string(),
+            pair: "BTC_USDT".to_string(),
+            price: price.to_string(),
+            amount: (price * quantity).to_string(),
+            quantity: quantity.to_string(),
+            side: side.to_string(),
+            create_time: T0 + i * 1000,
+            timestamp: T0 + i * 1000,
+        }
+    }
+
+    fn spec(value: &str) -> BarSpec {
+        BarSpec::parse(value).unwrap()
+    }
+
+    // (first_tid, last_tid, trade_count)
+    fn bounds(bars: &[Bar]) -> Vec<(String, String, i64)> {
+        bars.iter().map(|b| (b.first_tid.clone(), b.last_tid.clone(), b.trade_count)).collect()
+    }
+
+    fn ids(first: &str, last: &str, count: i64) -> (String, String, i64) {
+        (first.to_string(), last.to_string(), count)
+    }
+
+    #[test]
+    fn tick_bars() {
+        let trades: Vec<RecentTrade> = (1..=5).map(|i| trade(i, 10.0 + i as f64, 1.0, "buy")).collect();
+        let bars = build_bars("BTC_USDT", spec("tick:2"), &trades);
+        // Пятый трейд порога не добрал и в ответ не попадает
+        assert_eq!(bounds(&bars), vec![ids("1", "2", 2), ids("3", "4", 2)]);
+        let b = &bars[1];
+        assert_eq!((b.open, b.high, b.low, b.close), (13.0, 14.0, 13.0, 14.0));
+        assert_eq!((b.utc_begin, b.utc_end), (T0 + 3000, T0 + 4000));
+        assert_eq!(b.vwap, 13.5);
+    }
+
+    #[test]
+    fn volume_and_dollar_bars_include_closing_trade() {
+        let trades = vec![
+            trade(1, 10.0, 1.0, "buy"),
+            trade(2, 20.0, 1.5, "sell"),
+            trade(3, 10.0, 4.0, "buy"), // перевалил порог объёма - целиком в баре
+            trade(4, 50.0, 1.0, "sell"),
+            trade(5, 10.0, 1.0, "buy"),
+        ];
+        let bars = build_bars("BTC_USDT", spec("volume:3"), &trades);
+        assert_eq!(bounds(&bars), vec![ids("1", "3", 3)]);
+        let v = &bars[0].volume_bs;
+        assert_eq!((v.buy_base, v.sell_base, v.buy_quote, v.sell_quote), (5.0, 1.5, 50.0, 30.0));
+
+        // Обороты 10, 30, 40 | 50, 10
+        let bars = build_bars("BTC_USDT", spec("dollar:60"), &trades);
+        assert_eq!(bounds(&bars), vec![ids("1", "3", 3), ids("4", "5", 2)]);
+    }
+
+    #[test]
+    fn imbalance_bars() {
+        let trades = vec![
+            trade(1, 10.0, 1.0, "buy"),
+            trade(2, 10.0, 3.0, "sell"),
+            trade(3, 10.0, 1.0, "buy"),
+            trade(4, 10.0, 1.0, "buy"),
+        ];
+        // Перевес в трейдах 1, 0, 1, 2
+        let bars = build_bars("BTC_USDT", spec("tick_imbalance:2"), &trades);
+        assert_eq!(bounds(&bars), vec![ids("1", "4", 4)]);
+        assert_eq!(bars[0].imbalance, 2.0);
+
+        // Перевес в объёме 1, -2 - порог достигнут продажей; следующий бар с нуля: 1, 2
+        let bars = build_bars("BTC_USDT", spec("volume_imbalance:2"), &trades);
+        assert_eq!(bounds(&bars), vec![ids("1", "2", 2), ids("3", "4", 2)]);
+        assert_eq!((bars[0].imbalance, bars[1].imbalance), (-2.0, 2.0));
+    }
+
+    fn sample() -> Vec<RecentTrade> {
+        (1..=40)
+            .map(|i| {
+                let side = if i % 3 == 0 { "sell" } else { "buy" };
+                trade(i, 100.0 + (i % 7) as f64, 0.5 + (i % 4) as f64, side)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_equals_streaming() {
+        let trades = sample();
+        let specs = [spec("tick:5"), spec("volume:4"), spec("dollar:500"), spec("tick_imbalance:3"), spec("volume_imbalance:3")];
+        let mut builder = BarBuilder::new(specs.iter().map(|s| BarSeries::new("BTC_USDT", *s)).collect());
+        let streamed: Vec<Bar> = trades.iter().flat_map(|t| builder.push(t)).collect();
+
+        // Пакет сортирует трейды сам
+        let mut shuffled = trades.clone();
+        shuffled.reverse();
+        for s in specs {
+            let batch = build_bars("BTC_USDT", s, &shuffled);
+            let stream: Vec<Bar> = streamed.iter().filter(|b| b.spec == s).cloned().collect();
+            assert!(!batch.is_empty(), "{}", s.name());
+            assert_eq!(bounds(&batch), bounds(&stream), "{}", s.name());
+            assert_eq!(batch.iter().map(|b| b.close).collect::<Vec<_>>(), stream.iter().map(|b| b.close).collect::<Vec<_>>());
+        }
+
+        // Повтор и опоздавший трейд в бары не попадают
+        assert!(builder.push(&trades[10]).is_empty());
+        assert_eq!(builder.late_trades(), 1);
+    }
+
+    #[tokio::test]
+    async fn history_resumes_after_last_bar() {
+        let trades = sample();
+        let store = MemoryStore::default();
+        store.insert_trades(&trades[..25]).await.unwrap();
+        let tick = spec("tick:5");
+        let (_, report) = resume_series(&store, "BTC_USDT", tick, T0, T0 + 100_000).await.unwrap();
+        assert_eq!((report.trades, report.written), (25, 5));
+
+        // Остальные трейды: ряд продолжается с последнего записанного бара, не дублируя его трейды
+        store.insert_trades(&trades[25..]).await.unwrap();
+        let (_, report) = resume_series(&store, "BTC_USDT", tick, T0, T0 + 100_000).await.unwrap();
+        assert_eq!(report.written, 3);
+        let stored = store.bars_range("BTC_USDT", &tick, T0, T0 + 100_000).await.unwrap();
+        assert_eq!(bounds(&stored), bounds(&build_bars("BTC_USDT", tick, &trades)));
+    }
+
+    #[test]
+    fn parses_specs_per_pair() {
+        let pairs = vec!["BTC_USDT".to_string(), "ETH_USDT".to_string()];
+        let specs = parse_bar_specs("tick:1000, ETH_USDT:volume:5", &pairs).unwrap();
+        let names: Vec<(String, String)> = specs.iter().map(|(p, s)| (p.clone(), s.name())).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("BTC_USDT".to_string(), "tick:1000".to_string()),
+                ("ETH_USDT".to_string(), "tick:1000".to_string()),
+                ("ETH_USDT".to_string(), "volume:5".to_string()),
+            ]
+        );
+        assert!(parse_bar_specs("tick:0", &pairs).is_err());
+        assert!(parse_bar_specs("range:5", &pairs).is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+use chrono::Utc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, RecentTrade, VBS};
+use crate::storage::Storage;
+
+// Как часто проверять, не закрылись ли корзины по часам
+const CLOSE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Ёмкость канала между WS-читателем и построителем
+const CHANNEL_CAPACITY: usize = 10_000;
+// Сколько незаписанных свечей держать, пока хранилище недоступно. Сверх этого выбрасываются самые старые:
+// закрытые свечи всё равно пересчитает планировщик по трейдам из БД
+const MAX_PENDING: usize = 10_000;
+
+type SeriesKey = (String, String); // (pair, time_frame)
+
+// Незакрытая свеча и крайние трейды корзины: open/close определяются по (timestamp, tid),
+// а не по порядку прихода
+struct OpenCandle {
+    kline: Kline,
+    first: (i64, String),
+    last: (i64, String),
+}
+
+// Строит свечи из потока трейдов в памяти. На каждую (пару, таймфрейм) держит одну открытую корзину
+// и отдаёт окончательную свечу, как только корзина закрылась: пришёл трейд следующей корзины
+// или по часам прошёл конец интервала.
+pub struct CandleBuilder {
+    time_frames: Vec<(String, i64)>,
+    open: HashMap<SeriesKey, OpenCandle>,
+    closed_until: HashMap<SeriesKey, i64>, // конец последней отданной корзины
+    late_trades: u64,
+}
+
+impl CandleBuilder {
+    pub fn new(time_frames: &[&str]) -> Self {
+        let time_frames = time_frames
+            .iter()
+            .filter_map(|tf| match aggregate::time_frame_ms(tf) {
+                Some(ms) => Some((tf.to_string(), ms)),
+                None => {
+                    eprintln!("Неизвестный таймфрейм для построителя свечей: {}", tf);
+                    None
+                }
+            })
+            .collect();
+        CandleBuilder {
+            time_frames,
+            open: HashMap::new(),
+            closed_until: HashMap::new(),
+            late_trades: 0,
+        }
+    }
+
+    // Трейды в уже отданные корзины: в свечах построителя не учтены, их подхватит пересчёт закрытых свечей
+    pub fn late_trades(&self) -> u64 {
+        self.late_trades
+    }
+
+    // Учитывает трейд и возвращает свечи, которые он закрыл
+    pub fn push(&mut self, trade: &RecentTrade) -> Vec<Kline> {
+        let (price, quantity, amount) = match (
+            trade.price.parse::<f64>(),
+            trade.quantity.parse::<f64>(),
+            trade.amount.parse::<f64>(),
+        ) {
+            (Ok(p), Ok(q), Ok(a)) => (p, q, a),
+            _ => return Vec::new(),
+        };
+
+        let mut closed = Vec::new();
+        let mut late = false;
+        for (time_frame, bucket_ms) in &self.time_frames {
+            let key = (trade.pair.clone(), time_frame.clone());
+            let begin = aggregate::bucket_start(trade.timestamp, *bucket_ms);
+            if self.closed_until.get(&key).is_some_and(|until| begin < *until) {
+                late = true;
+                continue;
+            }
+
+            match self.open.get_mut(&key) {
+                Some(candle) if candle.kline.utc_begin == begin => {
+                    update(candle, trade, price, quantity, amount);
+                    continue;
+                }
+                Some(candle) if candle.kline.utc_begin > begin => {
+                    late = true;
+                    continue;
+                }
+                _ => {}
+            }
+
+            if let Some(prev) = self.open.remove(&key) {
+                self.closed_until.insert(key.clone(), prev.kline.utc_begin + bucket_ms);
+                closed.push(finish(prev));
+            }
+            let mut candle = OpenCandle {
+                kline: Kline {
+                    pair: trade.pair.clone(),
+                    time_frame: time_frame.clone(),
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    volume_bs: VBS {
+                        buy_base: 0.0,
+                        sell_base: 0.0,
+                        buy_quote: 0.0,
+                        sell_quote: 0.0,
+                    },
+                    utc_begin: begin,
+                    close_time: begin + bucket_ms - 1,
+                    trade_count: 0,
+                    vwap: 0.0,
+                    source: CandleSource::Aggregated,
+                    is_final: false,
+                    revision: 0,
+                },
+                first: (trade.timestamp, trade.tid.clone()),
+                last: (trade.timestamp, trade.tid.clone()),
+            };
+            add_volume(&mut candle.kline, &trade.side, quantity, amount);
+            self.open.insert(key, candle);
+        }
+        if late {
+            self.late_trades += 1;
+        }
+        closed
+    }
+
+    // Отдаёт свечи, интервал которых закончился к now_ms
+    pub fn close_due(&mut self, now_ms: i64) -> Vec<Kline> {
+        let bucket_ms: HashMap<&str, i64> = self.time_frames.iter().map(|(tf, ms)| (tf.as_str(), *ms)).collect();
+        let due: Vec<SeriesKey> = self
+            .open
+            .iter()
+            .filter(|((_, tf), c)| bucket_ms.get(tf.as_str()).is_some_and(|ms| c.kline.utc_begin + ms <= now_ms))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut closed = Vec::with_capacity(due.len());
+        for key in due {
+            if let Some(candle) = self.open.remove(&key) {
+                let end = candle.kline.utc_begin + bucket_ms[key.1.as_str()];
+                self.closed_until.insert(key, end);
+                closed.push(finish(candle));
+            }
+        }
+        closed.sort_by(|a, b| (&a.pair, &a.time_frame, a.utc_begin).cmp(&(&b.pair, &b.time_frame, b.utc_begin)));
+        closed
+    }
+}
+
+fn update(candle: &mut OpenCandle, trade: &RecentTrade, price: f64, quantity: f64, amount: f64) {
+    let key = (trade.timestamp, trade.tid.as_str());
+    if aggregate::trade_key_order(key, (candle.first.0, &candle.first.1)) == Ordering::Less {
+        candle.kline.open = price;
+        candle.first = (trade.timestamp, trade.tid.clone());
+    }
+    if aggregate::trade_key_order(key, (candle.last.0, &candle.last.1)) == Ordering::Greater {
+        candle.kline.close = price;
+        candle.last = (trade.timestamp, trade.tid.clone());
+    }
+    candle.kline.high = candle.kline.high.max(price);
+    candle.kline.low = candle.kline.low.min(price);
+    add_volume(&mut candle.kline, &trade.side, quantity, amount);
+}
+
+fn add_volume(kline: &mut Kline, side: &str, quantity: f64, amount: f64) {
+    kline.trade_count += 1;
+    match side {
+        "buy" => {
+            kline.volume_bs.buy_base += quantity;
+            kline.volume_bs.buy_quote += amount;
+        }
+        "sell" => {
+            kline.volume_bs.sell_base += quantity;
+            kline.volume_bs.sell_quote += amount;
+        }
+        _ => {}
+    }
+}
+
+fn finish(candle: OpenCandle) -> Kline {
+    let mut kline = candle.kline;
+    kline.vwap = kline.volume_bs.vwap();
+    kline.is_final = true;
+    kline
+}
+
+// Запускает построитель в фоне: трейды приходят через канал, закрытые свечи пишутся в хранилище
+pub fn spawn(store: Arc<dyn Storage>, time_frames: &[&str]) -> (mpsc::Sender<RecentTrade>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    let builder = CandleBuilder::new(time_frames);
+    let handle = tokio::spawn(run_builder(store, builder, rx));
+    (tx, handle)
+}
+
+async fn run_builder(store: Arc<dyn Storage>, mut builder: CandleBuilder, mut rx: mpsc::Receiver<RecentTrade>) {
+    let mut close_tick = interval(CLOSE_CHECK_INTERVAL);
+    close_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // Свечи, которые не удалось записать; повторяем на следующем тике
+    let mut pending: Vec<Kline> = Vec::new();
+    let mut dropped: u64 = 0;
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                match msg {
+                    Some(trade) => {
+                        let closed = builder.push(&trade);
+                        if !closed.is_empty() {
+                            pending.extend(closed);
+                            write(&*store, &mut pending, &mut dropped).await;
+                        }
+                    }
+                    None => {
+                        pending.extend(builder.close_due(Utc::now().timestamp_millis()));
+                        write(&*store, &mut pending, &mut dropped).await;
+                        println!(
+                            "Построитель свечей остановлен, опоздавших трейдов: {}, выброшено свечей: {}",
+                            builder.late_trades(), dropped
+                        );
+                        return;
+                    }
+                }
+            }
+            _ = close_tick.tick() => {
+                pending.extend(builder.close_due(Utc::now().timestamp_millis()));
+                write(&*store, &mut pending, &mut dropped).await;
+            }
+        }
+    }
+}
+
+async fn write(store: &dyn Storage, pending: &mut Vec<Kline>, dropped: &mut u64) {
+    if pending.is_empty() {
+        return;
+    }
+    match store.upsert_candles(pending.clone()).await {
+        Ok(()) => {
+            println!("Построитель свечей: записано {} закрытых свечей", pending.len());
+            pending.clear();
+        }
+        Err(e) => {
+            eprintln!("Ошибка записи {} свечей построителя: {}", pending.len(), e);
+            *dropped += cap_pending(pending, MAX_PENDING) as u64;
+        }
+    }
+}
+
+// Оставляет не больше max самых новых свечей, возвращает число выброшенных
+fn cap_pending(pending: &mut Vec<Kline>, max: usize) -> usize {
+    if pending.len() <= max {
+        return 0;
+    }
+    let excess = pending.len() - max;
+    pending.drain(..excess);
+    eprintln!("Выброшено {} самых старых свечей построителя из-за ошибок записи", excess);
+    excess
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(ts: i64, tid: &str, price: f64, side: &str) -> RecentTrade {
+        RecentTrade {
+            tid: tid.to_string(),
+            pair: "BTC_USDT".to_string(),
+            price: price.to_string(),
+            amount: price.to_string(),
+            quantity: "1".to_string(),
+            side: side.to_string(),
+            create_time: ts,
+            timestamp: ts,
+        }
+    }
+
+    const MIN: i64 = 60_000;
+
+    #[test]
+    fn closes_bucket_on_next_bucket_trade() {
+        let mut builder = CandleBuilder::new(&["1m"]);
+        assert!(builder.push(&trade(MIN + 1, "1", 10.0, "buy")).is_empty());
+        assert!(builder.push(&trade(MIN + 30_000, "2", 12.0, "sell")).is_empty());
+        assert!(builder.push(&trade(2 * MIN - 1, "3", 11.0, "buy")).is_empty());
+
+        let closed = builder.push(&trade(2 * MIN, "4", 20.0, "buy"));
+        assert_eq!(closed.len(), 1);
+        let c = &closed[0];
+        assert_eq!((c.utc_begin, c.close_time), (MIN, 2 * MIN - 1));
+        assert_eq!((c.open, c.high, c.low, c.close), (10.0, 12.0, 10.0, 11.0));
+        assert_eq!(c.trade_count, 3);
+        assert_eq!((c.volume_bs.buy_base, c.volume_bs.sell_base), (2.0, 1.0));
+        assert_eq!((c.volume_bs.buy_quote, c.volume_bs.sell_quote), (21.0, 12.0));
+        assert!((c.vwap - 11.0).abs() < 1e-12);
+        assert!(c.is_final);
+
+        // По часам закрывается только корзина, чей интервал уже прошёл
+        assert!(builder.close_due(3 * MIN - 1).is_empty());
+        let closed = builder.close_due(3 * MIN);
+        assert_eq!(closed.len(), 1);
+        assert_eq!(closed[0].utc_begin, 2 * MIN);
+    }
+
+    #[test]
+    fn out_of_order_trades_keep_open_close_by_time_and_tid() {
+        let mut builder = CandleBuilder::new(&["1m"]);
+        builder.push(&trade(MIN + 500, "10", 5.0, "buy"));
+        builder.push(&trade(MIN + 100, "9", 3.0, "buy")); // раньше по времени - новый open
+        builder.push(&trade(MIN + 500, "2", 4.0, "buy")); // то же время, меньший числовой tid - open не меняет
+        builder.push(&trade(MIN + 900, "a1", 7.0, "sell"));
+        builder.push(&trade(MIN + 900, "100", 6.0, "sell")); // числовой tid раньше нечислового
+
+        let c = &builder.close_due(2 * MIN)[0];
+        assert_eq!((c.open, c.close), (3.0, 7.0));
+        assert_eq!((c.high, c.low), (7.0, 3.0));
+        assert_eq!(c.trade_count, 5);
+    }
+
+    #[test]
+    fn late_trades_are_counted_not_applied() {
+        let mut builder = CandleBuilder::new(&["1m"]);
+        builder.push(&trade(MIN, "1", 1.0, "buy"));
+        let closed = builder.push(&trade(2 * MIN, "2", 2.0, "buy"));
+        assert_eq!(closed.len(), 1);
+
+        // Трейд в уже отданную корзину
+        assert!(builder.push(&trade(MIN + 5, "3", 9.0, "buy")).is_empty());
+        // Трейд раньше открытой корзины, когда отданных ещё нет
+        let mut fresh = CandleBuilder::new(&["1m"]);
+        fresh.push(&trade(2 * MIN, "5", 1.0, "buy"));
+        fresh.push(&trade(MIN, "4", 1.0, "buy"));
+        assert_eq!((builder.late_trades(), fresh.late_trades()), (1, 1));
+
+        let c = &builder.close_due(3 * MIN)[0];
+        assert_eq!((c.utc_begin, c.trade_count, c.high), (2 * MIN, 1, 2.0));
+    }
+
+    #[test]
+    fn duplicate_tids_are_counted_as_received() {
+        // Дедупликация - дело хранилища; построитель учитывает каждый пришедший трейд,
+        // а одинаковый ключ не меняет open/close
+        let mut builder = CandleBuilder::new(&["1m", "1h"]);
+        builder.push(&trade(MIN, "7", 1.0, "buy"));
+        builder.push(&trade(MIN, "7", 2.0, "buy"));
+        let closed = builder.close_due(2 * MIN);
+        assert_eq!(closed.len(), 1);
+        assert_eq!((closed[0].open, closed[0].close, closed[0].high), (1.0, 1.0, 2.0));
+        assert_eq!(closed[0].trade_count, 2);
+    }
+
+    #[test]
+    fn pending_is_capped() {
+        let mut builder = CandleBuilder::new(&["1m"]);
+        let mut pending = Vec::new();
+        for i in 0..5 {
+            builder.push(&trade(i * MIN, &i.to_string(), 1.0, "buy"));
+            pending.extend(builder.close_due((i + 1) * MIN));
+        }
+        assert_eq!(cap_pending(&mut pending, 3), 2);
+        assert_eq!(pending.iter().map(|c| c.utc_begin).collect::<Vec<_>>(), vec![2 * MIN, 3 * MIN, 4 * MIN]);
+        assert_eq!(cap_pending(&mut pending, 3), 0);
+    }
+}
@@ -0,0 +1,563 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use sqlx::PgPool;
+use crate::aggregate;
+use crate::api;
+use crate::audit;
+use crate::bar_builder;
+use crate::correlation;
+use crate::data_structs::{VolumeProfile, PAIRS};
+use crate::db;
+use crate::derived::BrickSize;
+use crate::export::{self, ExportFormat};
+use crate::import::{self, ImportFormat, ImportMode, ImportOptions, ImportTable};
+use crate::indicators;
+use crate::order_flow::{self, FlowInput};
+use crate::reconcile::{self, Tolerance};
+use crate::rollup::{self, ROLLUP_TIME_FRAMES};
+use crate::storage::Storage;
+use crate::volatility;
+use crate::volume_profile::{self, PriceBins, CUSTOM_PERIOD, DEFAULT_VALUE_AREA};
+
+// Флаги вида `--name value` после имени команды; флаг без значения считается "true"
+pub struct Args {
+    flags: HashMap<String, String>,
+}
+
+impl Args {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut flags = HashMap::new();
+        let mut iter = args.iter().peekable();
+        while let Some(arg) = iter.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("Ожидался флаг вида --name, получено: {}", arg))?;
+            let value = match iter.peek() {
+                Some(next) if !next.starts_with("--") => iter.next().cloned().unwrap_or_default(),
+                _ => "true".to_string(),
+            };
+            // Свечи хранятся под короткими именами таймфреймов, имена REST API ("MINUTE_1") переводятся в них
+            let value = if name == "time-frame" {
+                value.split(',').map(|tf| aggregate::canonical_time_frame(tf.trim())).collect::<Vec<_>>().join(",")
+            } else {
+                value
+            };
+            flags.insert(name.to_string(), value);
+        }
+        Ok(Args { flags })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.flags.get(name).map(String::as_str)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        matches!(self.get(name), Some("true") | Some("yes") | Some("1"))
+    }
+
+    pub fn require(&self, name: &str) -> Result<&str, String> {
+        self.get(name).ok_or_else(|| format!("Не задан обязательный флаг --{}", name))
+    }
+
+    // --pair BTC_USDT,ETH_USDT; по умолчанию все отслеживаемые пары. Повторы отбрасываются:
+    // экспорт не пишет одну партицию дважды.
+    pub fn pairs(&self) -> Vec<String> {
+        match self.get("pair") {
+            Some(list) => {
+                let mut pairs: Vec<String> = Vec::new();
+                for pair in list.split(',').map(str::trim) {
+                    if !pairs.iter().any(|p| p == pair) {
+                        pairs.push(pair.to_string());
+                    }
+                }
+                pairs
+            }
+            None => PAIRS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    pub fn time(&self, name: &str) -> Result<Option<i64>, String> {
+        self.get(name).map(parse_time).transpose()
+    }
+}
+
+// Миллисекунды эпохи, "2025-01-31" или "2025-01-31T12:00:00" (UTC)
+pub fn parse_time(value: &str) -> Result<i64, String> {
+    if let Ok(ms) = value.parse::<i64>() {
+        return Ok(ms);
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(dt.and_utc().timestamp_millis());
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp_millis());
+    }
+    Err(format!("Не удалось разобрать время: {}", value))
+}
+
+// Аудит и запросы db.rs проверяют и читают таблицы Postgres напрямую (дубли корзин, сверка с трейдами в SQL),
+// поэтому доступны только с хранилищем Postgres
+fn require_pg(store: &dyn Storage) -> Result<&PgPool, String> {
+    store
+        .pg_pool()
+        .ok_or_else(|| "Команда работает только с хранилищем Postgres".to_string())
+}
+
+// export --table candles|trades|heikin_ashi|renko|volume_profiles [--pair A,B] [--time-frame 1m]
+//        [--from ..] [--to ..] [--format parquet|csv] [--out ./export] [--brick 25.5|atr:14]
+//        [--period 1d|1w] [--bins count:100] [--overwrite]
+// Работает с любым хранилищем; диапазон по умолчанию - весь диапазон бэкфилла.
+// Файлы прошлых запусков без --overwrite не перезаписываются.
+pub async fn export(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let format = ExportFormat::parse(args.get("format").unwrap_or("parquet"))?;
+    let out_dir = PathBuf::from(args.get("out").unwrap_or("./export"));
+    let overwrite = args.flag("overwrite");
+    let pairs = args.pairs();
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    let summary = match args.require("table")? {
+        "candles" => {
+            let time_frame = args.require("time-frame")?;
+            export::export_candles(store, &pairs, time_frame, start_ts, end_ts, format, &out_dir, overwrite).await?
+        }
+        "trades" => export::export_trades(store, &pairs, start_ts, end_ts, format, &out_dir, overwrite).await?,
+        "heikin_ashi" => {
+            let time_frame = args.require("time-frame")?;
+            export::export_heikin_ashi(store, &pairs, time_frame, start_ts, end_ts, format, &out_dir, overwrite).await?
+        }
+        "renko" => {
+            let time_frame = args.require("time-frame")?;
+            let brick = BrickSize::parse(args.require("brick")?)?;
+            export::export_renko(store, &pairs, time_frame, brick, start_ts, end_ts, format, &out_dir, overwrite).await?
+        }
+        "volume_profiles" => {
+            let period = args.require("period")?;
+            let bins = args.get("bins").map(PriceBins::parse).transpose()?.unwrap_or_default();
+            export::export_volume_profiles(store, &pairs, period, bins, start_ts, end_ts, format, &out_dir, overwrite).await?
+        }
+        other => return Err(format!("Неизвестная таблица для экспорта: {}", other).into()),
+    };
+
+    println!(
+        "Экспорт завершён: {} строк в {} файлах, каталог {}",
+        summary.rows,
+        summary.files,
+        out_dir.display()
+    );
+    Ok(())
+}
+
+// import --table candles|trades --file path [--format csv|jsonl|parquet] [--pair P] [--time-frame TF]
+//        [--map поле=колонка,...] [--batch 5000] [--mode insert|upsert]
+pub async fn import(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(args.require("file")?);
+    let table = match args.require("table")? {
+        "candles" => ImportTable::Candles,
+        "trades" => ImportTable::Trades,
+        other => return Err(format!("Неизвестная таблица для импорта: {}", other).into()),
+    };
+    let format = match args.get("format") {
+        Some(f) => ImportFormat::parse(f)?,
+        None => ImportFormat::from_path(path)?,
+    };
+    let options = ImportOptions {
+        table,
+        format,
+        mode: ImportMode::parse(args.get("mode").unwrap_or("insert"))?,
+        mapping: import::parse_mapping(args.get("map").unwrap_or(""))?,
+        pair: args.get("pair").map(str::to_string),
+        time_frame: args.get("time-frame").map(str::to_string),
+        batch_size: args
+            .get("batch")
+            .map(|b| b.parse::<usize>().map_err(|_| format!("Неверный размер пачки: {}", b)))
+            .transpose()?
+            .unwrap_or(5000)
+            .max(1),
+    };
+
+    let report = import::import_file(store, path, &options).await?;
+    println!(
+        "Импорт {} завершён: записано {}, пропущено {}, отклонено {}",
+        path.display(),
+        report.imported,
+        report.skipped,
+        report.rejected
+    );
+    Ok(())
+}
+
+// audit [--pair A,B] [--time-frame TF] [--from ..] [--to ..] [--limit 50] [--refetch]
+pub async fn audit(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let pool = require_pg(store)?;
+    let start_ts = args.time("from")?;
+    let end_ts = args.time("to")?;
+    let limit: usize = args.get("limit").and_then(|l| l.parse().ok()).unwrap_or(50);
+    let mut total_issues = 0;
+
+    for pair in args.pairs() {
+        let time_frames = match args.get("time-frame") {
+            Some(tf) => vec![tf.to_string()],
+            None => db::list_time_frames(pool, Some(&pair)).await?,
+        };
+        for time_frame in time_frames {
+            let report = audit::audit_series(pool, &pair, &time_frame, start_ts, end_ts).await?;
+            println!(
+                "Аудит {} {}: проверено {} свечей, проблем {}",
+                pair,
+                time_frame,
+                report.candles_checked,
+                report.issues.len()
+            );
+            for issue in report.issues.iter().take(limit) {
+                println!("  {} {:?}", issue.utc_begin, issue.kind);
+            }
+            if report.issues.len() > limit {
+                println!("  ... и ещё {}", report.issues.len() - limit);
+            }
+            total_issues += report.issues.len();
+
+            if args.flag("refetch") && !report.issues.is_empty() {
+                match audit::refetch(store, &pair, &time_frame, &report.issues).await {
+                    Ok(written) => println!("  Перезапрошено и записано {} свечей", written),
+                    Err(e) => eprintln!("  Ошибка перезапроса {} {}: {}", pair, time_frame, e),
+                }
+            }
+        }
+    }
+
+    println!("Аудит завершён, всего проблем: {}", total_issues);
+    Ok(())
+}
+
+// indicators --spec sma:20,rsi,macd:12,26,9 --time-frame 1h,1d [--pair A,B] [--from ..] [--to ..]
+// Считает индикаторы по записанным свечам и сохраняет; по умолчанию - индикаторы из INDICATORS
+// и весь диапазон бэкфилла
+pub async fn indicators(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let specs = match args.get("spec") {
+        Some(list) => indicators::parse_indicator_specs(list)?,
+        None => indicators::indicator_specs_from_env()?,
+    };
+    if specs.is_empty() {
+        return Err("Не заданы индикаторы: --spec или INDICATORS".into());
+    }
+    let time_frames: Vec<&str> = args.require("time-frame")?.split(',').map(str::trim).collect();
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    for pair in args.pairs() {
+        for time_frame in &time_frames {
+            for spec in &specs {
+                let written = indicators::compute_range(store, &pair, time_frame, *spec, start_ts, end_ts).await?;
+                println!(
+                    "Индикатор {}({}) {} {}: записано {} значений",
+                    spec.name(),
+                    spec.params(),
+                    pair,
+                    time_frame,
+                    written
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+// order-flow --time-frame 1m,1h [--pair A,B] [--from ..] [--to ..] [--window 20] [--from-trades]
+// Считает дельту, CVD, перекос, расхождение с ценой и z-оценки объёмов и сохраняет;
+// с --from-trades - по свечам, заново собранным из трейдов, иначе по записанным свечам
+pub async fn order_flow(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let time_frames: Vec<&str> = args.require("time-frame")?.split(',').map(str::trim).collect();
+    let window = match args.get("window") {
+        Some(w) => w
+            .parse::<usize>()
+            .ok()
+            .filter(|w| *w >= 2)
+            .ok_or_else(|| format!("Неверное окно: {}", w))?,
+        None => order_flow::DEFAULT_WINDOW,
+    };
+    let input = if args.flag("from-trades") { FlowInput::Trades } else { FlowInput::Candles };
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    for pair in args.pairs() {
+        for time_frame in &time_frames {
+            let written =
+                order_flow::compute_range(store, input, &pair, time_frame, window, start_ts, end_ts).await?;
+            println!("Поток ордеров {} {}: записано {} свечей", pair, time_frame, written);
+        }
+    }
+    Ok(())
+}
+
+// volume-profile --period 1d|1w|custom [--pair A,B] [--from ..] [--to ..] [--bins 10|count:100] [--value-area 0.7]
+// Для 1d и 1w строит и сохраняет профили закрытых периодов диапазона (по умолчанию - как у свечей);
+// custom - один профиль за весь диапазон (по умолчанию - последние сутки), он только печатается
+pub async fn volume_profile(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let period = args.require("period")?;
+    let bins = args.get("bins").map(PriceBins::parse).transpose()?.unwrap_or_default();
+    let value_area = match args.get("value-area") {
+        Some(v) => v
+            .parse::<f64>()
+            .ok()
+            .filter(|v| *v > 0.0 && *v <= 1.0)
+            .ok_or_else(|| format!("Неверная доля зоны стоимости: {}", v))?,
+        None => DEFAULT_VALUE_AREA,
+    };
+
+    if period == CUSTOM_PERIOD {
+        let end_ts = args.time("to")?.unwrap_or_else(|| Utc::now().timestamp_millis());
+        let start_ts = args.time("from")?.unwrap_or(end_ts - 24 * 60 * 60_000);
+        for pair in args.pairs() {
+            match volume_profile::build_profile(store, &pair, period, start_ts, end_ts, bins, value_area).await? {
+                Some(profile) => print_profile(&profile),
+                None => println!("Профиль объёма {}: нет трейдов", pair),
+            }
+        }
+        return Ok(());
+    }
+
+    if !volume_profile::STORED_PERIODS.contains(&period) {
+        return Err(format!("Неизвестный период профиля: {}", period).into());
+    }
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+    for pair in args.pairs() {
+        let written = volume_profile::compute_range(store, &pair, period, bins, value_area, start_ts, end_ts).await?;
+        println!("Профили объёма {} {}: записано {}", pair, period, written);
+        for profile in store.volume_profiles_range(&pair, period, &bins.name(), start_ts, end_ts).await? {
+            print_profile(&profile);
+        }
+    }
+    Ok(())
+}
+
+fn print_profile(profile: &VolumeProfile) {
+    println!(
+        "{} {} {}: уровней {}, POC {}, VAH {}, VAL {}",
+        profile.pair,
+        profile.period,
+        profile.utc_begin,
+        profile.levels.len(),
+        profile.poc,
+        profile.value_area_high,
+        profile.value_area_low
+    );
+}
+
+// volatility [--time-frame 1h,1d] [--pair A,B] [--from ..] [--to ..] [--window 30] [--realized]
+// Скользящие оценки close-to-close, Паркинсона, Гарман-Класса, Роджерса-Сатчелла и Янг-Чжана по свечам
+// таймфреймов; с --realized - ещё дневная реализованная волатильность по минутным свечам. Всё сохраняется.
+pub async fn volatility(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let time_frames: Vec<&str> = args
+        .get("time-frame")
+        .map(|list| list.split(',').map(str::trim).collect())
+        .unwrap_or_default();
+    let realized = args.flag("realized");
+    if time_frames.is_empty() && !realized {
+        return Err("Нужен --time-frame или --realized".into());
+    }
+    let window = match args.get("window") {
+        Some(w) => w
+            .parse::<usize>()
+            .ok()
+            .filter(|w| *w >= 2)
+            .ok_or_else(|| format!("Неверное окно: {}", w))?,
+        None => volatility::DEFAULT_WINDOW,
+    };
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    for pair in args.pairs() {
+        for time_frame in &time_frames {
+            let written = volatility::compute_range(store, &pair, time_frame, window, start_ts, end_ts).await?;
+            println!("Волатильность {} {}: записано {} оценок", pair, time_frame, written);
+        }
+        if realized {
+            let days = volatility::compute_realized_range(store, &pair, start_ts, end_ts).await?;
+            println!("Реализованная волатильность {}: записано {} дней", pair, days);
+        }
+    }
+    Ok(())
+}
+
+// correlation --time-frame 1h,1d [--from ..] [--to ..] [--window 100]
+// Скользящие корреляции и беты доходностей всех отслеживаемых пар; сохраняет матрицы
+// и печатает беты к BTC_USDT на последней из них
+pub async fn correlation(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let time_frames: Vec<&str> = args.require("time-frame")?.split(',').map(str::trim).collect();
+    let window = match args.get("window") {
+        Some(w) => w
+            .parse::<usize>()
+            .ok()
+            .filter(|w| *w >= 3)
+            .ok_or_else(|| format!("Неверное окно: {}", w))?,
+        None => correlation::DEFAULT_WINDOW,
+    };
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    for time_frame in time_frames {
+        let written = correlation::compute_range(store, time_frame, window, start_ts, end_ts).await?;
+        println!("Корреляции {}: записано {} матриц", time_frame, written);
+        let latest = store
+            .correlation_range(time_frame, window as i64, start_ts, end_ts)
+            .await?
+            .pop();
+        if let Some(matrix) = latest {
+            for pair in &matrix.pairs {
+                if let Some(cell) = matrix.cell(pair, correlation::BENCHMARK) {
+                    println!(
+                        "  {} {} к {}: корреляция {}, бета {}, наблюдений {}",
+                        matrix.utc_begin,
+                        pair,
+                        correlation::BENCHMARK,
+                        cell.correlation.map_or("-".to_string(), |c| format!("{:.4}", c)),
+                        cell.beta.map_or("-".to_string(), |b| format!("{:.4}", b)),
+                        cell.observations
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// reconcile [--pair A,B] [--from ..] [--to ..] [--price-tol 1e-6] [--volume-tol 1e-3] [--limit 50] [--out report.csv]
+// Сверяет свечи биржи со свечами из своих трейдов и старшие таймфреймы со сборкой из минутных.
+// По умолчанию - последние сутки; допуски относительные.
+pub async fn reconcile(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let defaults = Tolerance::default();
+    let tolerance = Tolerance {
+        price: parse_tolerance(args.get("price-tol"), defaults.price)?,
+        volume: parse_tolerance(args.get("volume-tol"), defaults.volume)?,
+    };
+    let end_ts = args.time("to")?.unwrap_or_else(|| Utc::now().timestamp_millis());
+    let start_ts = args.time("from")?.unwrap_or(end_ts - 24 * 60 * 60_000);
+    let limit: usize = args.get("limit").and_then(|l| l.parse().ok()).unwrap_or(50);
+    let mut rows = Vec::new();
+
+    for pair in args.pairs() {
+        let report = reconcile::reconcile_pair(store, &pair, start_ts, end_ts, tolerance).await?;
+        for s in &report.summaries {
+            println!(
+                "Сверка {} {} {} с {}: корзин {}, совпало {}, расходится {}, нет у нас {}, нет в эталоне {}, захват объёма {}",
+                s.pair,
+                s.check.as_str(),
+                s.time_frame,
+                s.expected_time_frame,
+                s.buckets,
+                s.matched,
+                s.mismatched,
+                s.missing_actual,
+                s.missing_expected,
+                s.capture_ratio().map_or("-".to_string(), |r| format!("{:.4}%", r * 100.0))
+            );
+        }
+        rows.extend(report.rows);
+    }
+
+    println!(
+        "{:<12} {:<10} {:<9} {:>14} {:<13} {:>20} {:>20} {:>10}",
+        "pair", "tf", "check", "utc_begin", "field", "expected", "actual", "rel_diff"
+    );
+    let cell = |v: Option<f64>| v.map_or("-".to_string(), |v| v.to_string());
+    for row in rows.iter().take(limit) {
+        println!(
+            "{:<12} {:<10} {:<9} {:>14} {:<13} {:>20} {:>20} {:>10}",
+            row.pair,
+            row.time_frame,
+            row.check.as_str(),
+            row.utc_begin,
+            row.field,
+            cell(row.expected),
+            cell(row.actual),
+            row.rel_diff().map_or("-".to_string(), |d| format!("{:.2e}", d))
+        );
+    }
+    if rows.len() > limit {
+        println!("... и ещё {}", rows.len() - limit);
+    }
+    if let Some(out) = args.get("out") {
+        reconcile::write_csv(&rows, Path::new(out))?;
+        println!("Отчёт записан в {}", out);
+    }
+    println!("Сверка завершена, всего расхождений: {}", rows.len());
+    Ok(())
+}
+
+fn parse_tolerance(value: Option<&str>, default: f64) -> Result<f64, String> {
+    match value {
+        Some(v) => v
+            .parse::<f64>()
+            .ok()
+            .filter(|t| t.is_finite() && *t >= 0.0)
+            .ok_or_else(|| format!("Неверный допуск: {}", v)),
+        None => Ok(default),
+    }
+}
+
+// rollup [--pair A,B] [--time-frame 5m,1h] [--from ..] [--to ..]
+// Пересобирает старшие таймфреймы из минутных свечей; по умолчанию все и с начала бэкфилла
+pub async fn rollup(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let time_frames: Vec<String> = match args.get("time-frame") {
+        Some(list) => list.split(',').map(|tf| tf.trim().to_string()).collect(),
+        None => ROLLUP_TIME_FRAMES.iter().map(|tf| tf.to_string()).collect(),
+    };
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    for pair in args.pairs() {
+        for time_frame in &time_frames {
+            let report = rollup::rollup_history(store, &pair, time_frame, start_ts, end_ts).await?;
+            println!(
+                "Сборка {} {}: минутных свечей {}, записано {}",
+                pair, time_frame, report.base_candles, report.written
+            );
+        }
+    }
+    Ok(())
+}
+
+// bars [--spec tick:1000,dollar:1000000] [--pair A,B] [--from ..] [--to ..] [--rebuild]
+// Строит бары по трейдам из БД, продолжая каждый ряд с последнего записанного бара.
+// Ряды по умолчанию - из BAR_SPECS; --rebuild удаляет бары ряда с --from и строит заново.
+pub async fn bars(store: &dyn Storage, args: &Args) -> Result<(), Box<dyn Error>> {
+    let list = match args.get("spec") {
+        Some(list) => list.to_string(),
+        None => std::env::var("BAR_SPECS").unwrap_or_default(),
+    };
+    let specs = bar_builder::parse_bar_specs(&list, &args.pairs())?;
+    if specs.is_empty() {
+        return Err("Не заданы бары: --spec или BAR_SPECS".into());
+    }
+    let (default_start, default_end) = api::get_time_range();
+    let start_ts = args.time("from")?.unwrap_or(default_start);
+    let end_ts = args.time("to")?.unwrap_or(default_end);
+
+    for (pair, spec) in specs {
+        if args.flag("rebuild") {
+            let deleted = store.delete_bars_from(&pair, &spec, start_ts).await?;
+            println!("Бары {} {}: удалено {} для пересборки", pair, spec.name(), deleted);
+        }
+        let (series, report) = bar_builder::resume_series(store, &pair, spec, start_ts, end_ts).await?;
+        println!(
+            "Бары {} {}: трейдов {}, записано {}, в незакрытом баре {} трейдов",
+            pair,
+            spec.name(),
+            report.trades,
+            report.written,
+            series.open_bar().map_or(0, |b| b.trade_count)
+        );
+    }
+    Ok(())
+}
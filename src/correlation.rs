@@ -0,0 +1,427 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::aggregate;
+use crate::data_structs::{CandleSource, CorrelationCell, CorrelationMatrix, Kline, PAIRS};
+use crate::scheduler::{self, run_live, LiveSeries, BATCH_BUCKETS};
+use crate::storage::Storage;
+
+// Пара, к которой считаются беты остальных
+pub const BENCHMARK: &str = "BTC_USDT";
+// Окно по умолчанию, в корзинах таймфрейма
+pub const DEFAULT_WINDOW: usize = 100;
+
+// Скользящие корреляции и беты лог-доходностей всех пар по общей сетке корзин.
+// Доходность пары в корзине есть, только если есть свечи в ней и в предыдущей корзине:
+// пропуск свечи не склеивает доходность через разрыв. Ячейка считается по корзинам окна,
+// где есть доходности обеих пар, и только если таких не меньше половины окна.
+#[derive(Debug, Clone)]
+pub struct RollingCorrelation {
+    time_frame: String,
+    bucket_ms: i64,
+    window: usize,
+    pairs: Vec<String>,                         // по алфавиту
+    last_close: Vec<Option<(i64, f64)>>,        // (utc_begin, close) последней свечи пары
+    returns: VecDeque<(i64, Vec<Option<f64>>)>, // доходности пар по корзинам окна
+    first_bucket: Option<i64>,
+}
+
+impl RollingCorrelation {
+    // None для неизвестного таймфрейма
+    pub fn new(pairs: &[&str], time_frame: &str, window: usize) -> Option<Self> {
+        let bucket_ms = aggregate::time_frame_ms(time_frame)?;
+        let mut pairs: Vec<String> = pairs.iter().map(|p| p.to_string()).collect();
+        pairs.sort();
+        pairs.dedup();
+        let window = window.max(3);
+        Some(RollingCorrelation {
+            time_frame: time_frame.to_string(),
+            bucket_ms,
+            window,
+            last_close: vec![None; pairs.len()],
+            pairs,
+            returns: VecDeque::with_capacity(window + 1),
+            first_bucket: None,
+        })
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    fn min_observations(&self) -> usize {
+        (self.window / 2).max(3)
+    }
+
+    // Следующая корзина сетки и её свечи (любых пар, в любом порядке); корзины без свечей тоже
+    // передаются. Матрица - когда сетка покрыла окно целиком.
+    pub fn push(&mut self, utc_begin: i64, candles: &[Kline]) -> Option<CorrelationMatrix> {
+        let first = *self.first_bucket.get_or_insert(utc_begin);
+        let mut row = vec![None; self.pairs.len()];
+        for candle in candles.iter().filter(|c| c.utc_begin == utc_begin && c.close > 0.0) {
+            let index = match self.pairs.iter().position(|p| *p == candle.pair) {
+                Some(index) => index,
+                None => continue,
+            };
+            if let Some((prev_begin, prev_close)) = self.last_close[index] {
+                if prev_begin == utc_begin - self.bucket_ms {
+                    row[index] = Some((candle.close / prev_close).ln());
+                }
+            }
+            self.last_close[index] = Some((utc_begin, candle.close));
+        }
+        self.returns.push_back((utc_begin, row));
+        let window_start = utc_begin - (self.window as i64 - 1) * self.bucket_ms;
+        while self.returns.front().is_some_and(|(begin, _)| *begin < window_start) {
+            self.returns.pop_front();
+        }
+        if first > window_start {
+            return None;
+        }
+
+        let mut cells = Vec::with_capacity(self.pairs.len() * self.pairs.len());
+        for (i, pair) in self.pairs.iter().enumerate() {
+            for (j, other_pair) in self.pairs.iter().enumerate() {
+                cells.push(self.cell(i, j, pair, other_pair));
+            }
+        }
+        Some(CorrelationMatrix {
+            time_frame: self.time_frame.clone(),
+            utc_begin,
+            window: self.window as i64,
+            pairs: self.pairs.clone(),
+            cells,
+        })
+    }
+
+    fn cell(&self, i: usize, j: usize, pair: &str, other_pair: &str) -> CorrelationCell {
+        let joint: Vec<(f64, f64)> = self
+            .returns
+            .iter()
+            .filter_map(|(_, row)| Some((row[i]?, row[j]?)))
+            .collect();
+        let mut cell = CorrelationCell {
+            pair: pair.to_string(),
+            other_pair: other_pair.to_string(),
+            observations: joint.len() as i64,
+            correlation: None,
+            beta: None,
+        };
+        if joint.len() < self.min_observations() {
+            return cell;
+        }
+        let n = joint.len() as f64;
+        let mean_x = joint.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = joint.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+        for (x, y) in &joint {
+            cov += (x - mean_x) * (y - mean_y);
+            var_x += (x - mean_x).powi(2);
+            var_y += (y - mean_y).powi(2);
+        }
+        if var_y > 0.0 {
+            cell.beta = Some(cov / var_y);
+            if var_x > 0.0 {
+                cell.correlation = Some((cov / (var_x * var_y).sqrt()).clamp(-1.0, 1.0));
+            }
+        }
+        cell
+    }
+}
+
+// Матрицы по свечам всех пар одного таймфрейма; сетка - от первой до последней корзины среди свечей
+pub fn correlation_matrices(
+    pairs: &[&str],
+    time_frame: &str,
+    candles: &[Kline],
+    window: usize,
+) -> Vec<CorrelationMatrix> {
+    let mut series = match RollingCorrelation::new(pairs, time_frame, window) {
+        Some(series) => series,
+        None => return Vec::new(),
+    };
+    let buckets = by_bucket(candles.to_vec());
+    let (first, last) = match (buckets.keys().next(), buckets.keys().next_back()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Vec::new(),
+    };
+    let mut matrices = Vec::new();
+    let mut utc_begin = first;
+    while utc_begin <= last {
+        let bucket = buckets.get(&utc_begin).map(Vec::as_slice).unwrap_or_default();
+        matrices.extend(series.push(utc_begin, bucket));
+        utc_begin += series.bucket_ms;
+    }
+    matrices
+}
+
+fn by_bucket(candles: Vec<Kline>) -> BTreeMap<i64, Vec<Kline>> {
+    let mut buckets: BTreeMap<i64, Vec<Kline>> = BTreeMap::new();
+    for candle in candles {
+        buckets.entry(candle.utc_begin).or_default().push(candle);
+    }
+    buckets
+}
+
+// Проводит ряд по корзинам [from, to] со свечами всех его пар из хранилища;
+// возвращает матрицы корзин не раньше emit_from
+async fn advance_grid(
+    store: &dyn Storage,
+    series: &mut RollingCorrelation,
+    from: i64,
+    to: i64,
+    emit_from: i64,
+) -> Result<Vec<CorrelationMatrix>, sqlx::Error> {
+    let mut candles = Vec::new();
+    for pair in &series.pairs {
+        candles.extend(
+            store
+                .candles_range(pair, &series.time_frame, from, to, &CandleSource::DEFAULT_PREFERENCE)
+                .await?,
+        );
+    }
+    let buckets = by_bucket(candles);
+    let mut matrices = Vec::new();
+    let mut utc_begin = aggregate::bucket_start(from, series.bucket_ms);
+    if utc_begin < from {
+        utc_begin += series.bucket_ms;
+    }
+    while utc_begin <= to {
+        let bucket = buckets.get(&utc_begin).map(Vec::as_slice).unwrap_or_default();
+        if let Some(matrix) = series.push(utc_begin, bucket) {
+            if utc_begin >= emit_from {
+                matrices.push(matrix);
+            }
+        }
+        utc_begin += series.bucket_ms;
+    }
+    Ok(matrices)
+}
+
+// Ряд всех отслеживаемых пар, прогретый на `window` корзинах до before_ts
+async fn warm_up(
+    store: &dyn Storage,
+    time_frame: &str,
+    window: usize,
+    before_ts: i64,
+) -> Result<Option<RollingCorrelation>, sqlx::Error> {
+    let mut series = match RollingCorrelation::new(&PAIRS, time_frame, window) {
+        Some(series) => series,
+        None => return Ok(None),
+    };
+    let from = aggregate::bucket_start(before_ts, series.bucket_ms)
+        .saturating_sub(series.bucket_ms.saturating_mul(series.window as i64));
+    advance_grid(store, &mut series, from, before_ts - 1, i64::MAX).await?;
+    Ok(Some(series))
+}
+
+// Считает матрицы таймфрейма по всем отслеживаемым парам за [start_ts, end_ts] с прогревом
+// до start_ts и записывает; возвращает число записанных матриц
+pub async fn compute_range(
+    store: &dyn Storage,
+    time_frame: &str,
+    window: usize,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<usize, sqlx::Error> {
+    let mut series = match warm_up(store, time_frame, window, start_ts).await? {
+        Some(series) => series,
+        None => return Ok(0),
+    };
+    let mut written = 0;
+    let mut from = start_ts;
+    while from <= end_ts {
+        let to = from.saturating_add(series.bucket_ms * BATCH_BUCKETS - 1).min(end_ts);
+        let matrices = advance_grid(store, &mut series, from, to, start_ts).await?;
+        store.upsert_correlations(&matrices).await?;
+        written += matrices.len();
+        if to == end_ts {
+            break;
+        }
+        from = to + 1;
+    }
+    Ok(written)
+}
+
+// CORRELATION_WINDOW - окно живого расчёта в корзинах; не задано - корреляции не считаются
+pub fn window_from_env() -> Result<Option<usize>, String> {
+    match env::var("CORRELATION_WINDOW") {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|w| *w >= 3)
+            .map(Some)
+            .ok_or_else(|| format!("Неверное окно CORRELATION_WINDOW={}", value)),
+        _ => Ok(None),
+    }
+}
+
+pub fn watermark_name(time_frame: &str, window: usize) -> String {
+    format!("correlation:{}:{}", window, time_frame)
+}
+
+// Граница закрытых корзин для матрицы - самая ранняя среди пар: матрица ждёт все пары
+async fn closed_until(store: &dyn Storage, time_frame: &str) -> Result<Option<i64>, sqlx::Error> {
+    let mut closed: Option<i64> = None;
+    for pair in PAIRS.iter() {
+        match scheduler::closed_until(store, pair, time_frame).await? {
+            Some(mark) => closed = Some(closed.map_or(mark, |c| c.min(mark))),
+            None => return Ok(None),
+        }
+    }
+    Ok(closed)
+}
+
+// Матрица таймфрейма в живом расчёте
+struct LiveCorrelation {
+    time_frame: &'static str,
+    window: usize,
+}
+
+#[async_trait]
+impl LiveSeries for LiveCorrelation {
+    type State = RollingCorrelation;
+    type Row = CorrelationMatrix;
+
+    fn label(&self) -> String {
+        format!("корреляций {}", self.time_frame)
+    }
+
+    fn watermark_name(&self) -> String {
+        watermark_name(self.time_frame, self.window)
+    }
+
+    async fn closed_until(&self, store: &dyn Storage) -> Result<Option<i64>, sqlx::Error> {
+        closed_until(store, self.time_frame).await
+    }
+
+    async fn warm_up(&self, store: &dyn Storage, before_ts: i64) -> Result<Option<RollingCorrelation>, sqlx::Error> {
+        warm_up(store, self.time_frame, self.window, before_ts).await
+    }
+
+    async fn push(
+        &self,
+        store: &dyn Storage,
+        series: &mut RollingCorrelation,
+        from: i64,
+        until: i64,
+    ) -> Result<Vec<CorrelationMatrix>, sqlx::Error> {
+        advance_grid(store, series, from, until - 1, from).await
+    }
+
+    async fn write(&self, store: &dyn Storage, matrices: &[CorrelationMatrix]) -> Result<(), sqlx::Error> {
+        store.upsert_correlations(matrices).await
+    }
+}
+
+// Живой расчёт по свечам, закрытым агрегацией у всех пар; история досчитывается командой correlation
+pub async fn run_correlations(store: Arc<dyn Storage>, time_frames: Vec<&'static str>, window: usize) {
+    let series = time_frames
+        .into_iter()
+        .map(|time_frame| LiveCorrelation { time_frame, window })
+        .collect();
+    run_live(store, &format!("корреляций с {}, окно {}", BENCHMARK, window), series).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN, T0};
+
+    fn candle(pair: &str, i: i64, log_close: f64) -> Kline {
+        testing::candle("1m", T0 + i * MIN, 100.0 * log_close.exp()).with_pair(pair)
+    }
+
+    // Логарифм цены BTC по корзинам: доходности без закономерности
+    fn btc_log(i: i64) -> f64 {
+        (0..=i).map(|k| 0.01 * (k as f64 * 1.3).sin()).sum()
+    }
+
+    // Свечи пары с логарифмом цены k * btc_log в корзинах `present`
+    fn scaled(pair: &str, k: f64, present: impl Iterator<Item = i64>) -> Vec<Kline> {
+        present.map(|i| candle(pair, i, k * btc_log(i))).collect()
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn perfectly_correlated_pairs() {
+        let mut candles = scaled("BTC_USDT", 1.0, 0..20);
+        candles.extend(scaled("ETH_USDT", 2.0, 0..20));
+        candles.extend(scaled("TRX_USDT", -0.5, 0..20));
+        let matrices = correlation_matrices(&["ETH_USDT", "BTC_USDT", "TRX_USDT"], "1m", &candles, 10);
+
+        // Первая матрица - когда окно из 10 корзин покрыто целиком
+        assert_eq!(matrices.len(), 11);
+        let m = &matrices[0];
+        assert_eq!((m.utc_begin, m.window), (T0 + 9 * MIN, 10));
+        assert_eq!(m.pairs, vec!["BTC_USDT", "ETH_USDT", "TRX_USDT"]);
+        assert_eq!(m.cells.len(), 9);
+
+        let eth = m.cell("ETH_USDT", "BTC_USDT").unwrap();
+        // У первой корзины окна нет предыдущей свечи
+        assert_eq!(eth.observations, 9);
+        assert_close(eth.correlation, 1.0);
+        assert_close(eth.beta, 2.0);
+        assert_close(m.cell("BTC_USDT", "ETH_USDT").unwrap().beta, 0.5);
+        assert_close(m.cell("TRX_USDT", "BTC_USDT").unwrap().correlation, -1.0);
+        assert_close(m.cell("TRX_USDT", "BTC_USDT").unwrap().beta, -0.5);
+        assert_close(m.cell("BTC_USDT", "BTC_USDT").unwrap().correlation, 1.0);
+
+        let last = matrices.last().unwrap();
+        assert_eq!(last.utc_begin, T0 + 19 * MIN);
+        assert_eq!(last.cell("ETH_USDT", "BTC_USDT").unwrap().observations, 10);
+    }
+
+    #[test]
+    fn missing_bucket_breaks_returns() {
+        let mut candles = scaled("BTC_USDT", 1.0, 0..10);
+        candles.extend(scaled("ETH_USDT", 2.0, (0..10).filter(|i| *i != 5)));
+        let pairs = ["BTC_USDT", "ETH_USDT"];
+        let matrices = correlation_matrices(&pairs, "1m", &candles, 10);
+        assert_eq!(matrices.len(), 1);
+
+        // Доходностей ETH нет в корзине 0, в пропущенной 5 и в 6 после неё. Доходность 6 от закрытия 4
+        // вобрала бы две доходности BTC и сбила бы корреляцию с единицы.
+        let eth = matrices[0].cell("ETH_USDT", "BTC_USDT").unwrap();
+        assert_eq!(eth.observations, 7);
+        assert_close(eth.correlation, 1.0);
+        assert_close(eth.beta, 2.0);
+        assert_eq!(matrices[0].cell("BTC_USDT", "BTC_USDT").unwrap().observations, 9);
+
+        // Пустая корзина передаётся в поток как есть, матрица - на последней корзине окна
+        let mut series = RollingCorrelation::new(&pairs, "1m", 10).unwrap();
+        for i in 0..10 {
+            let bucket: Vec<Kline> = candles.iter().filter(|c| c.utc_begin == T0 + i * MIN).cloned().collect();
+            let matrix = series.push(T0 + i * MIN, &bucket);
+            assert_eq!(matrix.is_some(), i == 9);
+            if let Some(matrix) = matrix {
+                assert_eq!(Some(&matrix), matrices.first());
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_window_has_no_correlation() {
+        let mut candles = scaled("BTC_USDT", 1.0, 0..10);
+        // Доходности ETH только в корзинах 1, 2, 3 и 9: меньше половины окна
+        candles.extend(scaled("ETH_USDT", 2.0, [0, 1, 2, 3, 8, 9].into_iter()));
+        let matrices = correlation_matrices(&["BTC_USDT", "ETH_USDT"], "1m", &candles, 10);
+        let eth = matrices[0].cell("ETH_USDT", "BTC_USDT").unwrap();
+        assert_eq!(eth.observations, 4);
+        assert_eq!((eth.correlation, eth.beta), (None, None));
+
+        // Одна корзина больше - пятое наблюдение, половина окна набрана
+        candles.extend(scaled("ETH_USDT", 2.0, [7].into_iter()));
+        let matrices = correlation_matrices(&["BTC_USDT", "ETH_USDT"], "1m", &candles, 10);
+        let eth = matrices[0].cell("ETH_USDT", "BTC_USDT").unwrap();
+        assert_eq!(eth.observations, 5);
+        assert_close(eth.correlation, 1.0);
+        assert_close(eth.beta, 2.0);
+    }
+}
@@ -1,7 +1,10 @@
 pub const PAIRS: [&str; 5] = ["BTC_USDT", "TRX_USDT", "ETH_USDT", "DOGE_USDT", "BCH_USDT"];
 pub const INTERVALS: [&str; 4] = ["MINUTE_1", "MINUTE_15", "HOUR_1", "DAY_1"];
+// Таймфреймы, которые строятся из собственных трейдов
+pub const AGG_TIME_FRAMES: [&str; 4] = ["1m", "15m", "1h", "1d"];
 
-#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone)]
 pub struct VBS {
     pub buy_base: f64,   // объём покупок в базовой валюте
     pub sell_base: f64,  // объём продаж в базовой валюте
@@ -9,7 +12,59 @@ pub struct VBS {
     pub sell_quote: f64, // объём продаж в котируемой валюте
 }
 
-#[derive(Debug)]
+impl VBS {
+    // Средневзвешенная по объёму цена: оборот в котируемой валюте на объём в базовой, 0 без объёма
+    pub fn vwap(&self) -> f64 {
+        let base = self.buy_base + self.sell_base;
+        if base > 0.0 {
+            (self.buy_quote + self.sell_quote) / base
+        } else {
+            0.0
+        }
+    }
+}
+
+// Откуда взялась свеча; в одной корзине (pair, time_frame, utc_begin) может быть по свече от каждого источника
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandleSource {
+    Rest,       // REST API биржи
+    Ws,         // канал свечей WebSocket
+    Aggregated, // собрана из собственных трейдов: сборщиком в реальном времени или планировщиком
+    Imported,   // загружена из файла
+}
+
+impl CandleSource {
+    // Порядок по умолчанию при чтении: свеча биржи, затем своя агрегация, потом остальные
+    pub const DEFAULT_PREFERENCE: [CandleSource; 4] =
+        [CandleSource::Rest, CandleSource::Aggregated, CandleSource::Ws, CandleSource::Imported];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleSource::Rest => "rest",
+            CandleSource::Ws => "ws",
+            CandleSource::Aggregated => "aggregated",
+            CandleSource::Imported => "imported",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "rest" => Some(CandleSource::Rest),
+            "ws" => Some(CandleSource::Ws),
+            "aggregated" => Some(CandleSource::Aggregated),
+            "imported" => Some(CandleSource::Imported),
+            _ => None,
+        }
+    }
+
+    // Место источника в списке предпочтения; источники вне списка идут после, по имени, как в SQL
+    pub fn preference_key(&self, prefer: &[CandleSource]) -> (usize, &'static str) {
+        let rank = prefer.iter().position(|s| s == self).unwrap_or(prefer.len());
+        (rank, self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Kline {
     pub pair: String,
     pub time_frame: String,
@@ -19,9 +74,15 @@ pub struct Kline {
     pub close: f64,      // индекс 3
     pub volume_bs: VBS,  // вычисляемая структура
     pub utc_begin: i64,  // индекс 9
+    pub close_time: i64, // последняя миллисекунда интервала
+    pub trade_count: i64,
+    pub vwap: f64,       // средневзвешенная по объёму цена
+    pub source: CandleSource,
+    pub is_final: bool,  // интервал закрыт, свеча больше не изменится
+    pub revision: i64,   // сколько раз закрытую свечу пересчитали после публикации (опоздавшие трейды)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RecentTrade {
     pub tid: String,
     pub pair: String,
@@ -32,3 +93,250 @@ pub struct RecentTrade {
     pub create_time: i64,
     pub timestamp: i64,
 }
+
+// Бары, которые закрываются по потоку трейдов, а не по времени
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarKind {
+    Tick,            // каждые N трейдов
+    Volume,          // каждые N единиц базовой валюты
+    Dollar,          // каждые N единиц котируемой валюты
+    TickImbalance,   // |покупки - продажи| в трейдах достиг N
+    VolumeImbalance, // |объём покупок - объём продаж| в базовой валюте достиг N
+}
+
+impl BarKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BarKind::Tick => "tick",
+            BarKind::Volume => "volume",
+            BarKind::Dollar => "dollar",
+            BarKind::TickImbalance => "tick_imbalance",
+            BarKind::VolumeImbalance => "volume_imbalance",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "tick" => Some(BarKind::Tick),
+            "volume" => Some(BarKind::Volume),
+            "dollar" => Some(BarKind::Dollar),
+            "tick_imbalance" => Some(BarKind::TickImbalance),
+            "volume_imbalance" => Some(BarKind::VolumeImbalance),
+            _ => None,
+        }
+    }
+}
+
+// Тип бара и порог закрытия; ряд баров пары определяется этой парой значений
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarSpec {
+    pub kind: BarKind,
+    pub threshold: f64,
+}
+
+impl BarSpec {
+    // "tick:1000", "dollar:1000000", "volume_imbalance:25"
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (kind, threshold) = value
+            .split_once(':')
+            .ok_or_else(|| format!("Ожидался бар вида тип:порог, получено: {}", value))?;
+        let kind = BarKind::parse(kind.trim()).ok_or_else(|| format!("Неизвестный тип бара: {}", kind))?;
+        let threshold = threshold
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|t| t.is_finite() && *t > 0.0)
+            .ok_or_else(|| format!("Неверный порог бара: {}", threshold))?;
+        Ok(BarSpec { kind, threshold })
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}:{}", self.kind.as_str(), self.threshold)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Bar {
+    pub pair: String,
+    pub spec: BarSpec,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume_bs: VBS,
+    pub utc_begin: i64,    // время первого трейда бара
+    pub utc_end: i64,      // время последнего трейда бара
+    pub first_tid: String,
+    pub last_tid: String,
+    pub trade_count: i64,
+    pub vwap: f64,
+    pub imbalance: f64,    // покупки минус продажи: в базовой валюте для volume_imbalance, иначе в трейдах
+}
+
+// Значение индикатора на закрытой свече: indicator - имя ("macd"), params - параметры ("12,26,9").
+// values - выходы индикатора по порядку (у MACD - macd, signal, histogram), не больше трёх.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndicatorValue {
+    pub pair: String,
+    pub time_frame: String,
+    pub utc_begin: i64,
+    pub indicator: String,
+    pub params: String,
+    pub values: Vec<f64>,
+}
+
+// Метрики потока ордеров на свече по разделению объёма на агрессивные покупки и продажи (тейкер)
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderFlow {
+    pub pair: String,
+    pub time_frame: String,
+    pub utc_begin: i64,
+    pub buy_volume: f64,     // покупки в базовой валюте
+    pub sell_volume: f64,    // продажи в базовой валюте
+    pub delta: f64,          // покупки минус продажи
+    pub cvd: f64,            // накопленная дельта с начала ряда
+    pub imbalance: f64,      // (покупки - продажи) / (покупки + продажи), 0 без объёма
+    pub divergence: i8,      // за окно цена упала, а дельта выросла - 1, наоборот - -1, иначе 0
+    pub buy_z: Option<f64>,  // z-оценка покупок относительно предыдущих `window` свечей
+    pub sell_z: Option<f64>, // то же для продаж
+    pub window: i64,
+}
+
+// Объём на ценовом уровне профиля: цены в [price_low, price_low + bin_width)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceLevel {
+    pub price_low: f64,
+    pub buy_volume: f64,  // покупки в базовой валюте
+    pub sell_volume: f64, // продажи в базовой валюте
+    pub trade_count: i64,
+}
+
+// Профиль объёма за период: объёмы по ценовым уровням, точка контроля и зона стоимости
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeProfile {
+    pub pair: String,
+    pub period: String,          // таймфрейм периода ("1d", "1w") или "custom" для произвольного диапазона
+    pub utc_begin: i64,
+    pub utc_end: i64,            // последняя миллисекунда периода
+    pub bins: String,            // разбиение цены, как задано: "10" или "count:100"
+    pub bin_width: f64,
+    pub levels: Vec<PriceLevel>, // по возрастанию цены, только уровни с трейдами
+    pub poc: f64,                // середина уровня с наибольшим объёмом
+    pub value_area_high: f64,
+    pub value_area_low: f64,
+}
+
+// Оценка волатильности ряда на свече: дисперсия лог-доходности за один интервал и годовая волатильность
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volatility {
+    pub pair: String,
+    pub time_frame: String,
+    pub utc_begin: i64,    // последняя свеча окна
+    pub estimator: String, // "close_to_close", "parkinson", ..., "realized" - по минутным доходностям
+    pub window: i64,       // свечей в окне; у реализованной - 1 (одна дневная корзина)
+    pub variance: f64,     // за один интервал таймфрейма
+    pub volatility: f64,   // годовая: sqrt(variance * интервалов в году)
+}
+
+// Связь доходностей двух пар за окно: корреляция и бета pair к other_pair (cov / var(other_pair))
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorrelationCell {
+    pub pair: String,
+    pub other_pair: String,
+    pub observations: i64,        // корзин, где есть доходности обеих пар
+    pub correlation: Option<f64>, // None - мало наблюдений или нет разброса
+    pub beta: Option<f64>,
+}
+
+// Матрица корреляций и бет всех пар на свече таймфрейма: окно - `window` корзин, заканчивающихся utc_begin
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorrelationMatrix {
+    pub time_frame: String,
+    pub utc_begin: i64,
+    pub window: i64,
+    pub pairs: Vec<String>,          // по алфавиту
+    pub cells: Vec<CorrelationCell>, // построчно: pairs[i] против pairs[j]
+}
+
+impl CorrelationMatrix {
+    pub fn cell(&self, pair: &str, other_pair: &str) -> Option<&CorrelationCell> {
+        self.cells.iter().find(|c| c.pair == pair && c.other_pair == other_pair)
+    }
+}
+
+// Общие свечи модульных тестов
+#[cfg(test)]
+pub mod testing {
+    use super::{CandleSource, Kline, VBS};
+    use crate::aggregate;
+
+    // 2024-01-01 00:00 UTC, понедельник
+    pub const T0: i64 = 1_704_067_200_000;
+    pub const MIN: i64 = 60_000;
+
+    // Закрытая свеча BTC_USDT из трейдов: все цены равны close, одна сделка на покупку единицы по close.
+    // Остальное меняется методами with_*; объём в котируемой валюте всегда пересчитывается по vwap.
+    pub fn candle(time_frame: &str, utc_begin: i64, close: f64) -> Kline {
+        let len = aggregate::time_frame_ms(time_frame).expect("неизвестный таймфрейм тестовой свечи");
+        Kline {
+            pair: "BTC_USDT".to_string(),
+            time_frame: time_frame.to_string(),
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume_bs: VBS {
+                buy_base: 1.0,
+                sell_base: 0.0,
+                buy_quote: close,
+                sell_quote: 0.0,
+            },
+            utc_begin,
+            close_time: utc_begin + len - 1,
+            trade_count: 1,
+            vwap: close,
+            source: CandleSource::Aggregated,
+            is_final: true,
+            revision: 0,
+        }
+    }
+
+    impl Kline {
+        pub fn with_pair(mut self, pair: &str) -> Kline {
+            self.pair = pair.to_string();
+            self
+        }
+
+        // vwap становится равным close
+        pub fn with_ohlc(mut self, open: f64, high: f64, low: f64, close: f64) -> Kline {
+            (self.open, self.high, self.low, self.close) = (open, high, low, close);
+            self.with_vwap(close)
+        }
+
+        pub fn with_vwap(mut self, vwap: f64) -> Kline {
+            self.vwap = vwap;
+            self.requote()
+        }
+
+        pub fn with_volume(mut self, buy_base: f64, sell_base: f64) -> Kline {
+            (self.volume_bs.buy_base, self.volume_bs.sell_base) = (buy_base, sell_base);
+            self.requote()
+        }
+
+        pub fn with_trade_count(mut self, trade_count: i64) -> Kline {
+            self.trade_count = trade_count;
+            self
+        }
+
+        pub fn with_source(mut self, source: CandleSource) -> Kline {
+            self.source = source;
+            self
+        }
+
+        fn requote(mut self) -> Kline {
+            self.volume_bs.buy_quote = self.volume_bs.buy_base * self.vwap;
+            self.volume_bs.sell_quote = self.volume_bs.sell_base * self.vwap;
+            self
+        }
+    }
+}
@@ -1,78 +1,1445 @@
-use sqlx::postgres::PgPool;
-use sqlx::Error;
-use crate::data_structs::Kline;
-use crate::data_structs::RecentTrade;
+use futures_util::stream::{BoxStream, StreamExt};
+use sqlx::postgres::{PgPool, PgRow};
+use sqlx::query_builder::Separated;
+use sqlx::{Error, Postgres, QueryBuilder, Row, Transaction};
+use crate::aggregate;
+use crate::data_structs::{
+    Bar, BarKind, BarSpec, CandleSource, CorrelationCell, CorrelationMatrix, IndicatorValue, Kline, OrderFlow,
+    PriceLevel, RecentTrade, Volatility, VolumeProfile, VBS,
+};
 
 use sqlx::postgres::PgArguments;
 use sqlx::Arguments;
 
-pub async fn insert_candles(pool: &PgPool, candles: Vec<Kline>) -> Result<(), sqlx::Error> {
+const CANDLE_COLUMNS: &str =
+    "pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, utc_begin, \
+     close_time, trade_count, vwap, source, is_final, revision";
+
+const TRADE_COLUMNS: &str = "tid, pair, amount, side, quantity, create_time, price, time_stamp";
+
+// Порядок трейдов как aggregate::trade_key_order: по времени, затем числовые id по значению
+// (длина без ведущих нулей, затем сами цифры), затем остальные id побайтно
+const TRADE_ORDER_ASC: &str = "time_stamp, tid !~ '^[0-9]+$', \
+    CASE WHEN tid ~ '^[0-9]+$' THEN length(ltrim(tid, '0')) ELSE 0 END, \
+    CASE WHEN tid ~ '^[0-9]+$' THEN ltrim(tid, '0') ELSE '' END COLLATE \"C\", tid COLLATE \"C\"";
+const TRADE_ORDER_DESC: &str = "time_stamp DESC, tid !~ '^[0-9]+$' DESC, \
+    CASE WHEN tid ~ '^[0-9]+$' THEN length(ltrim(tid, '0')) ELSE 0 END DESC, \
+    CASE WHEN tid ~ '^[0-9]+$' THEN ltrim(tid, '0') ELSE '' END COLLATE \"C\" DESC, tid COLLATE \"C\" DESC";
+
+pub const BAR_COLUMNS: &str =
+    "pair, bar_type, threshold, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, \
+     utc_begin, utc_end, first_tid, last_tid, trade_count, vwap, imbalance";
+pub const BAR_KEY: &str = "pair, bar_type, threshold, utc_begin, first_tid";
+
+pub const INDICATOR_COLUMNS: &str = "pair, time_frame, utc_begin, indicator, params, value_1, value_2, value_3";
+pub const INDICATOR_KEY: &str = "pair, time_frame, utc_begin, indicator, params";
+
+pub const CORRELATION_COLUMNS: &str =
+    "time_frame, utc_begin, window_size, pair, other_pair, observations, correlation, beta";
+pub const CORRELATION_KEY: &str = "time_frame, window_size, utc_begin, pair, other_pair";
+
+pub const VOLATILITY_COLUMNS: &str = "pair, time_frame, utc_begin, estimator, window_size, variance, volatility";
+pub const VOLATILITY_KEY: &str = "pair, time_frame, estimator, window_size, utc_begin";
+
+pub const VOLUME_PROFILE_COLUMNS: &str =
+    "pair, period, utc_begin, utc_end, bins, bin_width, price_low, buy_volume, sell_volume, trade_count, \
+     poc, value_area_high, value_area_low";
+
+pub const ORDER_FLOW_COLUMNS: &str =
+    "pair, time_frame, utc_begin, buy_volume, sell_volume, delta, cvd, imbalance, divergence, buy_z, sell_z, window_size";
+pub const ORDER_FLOW_KEY: &str = "pair, time_frame, utc_begin";
+
+// Вставляет только новые свечи: уже записанные свечи корзины от того же источника не трогаются.
+// Возвращает число добавленных.
+pub async fn insert_candles(pool: &PgPool, candles: Vec<Kline>) -> Result<u64, sqlx::Error> {
+    write_candles(pool, candles, "ON CONFLICT (pair, time_frame, utc_begin, source) DO NOTHING").await
+}
+
+// Вставляет свечи, перезаписывая свечи того же источника в уже существующих корзинах
+pub async fn upsert_candles(pool: &PgPool, candles: Vec<Kline>) -> Result<(), sqlx::Error> {
+    let candles = dedup_candles(candles);
+    write_candles(
+        pool,
+        candles,
+        "ON CONFLICT (pair, time_frame, utc_begin, source) DO UPDATE SET
+            open = EXCLUDED.open,
+            high = EXCLUDED.high,
+            low = EXCLUDED.low,
+            close = EXCLUDED.close,
+            buy_base = EXCLUDED.buy_base,
+            sell_base = EXCLUDED.sell_base,
+            buy_quote = EXCLUDED.buy_quote,
+            sell_quote = EXCLUDED.sell_quote,
+            close_time = EXCLUDED.close_time,
+            trade_count = EXCLUDED.trade_count,
+            vwap = EXCLUDED.vwap,
+            is_final = EXCLUDED.is_final,
+            revision = GREATEST(candles.revision, EXCLUDED.revision)",
+    )
+    .await?;
+    Ok(())
+}
+
+// DO UPDATE не может дважды обновить одну строку за запрос, поэтому оставляем последнюю свечу корзины
+pub fn dedup_candles(candles: Vec<Kline>) -> Vec<Kline> {
+    let mut seen = std::collections::HashSet::new();
+    let mut result: Vec<Kline> = candles
+        .into_iter()
+        .rev()
+        .filter(|c| seen.insert((c.pair.clone(), c.time_frame.clone(), c.utc_begin, c.source)))
+        .collect();
+    result.reverse();
+    result
+}
+
+// Postgres принимает не больше 65535 параметров на запрос, у свечи их 17
+const PG_MAX_BIND_PARAMS: usize = 65535;
+const CANDLE_BIND_PARAMS: usize = 17;
+const CANDLES_PER_STATEMENT: usize = PG_MAX_BIND_PARAMS / CANDLE_BIND_PARAMS;
+
+// Большие пачки режем на несколько INSERT в одной транзакции: либо записываются все свечи, либо ни одной
+async fn write_candles(pool: &PgPool, candles: Vec<Kline>, on_conflict: &str) -> Result<u64, sqlx::Error> {
     if candles.is_empty() {
-        return Ok(());
+        return Ok(0);
     }
 
-    let mut query = String::from("INSERT INTO candles 
-        (pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, utc_begin)
-        VALUES ");
-    
-    let mut args = PgArguments::default();
-    let mut placeholders = vec![];
-
-    for (i, candle) in candles.iter().enumerate() {
-        let offset = i * 11;
-        placeholders.push(format!(
-            "(${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${})",
-            offset + 1,  // pair
-            offset + 2,  // time_frame
-            offset + 3,  // open
-            offset + 4,  // high
-            offset + 5,  // low
-            offset + 6,  // close
-            offset + 7,  // buy_base
-            offset + 8,  // sell_base
-            offset + 9,  // buy_quote
-            offset + 10, // sell_quote
-            offset + 11  // utc_begin
-        ));
+    let mut affected = 0;
+    let mut tx = pool.begin().await?;
+    for chunk in candles.chunks(CANDLES_PER_STATEMENT) {
+        let mut query = format!("INSERT INTO candles ({}) VALUES ", CANDLE_COLUMNS);
+
+        let mut args = PgArguments::default();
+        let mut placeholders = Vec::with_capacity(chunk.len());
+
+        for (i, candle) in chunk.iter().enumerate() {
+            let offset = i * CANDLE_BIND_PARAMS;
+            placeholders.push(format!(
+                "(${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${})",
+                offset + 1,  // pair
+                offset + 2,  // time_frame
+                offset + 3,  // open
+                offset + 4,  // high
+                offset + 5,  // low
+                offset + 6,  // close
+                offset + 7,  // buy_base
+                offset + 8,  // sell_base
+                offset + 9,  // buy_quote
+                offset + 10, // sell_quote
+                offset + 11, // utc_begin
+                offset + 12, // close_time
+                offset + 13, // trade_count
+                offset + 14, // vwap
+                offset + 15, // source
+                offset + 16, // is_final
+                offset + 17  // revision
+            ));
+
+            args.add(&candle.pair);
+            args.add(&candle.time_frame);
+            args.add(candle.open);
+            args.add(candle.high);
+            args.add(candle.low);
+            args.add(candle.close);
+            args.add(candle.volume_bs.buy_base);
+            args.add(candle.volume_bs.sell_base);
+            args.add(candle.volume_bs.buy_quote);
+            args.add(candle.volume_bs.sell_quote);
+            args.add(candle.utc_begin);
+            args.add(candle.close_time);
+            args.add(candle.trade_count);
+            args.add(candle.vwap);
+            args.add(candle.source.as_str());
+            args.add(candle.is_final);
+            args.add(candle.revision);
+        }
+
+        query.push_str(&placeholders.join(", "));
+        query.push(' ');
+        query.push_str(on_conflict);
+        affected += sqlx::query_with(&query, args).execute(&mut tx).await?.rows_affected();
+    }
+    tx.commit().await?;
+
+    Ok(affected)
+}
+
+// Пакетная запись трейдов: COPY во временную таблицу, затем перенос в `trades`
+// с дедупликацией по `tid` (и внутри пачки, и относительно уже записанных).
+// Без явной цели конфликта: в гипертаблице уникален (tid, time_stamp), а не tid
+pub async fn insert_trades(pool: &PgPool, trades: &[RecentTrade]) -> Result<u64, Error> {
+    copy_trades(pool, trades, "ON CONFLICT DO NOTHING").await
+}
+
+// Цель конфликта для перезаписи трейдов: обычная таблица и гипертаблица TimescaleDB
+pub const TRADES_CONFLICT: &str = "(tid)";
+pub const TRADES_CONFLICT_HYPERTABLE: &str = "(tid, time_stamp)";
+
+// То же, но уже записанные трейды перезаписываются
+pub async fn upsert_trades(pool: &PgPool, trades: &[RecentTrade], conflict: &str) -> Result<u64, Error> {
+    let on_conflict = format!(
+        "ON CONFLICT {} DO UPDATE SET
+            pair = EXCLUDED.pair,
+            amount = EXCLUDED.amount,
+            side = EXCLUDED.side,
+            quantity = EXCLUDED.quantity,
+            create_time = EXCLUDED.create_time,
+            price = EXCLUDED.price,
+            time_stamp = EXCLUDED.time_stamp",
+        conflict
+    );
+    copy_trades(pool, trades, &on_conflict).await
+}
+
+async fn copy_trades(pool: &PgPool, trades: &[RecentTrade], on_conflict: &str) -> Result<u64, Error> {
+    if trades.is_empty() {
+        return Ok(0);
+    }
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "CREATE TEMP TABLE IF NOT EXISTS trades_stage (LIKE trades INCLUDING DEFAULTS) ON COMMIT DELETE ROWS",
+    )
+    .execute(&mut tx)
+    .await?;
+
+    let mut data = String::with_capacity(trades.len() * 96);
+    for trade in trades {
+        let fields = [
+            copy_escape(&trade.tid),
+            copy_escape(&trade.pair),
+            copy_escape(&trade.amount),
+            copy_escape(&trade.side),
+            copy_escape(&trade.quantity),
+            trade.create_time.to_string(),
+            copy_escape(&trade.price),
+            trade.timestamp.to_string(),
+        ];
+        data.push_str(&fields.join("\t"));
+        data.push('\n');
+    }
+
+    let mut copy = tx
+        .copy_in_raw(&format!("COPY trades_stage ({}) FROM STDIN", TRADE_COLUMNS))
+        .await?;
+    copy.send(data.as_bytes()).await?;
+    copy.finish().await?;
+
+    let query = format!(
+        "INSERT INTO trades ({cols})
+        SELECT DISTINCT ON (tid) {cols}
+        FROM trades_stage
+        {on_conflict}",
+        cols = TRADE_COLUMNS,
+        on_conflict = on_conflict,
+    );
+    let inserted = sqlx::query(&query)
+        .execute(&mut tx)
+        .await?
+        .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(inserted)
+}
+
+// Экранирование значения для текстового формата COPY
+fn copy_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+// Непрерывные агрегаты TimescaleDB, заменяющие агрегацию старших таймфреймов
+pub const CONTINUOUS_AGGREGATES: [(&str, &str); 3] = [
+    ("15m", "candles_15m"),
+    ("1h", "candles_1h"),
+    ("1d", "candles_1d"),
+];
 
-        args.add(&candle.pair);
-        args.add(&candle.time_frame);
-        args.add(candle.open);
-        args.add(candle.high);
-        args.add(candle.low);
-        args.add(candle.close);
-        args.add(candle.volume_bs.buy_base);
-        args.add(candle.volume_bs.sell_base);
-        args.add(candle.volume_bs.buy_quote);
-        args.add(candle.volume_bs.sell_quote);
-        args.add(candle.utc_begin);
+pub fn continuous_aggregate(time_frame: &str) -> Option<&'static str> {
+    CONTINUOUS_AGGREGATES
+        .iter()
+        .find(|(tf, _)| *tf == time_frame)
+        .map(|(_, view)| *view)
+}
+
+// Проверка при старте: доступно ли расширение TimescaleDB в этой БД
+pub async fn timescale_available(pool: &PgPool) -> Result<bool, Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb')")
+        .fetch_one(pool)
+        .await
+}
+
+// Версии применённых миграций Postgres (и основных, и TimescaleDB)
+pub async fn applied_migrations(pool: &PgPool) -> Result<Vec<i64>, Error> {
+    sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success ORDER BY version")
+        .fetch_all(pool)
+        .await
+}
+
+// None - candles не гипертаблица (или TimescaleDB не подключён), иначе - включено ли сжатие
+pub async fn candles_hypertable(pool: &PgPool) -> Result<Option<bool>, Error> {
+    let installed: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb')")
+        .fetch_one(pool)
+        .await?;
+    if !installed {
+        return Ok(None);
     }
+    sqlx::query_scalar(
+        "SELECT compression_enabled FROM timescaledb_information.hypertables
+        WHERE hypertable_schema = current_schema() AND hypertable_name = 'candles'",
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+// Распаковывает все чанки candles и выключает сжатие: миграции основного каталога меняют
+// и обновляют candles, а на сжатых чанках TimescaleDB такие ALTER/UPDATE/DELETE не выполняет
+pub async fn decompress_candles(pool: &PgPool) -> Result<(), Error> {
+    sqlx::query("SELECT remove_compression_policy('candles', if_exists => true)")
+        .execute(pool)
+        .await?;
+    sqlx::query("SELECT count(decompress_chunk(c, if_compressed => true)) FROM show_chunks('candles') c")
+        .execute(pool)
+        .await?;
+    sqlx::query("ALTER TABLE candles SET (timescaledb.compress = false)")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
 
-    query.push_str(&placeholders.join(", "));
-    sqlx::query_with(&query, args).execute(pool).await?;
+// Включает сжатие candles с настройками миграции 20250310120000_hypertables.sql
+pub async fn compress_candles(pool: &PgPool) -> Result<(), Error> {
+    sqlx::query(
+        "ALTER TABLE candles SET (
+            timescaledb.compress,
+            timescaledb.compress_segmentby = 'pair, time_frame',
+            timescaledb.compress_orderby = 'utc_begin'
+        )",
+    )
+    .execute(pool)
+    .await?;
+    sqlx::query("SELECT add_compression_policy('candles', BIGINT '2592000000', if_not_exists => true)")
+        .execute(pool)
+        .await?;
+    Ok(())
+}
 
+// Полный пересчёт непрерывных агрегатов: политики обновляют только последние корзины
+pub async fn refresh_continuous_aggregates(pool: &PgPool) -> Result<(), Error> {
+    for (_, view) in CONTINUOUS_AGGREGATES {
+        sqlx::query(&format!("CALL refresh_continuous_aggregate('{view}', NULL, NULL)"))
+            .execute(pool)
+            .await?;
+    }
     Ok(())
 }
 
-pub async fn insert_trade(pool: &PgPool, trade: RecentTrade) -> Result<(), Error> {
+// Имена источников в порядке предпочтения, для параметра TEXT[]
+fn source_names(prefer: &[CandleSource]) -> Vec<&'static str> {
+    prefer.iter().map(CandleSource::as_str).collect()
+}
+
+// Колонки свечи в `relation`. Непрерывные агрегаты строятся из трейдов и колонок источника не имеют:
+// источник у них всегда aggregated, а свеча окончательна, когда интервал прошёл.
+// Ревизий агрегаты не ведут (пересчёт опоздавших трейдов делает сам TimescaleDB), для них revision = 0.
+fn relation_columns(relation: &str, time_frame: &str) -> String {
+    if relation == "candles" {
+        return CANDLE_COLUMNS.to_string();
+    }
+    let bucket_ms = aggregate::time_frame_ms(time_frame).unwrap_or(0);
+    format!(
+        "pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, utc_begin,
+        utc_begin + {bucket_ms} - 1 AS close_time,
+        trade_count,
+        CASE WHEN buy_base + sell_base > 0
+            THEN (buy_quote + sell_quote) / (buy_base + sell_base) ELSE 0 END AS vwap,
+        'aggregated'::TEXT AS source,
+        utc_begin + {bucket_ms} <= unix_now_ms() AS is_final,
+        0::BIGINT AS revision"
+    )
+}
+
+// Свечи ряда из `relation`. К свечам непрерывного агрегата добавляются свечи остальных источников
+// из таблицы candles, чтобы источник по-прежнему выбирался по предпочтению.
+fn candles_select(relation: &str, time_frame: &str, filter: &str) -> String {
+    let select = format!("SELECT {} FROM {} WHERE {}", relation_columns(relation, time_frame), relation, filter);
+    if relation == "candles" {
+        return select;
+    }
+    format!("{select} UNION ALL SELECT {CANDLE_COLUMNS} FROM candles WHERE {filter} AND source <> 'aggregated'")
+}
+
+pub async fn candles_range(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+    prefer: &[CandleSource],
+) -> Result<Vec<Kline>, Error> {
+    candles_range_from(pool, "candles", pair, time_frame, start_ts, end_ts, prefer).await
+}
+
+// `relation` - таблица `candles` или непрерывный агрегат.
+// На корзину одна свеча: от первого источника из `prefer`, для которого она есть;
+// источники вне списка берутся, только если других нет.
+pub async fn candles_range_from(
+    pool: &PgPool,
+    relation: &str,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+    prefer: &[CandleSource],
+) -> Result<Vec<Kline>, Error> {
+    let query = format!(
+        "SELECT DISTINCT ON (utc_begin) * FROM ({}) c
+        ORDER BY utc_begin, array_position($5::TEXT[], source), source",
+        candles_select(relation, time_frame, "pair = $1 AND time_frame = $2 AND utc_begin BETWEEN $3 AND $4")
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(time_frame)
+        .bind(start_ts)
+        .bind(end_ts)
+        .bind(source_names(prefer))
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(kline_from_row).collect()
+}
+
+pub async fn latest_candle(pool: &PgPool, pair: &str, time_frame: &str) -> Result<Option<Kline>, Error> {
+    latest_candle_from(pool, "candles", pair, time_frame).await
+}
+
+// Последняя корзина ряда, источник выбирается по порядку по умолчанию
+pub async fn latest_candle_from(
+    pool: &PgPool,
+    relation: &str,
+    pair: &str,
+    time_frame: &str,
+) -> Result<Option<Kline>, Error> {
+    let query = format!(
+        "SELECT * FROM ({}) c
+        ORDER BY utc_begin DESC, array_position($3::TEXT[], source), source
+        LIMIT 1",
+        candles_select(relation, time_frame, "pair = $1 AND time_frame = $2")
+    );
+    let row = sqlx::query(&query)
+        .bind(pair)
+        .bind(time_frame)
+        .bind(source_names(&CandleSource::DEFAULT_PREFERENCE))
+        .fetch_optional(pool)
+        .await?;
+    row.as_ref().map(kline_from_row).transpose()
+}
+
+pub async fn trades_range(pool: &PgPool, pair: &str, start_ts: i64, end_ts: i64) -> Result<Vec<RecentTrade>, Error> {
+    let query = format!(
+        "SELECT {} FROM trades
+        WHERE pair = $1 AND time_stamp BETWEEN $2 AND $3
+        ORDER BY {}",
+        TRADE_COLUMNS, TRADE_ORDER_ASC
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(trade_from_row).collect()
+}
+
+pub async fn latest_trade(pool: &PgPool, pair: &str) -> Result<Option<RecentTrade>, Error> {
+    let query = format!(
+        "SELECT {} FROM trades
+        WHERE pair = $1
+        ORDER BY {}
+        LIMIT 1",
+        TRADE_COLUMNS, TRADE_ORDER_DESC
+    );
+    let row = sqlx::query(&query).bind(pair).fetch_optional(pool).await?;
+    row.as_ref().map(trade_from_row).transpose()
+}
 
-    let query = "
-    INSERT INTO trades (tid, pair, amount, side, quantity, create_time, price, time_stamp)
-    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-    ON CONFLICT (tid) DO NOTHING;
-    ";
+pub async fn get_watermark(pool: &PgPool, name: &str) -> Result<Option<i64>, Error> {
+    sqlx::query_scalar("SELECT value FROM job_watermarks WHERE name = $1")
+        .bind(name)
+        .fetch_optional(pool)
+        .await
+}
 
-    sqlx::query(query)
-    .bind(trade.tid)       
-    .bind(trade.pair)       
-    .bind(trade.amount)    
-    .bind(trade.side)  
-    .bind(trade.quantity)
-    .bind(trade.create_time)
-    .bind(trade.price)
-    .bind(trade.timestamp)
+pub async fn set_watermark(pool: &PgPool, name: &str, value: i64) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO job_watermarks (name, value, updated_at)
+        VALUES ($1, $2, (extract(epoch FROM now()) * 1000)::BIGINT)
+        ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value, updated_at = EXCLUDED.updated_at",
+    )
+    .bind(name)
+    .bind(value)
     .execute(pool)
     .await?;
+    Ok(())
+}
+
+// Ряд пишется одной транзакцией: либо все строки, либо ни одной. Строка с тем же ключом `key` перезаписывается,
+// bind кладёт параметры строки в порядке колонок
+pub async fn upsert_rows<T: Sync>(
+    pool: &PgPool,
+    table: &str,
+    columns: &str,
+    key: &str,
+    rows: &[T],
+    bind: impl FnMut(Separated<'_, 'static, Postgres, &'static str>, &T) + Send,
+) -> Result<(), Error> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    insert_rows(&mut tx, table, columns, &on_conflict_update(key, columns), rows, bind).await?;
+    tx.commit().await
+}
 
+// Режет строки на INSERT в пределах лимита параметров; у строки по параметру на колонку
+async fn insert_rows<T: Sync>(
+    tx: &mut Transaction<'_, Postgres>,
+    table: &str,
+    columns: &str,
+    suffix: &str,
+    rows: &[T],
+    mut bind: impl FnMut(Separated<'_, 'static, Postgres, &'static str>, &T) + Send,
+) -> Result<(), Error> {
+    for chunk in rows.chunks(PG_MAX_BIND_PARAMS / columns.split(',').count()) {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new(format!("INSERT INTO {} ({}) ", table, columns));
+        builder.push_values(chunk, &mut bind);
+        builder.push(suffix);
+        builder.build().execute(&mut *tx).await?;
+    }
     Ok(())
 }
+
+// ON CONFLICT (key) с перезаписью всех остальных колонок; SQLite понимает тот же синтаксис
+pub fn on_conflict_update(key: &str, columns: &str) -> String {
+    let key_columns: Vec<&str> = key.split(',').map(str::trim).collect();
+    let updates: Vec<String> = columns
+        .split(',')
+        .map(str::trim)
+        .filter(|column| !key_columns.contains(column))
+        .map(|column| format!("{0} = EXCLUDED.{0}", column))
+        .collect();
+    format!(" ON CONFLICT ({}) DO UPDATE SET {}", key, updates.join(", "))
+}
+
+// Бар с тем же первым трейдом перезаписывается
+pub async fn upsert_bars(pool: &PgPool, bars: &[Bar]) -> Result<(), Error> {
+    upsert_rows(pool, "bars", BAR_COLUMNS, BAR_KEY, bars, |mut b, bar| {
+        b.push_bind(bar.pair.clone())
+            .push_bind(bar.spec.kind.as_str())
+            .push_bind(bar.spec.threshold)
+            .push_bind(bar.open)
+            .push_bind(bar.high)
+            .push_bind(bar.low)
+            .push_bind(bar.close)
+            .push_bind(bar.volume_bs.buy_base)
+            .push_bind(bar.volume_bs.sell_base)
+            .push_bind(bar.volume_bs.buy_quote)
+            .push_bind(bar.volume_bs.sell_quote)
+            .push_bind(bar.utc_begin)
+            .push_bind(bar.utc_end)
+            .push_bind(bar.first_tid.clone())
+            .push_bind(bar.last_tid.clone())
+            .push_bind(bar.trade_count)
+            .push_bind(bar.vwap)
+            .push_bind(bar.imbalance);
+    })
+    .await
+}
+
+// Несколько баров могут начаться в одну миллисекунду, порядок по id трейда досчитывается в процессе
+pub async fn bars_range(pool: &PgPool, pair: &str, spec: &BarSpec, start_ts: i64, end_ts: i64) -> Result<Vec<Bar>, Error> {
+    let query = format!(
+        "SELECT {} FROM bars
+        WHERE pair = $1 AND bar_type = $2 AND threshold = $3 AND utc_begin BETWEEN $4 AND $5",
+        BAR_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(spec.kind.as_str())
+        .bind(spec.threshold)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    let mut bars = rows.iter().map(bar_from_row).collect::<Result<Vec<_>, _>>()?;
+    bars.sort_by(aggregate::bar_order);
+    Ok(bars)
+}
+
+pub async fn latest_bar(pool: &PgPool, pair: &str, spec: &BarSpec) -> Result<Option<Bar>, Error> {
+    let query = format!(
+        "SELECT {} FROM bars
+        WHERE pair = $1 AND bar_type = $2 AND threshold = $3
+          AND utc_end = (SELECT MAX(utc_end) FROM bars WHERE pair = $1 AND bar_type = $2 AND threshold = $3)",
+        BAR_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(spec.kind.as_str())
+        .bind(spec.threshold)
+        .fetch_all(pool)
+        .await?;
+    let bars = rows.iter().map(bar_from_row).collect::<Result<Vec<_>, _>>()?;
+    Ok(bars
+        .into_iter()
+        .max_by(|a, b| aggregate::trade_key_order((a.utc_end, &a.last_tid), (b.utc_end, &b.last_tid))))
+}
+
+pub async fn delete_bars_from(pool: &PgPool, pair: &str, spec: &BarSpec, from_ts: i64) -> Result<u64, Error> {
+    let result = sqlx::query("DELETE FROM bars WHERE pair = $1 AND bar_type = $2 AND threshold = $3 AND utc_end >= $4")
+        .bind(pair)
+        .bind(spec.kind.as_str())
+        .bind(spec.threshold)
+        .bind(from_ts)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// Значение того же индикатора на той же свече перезаписывается
+pub async fn upsert_indicator_values(pool: &PgPool, values: &[IndicatorValue]) -> Result<(), Error> {
+    upsert_rows(pool, "indicators", INDICATOR_COLUMNS, INDICATOR_KEY, values, |mut b, value| {
+        b.push_bind(value.pair.clone())
+            .push_bind(value.time_frame.clone())
+            .push_bind(value.utc_begin)
+            .push_bind(value.indicator.clone())
+            .push_bind(value.params.clone())
+            .push_bind(value.values.first().copied().unwrap_or(f64::NAN))
+            .push_bind(value.values.get(1).copied())
+            .push_bind(value.values.get(2).copied());
+    })
+    .await
+}
+
+pub async fn indicator_range(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    indicator: &str,
+    params: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<IndicatorValue>, Error> {
+    let query = format!(
+        "SELECT {} FROM indicators
+        WHERE pair = $1 AND time_frame = $2 AND indicator = $3 AND params = $4 AND utc_begin BETWEEN $5 AND $6
+        ORDER BY utc_begin",
+        INDICATOR_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(time_frame)
+        .bind(indicator)
+        .bind(params)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(indicator_from_row).collect()
+}
+
+// Строка той же свечи перезаписывается
+pub async fn upsert_order_flow(pool: &PgPool, rows: &[OrderFlow]) -> Result<(), Error> {
+    upsert_rows(pool, "order_flow", ORDER_FLOW_COLUMNS, ORDER_FLOW_KEY, rows, |mut b, row| {
+        b.push_bind(row.pair.clone())
+            .push_bind(row.time_frame.clone())
+            .push_bind(row.utc_begin)
+            .push_bind(row.buy_volume)
+            .push_bind(row.sell_volume)
+            .push_bind(row.delta)
+            .push_bind(row.cvd)
+            .push_bind(row.imbalance)
+            .push_bind(row.divergence as i16)
+            .push_bind(row.buy_z)
+            .push_bind(row.sell_z)
+            .push_bind(row.window);
+    })
+    .await
+}
+
+pub async fn order_flow_range(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<OrderFlow>, Error> {
+    let query = format!(
+        "SELECT {} FROM order_flow
+        WHERE pair = $1 AND time_frame = $2 AND utc_begin BETWEEN $3 AND $4
+        ORDER BY utc_begin",
+        ORDER_FLOW_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(time_frame)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(order_flow_from_row).collect()
+}
+
+// Матрица хранится по строке на ячейку
+pub async fn upsert_correlations(pool: &PgPool, matrices: &[CorrelationMatrix]) -> Result<(), Error> {
+    let cells: Vec<(&CorrelationMatrix, &CorrelationCell)> =
+        matrices.iter().flat_map(|m| m.cells.iter().map(move |c| (m, c))).collect();
+    upsert_rows(pool, "correlations", CORRELATION_COLUMNS, CORRELATION_KEY, &cells, |mut b, (matrix, cell)| {
+        b.push_bind(matrix.time_frame.clone())
+            .push_bind(matrix.utc_begin)
+            .push_bind(matrix.window)
+            .push_bind(cell.pair.clone())
+            .push_bind(cell.other_pair.clone())
+            .push_bind(cell.observations)
+            .push_bind(cell.correlation)
+            .push_bind(cell.beta);
+    })
+    .await
+}
+
+pub async fn correlation_range(
+    pool: &PgPool,
+    time_frame: &str,
+    window: i64,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<CorrelationMatrix>, Error> {
+    let query = format!(
+        "SELECT {} FROM correlations
+        WHERE time_frame = $1 AND window_size = $2 AND utc_begin BETWEEN $3 AND $4
+        ORDER BY utc_begin, pair, other_pair",
+        CORRELATION_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(time_frame)
+        .bind(window)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    let cells = rows.iter().map(correlation_cell_from_row).collect::<Result<Vec<_>, _>>()?;
+    Ok(group_correlation_cells(cells))
+}
+
+// Строки таблицы - матрицы из одной ячейки, по utc_begin, pair и other_pair; собирает ячейки одной матрицы
+pub fn group_correlation_cells(rows: Vec<CorrelationMatrix>) -> Vec<CorrelationMatrix> {
+    let mut matrices: Vec<CorrelationMatrix> = Vec::new();
+    for row in rows {
+        match matrices.last_mut() {
+            Some(last) if last.utc_begin == row.utc_begin => {
+                for pair in row.pairs {
+                    if last.pairs.last() != Some(&pair) {
+                        last.pairs.push(pair);
+                    }
+                }
+                last.cells.extend(row.cells);
+            }
+            _ => matrices.push(row),
+        }
+    }
+    matrices
+}
+
+pub async fn upsert_volatility(pool: &PgPool, rows: &[Volatility]) -> Result<(), Error> {
+    upsert_rows(pool, "volatility", VOLATILITY_COLUMNS, VOLATILITY_KEY, rows, |mut b, row| {
+        b.push_bind(row.pair.clone())
+            .push_bind(row.time_frame.clone())
+            .push_bind(row.utc_begin)
+            .push_bind(row.estimator.clone())
+            .push_bind(row.window)
+            .push_bind(row.variance)
+            .push_bind(row.volatility);
+    })
+    .await
+}
+
+pub async fn volatility_range(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    estimator: &str,
+    window: i64,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<Volatility>, Error> {
+    let query = format!(
+        "SELECT {} FROM volatility
+        WHERE pair = $1 AND time_frame = $2 AND estimator = $3 AND window_size = $4 AND utc_begin BETWEEN $5 AND $6
+        ORDER BY utc_begin",
+        VOLATILITY_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(time_frame)
+        .bind(estimator)
+        .bind(window)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(volatility_from_row).collect()
+}
+
+// Профили перезаписываются целиком одной транзакцией: уровни, которых больше нет, удаляются
+pub async fn replace_volume_profiles(pool: &PgPool, profiles: &[VolumeProfile]) -> Result<(), Error> {
+    if profiles.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    for profile in profiles {
+        sqlx::query("DELETE FROM volume_profiles WHERE pair = $1 AND period = $2 AND utc_begin = $3 AND bins = $4")
+            .bind(&profile.pair)
+            .bind(&profile.period)
+            .bind(profile.utc_begin)
+            .bind(&profile.bins)
+            .execute(&mut tx)
+            .await?;
+        insert_rows(&mut tx, "volume_profiles", VOLUME_PROFILE_COLUMNS, "", &profile.levels, |mut b, level| {
+            b.push_bind(profile.pair.clone())
+                .push_bind(profile.period.clone())
+                .push_bind(profile.utc_begin)
+                .push_bind(profile.utc_end)
+                .push_bind(profile.bins.clone())
+                .push_bind(profile.bin_width)
+                .push_bind(level.price_low)
+                .push_bind(level.buy_volume)
+                .push_bind(level.sell_volume)
+                .push_bind(level.trade_count)
+                .push_bind(profile.poc)
+                .push_bind(profile.value_area_high)
+                .push_bind(profile.value_area_low);
+        })
+        .await?;
+    }
+    tx.commit().await
+}
+
+pub async fn volume_profiles_range(
+    pool: &PgPool,
+    pair: &str,
+    period: &str,
+    bins: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<VolumeProfile>, Error> {
+    let query = format!(
+        "SELECT {} FROM volume_profiles
+        WHERE pair = $1 AND period = $2 AND bins = $3 AND utc_begin BETWEEN $4 AND $5
+        ORDER BY utc_begin, price_low",
+        VOLUME_PROFILE_COLUMNS
+    );
+    let rows = sqlx::query(&query)
+        .bind(pair)
+        .bind(period)
+        .bind(bins)
+        .bind(start_ts)
+        .bind(end_ts)
+        .fetch_all(pool)
+        .await?;
+    let levels = rows.iter().map(profile_level_from_row).collect::<Result<Vec<_>, _>>()?;
+    Ok(group_profile_levels(levels))
+}
+
+// Строки таблицы - профили с одним уровнем, по utc_begin и цене; собирает уровни одного профиля вместе
+pub fn group_profile_levels(rows: Vec<VolumeProfile>) -> Vec<VolumeProfile> {
+    let mut profiles: Vec<VolumeProfile> = Vec::new();
+    for row in rows {
+        match profiles.last_mut() {
+            Some(last) if last.utc_begin == row.utc_begin && last.pair == row.pair && last.bins == row.bins => {
+                last.levels.extend(row.levels);
+            }
+            _ => profiles.push(row),
+        }
+    }
+    profiles
+}
+
+// Чтение свечей и трейдов для внешних потребителей с курсорами и потоками - только для Postgres.
+// Конвейер и экспорт читают через трейты хранилища и работают с любым бэкендом.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+// Границы диапазонов включительные; None - без ограничения с этой стороны.
+// На корзину одна свеча - от первого доступного источника из $6.
+const SELECT_CANDLES_ASC: &str = "SELECT DISTINCT ON (utc_begin)
+        pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, utc_begin,
+        close_time, trade_count, vwap, source, is_final, revision
+    FROM candles
+    WHERE pair = $1 AND time_frame = $2
+      AND ($3::BIGINT IS NULL OR utc_begin >= $3)
+      AND ($4::BIGINT IS NULL OR utc_begin <= $4)
+    ORDER BY utc_begin ASC, array_position($6::TEXT[], source), source
+    LIMIT $5";
+
+const SELECT_CANDLES_DESC: &str = "SELECT DISTINCT ON (utc_begin)
+        pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, utc_begin,
+        close_time, trade_count, vwap, source, is_final, revision
+    FROM candles
+    WHERE pair = $1 AND time_frame = $2
+      AND ($3::BIGINT IS NULL OR utc_begin >= $3)
+      AND ($4::BIGINT IS NULL OR utc_begin <= $4)
+    ORDER BY utc_begin DESC, array_position($6::TEXT[], source), source
+    LIMIT $5";
+
+// Постраничное чтение трейдов по курсору (time_stamp, tid) последней полученной строки.
+// Курсор сравнивается по тому же ключу, что и TRADE_ORDER_ASC
+const SELECT_TRADES_AFTER: &str = "SELECT tid, pair, amount, side, quantity, create_time, price, time_stamp
+    FROM trades
+    WHERE pair = $1
+      AND ($2::BIGINT IS NULL OR time_stamp >= $2)
+      AND ($3::BIGINT IS NULL OR time_stamp <= $3)
+      AND ($4::BIGINT IS NULL OR (
+        time_stamp,
+        tid !~ '^[0-9]+$',
+        CASE WHEN tid ~ '^[0-9]+$' THEN length(ltrim(tid, '0')) ELSE 0 END,
+        CASE WHEN tid ~ '^[0-9]+$' THEN ltrim(tid, '0') ELSE '' END COLLATE \"C\",
+        tid COLLATE \"C\"
+      ) > (
+        $4,
+        $5::TEXT !~ '^[0-9]+$',
+        CASE WHEN $5::TEXT ~ '^[0-9]+$' THEN length(ltrim($5::TEXT, '0')) ELSE 0 END,
+        CASE WHEN $5::TEXT ~ '^[0-9]+$' THEN ltrim($5::TEXT, '0') ELSE '' END COLLATE \"C\",
+        $5::TEXT COLLATE \"C\"
+      ))
+    ORDER BY time_stamp, tid !~ '^[0-9]+$',
+      CASE WHEN tid ~ '^[0-9]+$' THEN length(ltrim(tid, '0')) ELSE 0 END,
+      CASE WHEN tid ~ '^[0-9]+$' THEN ltrim(tid, '0') ELSE '' END COLLATE \"C\",
+      tid COLLATE \"C\"
+    LIMIT $6";
+
+fn candles_query(order: SortOrder) -> &'static str {
+    match order {
+        SortOrder::Asc => SELECT_CANDLES_ASC,
+        SortOrder::Desc => SELECT_CANDLES_DESC,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn get_candles(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+    limit: Option<i64>,
+    order: SortOrder,
+    prefer: &[CandleSource],
+) -> Result<Vec<Kline>, Error> {
+    let rows = sqlx::query(candles_query(order))
+        .bind(pair)
+        .bind(time_frame)
+        .bind(start_ts)
+        .bind(end_ts)
+        .bind(limit)
+        .bind(source_names(prefer))
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(kline_from_row).collect()
+}
+
+// Потоковое чтение свечей: строки приходят по мере чтения курсора, а не одним Vec
+pub fn stream_candles<'a>(
+    pool: &'a PgPool,
+    pair: &'a str,
+    time_frame: &'a str,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+    order: SortOrder,
+    prefer: &[CandleSource],
+) -> BoxStream<'a, Result<Kline, Error>> {
+    sqlx::query(candles_query(order))
+        .bind(pair)
+        .bind(time_frame)
+        .bind(start_ts)
+        .bind(end_ts)
+        .bind(None::<i64>)
+        .bind(source_names(prefer))
+        .fetch(pool)
+        .map(|row| row.and_then(|row| kline_from_row(&row)))
+        .boxed()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TradeCursor {
+    pub time_stamp: i64,
+    pub tid: String,
+}
+
+impl From<&RecentTrade> for TradeCursor {
+    fn from(trade: &RecentTrade) -> Self {
+        TradeCursor {
+            time_stamp: trade.timestamp,
+            tid: trade.tid.clone(),
+        }
+    }
+}
+
+// Следующая страница трейдов после `after`; курсор для продолжения - последний трейд страницы
+pub async fn get_trades(
+    pool: &PgPool,
+    pair: &str,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+    after: Option<&TradeCursor>,
+    limit: i64,
+) -> Result<Vec<RecentTrade>, Error> {
+    let rows = sqlx::query(SELECT_TRADES_AFTER)
+        .bind(pair)
+        .bind(start_ts)
+        .bind(end_ts)
+        .bind(after.map(|c| c.time_stamp))
+        .bind(after.map(|c| c.tid.clone()))
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+    rows.iter().map(trade_from_row).collect()
+}
+
+pub fn stream_trades<'a>(
+    pool: &'a PgPool,
+    pair: &'a str,
+    start_ts: Option<i64>,
+    end_ts: Option<i64>,
+) -> BoxStream<'a, Result<RecentTrade, Error>> {
+    sqlx::query(SELECT_TRADES_AFTER)
+        .bind(pair)
+        .bind(start_ts)
+        .bind(end_ts)
+        .bind(None::<i64>)
+        .bind(None::<String>)
+        .bind(None::<i64>)
+        .fetch(pool)
+        .map(|row| row.and_then(|row| trade_from_row(&row)))
+        .boxed()
+}
+
+// Пары, по которым есть свечи или трейды
+pub async fn list_pairs(pool: &PgPool) -> Result<Vec<String>, Error> {
+    sqlx::query_scalar(
+        "SELECT pair FROM candles
+        UNION
+        SELECT pair FROM trades WHERE pair IS NOT NULL
+        ORDER BY pair",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+// Таймфреймы свечей, по всем парам или по одной
+pub async fn list_time_frames(pool: &PgPool, pair: Option<&str>) -> Result<Vec<String>, Error> {
+    sqlx::query_scalar(
+        "SELECT DISTINCT time_frame FROM candles
+        WHERE $1::TEXT IS NULL OR pair = $1
+        ORDER BY time_frame",
+    )
+    .bind(pair)
+    .fetch_all(pool)
+    .await
+}
+
+// Агрегация трейдов в свечи средствами SQL. Возвращает свечи, запись делает вызывающий.
+// Корзины длиной в таймфрейм, выровненные как aggregate::bucket_start;
+// open/close - первый и последний трейд пары по (time_stamp, tid).
+pub async fn aggregate_trades(
+    pool: &PgPool,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<Kline>, Error> {
+    let bucket_ms = aggregate::time_frame_ms(time_frame).ok_or_else(|| aggregate::unknown_time_frame(time_frame))?;
+
+    let query = r#"
+    WITH grouped AS (
+      SELECT
+        pair,
+        time_stamp - mod(time_stamp - $5, $4) AS utc_begin,
+        (array_agg(price::numeric ORDER BY time_stamp ASC, tid_num ASC NULLS LAST, tid COLLATE "C" ASC))[1]::float8 AS open,
+        (array_agg(price::numeric ORDER BY time_stamp DESC, tid_num DESC NULLS FIRST, tid COLLATE "C" DESC))[1]::float8 AS close,
+        MIN(price::numeric)::float8 AS low,
+        MAX(price::numeric)::float8 AS high,
+        COUNT(*) AS trade_count,
+        SUM(CASE WHEN side = 'buy' THEN quantity::numeric ELSE 0 END)::float8 AS buy_base,
+        SUM(CASE WHEN side = 'sell' THEN quantity::numeric ELSE 0 END)::float8 AS sell_base,
+        SUM(CASE WHEN side = 'buy' THEN amount::numeric ELSE 0 END)::float8 AS buy_quote,
+        SUM(CASE WHEN side = 'sell' THEN amount::numeric ELSE 0 END)::float8 AS sell_quote
+      FROM (
+        -- Трейды одной миллисекунды упорядочиваем по id как aggregate::tid_order:
+        -- числовые id как числа, остальные побайтно (COLLATE "C")
+        SELECT *, CASE WHEN tid ~ '^[0-9]+$' THEN tid::numeric END AS tid_num
+        FROM trades
+        WHERE pair = $1
+          AND time_stamp BETWEEN $2 AND $3
+      ) t
+      GROUP BY pair, time_stamp - mod(time_stamp - $5, $4)
+    )
+    SELECT
+        g.pair,
+        g.open AS o,
+        g.high AS h,
+        g.low AS l,
+        g.close AS c,
+        g.utc_begin AS utc_begin_ms,
+        g.trade_count,
+        g.buy_base,
+        g.sell_base,
+        g.buy_quote,
+        g.sell_quote
+    FROM grouped g;
+    "#;
+    
+    let rows = sqlx::query(query)
+        .bind(pair)
+        .bind(start_ts)
+        .bind(end_ts)
+        .bind(bucket_ms)
+        .bind(aggregate::bucket_offset(bucket_ms))
+        .fetch_all(pool)
+        .await?;
+
+    let mut candles = Vec::new();
+    for row in rows {
+        let pair_val: String = pair.to_string();
+        let open: f64 = match row.try_get("o") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'o' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let high: f64 = match row.try_get("h") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'h' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let low: f64 = match row.try_get("l") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'l' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let close: f64 = match row.try_get("c") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'c' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let utc_begin: i64 = match row.try_get("utc_begin_ms") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'utc_begin_ms' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let trade_count: i64 = match row.try_get("trade_count") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'trade_count' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let buy_base: f64 = match row.try_get("buy_base") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'buy_base' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let sell_base: f64 = match row.try_get("sell_base") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'sell_base' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let buy_quote: f64 = match row.try_get("buy_quote") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'buy_quote' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        let sell_quote: f64 = match row.try_get("sell_quote") {
+            Ok(val) => val,
+            Err(e) => {
+                eprintln!("Ошибка получения 'sell_quote' для пары {}: {}", pair, e);
+                return Err(e);
+            }
+        };
+        
+        let volume_bs = VBS {
+            buy_base,
+            sell_base,
+            buy_quote,
+            sell_quote,
+        };
+        
+        let vwap = volume_bs.vwap();
+        let candle = Kline {
+            pair: pair_val,
+            time_frame: time_frame.to_string(),
+            open,
+            high,
+            low,
+            close,
+            volume_bs,
+            utc_begin,
+            close_time: utc_begin + bucket_ms - 1,
+            trade_count,
+            vwap,
+            source: CandleSource::Aggregated,
+            is_final: aggregate::bucket_closed(utc_begin, time_frame),
+            revision: 0,
+        };
+        
+        candles.push(candle);
+    }
+
+    Ok(candles)
+}
+
+pub fn kline_from_row(row: &PgRow) -> Result<Kline, Error> {
+    Ok(Kline {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        open: row.try_get("open")?,
+        high: row.try_get("high")?,
+        low: row.try_get("low")?,
+        close: row.try_get("close")?,
+        volume_bs: VBS {
+            buy_base: row.try_get("buy_base")?,
+            sell_base: row.try_get("sell_base")?,
+            buy_quote: row.try_get("buy_quote")?,
+            sell_quote: row.try_get("sell_quote")?,
+        },
+        utc_begin: row.try_get("utc_begin")?,
+        close_time: row.try_get("close_time")?,
+        trade_count: row.try_get("trade_count")?,
+        vwap: row.try_get("vwap")?,
+        source: source_from_row(row)?,
+        is_final: row.try_get("is_final")?,
+        revision: row.try_get("revision")?,
+    })
+}
+
+fn source_from_row(row: &PgRow) -> Result<CandleSource, Error> {
+    let source: String = row.try_get("source")?;
+    CandleSource::parse(&source).ok_or_else(|| Error::Decode(format!("неизвестный источник свечи: {}", source).into()))
+}
+
+// Колонки `trades` допускают NULL, поэтому пустые значения заменяем значениями по умолчанию
+pub fn trade_from_row(row: &PgRow) -> Result<RecentTrade, Error> {
+    Ok(RecentTrade {
+        tid: row.try_get("tid")?,
+        pair: row.try_get::<Option<String>, _>("pair")?.unwrap_or_default(),
+        price: row.try_get::<Option<String>, _>("price")?.unwrap_or_default(),
+        amount: row.try_get::<Option<String>, _>("amount")?.unwrap_or_default(),
+        quantity: row.try_get::<Option<String>, _>("quantity")?.unwrap_or_default(),
+        side: row.try_get::<Option<String>, _>("side")?.unwrap_or_default(),
+        create_time: row.try_get::<Option<i64>, _>("create_time")?.unwrap_or_default(),
+        timestamp: row.try_get::<Option<i64>, _>("time_stamp")?.unwrap_or_default(),
+    })
+}
+
+pub fn bar_from_row(row: &PgRow) -> Result<Bar, Error> {
+    let bar_type: String = row.try_get("bar_type")?;
+    Ok(Bar {
+        pair: row.try_get("pair")?,
+        spec: BarSpec {
+            kind: BarKind::parse(&bar_type)
+                .ok_or_else(|| Error::Decode(format!("неизвестный тип бара: {}", bar_type).into()))?,
+            threshold: row.try_get("threshold")?,
+        },
+        open: row.try_get("open")?,
+        high: row.try_get("high")?,
+        low: row.try_get("low")?,
+        close: row.try_get("close")?,
+        volume_bs: VBS {
+            buy_base: row.try_get("buy_base")?,
+            sell_base: row.try_get("sell_base")?,
+            buy_quote: row.try_get("buy_quote")?,
+            sell_quote: row.try_get("sell_quote")?,
+        },
+        utc_begin: row.try_get("utc_begin")?,
+        utc_end: row.try_get("utc_end")?,
+        first_tid: row.try_get("first_tid")?,
+        last_tid: row.try_get("last_tid")?,
+        trade_count: row.try_get("trade_count")?,
+        vwap: row.try_get("vwap")?,
+        imbalance: row.try_get("imbalance")?,
+    })
+}
+
+pub fn indicator_from_row(row: &PgRow) -> Result<IndicatorValue, Error> {
+    let mut values = vec![row.try_get::<f64, _>("value_1")?];
+    for column in ["value_2", "value_3"] {
+        match row.try_get::<Option<f64>, _>(column)? {
+            Some(value) => values.push(value),
+            None => break,
+        }
+    }
+    Ok(IndicatorValue {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        indicator: row.try_get("indicator")?,
+        params: row.try_get("params")?,
+        values,
+    })
+}
+
+pub fn order_flow_from_row(row: &PgRow) -> Result<OrderFlow, Error> {
+    Ok(OrderFlow {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        buy_volume: row.try_get("buy_volume")?,
+        sell_volume: row.try_get("sell_volume")?,
+        delta: row.try_get("delta")?,
+        cvd: row.try_get("cvd")?,
+        imbalance: row.try_get("imbalance")?,
+        divergence: row.try_get::<i16, _>("divergence")? as i8,
+        buy_z: row.try_get("buy_z")?,
+        sell_z: row.try_get("sell_z")?,
+        window: row.try_get("window_size")?,
+    })
+}
+
+pub fn profile_level_from_row(row: &PgRow) -> Result<VolumeProfile, Error> {
+    Ok(VolumeProfile {
+        pair: row.try_get("pair")?,
+        period: row.try_get("period")?,
+        utc_begin: row.try_get("utc_begin")?,
+        utc_end: row.try_get("utc_end")?,
+        bins: row.try_get("bins")?,
+        bin_width: row.try_get("bin_width")?,
+        levels: vec![PriceLevel {
+            price_low: row.try_get("price_low")?,
+            buy_volume: row.try_get("buy_volume")?,
+            sell_volume: row.try_get("sell_volume")?,
+            trade_count: row.try_get("trade_count")?,
+        }],
+        poc: row.try_get("poc")?,
+        value_area_high: row.try_get("value_area_high")?,
+        value_area_low: row.try_get("value_area_low")?,
+    })
+}
+
+pub fn volatility_from_row(row: &PgRow) -> Result<Volatility, Error> {
+    Ok(Volatility {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        estimator: row.try_get("estimator")?,
+        window: row.try_get("window_size")?,
+        variance: row.try_get("variance")?,
+        volatility: row.try_get("volatility")?,
+    })
+}
+
+pub fn correlation_cell_from_row(row: &PgRow) -> Result<CorrelationMatrix, Error> {
+    let pair: String = row.try_get("pair")?;
+    Ok(CorrelationMatrix {
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        window: row.try_get("window_size")?,
+        pairs: vec![pair.clone()],
+        cells: vec![CorrelationCell {
+            pair,
+            other_pair: row.try_get("other_pair")?,
+            observations: row.try_get("observations")?,
+            correlation: row.try_get("correlation")?,
+            beta: row.try_get("beta")?,
+        }],
+    })
+}
+
+// Удаляет до limit трейдов старше cutoff, если корзина трейда уже есть во всех таймфреймах guard.
+// guard - (таймфрейм, откуда читать его свечи): таблица candles или непрерывный агрегат
+pub async fn delete_trades_before(
+    pool: &PgPool,
+    cutoff: i64,
+    guard: &[(&str, &str)],
+    limit: i64,
+) -> Result<u64, Error> {
+    let mut conditions = vec!["t.time_stamp < $1".to_string()];
+    for (time_frame, relation) in guard {
+        let bucket_ms = aggregate::time_frame_ms(time_frame).ok_or_else(|| aggregate::unknown_time_frame(time_frame))?;
+        // Свеча биржи не считается: корзину ещё должен собрать из трейдов планировщик.
+        // Непрерывный агрегат строится только из трейдов
+        let source = if *relation == "candles" { " AND c.source = 'aggregated'" } else { "" };
+        conditions.push(format!(
+            "EXISTS (SELECT 1 FROM {relation} c
+                WHERE c.pair = t.pair AND c.time_frame = '{tf}'{source}
+                  AND c.utc_begin = t.time_stamp - mod(t.time_stamp - {offset}, {bucket_ms}))",
+            relation = relation,
+            source = source,
+            tf = time_frame,
+            offset = aggregate::bucket_offset(bucket_ms),
+            bucket_ms = bucket_ms
+        ));
+    }
+    let query = format!(
+        "DELETE FROM trades WHERE (tid, time_stamp) IN (
+            SELECT t.tid, t.time_stamp FROM trades t
+            WHERE {}
+            LIMIT $2
+        )",
+        conditions.join(" AND ")
+    );
+    Ok(sqlx::query(&query).bind(cutoff).bind(limit).execute(pool).await?.rows_affected())
+}
+
+// Удаляет до limit свечей таймфрейма всех источников с utc_begin < cutoff
+pub async fn delete_candles_before(
+    pool: &PgPool,
+    pair: Option<&str>,
+    time_frame: &str,
+    cutoff: i64,
+    limit: i64,
+) -> Result<u64, Error> {
+    let result = sqlx::query(
+        "DELETE FROM candles WHERE (pair, time_frame, utc_begin, source) IN (
+            SELECT pair, time_frame, utc_begin, source FROM candles
+            WHERE ($1::TEXT IS NULL OR pair = $1) AND time_frame = $2 AND utc_begin < $3
+            LIMIT $4
+        )",
+    )
+    .bind(pair)
+    .bind(time_frame)
+    .bind(cutoff)
+    .bind(limit)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn oldest_candle_begin(pool: &PgPool, pair: &str, time_frame: &str) -> Result<Option<i64>, Error> {
+    sqlx::query_scalar("SELECT min(utc_begin) FROM candles WHERE pair = $1 AND time_frame = $2")
+        .bind(pair)
+        .bind(time_frame)
+        .fetch_one(pool)
+        .await
+}
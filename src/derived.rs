@@ -0,0 +1,318 @@
+use crate::data_structs::Kline;
+
+// Свечи Хейкен-Аши по потоку обычных свечей одного ряда
+#[derive(Debug, Clone, Default)]
+pub struct HeikinAshi {
+    prev: Option<(f64, f64)>, // (open, close) предыдущей свечи Хейкен-Аши
+}
+
+impl HeikinAshi {
+    pub fn new() -> Self {
+        HeikinAshi::default()
+    }
+
+    // close = (o + h + l + c) / 4, open = середина тела предыдущей свечи (у первой - (o + c) / 2),
+    // high/low - экстремумы с учётом нового тела. Объёмы и остальные поля - от исходной свечи.
+    pub fn next(&mut self, candle: &Kline) -> Kline {
+        let close = (candle.open + candle.high + candle.low + candle.close) / 4.0;
+        let open = match self.prev {
+            Some((prev_open, prev_close)) => (prev_open + prev_close) / 2.0,
+            None => (candle.open + candle.close) / 2.0,
+        };
+        self.prev = Some((open, close));
+
+        let mut ha = candle.clone();
+        ha.open = open;
+        ha.close = close;
+        ha.high = candle.high.max(open).max(close);
+        ha.low = candle.low.min(open).min(close);
+        ha
+    }
+}
+
+// Свечи по возрастанию utc_begin
+pub fn heikin_ashi(candles: &[Kline]) -> Vec<Kline> {
+    let mut ha = HeikinAshi::new();
+    candles.iter().map(|c| ha.next(c)).collect()
+}
+
+// Средний истинный диапазон по Уайлдеру: первые `period` диапазонов усредняются,
+// дальше atr = (atr * (period - 1) + tr) / period
+#[derive(Debug, Clone)]
+pub struct Atr {
+    period: usize,
+    prev_close: Option<f64>,
+    seen: usize,
+    value: f64,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Self {
+        Atr {
+            period: period.max(1),
+            prev_close: None,
+            seen: 0,
+            value: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, candle: &Kline) -> Option<f64> {
+        let range = match self.prev_close {
+            Some(prev) => (candle.high - candle.low)
+                .max((candle.high - prev).abs())
+                .max((candle.low - prev).abs()),
+            None => candle.high - candle.low,
+        };
+        self.prev_close = Some(candle.close);
+        self.seen += 1;
+        let n = self.period as f64;
+        if self.seen <= self.period {
+            self.value += (range - self.value) / self.seen as f64;
+        } else {
+            self.value = (self.value * (n - 1.0) + range) / n;
+        }
+        self.value()
+    }
+
+    // Значение есть, когда набралось `period` свечей
+    pub fn value(&self) -> Option<f64> {
+        (self.seen >= self.period).then_some(self.value)
+    }
+}
+
+// Сколько кирпичей складывает одна свеча: крошечный размер рядом с ценовым разрывом
+// иначе дал бы миллионы кирпичей на свечу
+const MAX_BRICKS_PER_CANDLE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrickSize {
+    Fixed(f64),
+    Atr(usize), // скользящий ATR за период на свече, на закрытии которой складывается кирпич
+}
+
+impl BrickSize {
+    // "25.5" - фиксированный размер в цене, "atr:14" - по ATR
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let brick = match value.strip_prefix("atr:") {
+            Some(period) => BrickSize::Atr(period.parse::<usize>().map_err(|_| format!("Неверный период ATR: {}", period))?),
+            None => BrickSize::Fixed(value.parse::<f64>().map_err(|_| format!("Неверный размер кирпича: {}", value))?),
+        };
+        brick.validate()?;
+        Ok(brick)
+    }
+
+    // Размер должен быть конечным и положительным, иначе кирпичи на свече не кончаются
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            BrickSize::Fixed(size) if !(size.is_finite() && size > 0.0) => Err(format!("Неверный размер кирпича: {}", size)),
+            BrickSize::Atr(0) => Err("Неверный период ATR: 0".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenkoBrick {
+    pub pair: String,
+    pub time_frame: String, // таймфрейм исходных свечей
+    pub utc_begin: i64,     // свеча, на закрытии которой кирпич сложился
+    pub open: f64,
+    pub close: f64,
+    pub direction: i8,      // 1 - вверх, -1 - вниз
+    pub brick_size: f64,
+}
+
+// Кирпичи Ренко по ценам закрытия. Отсчёт от закрытия первой свечи, для которой известен размер;
+// кирпич в сторону тренда - при проходе на один размер от края последнего кирпича, разворот - на два.
+// Одна свеча может сложить несколько кирпичей, но не больше MAX_BRICKS_PER_CANDLE. Размер по ATR берётся на текущей свече
+// только по ней и предыдущим, поэтому кирпичи не зависят от того, где кончается ряд.
+#[derive(Debug, Clone)]
+pub struct Renko {
+    brick: BrickSize,
+    atr: Option<Atr>,
+    base: Option<f64>,            // цена отсчёта до первого кирпича
+    last: Option<(f64, f64, i8)>, // (open, close, direction) последнего кирпича
+}
+
+impl Renko {
+    pub fn new(brick: BrickSize) -> Result<Self, String> {
+        brick.validate()?;
+        Ok(Renko {
+            brick,
+            atr: match brick {
+                BrickSize::Atr(period) => Some(Atr::new(period)),
+                BrickSize::Fixed(_) => None,
+            },
+            base: None,
+            last: None,
+        })
+    }
+
+    // Свеча до начала ряда: только разогревает ATR, кирпичей не складывает
+    pub fn warm_up(&mut self, candle: &Kline) {
+        if let Some(atr) = self.atr.as_mut() {
+            atr.push(candle);
+        }
+    }
+
+    // Размер кирпича на этой свече; None, пока для ATR не набралось свечей
+    fn size(&mut self, candle: &Kline) -> Option<f64> {
+        match self.brick {
+            BrickSize::Fixed(size) => Some(size),
+            BrickSize::Atr(_) => self.atr.as_mut().and_then(|atr| atr.push(candle)).filter(|v| *v > 0.0),
+        }
+    }
+
+    pub fn push(&mut self, candle: &Kline) -> Vec<RenkoBrick> {
+        let size = match self.size(candle) {
+            Some(size) => size,
+            None => return Vec::new(),
+        };
+        let price = candle.close;
+        let base = *self.base.get_or_insert(price);
+        let mut bricks = Vec::new();
+        while bricks.len() < MAX_BRICKS_PER_CANDLE {
+            let next = match self.last {
+                None if price >= base + size => (base, base + size, 1),
+                None if price <= base - size => (base, base - size, -1),
+                None => break,
+                // От края кирпича, противоположного направлению, до цены разворота - два размера
+                Some((open, close, _)) => {
+                    let (top, bottom) = (open.max(close), open.min(close));
+                    if price >= top + size {
+                        (top, top + size, 1)
+                    } else if price <= bottom - size {
+                        (bottom, bottom - size, -1)
+                    } else {
+                        break;
+                    }
+                }
+            };
+            self.last = Some(next);
+            bricks.push(RenkoBrick {
+                pair: candle.pair.clone(),
+                time_frame: candle.time_frame.clone(),
+                utc_begin: candle.utc_begin,
+                open: next.0,
+                close: next.1,
+                direction: next.2,
+                brick_size: size,
+            });
+        }
+        if bricks.len() == MAX_BRICKS_PER_CANDLE {
+            // Остаток хода свечи дорисуют следующие свечи, от последнего сложенного кирпича
+            eprintln!(
+                "Ренко {} {}: свеча {} сложила больше {} кирпичей размером {}, остаток отложен",
+                candle.pair, candle.time_frame, candle.utc_begin, MAX_BRICKS_PER_CANDLE, size
+            );
+        }
+        bricks
+    }
+}
+
+// Кирпичи по свечам одного ряда
+pub fn renko(candles: &[Kline], size: BrickSize) -> Result<Vec<RenkoBrick>, String> {
+    let mut renko = Renko::new(size)?;
+    Ok(candles.iter().flat_map(|c| renko.push(c)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN};
+
+    fn candle(i: i64, open: f64, high: f64, low: f64, close: f64) -> Kline {
+        testing::candle("1m", i * MIN, close).with_ohlc(open, high, low, close)
+    }
+
+    fn closes(values: &[f64]) -> Vec<Kline> {
+        values.iter().enumerate().map(|(i, &c)| candle(i as i64, c, c, c, c)).collect()
+    }
+
+    // (utc_begin, open, close, direction, brick_size)
+    fn summary(bricks: &[RenkoBrick]) -> Vec<(i64, f64, f64, i8, f64)> {
+        bricks.iter().map(|b| (b.utc_begin, b.open, b.close, b.direction, b.brick_size)).collect()
+    }
+
+    #[test]
+    fn heikin_ashi_bodies() {
+        let candles = vec![
+            candle(0, 10.0, 12.0, 9.0, 11.0),
+            candle(1, 11.0, 15.0, 10.0, 14.0),
+            candle(2, 9.0, 9.5, 8.0, 9.0),
+        ];
+        let ha = heikin_ashi(&candles);
+        let ohlc: Vec<_> = ha.iter().map(|c| (c.open, c.high, c.low, c.close)).collect();
+        assert_eq!(
+            ohlc,
+            vec![
+                (10.5, 12.0, 9.0, 10.5),
+                (10.5, 15.0, 10.0, 12.5),
+                // Открытие - середина прошлого тела, выше максимума свечи: high растягивается до него
+                (11.5, 11.5, 8.0, 8.875),
+            ]
+        );
+        assert_eq!(ha[2].utc_begin, 2 * MIN);
+        assert_eq!(ha[2].volume_bs.buy_base, 1.0);
+    }
+
+    #[test]
+    fn fixed_renko_reverses_after_two_bricks() {
+        let candles = closes(&[10.0, 11.5, 13.2, 12.5, 11.9, 10.9, 9.8]);
+        let bricks = renko(&candles, BrickSize::Fixed(1.0)).unwrap();
+        assert_eq!(
+            summary(&bricks),
+            vec![
+                (MIN, 10.0, 11.0, 1, 1.0),
+                (2 * MIN, 11.0, 12.0, 1, 1.0),
+                (2 * MIN, 12.0, 13.0, 1, 1.0),
+                // 12.5 и 11.9 - меньше двух размеров от вершины 13, разворот только на 11
+                (5 * MIN, 12.0, 11.0, -1, 1.0),
+                (6 * MIN, 11.0, 10.0, -1, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn atr_renko_sizes_bricks_on_each_candle() {
+        let candles = vec![
+            candle(0, 9.0, 10.0, 8.0, 9.0),    // TR 2
+            candle(1, 9.0, 12.0, 9.0, 11.0),   // TR 3, ATR(2) 2.5 - отсчёт от 11
+            candle(2, 11.0, 16.0, 11.0, 16.0), // TR 5, ATR 3.75
+        ];
+        let bricks = renko(&candles, BrickSize::Atr(2)).unwrap();
+        assert_eq!(summary(&bricks), vec![(2 * MIN, 11.0, 14.75, 1, 3.75)]);
+
+        // Свеча прогрева только разогревает ATR
+        let mut warmed = Renko::new(BrickSize::Atr(2)).unwrap();
+        warmed.warm_up(&candles[0]);
+        assert!(warmed.push(&candles[1]).is_empty());
+        assert_eq!(summary(&warmed.push(&candles[2])), summary(&bricks));
+    }
+
+    #[test]
+    fn invalid_brick_sizes_are_rejected() {
+        for brick in [BrickSize::Fixed(0.0), BrickSize::Fixed(-1.0), BrickSize::Fixed(f64::NAN), BrickSize::Atr(0)] {
+            assert!(Renko::new(brick).is_err(), "{:?}", brick);
+            assert!(renko(&closes(&[1.0, 2.0]), brick).is_err());
+        }
+        for value in ["0", "-1", "NaN", "inf", "atr:0", "atr:x", "x"] {
+            assert!(BrickSize::parse(value).is_err(), "{}", value);
+        }
+        assert_eq!(BrickSize::parse("25.5"), Ok(BrickSize::Fixed(25.5)));
+        assert_eq!(BrickSize::parse("atr:14"), Ok(BrickSize::Atr(14)));
+    }
+
+    #[test]
+    fn bricks_per_candle_are_capped() {
+        let mut renko = Renko::new(BrickSize::Fixed(1e-6)).unwrap();
+        assert!(renko.push(&closes(&[10.0])[0]).is_empty());
+        let gap = candle(1, 20.0, 20.0, 20.0, 20.0);
+        let first = renko.push(&gap);
+        assert_eq!(first.len(), MAX_BRICKS_PER_CANDLE);
+        // Следующая свеча продолжает с последнего кирпича
+        let second = renko.push(&gap);
+        assert_eq!(second.len(), MAX_BRICKS_PER_CANDLE);
+        assert_eq!(second[0].open, first[MAX_BRICKS_PER_CANDLE - 1].close);
+    }
+}
@@ -0,0 +1,707 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use arrow_array::{ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use chrono::{TimeZone, Utc};
+use flate2::write::GzEncoder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, RecentTrade, VolumeProfile};
+use crate::derived::{BrickSize, HeikinAshi, Renko, RenkoBrick};
+use crate::storage::Storage;
+use crate::volume_profile::PriceBins;
+
+// Сколько строк копим перед записью в файл
+const BATCH_ROWS: usize = 8192;
+// Ряд читается из хранилища окнами, а не целиком: свечи - по стольку корзин, трейды - по часу
+const CANDLE_WINDOW_BUCKETS: i64 = 10_000;
+const TRADE_WINDOW_MS: i64 = 60 * 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,     // CSV со сжатием gzip (.csv.gz)
+    Parquet, // Parquet со сжатием snappy
+}
+
+impl ExportFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "csv" => Ok(ExportFormat::Csv),
+            "parquet" => Ok(ExportFormat::Parquet),
+            other => Err(format!("Неизвестный формат экспорта: {}", other)),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv.gz",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportSummary {
+    pub files: usize,
+    pub rows: usize,
+}
+
+// Строка, которую умеем выгружать. Схема колонок фиксирована и одинакова для CSV и Parquet.
+pub trait ExportRow: Sized {
+    fn schema() -> SchemaRef;
+    fn csv_record(&self) -> Vec<String>;
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, Box<dyn Error>>;
+    fn pair(&self) -> &str;
+    fn ts(&self) -> i64;
+}
+
+impl ExportRow for Kline {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("pair", DataType::Utf8, false),
+            Field::new("time_frame", DataType::Utf8, false),
+            Field::new("utc_begin", DataType::Int64, false),
+            Field::new("open", DataType::Float64, false),
+            Field::new("high", DataType::Float64, false),
+            Field::new("low", DataType::Float64, false),
+            Field::new("close", DataType::Float64, false),
+            Field::new("buy_base", DataType::Float64, false),
+            Field::new("sell_base", DataType::Float64, false),
+            Field::new("buy_quote", DataType::Float64, false),
+            Field::new("sell_quote", DataType::Float64, false),
+            Field::new("close_time", DataType::Int64, false),
+            Field::new("trade_count", DataType::Int64, false),
+            Field::new("vwap", DataType::Float64, false),
+            Field::new("source", DataType::Utf8, false),
+            Field::new("is_final", DataType::Boolean, false),
+            Field::new("revision", DataType::Int64, false),
+        ]))
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![
+            self.pair.clone(),
+            self.time_frame.clone(),
+            self.utc_begin.to_string(),
+            self.open.to_string(),
+            self.high.to_string(),
+            self.low.to_string(),
+            self.close.to_string(),
+            self.volume_bs.buy_base.to_string(),
+            self.volume_bs.sell_base.to_string(),
+            self.volume_bs.buy_quote.to_string(),
+            self.volume_bs.sell_quote.to_string(),
+            self.close_time.to_string(),
+            self.trade_count.to_string(),
+            self.vwap.to_string(),
+            self.source.as_str().to_string(),
+            self.is_final.to_string(),
+            self.revision.to_string(),
+        ]
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, Box<dyn Error>> {
+        let floats = |f: fn(&Kline) -> f64| -> ArrayRef { Arc::new(Float64Array::from_iter_values(rows.iter().map(f))) };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|k| k.pair.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|k| k.time_frame.as_str()))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|k| k.utc_begin))),
+            floats(|k| k.open),
+            floats(|k| k.high),
+            floats(|k| k.low),
+            floats(|k| k.close),
+            floats(|k| k.volume_bs.buy_base),
+            floats(|k| k.volume_bs.sell_base),
+            floats(|k| k.volume_bs.buy_quote),
+            floats(|k| k.volume_bs.sell_quote),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|k| k.close_time))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|k| k.trade_count))),
+            floats(|k| k.vwap),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|k| k.source.as_str()))),
+            Arc::new(BooleanArray::from(rows.iter().map(|k| k.is_final).collect::<Vec<bool>>())),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|k| k.revision))),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn ts(&self) -> i64 {
+        self.utc_begin
+    }
+}
+
+impl ExportRow for RecentTrade {
+    // Цены и объёмы в `trades` хранятся строками, в выгрузке - числа (null, если не парсится)
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("tid", DataType::Utf8, false),
+            Field::new("pair", DataType::Utf8, false),
+            Field::new("time_stamp", DataType::Int64, false),
+            Field::new("create_time", DataType::Int64, false),
+            Field::new("side", DataType::Utf8, false),
+            Field::new("price", DataType::Float64, true),
+            Field::new("quantity", DataType::Float64, true),
+            Field::new("amount", DataType::Float64, true),
+        ]))
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![
+            self.tid.clone(),
+            self.pair.clone(),
+            self.timestamp.to_string(),
+            self.create_time.to_string(),
+            self.side.clone(),
+            self.price.clone(),
+            self.quantity.clone(),
+            self.amount.clone(),
+        ]
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, Box<dyn Error>> {
+        let floats = |f: fn(&RecentTrade) -> &str| -> ArrayRef {
+            Arc::new(Float64Array::from_iter(rows.iter().map(|t| f(t).parse::<f64>().ok())))
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|t| t.tid.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|t| t.pair.as_str()))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|t| t.timestamp))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|t| t.create_time))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|t| t.side.as_str()))),
+            floats(|t| &t.price),
+            floats(|t| &t.quantity),
+            floats(|t| &t.amount),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn ts(&self) -> i64 {
+        self.timestamp
+    }
+}
+
+impl ExportRow for RenkoBrick {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("pair", DataType::Utf8, false),
+            Field::new("time_frame", DataType::Utf8, false),
+            Field::new("utc_begin", DataType::Int64, false),
+            Field::new("open", DataType::Float64, false),
+            Field::new("close", DataType::Float64, false),
+            Field::new("direction", DataType::Int64, false),
+            Field::new("brick_size", DataType::Float64, false),
+        ]))
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![
+            self.pair.clone(),
+            self.time_frame.clone(),
+            self.utc_begin.to_string(),
+            self.open.to_string(),
+            self.close.to_string(),
+            self.direction.to_string(),
+            self.brick_size.to_string(),
+        ]
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, Box<dyn Error>> {
+        let floats = |f: fn(&RenkoBrick) -> f64| -> ArrayRef {
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(f)))
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|b| b.pair.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|b| b.time_frame.as_str()))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|b| b.utc_begin))),
+            floats(|b| b.open),
+            floats(|b| b.close),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|b| b.direction as i64))),
+            floats(|b| b.brick_size),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn ts(&self) -> i64 {
+        self.utc_begin
+    }
+}
+
+// Профиль объёма в экспорте - строка на ценовой уровень, как в таблице volume_profiles
+pub struct ProfileLevelRow {
+    pub pair: String,
+    pub period: String,
+    pub utc_begin: i64,
+    pub utc_end: i64,
+    pub bins: String,
+    pub bin_width: f64,
+    pub price_low: f64,
+    pub buy_volume: f64,
+    pub sell_volume: f64,
+    pub trade_count: i64,
+    pub poc: f64,
+    pub value_area_high: f64,
+    pub value_area_low: f64,
+}
+
+impl ProfileLevelRow {
+    pub fn from_profile(profile: &VolumeProfile) -> Vec<Self> {
+        profile
+            .levels
+            .iter()
+            .map(|level| ProfileLevelRow {
+                pair: profile.pair.clone(),
+                period: profile.period.clone(),
+                utc_begin: profile.utc_begin,
+                utc_end: profile.utc_end,
+                bins: profile.bins.clone(),
+                bin_width: profile.bin_width,
+                price_low: level.price_low,
+                buy_volume: level.buy_volume,
+                sell_volume: level.sell_volume,
+                trade_count: level.trade_count,
+                poc: profile.poc,
+                value_area_high: profile.value_area_high,
+                value_area_low: profile.value_area_low,
+            })
+            .collect()
+    }
+}
+
+impl ExportRow for ProfileLevelRow {
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("pair", DataType::Utf8, false),
+            Field::new("period", DataType::Utf8, false),
+            Field::new("utc_begin", DataType::Int64, false),
+            Field::new("utc_end", DataType::Int64, false),
+            Field::new("bins", DataType::Utf8, false),
+            Field::new("bin_width", DataType::Float64, false),
+            Field::new("price_low", DataType::Float64, false),
+            Field::new("buy_volume", DataType::Float64, false),
+            Field::new("sell_volume", DataType::Float64, false),
+            Field::new("trade_count", DataType::Int64, false),
+            Field::new("poc", DataType::Float64, false),
+            Field::new("value_area_high", DataType::Float64, false),
+            Field::new("value_area_low", DataType::Float64, false),
+        ]))
+    }
+
+    fn csv_record(&self) -> Vec<String> {
+        vec![
+            self.pair.clone(),
+            self.period.clone(),
+            self.utc_begin.to_string(),
+            self.utc_end.to_string(),
+            self.bins.clone(),
+            self.bin_width.to_string(),
+            self.price_low.to_string(),
+            self.buy_volume.to_string(),
+            self.sell_volume.to_string(),
+            self.trade_count.to_string(),
+            self.poc.to_string(),
+            self.value_area_high.to_string(),
+            self.value_area_low.to_string(),
+        ]
+    }
+
+    fn to_batch(rows: &[Self]) -> Result<RecordBatch, Box<dyn Error>> {
+        let floats = |f: fn(&ProfileLevelRow) -> f64| -> ArrayRef {
+            Arc::new(Float64Array::from_iter_values(rows.iter().map(f)))
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.pair.as_str()))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.period.as_str()))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.utc_begin))),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.utc_end))),
+            Arc::new(StringArray::from_iter_values(rows.iter().map(|r| r.bins.as_str()))),
+            floats(|r| r.bin_width),
+            floats(|r| r.price_low),
+            floats(|r| r.buy_volume),
+            floats(|r| r.sell_volume),
+            Arc::new(Int64Array::from_iter_values(rows.iter().map(|r| r.trade_count))),
+            floats(|r| r.poc),
+            floats(|r| r.value_area_high),
+            floats(|r| r.value_area_low),
+        ];
+        Ok(RecordBatch::try_new(Self::schema(), columns)?)
+    }
+
+    fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    fn ts(&self) -> i64 {
+        self.utc_begin
+    }
+}
+
+enum FileWriter {
+    Csv(Box<csv::Writer<GzEncoder<File>>>),
+    Parquet(Box<ArrowWriter<File>>),
+}
+
+impl FileWriter {
+    fn create<R: ExportRow>(path: &Path, format: ExportFormat) -> Result<Self, Box<dyn Error>> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = File::create(path)?;
+        match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(GzEncoder::new(file, flate2::Compression::default()));
+                let header: Vec<String> = R::schema().fields().iter().map(|f| f.name().clone()).collect();
+                writer.write_record(&header)?;
+                Ok(FileWriter::Csv(Box::new(writer)))
+            }
+            ExportFormat::Parquet => {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                Ok(FileWriter::Parquet(Box::new(ArrowWriter::try_new(file, R::schema(), Some(props))?)))
+            }
+        }
+    }
+
+    fn write<R: ExportRow>(&mut self, rows: &[R]) -> Result<(), Box<dyn Error>> {
+        match self {
+            FileWriter::Csv(writer) => {
+                for row in rows {
+                    writer.write_record(row.csv_record())?;
+                }
+            }
+            FileWriter::Parquet(writer) => writer.write(&R::to_batch(rows)?)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            FileWriter::Csv(writer) => {
+                writer.into_inner().map_err(|e| e.to_string())?.finish()?;
+            }
+            FileWriter::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Раскладывает строки по файлам <out>/<table>/pair=<PAIR>/date=<YYYY-MM-DD>/<file_stem>.<ext>.
+// Строки должны приходить упорядоченными по времени внутри пары. Файл партиции создаётся заново,
+// поэтому вернуться к уже закрытой партиции нельзя - это ошибка, а не перезапись записанного.
+// Файл прошлого запуска перезаписывается только с `overwrite`. Партиция пишется во временный файл
+// и встаёт на место, когда закрыта, поэтому прерванный экспорт не оставляет обрезанных файлов.
+struct PartitionedWriter {
+    root: PathBuf,
+    file_stem: String,
+    format: ExportFormat,
+    overwrite: bool,
+    current: Option<(String, String, FileWriter, PathBuf)>, // (pair, date, writer, итоговый путь)
+    written: HashSet<(String, String)>,            // (pair, date) всех открытых за запуск партиций
+    summary: ExportSummary,
+}
+
+impl PartitionedWriter {
+    fn new(out_dir: &Path, table: &str, file_stem: &str, format: ExportFormat, overwrite: bool) -> Self {
+        PartitionedWriter {
+            root: out_dir.join(table),
+            file_stem: file_stem.to_string(),
+            format,
+            overwrite,
+            current: None,
+            written: HashSet::new(),
+            summary: ExportSummary::default(),
+        }
+    }
+
+    fn write<R: ExportRow>(&mut self, rows: &[R]) -> Result<(), Box<dyn Error>> {
+        let mut start = 0;
+        while start < rows.len() {
+            let pair = rows[start].pair().to_string();
+            let date = partition_date(rows[start].ts());
+            let end = rows[start..]
+                .iter()
+                .position(|r| r.pair() != pair || partition_date(r.ts()) != date)
+                .map(|p| start + p)
+                .unwrap_or(rows.len());
+
+            let same = matches!(&self.current, Some((p, d, _, _)) if *p == pair && *d == date);
+            if !same {
+                self.close_current()?;
+                if !self.written.insert((pair.clone(), date.clone())) {
+                    return Err(format!(
+                        "Партиция pair={} date={} уже записана: строки пришли не по порядку",
+                        pair, date
+                    )
+                    .into());
+                }
+                let path = self
+                    .root
+                    .join(format!("pair={}", pair))
+                    .join(format!("date={}", date))
+                    .join(format!("{}.{}", self.file_stem, self.format.extension()));
+                if !self.overwrite && path.exists() {
+                    return Err(format!("Файл {} уже есть: задайте --overwrite, чтобы перезаписать", path.display()).into());
+                }
+                let writer = FileWriter::create::<R>(&partial_path(&path), self.format)?;
+                self.summary.files += 1;
+                self.current = Some((pair, date, writer, path));
+            }
+
+            if let Some((_, _, writer, _)) = self.current.as_mut() {
+                if let Err(e) = writer.write(&rows[start..end]) {
+                    if let Some((_, _, writer, path)) = self.current.take() {
+                        drop(writer);
+                        let _ = fs::remove_file(partial_path(&path));
+                    }
+                    return Err(e);
+                }
+            }
+            self.summary.rows += end - start;
+            start = end;
+        }
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((_, _, writer, path)) = self.current.take() {
+            let partial = partial_path(&path);
+            // Незакрытый временный файл не оставляем: следующий запуск начнёт партицию заново
+            if let Err(e) = writer.finish().and_then(|()| Ok(fs::rename(&partial, &path)?)) {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ExportSummary, Box<dyn Error>> {
+        self.close_current()?;
+        Ok(self.summary)
+    }
+}
+
+// Временный файл партиции рядом с итоговым
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+fn partition_date(ts: i64) -> String {
+    Utc.timestamp_millis_opt(ts)
+        .single()
+        .map(|t| t.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Свечи ряда за [start_ts, end_ts] окнами по CANDLE_WINDOW_BUCKETS корзин
+struct CandlePages<'a> {
+    store: &'a dyn Storage,
+    pair: &'a str,
+    time_frame: &'a str,
+    window_ms: i64,
+    next: i64,
+    end: i64,
+}
+
+impl<'a> CandlePages<'a> {
+    fn new(store: &'a dyn Storage, pair: &'a str, time_frame: &'a str, start_ts: i64, end_ts: i64) -> Result<Self, String> {
+        let bucket_ms = aggregate::time_frame_ms(time_frame).ok_or_else(|| format!("Неизвестный таймфрейм: {}", time_frame))?;
+        Ok(CandlePages {
+            store,
+            pair,
+            time_frame,
+            window_ms: bucket_ms * CANDLE_WINDOW_BUCKETS,
+            next: start_ts,
+            end: end_ts,
+        })
+    }
+
+    async fn next(&mut self) -> Result<Option<Vec<Kline>>, sqlx::Error> {
+        if self.next > self.end {
+            return Ok(None);
+        }
+        let to = self.next.saturating_add(self.window_ms - 1).min(self.end);
+        let candles = self
+            .store
+            .candles_range(self.pair, self.time_frame, self.next, to, &CandleSource::DEFAULT_PREFERENCE)
+            .await?;
+        self.next = to.saturating_add(1);
+        Ok(Some(candles))
+    }
+}
+
+// Трейды пары за [start_ts, end_ts] окнами по TRADE_WINDOW_MS
+struct TradePages<'a> {
+    store: &'a dyn Storage,
+    pair: &'a str,
+    next: i64,
+    end: i64,
+}
+
+impl TradePages<'_> {
+    async fn next(&mut self) -> Result<Option<Vec<RecentTrade>>, sqlx::Error> {
+        if self.next > self.end {
+            return Ok(None);
+        }
+        let to = self.next.saturating_add(TRADE_WINDOW_MS - 1).min(self.end);
+        let trades = self.store.trades_range(self.pair, self.next, to).await?;
+        self.next = to.saturating_add(1);
+        Ok(Some(trades))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn export_candles(
+    store: &dyn Storage,
+    pairs: &[String],
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+    format: ExportFormat,
+    out_dir: &Path,
+    overwrite: bool,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let mut writer = PartitionedWriter::new(out_dir, "candles", &format!("candles_{}", time_frame), format, overwrite);
+    for pair in pairs {
+        let mut pages = CandlePages::new(store, pair, time_frame, start_ts, end_ts)?;
+        while let Some(candles) = pages.next().await? {
+            for batch in candles.chunks(BATCH_ROWS) {
+                writer.write(batch)?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+pub async fn export_trades(
+    store: &dyn Storage,
+    pairs: &[String],
+    start_ts: i64,
+    end_ts: i64,
+    format: ExportFormat,
+    out_dir: &Path,
+    overwrite: bool,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let mut writer = PartitionedWriter::new(out_dir, "trades", "trades", format, overwrite);
+    for pair in pairs {
+        let mut pages = TradePages {
+            store,
+            pair,
+            next: start_ts,
+            end: end_ts,
+        };
+        while let Some(trades) = pages.next().await? {
+            for batch in trades.chunks(BATCH_ROWS) {
+                writer.write(batch)?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+// Свечи Хейкен-Аши по сохранённым свечам; ряд считается с начала запрошенного диапазона
+#[allow(clippy::too_many_arguments)]
+pub async fn export_heikin_ashi(
+    store: &dyn Storage,
+    pairs: &[String],
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+    format: ExportFormat,
+    out_dir: &Path,
+    overwrite: bool,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let mut writer = PartitionedWriter::new(out_dir, "heikin_ashi", &format!("heikin_ashi_{}", time_frame), format, overwrite);
+    for pair in pairs {
+        let mut ha = HeikinAshi::new();
+        let mut pages = CandlePages::new(store, pair, time_frame, start_ts, end_ts)?;
+        while let Some(candles) = pages.next().await? {
+            let rows: Vec<_> = candles.iter().map(|c| ha.next(c)).collect();
+            for batch in rows.chunks(BATCH_ROWS) {
+                writer.write(batch)?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+// Кирпичи Ренко по сохранённым свечам. Размер по ATR скользящий: ATR(period) разогревается на
+// `period` корзинах перед диапазоном и дальше считается на каждой свече только по прошлым данным.
+#[allow(clippy::too_many_arguments)]
+pub async fn export_renko(
+    store: &dyn Storage,
+    pairs: &[String],
+    time_frame: &str,
+    brick: BrickSize,
+    start_ts: i64,
+    end_ts: i64,
+    format: ExportFormat,
+    out_dir: &Path,
+    overwrite: bool,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let bucket_ms = aggregate::time_frame_ms(time_frame).ok_or_else(|| format!("Неизвестный таймфрейм: {}", time_frame))?;
+    let mut writer = PartitionedWriter::new(out_dir, "renko", &format!("renko_{}", time_frame), format, overwrite);
+    for pair in pairs {
+        let mut renko = Renko::new(brick)?;
+        if let BrickSize::Atr(period) = brick {
+            let warm_up_ts = start_ts.saturating_sub(bucket_ms.saturating_mul(period as i64));
+            let candles = store
+                .candles_range(pair, time_frame, warm_up_ts, start_ts - 1, &CandleSource::DEFAULT_PREFERENCE)
+                .await?;
+            candles.iter().for_each(|c| renko.warm_up(c));
+        }
+
+        let mut pages = CandlePages::new(store, pair, time_frame, start_ts, end_ts)?;
+        while let Some(candles) = pages.next().await? {
+            let rows: Vec<RenkoBrick> = candles.iter().flat_map(|c| renko.push(c)).collect();
+            for batch in rows.chunks(BATCH_ROWS) {
+                writer.write(batch)?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+// Сохранённые профили объёма периода; раскладываются по дате начала периода рядом со свечами
+#[allow(clippy::too_many_arguments)]
+pub async fn export_volume_profiles(
+    store: &dyn Storage,
+    pairs: &[String],
+    period: &str,
+    bins: PriceBins,
+    start_ts: i64,
+    end_ts: i64,
+    format: ExportFormat,
+    out_dir: &Path,
+    overwrite: bool,
+) -> Result<ExportSummary, Box<dyn Error>> {
+    let mut writer =
+        PartitionedWriter::new(out_dir, "volume_profiles", &format!("volume_profiles_{}", period), format, overwrite);
+    for pair in pairs {
+        let profiles = store.volume_profiles_range(pair, period, &bins.name(), start_ts, end_ts).await?;
+        let rows: Vec<ProfileLevelRow> = profiles.iter().flat_map(ProfileLevelRow::from_profile).collect();
+        for batch in rows.chunks(BATCH_ROWS) {
+            writer.write(batch)?;
+        }
+    }
+    writer.finish()
+}
@@ -0,0 +1,517 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float32Type, Float64Type, Int32Type, Int64Type};
+use arrow_array::{Array, RecordBatch};
+use arrow_schema::DataType;
+use flate2::read::GzDecoder;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::{json, Value};
+use crate::aggregate;
+use crate::api::RestCandle;
+use crate::data_structs::{CandleSource, Kline, RecentTrade};
+use crate::storage::Storage;
+use crate::websocket::trade_from_json;
+
+// Сколько отклонённых строк печатать с причиной
+const MAX_REPORTED_REJECTS: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportTable {
+    Candles,
+    Trades,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl ImportFormat {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "csv" => Ok(ImportFormat::Csv),
+            "jsonl" => Ok(ImportFormat::Jsonl),
+            "parquet" => Ok(ImportFormat::Parquet),
+            other => Err(format!("Неизвестный формат импорта: {}", other)),
+        }
+    }
+
+    // По расширению файла, в том числе сжатого .csv.gz/.jsonl.gz
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let name = path.to_string_lossy().to_lowercase();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        if name.ends_with(".csv") {
+            Ok(ImportFormat::Csv)
+        } else if name.ends_with(".jsonl") || name.ends_with(".ndjson") {
+            Ok(ImportFormat::Jsonl)
+        } else if name.ends_with(".parquet") {
+            Ok(ImportFormat::Parquet)
+        } else {
+            Err(format!("Не удалось определить формат файла {}, задайте --format", path.display()))
+        }
+    }
+}
+
+// Что делать со строками, которые уже есть в хранилище
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    Insert, // оставить имеющиеся как есть
+    Upsert, // перезаписать значениями из файла
+}
+
+impl ImportMode {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "insert" => Ok(ImportMode::Insert),
+            "upsert" => Ok(ImportMode::Upsert),
+            other => Err(format!("Неизвестный режим импорта: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub table: ImportTable,
+    pub format: ImportFormat,
+    pub mode: ImportMode,
+    pub mapping: HashMap<String, String>, // поле Poloniex -> колонка файла
+    pub pair: Option<String>,             // если в файле нет колонки пары
+    pub time_frame: Option<String>,       // если в файле нет колонки таймфрейма
+    pub batch_size: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    pub imported: u64, // записано в хранилище
+    pub skipped: u64,  // дубли внутри файла, а при вставке - и уже имеющиеся свечи и трейды
+    pub rejected: u64, // не прошли разбор или проверку
+}
+
+// Поля строятся в именах API Poloniex, чтобы дальше идти тем же путём, что и живые данные.
+// Для каждого поля - принятые по умолчанию имена колонок (имена API, колонок БД и экспорта).
+const CANDLE_FIELDS: [(&str, &[&str]); 14] = [
+    ("symbol", &["symbol", "pair"]),
+    ("interval", &["interval", "time_frame"]),
+    ("low", &["low"]),
+    ("high", &["high"]),
+    ("open", &["open"]),
+    ("close", &["close"]),
+    ("amount", &["amount"]),
+    ("quantity", &["quantity"]),
+    ("buyTakerAmount", &["buyTakerAmount", "buy_taker_amount"]),
+    ("buyTakerQuantity", &["buyTakerQuantity", "buy_taker_quantity"]),
+    ("startTime", &["startTime", "utc_begin"]),
+    // Необязательные: без них считаются по объёму и таймфрейму
+    ("tradeCount", &["tradeCount", "trade_count"]),
+    ("weightedAverage", &["weightedAverage", "vwap"]),
+    ("closeTime", &["closeTime", "close_time"]),
+];
+
+// Разложенный объём из выгрузки (`candles` / export) вместо полей REST
+const CANDLE_VBS_FIELDS: [&str; 4] = ["buy_base", "sell_base", "buy_quote", "sell_quote"];
+
+const TRADE_FIELDS: [(&str, &[&str]); 8] = [
+    ("id", &["id", "tid"]),
+    ("symbol", &["symbol", "pair"]),
+    ("price", &["price"]),
+    ("amount", &["amount"]),
+    ("quantity", &["quantity"]),
+    ("takerSide", &["takerSide", "side"]),
+    ("createTime", &["createTime", "create_time"]),
+    ("ts", &["ts", "time_stamp", "timestamp"]),
+];
+
+type RawRow = HashMap<String, String>;
+type RowIter = Box<dyn Iterator<Item = Result<RawRow, String>>>;
+
+// --map ts=time,id=trade_id
+pub fn parse_mapping(spec: &str) -> Result<HashMap<String, String>, String> {
+    spec.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|item| {
+            item.split_once('=')
+                .map(|(field, column)| (field.trim().to_string(), column.trim().to_string()))
+                .ok_or_else(|| format!("Неверное сопоставление колонок: {}", item))
+        })
+        .collect()
+}
+
+pub async fn import_file(store: &dyn Storage, path: &Path, options: &ImportOptions) -> Result<ImportReport, Box<dyn Error>> {
+    let rows = open_rows(path, options)?;
+    let mut report = ImportReport::default();
+    let mut candles: Vec<Kline> = Vec::with_capacity(options.batch_size);
+    let mut trades: Vec<RecentTrade> = Vec::with_capacity(options.batch_size);
+    // Строки текущей пачки по ключу. Повтор в файле пропускается: при вставке остаётся первая строка,
+    // при перезаписи - последняя. Повторы из разных пачек разрешает хранилище.
+    let mut candle_rows: HashMap<(String, String, i64), usize> = HashMap::new();
+    let mut trade_rows: HashMap<String, usize> = HashMap::new();
+    let upsert = options.mode == ImportMode::Upsert;
+
+    for (index, row) in rows.enumerate() {
+        let line = index + 1;
+        let parsed = row.and_then(|raw| match options.table {
+            ImportTable::Candles => build_candle(&raw, options).map(|c| {
+                let key = (c.pair.clone(), c.time_frame.clone(), c.utc_begin);
+                match candle_rows.get(&key) {
+                    Some(&i) => {
+                        if upsert {
+                            candles[i] = c;
+                        }
+                        report.skipped += 1;
+                    }
+                    None => {
+                        candle_rows.insert(key, candles.len());
+                        candles.push(c);
+                    }
+                }
+            }),
+            ImportTable::Trades => build_trade(&raw, options).map(|t| match trade_rows.get(&t.tid) {
+                Some(&i) => {
+                    if upsert {
+                        trades[i] = t;
+                    }
+                    report.skipped += 1;
+                }
+                None => {
+                    trade_rows.insert(t.tid.clone(), trades.len());
+                    trades.push(t);
+                }
+            }),
+        });
+
+        if let Err(reason) = parsed {
+            report.rejected += 1;
+            if report.rejected as usize <= MAX_REPORTED_REJECTS {
+                eprintln!("Строка {} отклонена: {}", line, reason);
+            }
+        }
+
+        if candles.len() >= options.batch_size {
+            flush_candles(store, options.mode, &mut candles, &mut report).await?;
+            candle_rows.clear();
+        }
+        if trades.len() >= options.batch_size {
+            flush_trades(store, options.mode, &mut trades, &mut report).await?;
+            trade_rows.clear();
+        }
+    }
+
+    flush_candles(store, options.mode, &mut candles, &mut report).await?;
+    flush_trades(store, options.mode, &mut trades, &mut report).await?;
+    Ok(report)
+}
+
+async fn flush_candles(
+    store: &dyn Storage,
+    mode: ImportMode,
+    candles: &mut Vec<Kline>,
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    if candles.is_empty() {
+        return Ok(());
+    }
+    let count = candles.len() as u64;
+    let written = match mode {
+        ImportMode::Insert => store.insert_candles(std::mem::take(candles)).await?,
+        ImportMode::Upsert => {
+            store.upsert_candles(std::mem::take(candles)).await?;
+            count
+        }
+    };
+    report.imported += written;
+    report.skipped += count - written;
+    Ok(())
+}
+
+async fn flush_trades(
+    store: &dyn Storage,
+    mode: ImportMode,
+    trades: &mut Vec<RecentTrade>,
+    report: &mut ImportReport,
+) -> Result<(), sqlx::Error> {
+    if trades.is_empty() {
+        return Ok(());
+    }
+    let written = match mode {
+        ImportMode::Insert => store.insert_trades(trades).await?,
+        ImportMode::Upsert => store.upsert_trades(trades).await?,
+    };
+    report.imported += written;
+    report.skipped += trades.len() as u64 - written;
+    trades.clear();
+    Ok(())
+}
+
+// Значение поля с учётом --map и имён колонок по умолчанию
+fn field<'a>(raw: &'a RawRow, options: &ImportOptions, name: &str, aliases: &[&str]) -> Option<&'a str> {
+    if let Some(column) = options.mapping.get(name) {
+        return raw.get(column).map(String::as_str);
+    }
+    aliases.iter().find_map(|a| raw.get(*a)).map(String::as_str)
+}
+
+fn number(raw: &RawRow, options: &ImportOptions, name: &str, aliases: &[&str]) -> Result<f64, String> {
+    let value = field(raw, options, name, aliases).ok_or_else(|| format!("нет поля {}", name))?;
+    let parsed = value
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| format!("поле {} не число: {}", name, value))?;
+    if !parsed.is_finite() {
+        return Err(format!("поле {} не конечно: {}", name, value));
+    }
+    Ok(parsed)
+}
+
+fn integer(raw: &RawRow, options: &ImportOptions, name: &str, aliases: &[&str]) -> Result<i64, String> {
+    let value = field(raw, options, name, aliases).ok_or_else(|| format!("нет поля {}", name))?;
+    value
+        .trim()
+        .parse::<i64>()
+        .map_err(|_| format!("поле {} не целое: {}", name, value))
+}
+
+fn aliases(fields: &[(&'static str, &'static [&'static str])], name: &str) -> &'static [&'static str] {
+    fields.iter().find(|(f, _)| *f == name).map(|(_, a)| *a).unwrap_or(&[])
+}
+
+fn build_candle(raw: &RawRow, options: &ImportOptions) -> Result<Kline, String> {
+    let num = |name: &str| number(raw, options, name, aliases(&CANDLE_FIELDS, name));
+
+    let symbol = field(raw, options, "symbol", aliases(&CANDLE_FIELDS, "symbol"))
+        .map(str::to_string)
+        .or_else(|| options.pair.clone())
+        .ok_or("нет пары (колонка или --pair)")?;
+    let interval = field(raw, options, "interval", aliases(&CANDLE_FIELDS, "interval"))
+        .map(str::to_string)
+        .or_else(|| options.time_frame.clone())
+        .ok_or("нет таймфрейма (колонка или --time-frame)")?;
+    if aggregate::time_frame_ms(&interval).is_none() {
+        return Err(format!("неизвестный таймфрейм {}", interval));
+    }
+
+    // Либо поля REST, либо уже разложенный объём из выгрузки
+    let has_vbs = CANDLE_VBS_FIELDS.iter().all(|f| raw.contains_key(*f)) && !raw.contains_key("quantity");
+    let (amount, quantity, buy_taker_amount, buy_taker_quantity) = if has_vbs {
+        let vbs = |name: &str| number(raw, options, name, &[name]);
+        let (buy_base, sell_base) = (vbs("buy_base")?, vbs("sell_base")?);
+        let (buy_quote, sell_quote) = (vbs("buy_quote")?, vbs("sell_quote")?);
+        (buy_quote + sell_quote, buy_base + sell_base, buy_quote, buy_base)
+    } else {
+        (num("amount")?, num("quantity")?, num("buyTakerAmount")?, num("buyTakerQuantity")?)
+    };
+
+    let start_time = integer(raw, options, "startTime", aliases(&CANDLE_FIELDS, "startTime"))?;
+    let present = |name: &str| field(raw, options, name, aliases(&CANDLE_FIELDS, name)).is_some();
+    let trade_count = if present("tradeCount") {
+        integer(raw, options, "tradeCount", aliases(&CANDLE_FIELDS, "tradeCount"))?
+    } else {
+        0
+    };
+    let weighted_average = if present("weightedAverage") {
+        num("weightedAverage")?
+    } else if quantity > 0.0 {
+        amount / quantity
+    } else {
+        0.0
+    };
+    let close_time = if present("closeTime") {
+        integer(raw, options, "closeTime", aliases(&CANDLE_FIELDS, "closeTime"))?
+    } else {
+        aggregate::close_time(start_time, &interval)
+    };
+
+    let candle = RestCandle {
+        low: num("low")?,
+        high: num("high")?,
+        open: num("open")?,
+        close: num("close")?,
+        amount,
+        quantity,
+        buy_taker_amount,
+        buy_taker_quantity,
+        trade_count,
+        weighted_average,
+        start_time,
+        close_time,
+    };
+    validate_candle(&candle)?;
+    let mut kline = candle.into_kline(&symbol, &interval);
+    kline.source = CandleSource::Imported;
+    Ok(kline)
+}
+
+fn validate_candle(c: &RestCandle) -> Result<(), String> {
+    if c.start_time <= 0 {
+        return Err(format!("неверное время начала {}", c.start_time));
+    }
+    if c.high < c.low {
+        return Err(format!("high {} < low {}", c.high, c.low));
+    }
+    if c.open < c.low || c.open > c.high || c.close < c.low || c.close > c.high {
+        return Err(format!("open/close вне [low, high]: o={} c={} l={} h={}", c.open, c.close, c.low, c.high));
+    }
+    if c.amount < 0.0 || c.quantity < 0.0 || c.buy_taker_amount < 0.0 || c.buy_taker_quantity < 0.0 {
+        return Err("отрицательный объём".to_string());
+    }
+    if c.buy_taker_quantity > c.quantity || c.buy_taker_amount > c.amount {
+        return Err("объём покупок тейкеров больше общего".to_string());
+    }
+    Ok(())
+}
+
+fn build_trade(raw: &RawRow, options: &ImportOptions) -> Result<RecentTrade, String> {
+    let get = |name: &str| field(raw, options, name, aliases(&TRADE_FIELDS, name));
+
+    let symbol = get("symbol")
+        .map(str::to_string)
+        .or_else(|| options.pair.clone())
+        .ok_or("нет пары (колонка или --pair)")?;
+    let price = number(raw, options, "price", aliases(&TRADE_FIELDS, "price"))?;
+    let quantity = number(raw, options, "quantity", aliases(&TRADE_FIELDS, "quantity"))?;
+    let amount = number(raw, options, "amount", aliases(&TRADE_FIELDS, "amount"))?;
+    let ts = integer(raw, options, "ts", aliases(&TRADE_FIELDS, "ts"))?;
+    let create_time = match get("createTime") {
+        Some(_) => integer(raw, options, "createTime", aliases(&TRADE_FIELDS, "createTime"))?,
+        None => ts,
+    };
+    let side = get("takerSide").ok_or("нет поля takerSide")?.to_lowercase();
+
+    if price <= 0.0 {
+        return Err(format!("неположительная цена {}", price));
+    }
+    if quantity < 0.0 || amount < 0.0 {
+        return Err("отрицательный объём".to_string());
+    }
+    if side != "buy" && side != "sell" {
+        return Err(format!("неизвестная сторона {}", side));
+    }
+    if ts <= 0 {
+        return Err(format!("неверное время {}", ts));
+    }
+
+    // Собираем сообщение в формате канала `trades` и разбираем его тем же кодом, что и WS
+    let message = json!({
+        "id": get("id").ok_or("нет поля id")?,
+        "symbol": symbol,
+        "price": get("price"),
+        "amount": get("amount"),
+        "quantity": get("quantity"),
+        "takerSide": side,
+        "createTime": create_time,
+        "ts": ts,
+    });
+    trade_from_json(&message).ok_or_else(|| "не удалось собрать трейд".to_string())
+}
+
+fn open_reader(path: &Path) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|e| e == "gz") {
+        Ok(Box::new(GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+// Колонки файла, из которых строятся поля: заданные через --map и имена по умолчанию для остальных полей
+fn used_columns(options: &ImportOptions) -> HashSet<String> {
+    let fields: &[(&str, &[&str])] = match options.table {
+        ImportTable::Candles => &CANDLE_FIELDS,
+        ImportTable::Trades => &TRADE_FIELDS,
+    };
+    let mut columns: HashSet<String> = options.mapping.values().cloned().collect();
+    for (name, aliases) in fields {
+        if !options.mapping.contains_key(*name) {
+            columns.extend(aliases.iter().map(|a| a.to_string()));
+        }
+    }
+    if options.table == ImportTable::Candles {
+        columns.extend(CANDLE_VBS_FIELDS.iter().map(|f| f.to_string()));
+    }
+    columns
+}
+
+fn open_rows(path: &Path, options: &ImportOptions) -> Result<RowIter, Box<dyn Error>> {
+    match options.format {
+        ImportFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(open_reader(path)?);
+            let headers = reader.headers()?.clone();
+            Ok(Box::new(reader.into_records().map(move |record| {
+                let record = record.map_err(|e| e.to_string())?;
+                Ok(headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(h, v)| (h.to_string(), v.to_string()))
+                    .collect())
+            })))
+        }
+        ImportFormat::Jsonl => {
+            let lines = BufReader::new(open_reader(path)?).lines();
+            Ok(Box::new(lines.filter_map(|line| match line {
+                Ok(line) if line.trim().is_empty() => None,
+                Ok(line) => Some(json_row(&line)),
+                Err(e) => Some(Err(e.to_string())),
+            })))
+        }
+        ImportFormat::Parquet => {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+            let columns = used_columns(options);
+            Ok(Box::new(reader.flat_map(move |batch| -> Vec<Result<RawRow, String>> {
+                match batch {
+                    Ok(batch) => batch_rows(&batch, &columns),
+                    Err(e) => vec![Err(e.to_string())],
+                }
+            })))
+        }
+    }
+}
+
+fn json_row(line: &str) -> Result<RawRow, String> {
+    let value: Value = serde_json::from_str(line).map_err(|e| format!("неверный JSON: {}", e))?;
+    let object = value.as_object().ok_or("строка JSONL не объект")?;
+    Ok(object
+        .iter()
+        .filter_map(|(k, v)| {
+            let text = match v {
+                Value::String(s) => s.clone(),
+                Value::Number(n) => n.to_string(),
+                Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            Some((k.clone(), text))
+        })
+        .collect())
+}
+
+// Строки пачки Parquet; переводятся в текст только нужные колонки, тип остальных не важен
+fn batch_rows(batch: &RecordBatch, columns: &HashSet<String>) -> Vec<Result<RawRow, String>> {
+    let schema = batch.schema();
+    (0..batch.num_rows())
+        .map(|i| {
+            let mut row = RawRow::new();
+            for (field, column) in schema.fields().iter().zip(batch.columns()) {
+                if !columns.contains(field.name()) || column.is_null(i) {
+                    continue;
+                }
+                let text = match column.data_type() {
+                    DataType::Utf8 => column.as_string::<i32>().value(i).to_string(),
+                    DataType::LargeUtf8 => column.as_string::<i64>().value(i).to_string(),
+                    DataType::Int64 => column.as_primitive::<Int64Type>().value(i).to_string(),
+                    DataType::Int32 => column.as_primitive::<Int32Type>().value(i).to_string(),
+                    DataType::Float64 => column.as_primitive::<Float64Type>().value(i).to_string(),
+                    DataType::Float32 => column.as_primitive::<Float32Type>().value(i).to_string(),
+                    DataType::Boolean => column.as_boolean().value(i).to_string(),
+                    other => return Err(format!("неподдерживаемый тип колонки {}: {}", field.name(), other)),
+                };
+                row.insert(field.name().clone(), text);
+            }
+            Ok(row)
+        })
+        .collect()
+}
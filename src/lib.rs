@@ -0,0 +1,23 @@
+pub mod aggregate;
+pub mod api;
+pub mod audit;
+pub mod bar_builder;
+pub mod candle_builder;
+pub mod cli;
+pub mod correlation;
+pub mod data_structs;
+pub mod db;
+pub mod derived;
+pub mod export;
+pub mod import;
+pub mod indicators;
+pub mod order_flow;
+pub mod reconcile;
+pub mod retention;
+pub mod rollup;
+pub mod scheduler;
+pub mod storage;
+pub mod trade_writer;
+pub mod volatility;
+pub mod volume_profile;
+pub mod websocket;
@@ -1,30 +1,53 @@
-mod api;
-mod websocket;
-mod db;
-mod data_structs;
-use websocket::start_ws_trades;
-use sqlx::postgres::PgPoolOptions;
+use poloniex::{api, cli, data_structs, retention, storage};
+use poloniex::websocket::start_ws_trades;
+use poloniex::storage::StorageKind;
 use dotenvy::dotenv;
-use std::{env, sync::Arc};
+use std::env;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
      dotenv().ok();
     println!("Загружены переменные окружения.");
 
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL не задан");
+    let kind = StorageKind::from_env()?;
+    let database_url = match kind {
+        StorageKind::Memory => String::new(),
+        _ => env::var("DATABASE_URL").expect("DATABASE_URL не задан"),
+    };
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+    let store = storage::connect(kind, &database_url)
         .await
         .expect("Не удалось подключиться к БД");
 
-    println!("Подключение к БД установлено.");
+    println!("Хранилище {:?} готово.", kind);
 
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    // Разовые команды: `poloniex <команда> --флаг значение ...`
+    let args: Vec<String> = env::args().collect();
+    if let Some(command) = args.get(1) {
+        let flags = cli::Args::parse(&args[2..])?;
+        match command.as_str() {
+            "export" => cli::export(&*store, &flags).await?,
+            "import" => cli::import(&*store, &flags).await?,
+            "audit" => cli::audit(&*store, &flags).await?,
+            "rollup" => cli::rollup(&*store, &flags).await?,
+            "bars" => cli::bars(&*store, &flags).await?,
+            "reconcile" => cli::reconcile(&*store, &flags).await?,
+            "indicators" => cli::indicators(&*store, &flags).await?,
+            "order-flow" => cli::order_flow(&*store, &flags).await?,
+            "volume-profile" => cli::volume_profile(&*store, &flags).await?,
+            "volatility" => cli::volatility(&*store, &flags).await?,
+            "correlation" => cli::correlation(&*store, &flags).await?,
+            other => return Err(format!("Неизвестная команда: {}", other).into()),
+        }
+        return Ok(());
+    }
 
-    println!("Миграции успешно выполнены.");
+    let retention_config = retention::RetentionConfig::from_env()?;
+    if !retention_config.rules.is_empty() {
+        println!("Правила хранения: {:?}", retention_config.rules);
+        tokio::spawn(retention::run_retention(Arc::clone(&store), retention_config));
+    }
 
     println!("Таблица `candles` готова.");
 
@@ -40,7 +63,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         println!("Пустой ответ от API для {} - {}", pair, interval);
                     }
     
-                    if let Err(e) = db::insert_candles(&pool, candles).await {
+                    if let Err(e) = store.upsert_candles(candles).await {
                         eprintln!("Ошибка записи в БД: {}", e);
                     } else {
                         println!("Данные записаны в БД.");
@@ -55,9 +78,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Данные успешно загружены в БД.");
 
-    let pool = Arc::new(pool);
-
-    start_ws_trades(Arc::clone(&pool)).await;
+    start_ws_trades(store).await;
 
     Ok(())
 }
\ No newline at end of file
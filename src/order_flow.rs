@@ -0,0 +1,393 @@
+use std::collections::VecDeque;
+use std::env;
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, OrderFlow, RecentTrade, PAIRS};
+use crate::scheduler::{self, run_live, LiveSeries, BATCH_BUCKETS};
+use crate::storage::Storage;
+
+// Окно для расхождения и z-оценок по умолчанию, в свечах
+pub const DEFAULT_WINDOW: usize = 20;
+
+// Среднее и стандартное отклонение последних `period` значений
+#[derive(Debug, Clone)]
+struct RollingStats {
+    period: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl RollingStats {
+    fn new(period: usize) -> Self {
+        RollingStats {
+            period,
+            window: VecDeque::with_capacity(period + 1),
+            sum: 0.0,
+            sum_sq: 0.0,
+        }
+    }
+
+    fn push(&mut self, value: f64) {
+        self.window.push_back(value);
+        self.sum += value;
+        self.sum_sq += value * value;
+        if self.window.len() > self.period {
+            let old = self.window.pop_front().unwrap_or_default();
+            self.sum -= old;
+            self.sum_sq -= old * old;
+        }
+    }
+
+    // Насколько значение отклоняется от окна в стандартных отклонениях; None, пока окно
+    // не набрано или все значения в нём одинаковые
+    fn z_score(&self, value: f64) -> Option<f64> {
+        if self.window.len() < self.period {
+            return None;
+        }
+        let n = self.period as f64;
+        let mean = self.sum / n;
+        let deviation = (self.sum_sq / n - mean * mean).max(0.0).sqrt();
+        (deviation > 1e-12 * mean.abs().max(1.0)).then(|| (value - mean) / deviation)
+    }
+}
+
+// Поток ордеров одного ряда по закрытым свечам; каждая свеча учитывается за O(1).
+// Расхождение - знак изменения цены закрытия и CVD за `window` свечей не совпадает,
+// z-оценки объёмов считаются относительно `window` предыдущих свечей.
+#[derive(Debug, Clone)]
+pub struct OrderFlowSeries {
+    window: usize,
+    cvd: f64,
+    recent: VecDeque<(f64, f64)>, // (close, cvd) последних window свечей
+    buys: RollingStats,
+    sells: RollingStats,
+}
+
+impl OrderFlowSeries {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(2);
+        OrderFlowSeries {
+            window,
+            cvd: 0.0,
+            recent: VecDeque::with_capacity(window + 1),
+            buys: RollingStats::new(window),
+            sells: RollingStats::new(window),
+        }
+    }
+
+    // Продолжение ряда: CVD на последней учтённой свече берётся из хранилища
+    pub fn continue_from(&mut self, cvd: f64) {
+        let shift = cvd - self.cvd;
+        self.cvd = cvd;
+        for (_, recent_cvd) in self.recent.iter_mut() {
+            *recent_cvd += shift;
+        }
+    }
+
+    pub fn push(&mut self, candle: &Kline) -> OrderFlow {
+        let buy = candle.volume_bs.buy_base;
+        let sell = candle.volume_bs.sell_base;
+        let delta = buy - sell;
+        self.cvd += delta;
+
+        let divergence = match self.recent.front() {
+            Some(&(close, cvd)) if self.recent.len() == self.window => {
+                let (price_change, flow_change) = (candle.close - close, self.cvd - cvd);
+                if price_change < 0.0 && flow_change > 0.0 {
+                    1
+                } else if price_change > 0.0 && flow_change < 0.0 {
+                    -1
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+        self.recent.push_back((candle.close, self.cvd));
+        if self.recent.len() > self.window {
+            self.recent.pop_front();
+        }
+
+        let buy_z = self.buys.z_score(buy);
+        let sell_z = self.sells.z_score(sell);
+        self.buys.push(buy);
+        self.sells.push(sell);
+
+        OrderFlow {
+            pair: candle.pair.clone(),
+            time_frame: candle.time_frame.clone(),
+            utc_begin: candle.utc_begin,
+            buy_volume: buy,
+            sell_volume: sell,
+            delta,
+            cvd: self.cvd,
+            imbalance: if buy + sell > 0.0 { delta / (buy + sell) } else { 0.0 },
+            divergence,
+            buy_z,
+            sell_z,
+            window: self.window as i64,
+        }
+    }
+}
+
+// Метрики по свечам одного ряда (по возрастанию utc_begin), CVD - с нуля на первой свече
+pub fn order_flow(candles: &[Kline], window: usize) -> Vec<OrderFlow> {
+    let mut series = OrderFlowSeries::new(window);
+    candles.iter().map(|c| series.push(c)).collect()
+}
+
+// Метрики по сырым трейдам: трейды собираются в свечи таймфрейма, корзины без трейдов пропускаются.
+// None - неизвестный таймфрейм.
+pub fn order_flow_from_trades(pair: &str, time_frame: &str, trades: &[RecentTrade], window: usize) -> Option<Vec<OrderFlow>> {
+    aggregate::aggregate_trades(pair, time_frame, trades).map(|candles| order_flow(&candles, window))
+}
+
+// Откуда берётся разделение объёма
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowInput {
+    Candles, // записанные свечи, источник - по CandleSource::DEFAULT_PREFERENCE
+    Trades,  // свечи, заново собранные из трейдов в БД
+}
+
+async fn read_candles(
+    store: &dyn Storage,
+    input: FlowInput,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<Kline>, sqlx::Error> {
+    if start_ts > end_ts {
+        return Ok(Vec::new());
+    }
+    match input {
+        FlowInput::Candles => {
+            store
+                .candles_range(pair, time_frame, start_ts, end_ts, &CandleSource::DEFAULT_PREFERENCE)
+                .await
+        }
+        FlowInput::Trades => store.aggregate_candles(pair, time_frame, start_ts, end_ts).await,
+    }
+}
+
+// Ряд, прогретый на `window` корзинах до before_ts; CVD продолжается с записанного
+// значения на последней свече прогрева (без него - с нуля)
+async fn warm_up(
+    store: &dyn Storage,
+    input: FlowInput,
+    pair: &str,
+    time_frame: &str,
+    window: usize,
+    before_ts: i64,
+) -> Result<OrderFlowSeries, sqlx::Error> {
+    let mut series = OrderFlowSeries::new(window);
+    let bucket_ms = aggregate::time_frame_ms(time_frame).unwrap_or(0);
+    let from = before_ts.saturating_sub(bucket_ms.saturating_mul(series.window as i64));
+    let candles = read_candles(store, input, pair, time_frame, from, before_ts - 1).await?;
+    for candle in &candles {
+        series.push(candle);
+    }
+    if let Some(last) = candles.last() {
+        let stored = store
+            .order_flow_range(pair, time_frame, last.utc_begin, last.utc_begin)
+            .await?;
+        if let Some(row) = stored.first() {
+            series.continue_from(row.cvd);
+        }
+    }
+    Ok(series)
+}
+
+// Считает метрики ряда за [start_ts, end_ts] с прогревом до start_ts и записывает;
+// возвращает число записанных строк
+pub async fn compute_range(
+    store: &dyn Storage,
+    input: FlowInput,
+    pair: &str,
+    time_frame: &str,
+    window: usize,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<usize, sqlx::Error> {
+    let bucket_ms = match aggregate::time_frame_ms(time_frame) {
+        Some(ms) => ms,
+        None => return Ok(0),
+    };
+    let mut series = warm_up(store, input, pair, time_frame, window, start_ts).await?;
+    let mut written = 0;
+    let mut from = start_ts;
+    while from <= end_ts {
+        let to = from.saturating_add(bucket_ms * BATCH_BUCKETS - 1).min(end_ts);
+        let candles = read_candles(store, input, pair, time_frame, from, to).await?;
+        let rows: Vec<OrderFlow> = candles.iter().map(|c| series.push(c)).collect();
+        store.upsert_order_flow(&rows).await?;
+        written += rows.len();
+        if to == end_ts {
+            break;
+        }
+        from = to + 1;
+    }
+    Ok(written)
+}
+
+// ORDER_FLOW_WINDOW - окно живого расчёта в свечах; не задано - поток ордеров по живым свечам не считается
+pub fn window_from_env() -> Result<Option<usize>, String> {
+    match env::var("ORDER_FLOW_WINDOW") {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|w| *w >= 2)
+            .map(Some)
+            .ok_or_else(|| format!("Неверное окно ORDER_FLOW_WINDOW={}", value)),
+        _ => Ok(None),
+    }
+}
+
+// Имя отметки живого расчёта в job_watermarks
+pub fn watermark_name(time_frame: &str, pair: &str) -> String {
+    format!("order_flow:{}:{}", time_frame, pair)
+}
+
+// Поток ордеров одного ряда в живом расчёте
+struct LiveOrderFlow {
+    pair: &'static str,
+    time_frame: &'static str,
+    window: usize,
+}
+
+#[async_trait]
+impl LiveSeries for LiveOrderFlow {
+    type State = OrderFlowSeries;
+    type Row = OrderFlow;
+
+    fn label(&self) -> String {
+        format!("потока ордеров {} {}", self.pair, self.time_frame)
+    }
+
+    fn watermark_name(&self) -> String {
+        watermark_name(self.time_frame, self.pair)
+    }
+
+    async fn closed_until(&self, store: &dyn Storage) -> Result<Option<i64>, sqlx::Error> {
+        scheduler::closed_until(store, self.pair, self.time_frame).await
+    }
+
+    async fn warm_up(&self, store: &dyn Storage, before_ts: i64) -> Result<Option<OrderFlowSeries>, sqlx::Error> {
+        let series = warm_up(store, FlowInput::Candles, self.pair, self.time_frame, self.window, before_ts).await?;
+        Ok(Some(series))
+    }
+
+    async fn push(
+        &self,
+        store: &dyn Storage,
+        series: &mut OrderFlowSeries,
+        from: i64,
+        until: i64,
+    ) -> Result<Vec<OrderFlow>, sqlx::Error> {
+        let candles = read_candles(store, FlowInput::Candles, self.pair, self.time_frame, from, until - 1).await?;
+        Ok(candles.iter().map(|c| series.push(c)).collect())
+    }
+
+    async fn write(&self, store: &dyn Storage, rows: &[OrderFlow]) -> Result<(), sqlx::Error> {
+        store.upsert_order_flow(rows).await
+    }
+}
+
+// Живой расчёт по свечам, закрытым агрегацией; история досчитывается командой order-flow
+pub async fn run_order_flow(store: Arc<dyn Storage>, time_frames: Vec<&'static str>, window: usize) {
+    let mut series = Vec::new();
+    for &time_frame in &time_frames {
+        for &pair in PAIRS.iter() {
+            series.push(LiveOrderFlow { pair, time_frame, window });
+        }
+    }
+    run_live(store, &format!("потока ордеров, окно {}", window), series).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN, T0};
+    use crate::storage::{CandleStore, MemoryStore, SeriesStore};
+
+    fn candle(i: i64, close: f64, buy: f64, sell: f64) -> Kline {
+        testing::candle("1m", T0 + i * MIN, close).with_volume(buy, sell)
+    }
+
+    fn sample() -> Vec<Kline> {
+        vec![
+            candle(0, 10.0, 3.0, 1.0),
+            candle(1, 11.0, 1.0, 1.0),
+            candle(2, 9.0, 4.0, 0.0),
+            candle(3, 12.0, 0.0, 5.0),
+            candle(4, 13.0, 0.0, 0.0),
+        ]
+    }
+
+    #[test]
+    fn delta_cvd_and_imbalance() {
+        let rows = order_flow(&sample(), 2);
+        let summary: Vec<_> = rows.iter().map(|r| (r.delta, r.cvd, r.imbalance)).collect();
+        assert_eq!(summary, vec![(2.0, 2.0, 0.5), (0.0, 2.0, 0.0), (4.0, 6.0, 1.0), (-5.0, 1.0, -1.0), (0.0, 1.0, 0.0)]);
+        assert!(rows.iter().all(|r| r.window == 2));
+    }
+
+    #[test]
+    fn divergence_over_window() {
+        let rows = order_flow(&sample(), 2);
+        // Свеча 2: цена 10 -> 9, CVD 2 -> 6; свеча 3: цена 11 -> 12, CVD 2 -> 1; свеча 4: 9 -> 13, 6 -> 1
+        let divergence: Vec<i8> = rows.iter().map(|r| r.divergence).collect();
+        assert_eq!(divergence, vec![0, 0, 1, -1, -1]);
+    }
+
+    #[test]
+    fn z_scores_against_previous_window() {
+        let rows = order_flow(&sample(), 2);
+        assert_eq!((rows[0].buy_z, rows[1].buy_z), (None, None));
+        // Покупки [3, 1]: среднее 2, отклонение 1
+        assert_eq!(rows[2].buy_z, Some(2.0));
+        // Продажи [1, 1] без разброса
+        assert_eq!(rows[2].sell_z, None);
+        // Покупки [1, 4]: 2.5 ± 1.5; продажи [1, 0]: 0.5 ± 0.5
+        assert!((rows[3].buy_z.unwrap() + 5.0 / 3.0).abs() < 1e-12);
+        assert_eq!(rows[3].sell_z, Some(9.0));
+    }
+
+    #[test]
+    fn from_trades_splits_by_taker_side() {
+        let trade = |ts: i64, tid: &str, quantity: &str, side: &str| RecentTrade {
+            tid: tid.to_string(),
+            pair: "BTC_USDT".to_string(),
+            price: "10".to_string(),
+            amount: "10".to_string(),
+            quantity: quantity.to_string(),
+            side: side.to_string(),
+            create_time: ts,
+            timestamp: ts,
+        };
+        let trades = [trade(T0, "1", "2", "buy"), trade(T0 + 1, "2", "0.5", "sell"), trade(T0 + 2 * MIN, "3", "1", "sell")];
+        let rows = order_flow_from_trades("BTC_USDT", "1m", &trades, 2).unwrap();
+        // Корзина без трейдов пропущена
+        let summary: Vec<_> = rows.iter().map(|r| (r.utc_begin, r.buy_volume, r.sell_volume, r.cvd)).collect();
+        assert_eq!(summary, vec![(T0, 2.0, 0.5, 1.5), (T0 + 2 * MIN, 0.0, 1.0, 0.5)]);
+        assert!(order_flow_from_trades("BTC_USDT", "7m", &trades, 2).is_none());
+    }
+
+    #[tokio::test]
+    async fn compute_range_continues_cvd() {
+        let candles: Vec<Kline> = (0..50)
+            .map(|i| candle(i, 100.0 + (i as f64 * 0.9).sin(), 1.0 + (i % 3) as f64, 1.0 + (i % 5) as f64))
+            .collect();
+        let expected = order_flow(&candles, 4);
+
+        let store = MemoryStore::default();
+        store.upsert_candles(candles).await.unwrap();
+        let end = T0 + 50 * MIN - 1;
+        compute_range(&store, FlowInput::Candles, "BTC_USDT", "1m", 4, T0, T0 + 30 * MIN - 1).await.unwrap();
+        compute_range(&store, FlowInput::Candles, "BTC_USDT", "1m", 4, T0 + 30 * MIN, end).await.unwrap();
+        assert_eq!(store.order_flow_range("BTC_USDT", "1m", T0, end).await.unwrap(), expected);
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use chrono::Utc;
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, AGG_TIME_FRAMES, INTERVALS};
+use crate::rollup::{self, BASE_TIME_FRAME, ROLLUP_TIME_FRAMES};
+use crate::storage::Storage;
+
+// Окно чтения свечей: неделя от эпохи делится на корзины всех таймфреймов
+const WINDOW_MS: i64 = 7 * 24 * 60 * 60_000;
+
+// Допустимые относительные расхождения
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    pub price: f64,  // open/high/low/close
+    pub volume: f64, // объёмы в базовой и котируемой валюте, число трейдов
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            price: 1e-6,
+            volume: 1e-3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Exchange, // свеча биржи (expected) против свечи из своих трейдов (actual)
+    Rollup,   // сборка из минутных (expected) против записанного старшего таймфрейма (actual)
+}
+
+impl Check {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Check::Exchange => "exchange",
+            Check::Rollup => "rollup",
+        }
+    }
+}
+
+// Строка отчёта: одно расхождение в одной корзине. Пропуск свечи - поле "candle" без значения с одной стороны.
+#[derive(Debug, Clone)]
+pub struct ReconcileRow {
+    pub pair: String,
+    pub time_frame: String, // таймфрейм проверяемого ряда (actual)
+    pub check: Check,
+    pub utc_begin: i64,
+    pub field: &'static str,
+    pub expected: Option<f64>,
+    pub actual: Option<f64>,
+}
+
+impl ReconcileRow {
+    pub fn rel_diff(&self) -> Option<f64> {
+        match (self.expected, self.actual) {
+            (Some(e), Some(a)) => Some(relative_diff(e, a)),
+            _ => None,
+        }
+    }
+}
+
+// Итог по ряду
+#[derive(Debug, Clone)]
+pub struct ReconcileSummary {
+    pub pair: String,
+    pub expected_time_frame: String,
+    pub time_frame: String,
+    pub check: Check,
+    pub buckets: usize,
+    pub matched: usize,
+    pub missing_expected: usize, // есть у нас, нет в эталоне
+    pub missing_actual: usize,   // есть в эталоне с объёмом, нет у нас
+    pub mismatched: usize,       // есть с обеих сторон, но расходятся
+    pub expected_volume: f64,
+    pub actual_volume: f64,
+}
+
+impl ReconcileSummary {
+    // Доля объёма эталона, которая есть у нас; для сверки с биржей - полнота захвата трейдов
+    pub fn capture_ratio(&self) -> Option<f64> {
+        (self.expected_volume > 0.0).then(|| self.actual_volume / self.expected_volume)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReconcileReport {
+    pub rows: Vec<ReconcileRow>,
+    pub summaries: Vec<ReconcileSummary>,
+}
+
+// Что с чем сверяем: (эталон, источник эталона, проверяемый ряд, его источник, проверка)
+struct Pairing {
+    expected: &'static str,
+    expected_source: CandleSource,
+    actual: &'static str,
+    actual_source: CandleSource,
+    check: Check,
+}
+
+fn pairings() -> Vec<Pairing> {
+    let mut result = Vec::new();
+    // Свои таймфреймы, которые отдаёт и биржа
+    let exchange: Vec<&'static str> = AGG_TIME_FRAMES
+        .into_iter()
+        .filter(|tf| aggregate::rest_interval(tf).is_some_and(|i| INTERVALS.contains(&i)))
+        .collect();
+    for tf in &exchange {
+        result.push(Pairing {
+            expected: tf,
+            expected_source: CandleSource::Rest,
+            actual: tf,
+            actual_source: CandleSource::Aggregated,
+            check: Check::Exchange,
+        });
+    }
+    // Свои старшие таймфреймы против сборки из своих минутных
+    let mut own: Vec<&'static str> = AGG_TIME_FRAMES.into_iter().chain(ROLLUP_TIME_FRAMES).collect();
+    own.sort_by_key(|tf| aggregate::time_frame_ms(tf));
+    own.dedup();
+    for tf in own.into_iter().filter(|tf| *tf != BASE_TIME_FRAME) {
+        result.push(Pairing {
+            expected: BASE_TIME_FRAME,
+            expected_source: CandleSource::Aggregated,
+            actual: tf,
+            actual_source: CandleSource::Aggregated,
+            check: Check::Rollup,
+        });
+    }
+    // Старшие интервалы биржи против сборки из её минутных
+    for tf in exchange.into_iter().filter(|tf| *tf != BASE_TIME_FRAME) {
+        result.push(Pairing {
+            expected: BASE_TIME_FRAME,
+            expected_source: CandleSource::Rest,
+            actual: tf,
+            actual_source: CandleSource::Rest,
+            check: Check::Rollup,
+        });
+    }
+    result
+}
+
+// Сверяет ряды пары за [start_ts, end_ts]; незакрытые корзины не сверяются
+pub async fn reconcile_pair(
+    store: &dyn Storage,
+    pair: &str,
+    start_ts: i64,
+    end_ts: i64,
+    tolerance: Tolerance,
+) -> Result<ReconcileReport, sqlx::Error> {
+    let mut report = ReconcileReport::default();
+    let now = Utc::now().timestamp_millis();
+
+    for pairing in pairings() {
+        let bucket_ms = match aggregate::time_frame_ms(pairing.actual) {
+            Some(ms) => ms,
+            None => continue,
+        };
+        // Только корзины, целиком лежащие в диапазоне и уже закрытые
+        let first = aggregate::bucket_start(start_ts + bucket_ms - 1, bucket_ms);
+        let last = aggregate::bucket_start(end_ts.min(now) + 1, bucket_ms) - bucket_ms;
+        if first > last {
+            continue;
+        }
+
+        let mut summary = ReconcileSummary {
+            pair: pair.to_string(),
+            expected_time_frame: pairing.expected.to_string(),
+            time_frame: pairing.actual.to_string(),
+            check: pairing.check,
+            buckets: 0,
+            matched: 0,
+            missing_expected: 0,
+            missing_actual: 0,
+            mismatched: 0,
+            expected_volume: 0.0,
+            actual_volume: 0.0,
+        };
+
+        let mut window_start = aggregate::bucket_start(first, WINDOW_MS);
+        while window_start <= last {
+            let from = window_start.max(first);
+            let to = (window_start + WINDOW_MS - 1).min(last + bucket_ms - 1);
+            let actual = read_series(store, pair, pairing.actual, pairing.actual_source, from, to).await?;
+            // Для сборки без записанного ряда сверять нечего: это не пропуск, ряд просто не хранится
+            if pairing.check == Check::Rollup && actual.is_empty() {
+                window_start += WINDOW_MS;
+                continue;
+            }
+            let mut expected = read_series(store, pair, pairing.expected, pairing.expected_source, from, to).await?;
+            if pairing.check == Check::Rollup {
+                let base: Vec<Kline> = expected.into_values().collect();
+                expected = by_bucket(rollup::rollup(&base, pairing.actual));
+            }
+            compare(&expected, &actual, &pairing, pair, tolerance, &mut summary, &mut report.rows);
+            window_start += WINDOW_MS;
+        }
+
+        if summary.buckets > 0 {
+            report.summaries.push(summary);
+        }
+    }
+    Ok(report)
+}
+
+// Свечи ряда ровно от указанного источника
+async fn read_series(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    source: CandleSource,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<BTreeMap<i64, Kline>, sqlx::Error> {
+    let candles = store.candles_range(pair, time_frame, start_ts, end_ts, &[source]).await?;
+    Ok(by_bucket(candles.into_iter().filter(|c| c.source == source).collect()))
+}
+
+fn by_bucket(candles: Vec<Kline>) -> BTreeMap<i64, Kline> {
+    candles.into_iter().map(|c| (c.utc_begin, c)).collect()
+}
+
+fn compare(
+    expected: &BTreeMap<i64, Kline>,
+    actual: &BTreeMap<i64, Kline>,
+    pairing: &Pairing,
+    pair: &str,
+    tolerance: Tolerance,
+    summary: &mut ReconcileSummary,
+    rows: &mut Vec<ReconcileRow>,
+) {
+    let mut buckets: Vec<i64> = expected.keys().chain(actual.keys()).copied().collect();
+    buckets.sort_unstable();
+    buckets.dedup();
+
+    let row = |utc_begin: i64, field: &'static str, expected: Option<f64>, actual: Option<f64>| ReconcileRow {
+        pair: pair.to_string(),
+        time_frame: pairing.actual.to_string(),
+        check: pairing.check,
+        utc_begin,
+        field,
+        expected,
+        actual,
+    };
+
+    for utc_begin in buckets {
+        let (e, a) = (expected.get(&utc_begin), actual.get(&utc_begin));
+        if let Some(e) = e {
+            summary.expected_volume += base_volume(e);
+        }
+        if let Some(a) = a {
+            summary.actual_volume += base_volume(a);
+        }
+        match (e, a) {
+            // Свеча биржи без сделок: у нас её и не должно быть
+            (Some(e), None) if base_volume(e) == 0.0 => continue,
+            (Some(e), None) => {
+                summary.missing_actual += 1;
+                rows.push(row(utc_begin, "candle", Some(base_volume(e)), None));
+            }
+            (None, Some(a)) => {
+                summary.missing_expected += 1;
+                rows.push(row(utc_begin, "candle", None, Some(base_volume(a))));
+            }
+            (Some(e), Some(a)) => {
+                let diffs = field_diffs(e, a, tolerance);
+                if diffs.is_empty() {
+                    summary.matched += 1;
+                } else {
+                    summary.mismatched += 1;
+                    rows.extend(diffs.into_iter().map(|(field, ev, av)| row(utc_begin, field, Some(ev), Some(av))));
+                }
+            }
+            (None, None) => continue,
+        }
+        summary.buckets += 1;
+    }
+}
+
+fn base_volume(candle: &Kline) -> f64 {
+    candle.volume_bs.buy_base + candle.volume_bs.sell_base
+}
+
+fn quote_volume(candle: &Kline) -> f64 {
+    candle.volume_bs.buy_quote + candle.volume_bs.sell_quote
+}
+
+fn relative_diff(expected: f64, actual: f64) -> f64 {
+    let scale = expected.abs().max(actual.abs());
+    if scale == 0.0 {
+        0.0
+    } else {
+        (actual - expected).abs() / scale
+    }
+}
+
+// Поля, расходящиеся сверх допуска: (поле, эталон, наше)
+fn field_diffs(expected: &Kline, actual: &Kline, tolerance: Tolerance) -> Vec<(&'static str, f64, f64)> {
+    let mut fields = vec![
+        ("open", expected.open, actual.open, tolerance.price),
+        ("high", expected.high, actual.high, tolerance.price),
+        ("low", expected.low, actual.low, tolerance.price),
+        ("close", expected.close, actual.close, tolerance.price),
+        ("base_volume", base_volume(expected), base_volume(actual), tolerance.volume),
+        ("quote_volume", quote_volume(expected), quote_volume(actual), tolerance.volume),
+    ];
+    // Число трейдов неизвестно у старых свечей и у непрерывных агрегатов (0) - такие не сравниваем
+    if expected.trade_count > 0 && actual.trade_count > 0 {
+        fields.push(("trade_count", expected.trade_count as f64, actual.trade_count as f64, tolerance.volume));
+    }
+    fields
+        .into_iter()
+        .filter(|(_, e, a, tol)| relative_diff(*e, *a) > *tol)
+        .map(|(field, e, a, _)| (field, e, a))
+        .collect()
+}
+
+// Полный отчёт в CSV
+pub fn write_csv(rows: &[ReconcileRow], path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["pair", "time_frame", "check", "utc_begin", "field", "expected", "actual", "rel_diff"])?;
+    let opt = |v: Option<f64>| v.map(|v| v.to_string()).unwrap_or_default();
+    for row in rows {
+        writer.write_record([
+            row.pair.clone(),
+            row.time_frame.clone(),
+            row.check.as_str().to_string(),
+            row.utc_begin.to_string(),
+            row.field.to_string(),
+            opt(row.expected),
+            opt(row.actual),
+            opt(row.rel_diff()),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN, T0};
+    use crate::storage::{CandleStore, MemoryStore};
+
+    fn candle(time_frame: &str, utc_begin: i64, close: f64, volume: f64, source: CandleSource) -> Kline {
+        testing::candle(time_frame, utc_begin, close)
+            .with_ohlc(100.0, 110.0, 90.0, close)
+            .with_vwap(100.0)
+            .with_volume(volume, 0.0)
+            .with_trade_count(10)
+            .with_source(source)
+    }
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let e = candle("1m", T0, 100.0, 1.0, CandleSource::Rest);
+        let tolerance = Tolerance::default();
+
+        let mut a = candle("1m", T0, 100.0 * (1.0 + 5e-7), 1.0 + 5e-4, CandleSource::Aggregated);
+        assert!(field_diffs(&e, &a, tolerance).is_empty());
+
+        a.close = 100.1;
+        a.volume_bs.buy_base = 1.01;
+        a.trade_count = 12;
+        let fields: Vec<&str> = field_diffs(&e, &a, tolerance).into_iter().map(|(f, _, _)| f).collect();
+        assert_eq!(fields, vec!["close", "base_volume", "trade_count"]);
+
+        // Неизвестное число трейдов (0) не сравнивается
+        a.trade_count = 0;
+        assert!(!field_diffs(&e, &a, tolerance).iter().any(|(f, _, _)| *f == "trade_count"));
+
+        assert_eq!(relative_diff(0.0, 0.0), 0.0);
+        assert_eq!(relative_diff(2.0, 1.0), 0.5);
+    }
+
+    #[tokio::test]
+    async fn counts_matches_mismatches_and_missing_candles() {
+        let store = MemoryStore::default();
+        let rest = |i: i64, volume: f64| candle("1m", T0 + i * MIN, 100.0, volume, CandleSource::Rest);
+        let own = |i: i64, close: f64, volume: f64| candle("1m", T0 + i * MIN, close, volume, CandleSource::Aggregated);
+        store
+            .upsert_candles(vec![
+                rest(0, 1.0),
+                own(0, 100.0, 1.0),
+                rest(1, 1.0),
+                own(1, 100.00001, 1.0), // в пределах допуска цены
+                rest(2, 1.0),
+                own(2, 100.1, 1.0), // close расходится
+                rest(3, 1.0),       // у нас нет
+                rest(4, 0.0),       // у биржи без сделок - не пропуск
+                own(5, 100.0, 2.0), // у биржи нет
+            ])
+            .await
+            .unwrap();
+
+        let report = reconcile_pair(&store, "BTC_USDT", T0, T0 + 6 * MIN - 1, Tolerance::default()).await.unwrap();
+        assert_eq!(report.summaries.len(), 1);
+        let s = &report.summaries[0];
+        assert_eq!((s.check, s.expected_time_frame.as_str(), s.time_frame.as_str()), (Check::Exchange, "1m", "1m"));
+        assert_eq!((s.buckets, s.matched, s.mismatched), (5, 2, 1));
+        assert_eq!((s.missing_actual, s.missing_expected), (1, 1));
+        assert_eq!(s.capture_ratio(), Some(5.0 / 4.0));
+
+        let rows: Vec<(i64, &str, Option<f64>, Option<f64>)> =
+            report.rows.iter().map(|r| (r.utc_begin, r.field, r.expected, r.actual)).collect();
+        assert_eq!(
+            rows,
+            vec![
+                (T0 + 2 * MIN, "close", Some(100.0), Some(100.1)),
+                (T0 + 3 * MIN, "candle", Some(1.0), None),
+                (T0 + 5 * MIN, "candle", None, Some(2.0)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn exchange_intervals_are_checked_against_their_minutes() {
+        let store = MemoryStore::default();
+        let mut candles: Vec<Kline> = (0..15).map(|i| candle("1m", T0 + i * MIN, 100.0, 1.0, CandleSource::Rest)).collect();
+        let mut quarter = candle("15m", T0, 100.0, 15.0, CandleSource::Rest);
+        quarter.trade_count = 150;
+        candles.push(quarter);
+        candles[14].close = 101.0;
+        store.upsert_candles(candles).await.unwrap();
+
+        let report = reconcile_pair(&store, "BTC_USDT", T0, T0 + 15 * MIN - 1, Tolerance::default()).await.unwrap();
+        let s = report.summaries.iter().find(|s| s.check == Check::Rollup).unwrap();
+        assert_eq!((s.expected_time_frame.as_str(), s.time_frame.as_str()), ("1m", "15m"));
+        assert_eq!((s.buckets, s.mismatched), (1, 1));
+        let fields: Vec<&str> = report.rows.iter().filter(|r| r.check == Check::Rollup).map(|r| r.field).collect();
+        assert_eq!(fields, vec!["close"]);
+    }
+}
@@ -0,0 +1,299 @@
+use std::env;
+use std::error::Error;
+use std::sync::Arc;
+use chrono::Utc;
+use tokio::time::{sleep, Duration};
+use crate::aggregate;
+use crate::data_structs::{CandleSource, AGG_TIME_FRAMES, PAIRS};
+use crate::rollup;
+use crate::storage::Storage;
+
+// Сколько времени прореживание собирает за один проход
+const DOWNSAMPLE_WINDOW_MS: i64 = 7 * 24 * 60 * 60_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetentionTarget {
+    Trades,
+    Candles { time_frame: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionRule {
+    pub target: RetentionTarget,
+    pub keep_ms: Option<i64>,          // None - хранить всегда
+    pub downsample_to: Option<String>, // перед удалением свечи собираются в этот таймфрейм
+}
+
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    pub rules: Vec<RetentionRule>,
+    pub batch_size: i64,
+    pub interval: Duration,
+}
+
+impl RetentionConfig {
+    // RETENTION="trades=30d,candles:1m=365d>1h,candles:1d=forever"
+    // candles:1m=365d>1h - минутные свечи старше года удаляются, но сначала собираются в часовые
+    // RETENTION_BATCH_SIZE - строк за один DELETE, RETENTION_INTERVAL_SECS - период запуска
+    pub fn from_env() -> Result<Self, String> {
+        let rules = match env::var("RETENTION") {
+            Ok(spec) => parse_rules(&spec)?,
+            Err(_) => Vec::new(),
+        };
+        let batch_size = env::var("RETENTION_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(10_000);
+        let interval = env::var("RETENTION_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(3600);
+        Ok(RetentionConfig {
+            rules,
+            batch_size,
+            interval: Duration::from_secs(interval),
+        })
+    }
+}
+
+pub fn parse_rules(spec: &str) -> Result<Vec<RetentionRule>, String> {
+    let rules = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|item| {
+            let (target, keep) = item
+                .split_once('=')
+                .ok_or_else(|| format!("Правило хранения без '=': {}", item))?;
+            let target = match target.trim() {
+                "trades" => RetentionTarget::Trades,
+                other => match other.strip_prefix("candles:") {
+                    Some(tf) if aggregate::time_frame_ms(tf).is_some() => {
+                        RetentionTarget::Candles { time_frame: aggregate::canonical_time_frame(tf) }
+                    }
+                    Some(tf) if !tf.is_empty() => return Err(format!("Неизвестный таймфрейм в правиле хранения: {}", tf)),
+                    _ => return Err(format!("Неизвестная таблица в правиле хранения: {}", other)),
+                },
+            };
+            let (keep, downsample_to) = match keep.split_once('>') {
+                Some((keep, to)) => (keep, Some(aggregate::canonical_time_frame(to.trim()))),
+                None => (keep, None),
+            };
+            let keep_ms = parse_keep(keep.trim())?;
+            if let Some(to) = &downsample_to {
+                match (&target, keep_ms) {
+                    (RetentionTarget::Candles { time_frame }, Some(_)) => {
+                        rollup::rollup_bucket_ms(time_frame, to)?;
+                    }
+                    _ => return Err(format!("Прореживание задаётся только для удаляемых свечей: {}", item)),
+                }
+            }
+            Ok(RetentionRule {
+                target,
+                keep_ms,
+                downsample_to,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Трейд удаляется только после того, как его корзины собраны во всех агрегируемых таймфреймах:
+    // если эти свечи удаляются раньше трейдов, трейды уже никогда не удалятся
+    let keep_of = |target: &RetentionTarget| rules.iter().find(|r| &r.target == target).and_then(|r| r.keep_ms);
+    if let Some(trades_ms) = keep_of(&RetentionTarget::Trades) {
+        for time_frame in AGG_TIME_FRAMES {
+            let target = RetentionTarget::Candles { time_frame: time_frame.to_string() };
+            if let Some(candles_ms) = keep_of(&target).filter(|ms| *ms < trades_ms) {
+                return Err(format!(
+                    "Свечи {} хранятся меньше трейдов ({} мс < {} мс): трейды не смогут удаляться",
+                    time_frame, candles_ms, trades_ms
+                ));
+            }
+        }
+    }
+    Ok(rules)
+}
+
+// "30d", "12h", "52w", "forever"
+fn parse_keep(value: &str) -> Result<Option<i64>, String> {
+    if value == "forever" {
+        return Ok(None);
+    }
+    let unit_at = value.char_indices().last().map_or(0, |(i, _)| i);
+    let (number, unit) = value.split_at(unit_at);
+    let number: i64 = number
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| format!("Неверный срок хранения: {}", value))?;
+    let unit_ms: i64 = match unit {
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        "w" => 7 * 86_400_000,
+        _ => return Err(format!("Неверная единица срока хранения: {}", value)),
+    };
+    number
+        .checked_mul(unit_ms)
+        .map(Some)
+        .ok_or_else(|| format!("Слишком большой срок хранения: {}", value))
+}
+
+// Периодически применяет правила хранения
+pub async fn run_retention(store: Arc<dyn Storage>, config: RetentionConfig) {
+    loop {
+        apply_rules(&*store, &config, Utc::now().timestamp_millis()).await;
+        sleep(config.interval).await;
+    }
+}
+
+// Один проход по всем правилам на момент now_ms
+pub async fn apply_rules(store: &dyn Storage, config: &RetentionConfig, now_ms: i64) {
+    for rule in &config.rules {
+        let keep_ms = match rule.keep_ms {
+            Some(ms) => ms,
+            None => continue,
+        };
+        let cutoff = now_ms - keep_ms;
+        let result = match &rule.target {
+            RetentionTarget::Trades => delete_trades(store, cutoff, config.batch_size).await,
+            RetentionTarget::Candles { time_frame } => {
+                delete_candles(store, time_frame, rule.downsample_to.as_deref(), cutoff, config.batch_size).await
+            }
+        };
+        match result {
+            Ok(deleted) if deleted > 0 => {
+                println!("Хранение {:?}: удалено {} строк старше {}", rule.target, deleted, cutoff)
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Ошибка применения правила хранения {:?}: {}", rule.target, e),
+        }
+    }
+}
+
+// Удаляет трейды старше cutoff, но только те, чьи корзины уже собраны из трейдов во всех агрегируемых таймфреймах
+async fn delete_trades(store: &dyn Storage, cutoff: i64, batch_size: i64) -> Result<u64, Box<dyn Error>> {
+    let mut total = 0;
+    loop {
+        let deleted = store.delete_trades_before(cutoff, &AGG_TIME_FRAMES, batch_size).await?;
+        total += deleted;
+        if (deleted as i64) < batch_size {
+            return Ok(total);
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+// Удаляет свечи таймфрейма старше cutoff. С прореживанием граница
+// опускается до начала корзины старшего таймфрейма, и свечи каждой пары сначала собираются в него;
+// если сборка пары не удалась, её свечи не удаляются.
+async fn delete_candles(
+    store: &dyn Storage,
+    time_frame: &str,
+    downsample_to: Option<&str>,
+    cutoff: i64,
+    batch_size: i64,
+) -> Result<u64, Box<dyn Error>> {
+    let (cutoff, pairs): (i64, Vec<Option<&str>>) = match downsample_to {
+        None => (cutoff, vec![None]),
+        Some(to) => {
+            let bucket_ms = aggregate::time_frame_ms(to).ok_or_else(|| format!("Неизвестный таймфрейм: {}", to))?;
+            let cutoff = aggregate::bucket_start(cutoff, bucket_ms);
+            let mut pairs = Vec::new();
+            for pair in PAIRS {
+                match downsample(store, pair, time_frame, to, cutoff).await {
+                    Ok(written) => {
+                        if written > 0 {
+                            println!("Хранение: {} {} собрано в {} свечей {}", pair, time_frame, written, to);
+                        }
+                        pairs.push(Some(pair));
+                    }
+                    Err(e) => eprintln!("Ошибка прореживания {} {} в {}: {}", pair, time_frame, to, e),
+                }
+            }
+            (cutoff, pairs)
+        }
+    };
+
+    let mut total = 0;
+    for pair in pairs {
+        loop {
+            let deleted = store.delete_candles_before(pair, time_frame, cutoff, batch_size).await?;
+            total += deleted;
+            if (deleted as i64) < batch_size {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+    Ok(total)
+}
+
+// Собирает свечи пары до cutoff (граница корзины `to`) в таймфрейм `to`. Уже записанные корзины `to`
+// не перезаписываются: после частично прошедшего удаления пересборка дала бы неполную свечу.
+// Возвращает число собранных свечей.
+pub async fn downsample(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    to: &str,
+    cutoff: i64,
+) -> Result<usize, Box<dyn Error>> {
+    let bucket_ms = aggregate::time_frame_ms(to).ok_or_else(|| format!("Неизвестный таймфрейм: {}", to))?;
+    let oldest = match store.oldest_candle_begin(pair, time_frame).await? {
+        Some(ts) => ts,
+        None => return Ok(0),
+    };
+    let window_ms = (DOWNSAMPLE_WINDOW_MS / bucket_ms).max(1) * bucket_ms;
+
+    let mut written = 0;
+    let mut from = aggregate::bucket_start(oldest, bucket_ms);
+    while from < cutoff {
+        let until = (from + window_ms).min(cutoff);
+        let base = store
+            .candles_range(pair, time_frame, from, until - 1, &CandleSource::DEFAULT_PREFERENCE)
+            .await?;
+        let rolled = rollup::rollup(&base, to);
+        written += rolled.len();
+        store.insert_candles(rolled).await?;
+        from = until;
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keep() {
+        assert_eq!(parse_keep("30d"), Ok(Some(30 * 86_400_000)));
+        assert_eq!(parse_keep("12h"), Ok(Some(12 * 3_600_000)));
+        assert_eq!(parse_keep("forever"), Ok(None));
+        for bad in ["30д", "д", "", "0d", "-5d", "d", "5", "5x", "99999999999999999w"] {
+            assert!(parse_keep(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rules = parse_rules("trades=30d, candles:1m=365d>1h, candles:1d=forever").unwrap();
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[1].target, RetentionTarget::Candles { time_frame: "1m".to_string() });
+        assert_eq!(rules[1].downsample_to.as_deref(), Some("1h"));
+        assert!(parse_rules("trades=30д").is_err());
+        assert!(parse_rules("candles:7m=1d").is_err());
+        assert!(parse_rules("candles:1h=1d>1m").is_err());
+        assert!(parse_rules("trades=1d>1h").is_err());
+        assert!(parse_rules("candles:1m=forever>1h").is_err());
+        // Минутки не могут жить меньше трейдов, иначе трейды никогда не удалятся
+        assert!(parse_rules("trades=30d,candles:1m=7d>1h").is_err());
+        assert!(parse_rules("candles:15m=1d,trades=2d").is_err());
+        assert!(parse_rules("trades=7d,candles:1m=7d>1h,candles:1w=1d").is_ok());
+        assert!(parse_rules("trades=forever,candles:1m=7d").is_ok());
+        // Имена REST API приводятся к тем, под которыми хранятся свечи
+        let rules = parse_rules("candles:MINUTE_1=1d>HOUR_1").unwrap();
+        assert_eq!(rules[0].target, RetentionTarget::Candles { time_frame: "1m".to_string() });
+        assert_eq!(rules[0].downsample_to.as_deref(), Some("1h"));
+    }
+}
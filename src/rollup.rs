@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use chrono::Utc;
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, VBS};
+use crate::storage::Storage;
+
+// Таймфрейм, из которого строятся старшие
+pub const BASE_TIME_FRAME: &str = "1m";
+// Таймфреймы, которые умеем собирать из минутных свечей
+pub const ROLLUP_TIME_FRAMES: [&str; 6] = ["5m", "15m", "1h", "4h", "1d", "1w"];
+// Сколько времени обрабатывает пакетный пересчёт за один проход
+const BATCH_WINDOW_MS: i64 = 7 * 24 * 60 * 60_000;
+
+// Проверяет, что `time_frame` собирается из `base`: длительность больше и делится на базовую
+pub fn rollup_bucket_ms(base: &str, time_frame: &str) -> Result<(i64, i64), String> {
+    let base_ms = aggregate::time_frame_ms(base).ok_or_else(|| format!("Неизвестный таймфрейм: {}", base))?;
+    let bucket_ms = aggregate::time_frame_ms(time_frame).ok_or_else(|| format!("Неизвестный таймфрейм: {}", time_frame))?;
+    if bucket_ms <= base_ms || bucket_ms % base_ms != 0 {
+        return Err(format!("Таймфрейм {} нельзя собрать из {}", time_frame, base));
+    }
+    Ok((base_ms, bucket_ms))
+}
+
+// Собирает свечи старшего таймфрейма из свечей одной пары и одного младшего таймфрейма.
+// Корзины выровнены как aggregate::bucket_start (недели начинаются с понедельника).
+// open - первой свечи корзины, close - последней, high/low - экстремумы, объёмы VBS суммируются.
+// Свеча окончательна, если интервал прошёл и все входные свечи окончательны.
+pub fn rollup(candles: &[Kline], time_frame: &str) -> Vec<Kline> {
+    let bucket_ms = match aggregate::time_frame_ms(time_frame) {
+        Some(ms) => ms,
+        None => {
+            eprintln!("Неизвестный таймфрейм для сборки: {}", time_frame);
+            return Vec::new();
+        }
+    };
+
+    let mut sorted: Vec<&Kline> = candles.iter().collect();
+    sorted.sort_by_key(|c| c.utc_begin);
+
+    let mut buckets: BTreeMap<i64, Kline> = BTreeMap::new();
+    for candle in sorted {
+        let begin = aggregate::bucket_start(candle.utc_begin, bucket_ms);
+        let rolled = buckets.entry(begin).or_insert_with(|| Kline {
+            pair: candle.pair.clone(),
+            time_frame: time_frame.to_string(),
+            open: candle.open,
+            high: candle.high,
+            low: candle.low,
+            close: candle.close,
+            volume_bs: VBS {
+                buy_base: 0.0,
+                sell_base: 0.0,
+                buy_quote: 0.0,
+                sell_quote: 0.0,
+            },
+            utc_begin: begin,
+            close_time: begin + bucket_ms - 1,
+            trade_count: 0,
+            vwap: 0.0,
+            source: CandleSource::Aggregated,
+            is_final: true,
+            revision: 0,
+        });
+
+        rolled.high = rolled.high.max(candle.high);
+        rolled.low = rolled.low.min(candle.low);
+        rolled.close = candle.close;
+        rolled.volume_bs.buy_base += candle.volume_bs.buy_base;
+        rolled.volume_bs.sell_base += candle.volume_bs.sell_base;
+        rolled.volume_bs.buy_quote += candle.volume_bs.buy_quote;
+        rolled.volume_bs.sell_quote += candle.volume_bs.sell_quote;
+        rolled.trade_count += candle.trade_count;
+        rolled.is_final &= candle.is_final;
+        rolled.revision = rolled.revision.max(candle.revision);
+    }
+
+    let now = Utc::now().timestamp_millis();
+    buckets
+        .into_values()
+        .map(|mut c| {
+            c.vwap = c.volume_bs.vwap();
+            c.is_final &= c.utc_begin + bucket_ms <= now;
+            c
+        })
+        .collect()
+}
+
+// Свечи таймфрейма при чтении: что записано, плюс собранные из минутных для корзин, которых нет.
+// Для таймфреймов, которые не храним, получается чистая сборка из минутных.
+pub async fn candles_with_rollup(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+    prefer: &[CandleSource],
+) -> Result<Vec<Kline>, Box<dyn std::error::Error>> {
+    let stored = store.candles_range(pair, time_frame, start_ts, end_ts, prefer).await?;
+    let (_, bucket_ms) = match rollup_bucket_ms(BASE_TIME_FRAME, time_frame) {
+        Ok(ms) => ms,
+        Err(_) => return Ok(stored),
+    };
+
+    // Корзина целиком: с начала первой до конца последней, попадающей в диапазон
+    let base_start = aggregate::bucket_start(start_ts, bucket_ms);
+    let base_end = aggregate::bucket_start(end_ts, bucket_ms) + bucket_ms - 1;
+    let base = store.candles_range(pair, BASE_TIME_FRAME, base_start, base_end, prefer).await?;
+
+    let mut merged: BTreeMap<i64, Kline> = rollup(&base, time_frame)
+        .into_iter()
+        .filter(|c| c.utc_begin >= start_ts && c.utc_begin <= end_ts)
+        .map(|c| (c.utc_begin, c))
+        .collect();
+    for candle in stored {
+        merged.insert(candle.utc_begin, candle);
+    }
+    Ok(merged.into_values().collect())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RollupReport {
+    pub base_candles: usize,
+    pub written: usize,
+}
+
+// Пакетный пересчёт истории: собирает `time_frame` из минутных свечей за [start_ts, end_ts]
+// окнами по неделе и перезаписывает собранные свечи. Берутся только минутки из трейдов: собранная свеча
+// пишется как aggregated в ту же корзину, что и планировщик, и свечи биржи не должны её подменять
+pub async fn rollup_history(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<RollupReport, Box<dyn std::error::Error>> {
+    let (_, bucket_ms) = rollup_bucket_ms(BASE_TIME_FRAME, time_frame)?;
+    // Окно кратно корзине, чтобы корзина не разрезалась между проходами
+    let window_ms = (BATCH_WINDOW_MS / bucket_ms).max(1) * bucket_ms;
+
+    let mut report = RollupReport::default();
+    let mut from = aggregate::bucket_start(start_ts, bucket_ms);
+    while from <= end_ts {
+        let to = from + window_ms - 1;
+        let mut base = store
+            .candles_range(pair, BASE_TIME_FRAME, from, to, &[CandleSource::Aggregated])
+            .await?;
+        base.retain(|c| c.source == CandleSource::Aggregated);
+        report.base_candles += base.len();
+        let rolled = rollup(&base, time_frame);
+        report.written += rolled.len();
+        store.upsert_candles(rolled).await?;
+        from += window_ms;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN, T0};
+    use crate::storage::{CandleStore, MemoryStore};
+
+    const WEEK: i64 = 7 * 24 * 60 * MIN;
+
+    fn minute(utc_begin: i64, open: f64, high: f64, low: f64, close: f64, trade_count: i64) -> Kline {
+        testing::candle(BASE_TIME_FRAME, utc_begin, close)
+            .with_ohlc(open, high, low, close)
+            .with_volume(1.0, 2.0)
+            .with_trade_count(trade_count)
+    }
+
+    #[test]
+    fn sums_volumes_and_keeps_ohlc_order() {
+        // Не по порядку: open и close берутся по времени
+        let candles = vec![
+            minute(T0 + 2 * MIN, 11.0, 15.0, 10.0, 14.0, 3),
+            minute(T0, 10.0, 12.0, 9.0, 11.0, 2),
+            minute(T0 + MIN, 11.0, 11.5, 8.0, 11.0, 1),
+            minute(T0 + 5 * MIN, 20.0, 21.0, 19.0, 20.0, 4),
+        ];
+        let rolled = rollup(&candles, "5m");
+        assert_eq!(rolled.len(), 2);
+        let c = &rolled[0];
+        assert_eq!((c.time_frame.as_str(), c.utc_begin, c.close_time), ("5m", T0, T0 + 5 * MIN - 1));
+        assert_eq!((c.open, c.high, c.low, c.close), (10.0, 15.0, 8.0, 14.0));
+        let v = &c.volume_bs;
+        assert_eq!((v.buy_base, v.sell_base, v.buy_quote, v.sell_quote), (3.0, 6.0, 36.0, 72.0));
+        assert_eq!(c.trade_count, 6);
+        assert_eq!(c.vwap, 12.0);
+        assert_eq!(c.source, CandleSource::Aggregated);
+        assert!(c.is_final);
+        assert_eq!((rolled[1].utc_begin, rolled[1].trade_count), (T0 + 5 * MIN, 4));
+    }
+
+    #[test]
+    fn final_only_when_every_input_is_final() {
+        let mut open = minute(T0 + MIN, 10.0, 10.0, 10.0, 10.0, 1);
+        open.is_final = false;
+        open.revision = 2;
+        let rolled = rollup(&[minute(T0, 10.0, 10.0, 10.0, 10.0, 1), open], "1h");
+        assert!(!rolled[0].is_final);
+        assert_eq!(rolled[0].revision, 2);
+
+        // Интервал ещё идёт
+        let now = Utc::now().timestamp_millis();
+        let current = aggregate::bucket_start(now, 60 * MIN);
+        assert!(!rollup(&[minute(current, 10.0, 10.0, 10.0, 10.0, 1)], "1h")[0].is_final);
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        let candles = vec![
+            minute(T0 - MIN, 1.0, 1.0, 1.0, 1.0, 1), // воскресенье 23:59
+            minute(T0, 2.0, 2.0, 2.0, 2.0, 1),
+            minute(T0 + WEEK - MIN, 3.0, 3.0, 3.0, 3.0, 1),
+            minute(T0 + WEEK, 4.0, 4.0, 4.0, 4.0, 1),
+        ];
+        let rolled = rollup(&candles, "1w");
+        let weeks: Vec<(i64, f64, f64, i64)> = rolled.iter().map(|c| (c.utc_begin, c.open, c.close, c.trade_count)).collect();
+        assert_eq!(weeks, vec![(T0 - WEEK, 1.0, 1.0, 1), (T0, 2.0, 3.0, 2), (T0 + WEEK, 4.0, 4.0, 1)]);
+        assert_eq!(rolled[1].close_time, T0 + WEEK - 1);
+    }
+
+    #[test]
+    fn rollup_time_frames_must_divide() {
+        assert_eq!(rollup_bucket_ms("1m", "1h"), Ok((MIN, 60 * MIN)));
+        assert!(rollup_bucket_ms("1m", "1m").is_err());
+        assert!(rollup_bucket_ms("1h", "15m").is_err());
+        assert!(rollup_bucket_ms("1m", "7m").is_err());
+        assert!(rollup(&[minute(T0, 1.0, 1.0, 1.0, 1.0, 1)], "7m").is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_fills_missing_buckets_and_history_writes_them() {
+        let store = MemoryStore::default();
+        store
+            .upsert_candles((0..180).map(|i| minute(T0 + i * MIN, 10.0, 11.0, 9.0, 10.0, 1)).collect())
+            .await
+            .unwrap();
+        // Записанная часовая свеча важнее собранной
+        let mut stored = rollup(&[minute(T0 + 60 * MIN, 99.0, 99.0, 99.0, 99.0, 1)], "1h");
+        stored[0].source = CandleSource::Rest;
+        store.upsert_candles(stored).await.unwrap();
+
+        let hours = candles_with_rollup(&store, "BTC_USDT", "1h", T0, T0 + 179 * MIN, &CandleSource::DEFAULT_PREFERENCE)
+            .await
+            .unwrap();
+        let summary: Vec<(i64, f64, i64)> = hours.iter().map(|c| (c.utc_begin, c.open, c.trade_count)).collect();
+        assert_eq!(summary, vec![(T0, 10.0, 60), (T0 + 60 * MIN, 99.0, 1), (T0 + 120 * MIN, 10.0, 60)]);
+
+        let report = rollup_history(&store, "BTC_USDT", "1h", T0, T0 + 179 * MIN).await.unwrap();
+        assert_eq!((report.base_candles, report.written), (180, 3));
+        let written = store
+            .candles_range("BTC_USDT", "1h", T0, T0 + 179 * MIN, &[CandleSource::Aggregated])
+            .await
+            .unwrap();
+        assert_eq!(written.iter().map(|c| c.trade_count).collect::<Vec<_>>(), vec![60, 60, 60]);
+    }
+
+    #[tokio::test]
+    async fn history_ignores_exchange_minutes() {
+        let store = MemoryStore::default();
+        let rest = |utc_begin: i64| {
+            let mut candle = minute(utc_begin, 50.0, 50.0, 50.0, 50.0, 5);
+            candle.source = CandleSource::Rest;
+            candle
+        };
+        // Первый час: свои минутки и поверх них биржевые; второй час - только биржевые,
+        // а его свеча из трейдов уже записана планировщиком
+        let mut candles: Vec<Kline> = (0..60).map(|i| minute(T0 + i * MIN, 10.0, 11.0, 9.0, 10.0, 1)).collect();
+        candles.extend((0..120).map(|i| rest(T0 + i * MIN)));
+        let mut scheduled = rollup(&[minute(T0 + 60 * MIN, 20.0, 20.0, 20.0, 20.0, 7)], "1h");
+        candles.append(&mut scheduled);
+        store.upsert_candles(candles).await.unwrap();
+
+        let report = rollup_history(&store, "BTC_USDT", "1h", T0, T0 + 119 * MIN).await.unwrap();
+        assert_eq!((report.base_candles, report.written), (60, 1));
+        let hours = store
+            .candles_range("BTC_USDT", "1h", T0, T0 + 119 * MIN, &[CandleSource::Aggregated])
+            .await
+            .unwrap();
+        let summary: Vec<(i64, f64, i64)> = hours.iter().map(|c| (c.utc_begin, c.open, c.trade_count)).collect();
+        assert_eq!(summary, vec![(T0, 10.0, 60), (T0 + 60 * MIN, 20.0, 7)]);
+    }
+}
@@ -0,0 +1,601 @@
+use std::env;
+use std::sync::Arc;
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, PAIRS};
+use crate::storage::Storage;
+use crate::websocket::aggregate_trades_to_candles;
+
+#[derive(Debug, Clone)]
+pub struct AggSchedulerConfig {
+    pub settle: Duration,         // пауза после границы интервала, чтобы дождаться опоздавших трейдов
+    pub max_catchup_buckets: i64, // сколько закрытых корзин догоняем после простоя
+    pub grace: Duration,          // сколько после закрытия корзины свеча пересчитывается из-за опоздавших трейдов
+    pub revision_interval: Duration, // как часто проверять закрытые корзины на опоздавшие трейды
+}
+
+impl Default for AggSchedulerConfig {
+    fn default() -> Self {
+        AggSchedulerConfig {
+            settle: Duration::from_secs(5),
+            max_catchup_buckets: 1440,
+            grace: Duration::from_secs(15 * 60),
+            revision_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl AggSchedulerConfig {
+    // AGG_SETTLE_MS, AGG_MAX_CATCHUP_BUCKETS, AGG_GRACE_MS (0 - не пересчитывать), AGG_REVISION_INTERVAL_MS
+    pub fn from_env() -> Self {
+        let default = AggSchedulerConfig::default();
+        let settle = env::var("AGG_SETTLE_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default.settle);
+        let max_catchup_buckets = env::var("AGG_MAX_CATCHUP_BUCKETS")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(default.max_catchup_buckets);
+        let grace = env::var("AGG_GRACE_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default.grace);
+        let revision_interval = env::var("AGG_REVISION_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0)
+            .map(Duration::from_millis)
+            .unwrap_or(default.revision_interval);
+        AggSchedulerConfig {
+            settle,
+            max_catchup_buckets,
+            grace,
+            revision_interval,
+        }
+    }
+}
+
+// Имя отметки задачи агрегации в job_watermarks
+pub fn watermark_name(time_frame: &str, pair: &str) -> String {
+    format!("aggregate:{}:{}", time_frame, pair)
+}
+
+// До какого момента (не включая) закрытые свечи ряда уже записаны агрегацией; без отметки агрегации
+// (таймфрейм строит TimescaleDB) - по часам. Для неизвестного таймфрейма - None.
+pub async fn closed_until(store: &dyn Storage, pair: &str, time_frame: &str) -> Result<Option<i64>, sqlx::Error> {
+    if let Some(mark) = store.watermark(&watermark_name(time_frame, pair)).await? {
+        return Ok(Some(mark));
+    }
+    Ok(aggregate::time_frame_ms(time_frame).map(|ms| aggregate::bucket_start(Utc::now().timestamp_millis(), ms)))
+}
+
+// Момент следующего запуска: ближайшая граница интервала плюс пауза на опоздавшие трейды
+pub fn next_run(now_ms: i64, bucket_ms: i64, settle_ms: i64) -> i64 {
+    aggregate::bucket_start(now_ms - settle_ms, bucket_ms) + bucket_ms + settle_ms
+}
+
+// Агрегация трейдов в свечи таймфрейма по часам: запускается сразу после каждой границы интервала
+// и обрабатывает ровно закрытые корзины с прошлого запуска. Отметка хранится в БД,
+// поэтому после перезапуска агрегация продолжается с того же места.
+pub async fn run_aggregation(store: Arc<dyn Storage>, time_frame: &'static str, config: AggSchedulerConfig) {
+    let bucket_ms = match aggregate::time_frame_ms(time_frame) {
+        Some(ms) => ms,
+        None => {
+            eprintln!("Неизвестный таймфрейм для планировщика агрегации: {}", time_frame);
+            return;
+        }
+    };
+    let settle_ms = config.settle.as_millis() as i64;
+
+    loop {
+        // Сначала всё уже закрытое (при старте - и то, что закрылось за время простоя), потом ждём границы
+        let now = Utc::now().timestamp_millis();
+        let closed_until = aggregate::bucket_start(now - settle_ms, bucket_ms);
+        for &pair in PAIRS.iter() {
+            if let Err(e) = aggregate_closed(&store, pair, time_frame, bucket_ms, closed_until, &config).await {
+                eprintln!("Ошибка агрегации {} для пары {}: {}", time_frame, pair, e);
+            }
+        }
+
+        let now = Utc::now().timestamp_millis();
+        let wait = (next_run(now, bucket_ms, settle_ms) - now).max(0);
+        sleep(Duration::from_millis(wait as u64)).await;
+    }
+}
+
+// Агрегирует корзины пары от отметки до closed_until (не включая) и двигает отметку
+async fn aggregate_closed(
+    store: &Arc<dyn Storage>,
+    pair: &str,
+    time_frame: &str,
+    bucket_ms: i64,
+    closed_until: i64,
+    config: &AggSchedulerConfig,
+) -> Result<(), sqlx::Error> {
+    let name = watermark_name(time_frame, pair);
+    let earliest = closed_until - bucket_ms * config.max_catchup_buckets;
+    let from = match store.watermark(&name).await? {
+        Some(mark) if mark < earliest => {
+            eprintln!(
+                "Агрегация {} {}: пропущено {} корзин сверх лимита догона",
+                pair,
+                time_frame,
+                (earliest - mark) / bucket_ms
+            );
+            earliest
+        }
+        Some(mark) => mark,
+        None => closed_until - bucket_ms,
+    };
+    if from >= closed_until {
+        return Ok(());
+    }
+
+    println!("Агрегация свечей {} {}: {} - {}", pair, time_frame, from, closed_until);
+    aggregate_trades_to_candles(Arc::clone(store), pair, time_frame, from, closed_until - 1).await?;
+    store.set_watermark(&name, closed_until).await
+}
+
+// Пересчёт закрытых свечей по опоздавшим трейдам: корзины, уже обработанные агрегацией и закрывшиеся
+// не раньше чем `grace` назад, пересобираются из трейдов БД. Изменившаяся свеча перезаписывается
+// с ревизией на единицу больше, каждая ревизия пишется в лог.
+pub async fn run_revisions(store: Arc<dyn Storage>, time_frames: Vec<&'static str>, config: AggSchedulerConfig) {
+    if config.grace.is_zero() {
+        println!("Пересчёт закрытых свечей отключён (AGG_GRACE_MS=0)");
+        return;
+    }
+    let grace_ms = config.grace.as_millis() as i64;
+    let mut tick = interval(config.revision_interval);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut revised_total: u64 = 0;
+
+    loop {
+        tick.tick().await;
+        let now = Utc::now().timestamp_millis();
+        for &time_frame in &time_frames {
+            for &pair in PAIRS.iter() {
+                match revise_closed(&*store, pair, time_frame, now, grace_ms).await {
+                    Ok(revised) => {
+                        for candle in &revised {
+                            revised_total += 1;
+                            println!(
+                                "Свеча {} {} {} пересчитана по опоздавшим трейдам: ревизия {}, трейдов {} (всего ревизий {})",
+                                pair, time_frame, candle.utc_begin, candle.revision, candle.trade_count, revised_total
+                            );
+                        }
+                    }
+                    Err(e) => eprintln!("Ошибка пересчёта свечей {} {}: {}", pair, time_frame, e),
+                }
+            }
+        }
+    }
+}
+
+// Пересобирает корзины пары, закрывшиеся в [now_ms - grace_ms, отметка агрегации), и записывает те,
+// что разошлись с сохранёнными. Возвращает записанные свечи.
+pub async fn revise_closed(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    now_ms: i64,
+    grace_ms: i64,
+) -> Result<Vec<Kline>, sqlx::Error> {
+    let bucket_ms = match aggregate::time_frame_ms(time_frame) {
+        Some(ms) => ms,
+        None => return Ok(Vec::new()),
+    };
+    // Корзины за отметкой ещё не агрегированы: их посчитает планировщик
+    let mark = match store.watermark(&watermark_name(time_frame, pair)).await? {
+        Some(mark) => mark,
+        None => return Ok(Vec::new()),
+    };
+    // Первая корзина, закончившаяся позже now - grace
+    let from = aggregate::bucket_start(now_ms - grace_ms, bucket_ms);
+    if from >= mark {
+        return Ok(Vec::new());
+    }
+
+    let fresh = store.aggregate_candles(pair, time_frame, from, mark - 1).await?;
+    if fresh.is_empty() {
+        return Ok(Vec::new());
+    }
+    let stored = store
+        .candles_range(pair, time_frame, from, mark - 1, &[CandleSource::Aggregated])
+        .await?;
+
+    let mut revised = Vec::new();
+    for mut candle in fresh {
+        let previous = stored
+            .iter()
+            .find(|c| c.utc_begin == candle.utc_begin && c.source == CandleSource::Aggregated);
+        match previous {
+            Some(prev) if !candle_changed(prev, &candle) => continue,
+            // Корзина была пустой, а трейд пришёл позже - тоже изменение опубликованного
+            Some(prev) => candle.revision = prev.revision + 1,
+            None => candle.revision = 1,
+        }
+        revised.push(candle);
+    }
+    store.upsert_candles(revised.clone()).await?;
+    Ok(revised)
+}
+
+// Свеча из тех же трейдов получается той же; суммы объёмов сравниваем с допуском на округление
+fn candle_changed(stored: &Kline, fresh: &Kline) -> bool {
+    let differs = |a: f64, b: f64| (a - b).abs() > 1e-9 * a.abs().max(b.abs()).max(1.0);
+    stored.trade_count != fresh.trade_count
+        || differs(stored.open, fresh.open)
+        || differs(stored.high, fresh.high)
+        || differs(stored.low, fresh.low)
+        || differs(stored.close, fresh.close)
+        || differs(stored.volume_bs.buy_base, fresh.volume_bs.buy_base)
+        || differs(stored.volume_bs.sell_base, fresh.volume_bs.sell_base)
+        || differs(stored.volume_bs.buy_quote, fresh.volume_bs.buy_quote)
+        || differs(stored.volume_bs.sell_quote, fresh.volume_bs.sell_quote)
+}
+
+// Как часто живые расчёты проверяют, не закрылись ли новые свечи
+const LIVE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Сколько корзин таймфрейма читается за один запрос при пакетном расчёте
+pub const BATCH_BUCKETS: i64 = 10_000;
+
+// Ряд живого расчёта по свечам, закрытым агрегацией: индикатор, поток ордеров, волатильность и т.п.
+// Сам ряд - ключ (пара, таймфрейм, параметры); накопленное состояние живёт в State.
+#[async_trait]
+pub trait LiveSeries: Send + Sync + 'static {
+    type State: Clone + Send + Sync;
+    type Row: Send + Sync;
+
+    // Ряд в сообщениях лога: "потока ордеров BTC_USDT 1m"
+    fn label(&self) -> String;
+    // Имя отметки ряда в job_watermarks
+    fn watermark_name(&self) -> String;
+    // До какого момента (не включая) входные свечи ряда закрыты
+    async fn closed_until(&self, store: &dyn Storage) -> Result<Option<i64>, sqlx::Error>;
+    // Состояние, прогретое на свечах до before_ts; None - ряд не считается
+    async fn warm_up(&self, store: &dyn Storage, before_ts: i64) -> Result<Option<Self::State>, sqlx::Error>;
+    // Учитывает корзины [from, until) и возвращает строки для записи
+    async fn push(&self, store: &dyn Storage, state: &mut Self::State, from: i64, until: i64)
+        -> Result<Vec<Self::Row>, sqlx::Error>;
+    async fn write(&self, store: &dyn Storage, rows: &[Self::Row]) -> Result<(), sqlx::Error>;
+}
+
+struct LiveRun<S: LiveSeries> {
+    series: S,
+    state: S::State,
+    mark: i64, // до какой свечи (не включая) ряд досчитан
+}
+
+// Живой расчёт рядов: закрытые свечи учитываются по мере закрытия, отметка каждого ряда хранится в БД,
+// после перезапуска ряд прогревается на свечах до отметки и продолжает с неё
+pub async fn run_live<S: LiveSeries>(store: Arc<dyn Storage>, title: &str, series: Vec<S>) {
+    let mut runs = Vec::new();
+    for series in series {
+        let label = series.label();
+        match resume_live(&*store, series).await {
+            Ok(Some(run)) => runs.push(run),
+            Ok(None) => {}
+            Err(e) => eprintln!("Ошибка прогрева {}: {}", label, e),
+        }
+    }
+    println!("Живой расчёт {}: {} рядов", title, runs.len());
+
+    let mut tick = interval(LIVE_POLL_INTERVAL);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        for run in runs.iter_mut() {
+            if let Err(e) = advance_live(&*store, run).await {
+                eprintln!("Ошибка расчёта {}: {}", run.series.label(), e);
+            }
+        }
+    }
+}
+
+async fn resume_live<S: LiveSeries>(store: &dyn Storage, series: S) -> Result<Option<LiveRun<S>>, sqlx::Error> {
+    let name = series.watermark_name();
+    // Новый ряд начинает с текущего момента, история досчитывается пакетной командой
+    let mark = match store.watermark(&name).await? {
+        Some(mark) => mark,
+        None => match series.closed_until(store).await? {
+            Some(mark) => {
+                store.set_watermark(&name, mark).await?;
+                mark
+            }
+            None => return Ok(None),
+        },
+    };
+    Ok(series.warm_up(store, mark).await?.map(|state| LiveRun { series, state, mark }))
+}
+
+async fn advance_live<S: LiveSeries>(store: &dyn Storage, run: &mut LiveRun<S>) -> Result<(), sqlx::Error> {
+    let until = match run.series.closed_until(store).await? {
+        Some(until) if until > run.mark => until,
+        _ => return Ok(()),
+    };
+    // Состояние меняется только после записи, иначе при повторе свечи учлись бы дважды
+    let mut state = run.state.clone();
+    let rows = run.series.push(store, &mut state, run.mark, until).await?;
+    run.series.write(store, &rows).await?;
+    store.set_watermark(&run.series.watermark_name(), until).await?;
+    run.state = state;
+    run.mark = until;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::RecentTrade;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use crate::storage::{MemoryStore, TradeStore, WatermarkStore};
+
+    // 2024-01-01 00:00 UTC
+    const T0: i64 = 1_704_067_200_000;
+    const MIN: i64 = 60_000;
+    const GRACE: i64 = 15 * MIN;
+
+    fn trade(ts: i64, tid: &str, price: f64) -> RecentTrade {
+        RecentTrade {
+            tid: tid.to_string(),
+            pair: "BTC_USDT".to_string(),
+            price: price.to_string(),
+            amount: price.to_string(),
+            quantity: "1".to_string(),
+            side: "buy".to_string(),
+            create_time: ts,
+            timestamp: ts,
+        }
+    }
+
+    fn config(max_catchup_buckets: i64) -> AggSchedulerConfig {
+        AggSchedulerConfig {
+            max_catchup_buckets,
+            ..AggSchedulerConfig::default()
+        }
+    }
+
+    async fn minutes(store: &dyn Storage) -> Vec<(i64, i64, i64)> {
+        store
+            .candles_range("BTC_USDT", "1m", T0 - 60 * MIN, T0 + 60 * MIN, &[CandleSource::Aggregated])
+            .await
+            .unwrap()
+            .iter()
+            .map(|c| (c.utc_begin, c.trade_count, c.revision))
+            .collect()
+    }
+
+    async fn mark(store: &dyn Storage) -> Option<i64> {
+        store.watermark(&watermark_name("1m", "BTC_USDT")).await.unwrap()
+    }
+
+    #[test]
+    fn next_run_is_boundary_plus_settle() {
+        assert_eq!(next_run(T0 + 10_000, MIN, 5_000), T0 + MIN + 5_000);
+        // Граница прошла, но пауза ещё нет - запуск на этой же границе
+        assert_eq!(next_run(T0 + 3_000, MIN, 5_000), T0 + 5_000);
+        assert_eq!(next_run(T0 + 5_000, MIN, 5_000), T0 + MIN + 5_000);
+        assert_eq!(next_run(T0, MIN, 0), T0 + MIN);
+        assert_eq!(next_run(T0 - 1, 60 * MIN, 0), T0);
+    }
+
+    #[tokio::test]
+    async fn first_run_aggregates_only_the_last_closed_bucket() {
+        let store: Arc<dyn Storage> = Arc::new(MemoryStore::default());
+        store
+            .insert_trades(&[trade(T0 + MIN + 1_000, "1", 100.0), trade(T0 + 2 * MIN + 1_000, "2", 101.0)])
+            .await
+            .unwrap();
+
+        aggregate_closed(&store, "BTC_USDT", "1m", MIN, T0 + 3 * MIN, &config(1440)).await.unwrap();
+        assert_eq!(minutes(&*store).await, vec![(T0 + 2 * MIN, 1, 0)]);
+        assert_eq!(mark(&*store).await, Some(T0 + 3 * MIN));
+    }
+
+    #[tokio::test]
+    async fn resumes_from_stored_watermark() {
+        let store: Arc<dyn Storage> = Arc::new(MemoryStore::default());
+        let trades: Vec<RecentTrade> = (0..4).map(|i| trade(T0 + i * MIN + 1_000, &i.to_string(), 100.0)).collect();
+        store.insert_trades(&trades).await.unwrap();
+        store.set_watermark(&watermark_name("1m", "BTC_USDT"), T0 + MIN).await.unwrap();
+
+        aggregate_closed(&store, "BTC_USDT", "1m", MIN, T0 + 3 * MIN, &config(1440)).await.unwrap();
+        assert_eq!(minutes(&*store).await, vec![(T0 + MIN, 1, 0), (T0 + 2 * MIN, 1, 0)]);
+        assert_eq!(mark(&*store).await, Some(T0 + 3 * MIN));
+
+        // Повторный запуск до следующей границы ничего не делает
+        aggregate_closed(&store, "BTC_USDT", "1m", MIN, T0 + 3 * MIN, &config(1440)).await.unwrap();
+        assert_eq!(minutes(&*store).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn catchup_is_limited_to_max_buckets() {
+        let store: Arc<dyn Storage> = Arc::new(MemoryStore::default());
+        let trades: Vec<RecentTrade> = (0..5).map(|i| trade(T0 + i * MIN + 1_000, &i.to_string(), 100.0)).collect();
+        store.insert_trades(&trades).await.unwrap();
+        store.set_watermark(&watermark_name("1m", "BTC_USDT"), T0).await.unwrap();
+
+        aggregate_closed(&store, "BTC_USDT", "1m", MIN, T0 + 5 * MIN, &config(2)).await.unwrap();
+        assert_eq!(minutes(&*store).await, vec![(T0 + 3 * MIN, 1, 0), (T0 + 4 * MIN, 1, 0)]);
+        assert_eq!(mark(&*store).await, Some(T0 + 5 * MIN));
+    }
+
+    #[tokio::test]
+    async fn closed_until_prefers_watermark_then_clock() {
+        let store = MemoryStore::default();
+        assert_eq!(closed_until(&store, "BTC_USDT", "7m").await.unwrap(), None);
+
+        let before = aggregate::bucket_start(Utc::now().timestamp_millis(), MIN);
+        let fallback = closed_until(&store, "BTC_USDT", "1m").await.unwrap().unwrap();
+        let after = aggregate::bucket_start(Utc::now().timestamp_millis(), MIN);
+        assert!(before <= fallback && fallback <= after);
+        assert_eq!(fallback % MIN, 0);
+
+        store.set_watermark(&watermark_name("1m", "BTC_USDT"), T0).await.unwrap();
+        assert_eq!(closed_until(&store, "BTC_USDT", "1m").await.unwrap(), Some(T0));
+    }
+
+    // Корзины T0 и T0 + MIN обработаны агрегацией, в T0 один трейд, T0 + MIN пустая
+    async fn aggregated() -> Arc<dyn Storage> {
+        let store: Arc<dyn Storage> = Arc::new(MemoryStore::default());
+        store.insert_trades(&[trade(T0 + 1_000, "1", 100.0)]).await.unwrap();
+        store.set_watermark(&watermark_name("1m", "BTC_USDT"), T0).await.unwrap();
+        aggregate_closed(&store, "BTC_USDT", "1m", MIN, T0 + 2 * MIN, &config(1440)).await.unwrap();
+        assert_eq!(minutes(&*store).await, vec![(T0, 1, 0)]);
+        store
+    }
+
+    #[tokio::test]
+    async fn late_trade_within_grace_bumps_revision() {
+        let store = aggregated().await;
+        let now = T0 + 2 * MIN + 10_000;
+        store
+            .insert_trades(&[trade(T0 + 30_000, "2", 105.0), trade(T0 + MIN + 5_000, "3", 99.0)])
+            .await
+            .unwrap();
+
+        let revised = revise_closed(&*store, "BTC_USDT", "1m", now, GRACE).await.unwrap();
+        let summary: Vec<(i64, i64, i64)> = revised.iter().map(|c| (c.utc_begin, c.trade_count, c.revision)).collect();
+        // Записанная свеча - ревизия 1, пустая до этого корзина - тоже 1
+        assert_eq!(summary, vec![(T0, 2, 1), (T0 + MIN, 1, 1)]);
+        assert_eq!(minutes(&*store).await, vec![(T0, 2, 1), (T0 + MIN, 1, 1)]);
+        assert_eq!(revised[0].close, 105.0);
+
+        store.insert_trades(&[trade(T0 + 40_000, "4", 90.0)]).await.unwrap();
+        let revised = revise_closed(&*store, "BTC_USDT", "1m", now, GRACE).await.unwrap();
+        assert_eq!(revised.iter().map(|c| (c.utc_begin, c.revision)).collect::<Vec<_>>(), vec![(T0, 2)]);
+    }
+
+    #[tokio::test]
+    async fn unchanged_candle_is_not_rewritten() {
+        let store = aggregated().await;
+        let revised = revise_closed(&*store, "BTC_USDT", "1m", T0 + 2 * MIN + 10_000, GRACE).await.unwrap();
+        assert!(revised.is_empty());
+        assert_eq!(minutes(&*store).await, vec![(T0, 1, 0)]);
+    }
+
+    #[tokio::test]
+    async fn candles_past_grace_are_left_alone() {
+        let store = aggregated().await;
+        store.insert_trades(&[trade(T0 + 30_000, "2", 105.0)]).await.unwrap();
+        let revised = revise_closed(&*store, "BTC_USDT", "1m", T0 + 2 * MIN + GRACE, GRACE).await.unwrap();
+        assert!(revised.is_empty());
+        assert_eq!(minutes(&*store).await, vec![(T0, 1, 0)]);
+
+        // Без отметки агрегации корзины ещё не опубликованы
+        let fresh = MemoryStore::default();
+        fresh.insert_trades(&[trade(T0 + 30_000, "2", 105.0)]).await.unwrap();
+        assert!(revise_closed(&fresh, "BTC_USDT", "1m", T0 + MIN, GRACE).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn zero_grace_disables_revisions() {
+        env::set_var("AGG_GRACE_MS", "0");
+        let config = AggSchedulerConfig::from_env();
+        env::remove_var("AGG_GRACE_MS");
+        assert!(config.grace.is_zero());
+
+        let store = aggregated().await;
+        store.insert_trades(&[trade(T0 + 30_000, "2", 105.0)]).await.unwrap();
+        // С нулевым grace задача сразу завершается и ничего не трогает
+        tokio::time::timeout(Duration::from_secs(1), run_revisions(Arc::clone(&store), vec!["1m"], config))
+            .await
+            .expect("пересчёт с AGG_GRACE_MS=0 должен завершиться сразу");
+        assert_eq!(minutes(&*store).await, vec![(T0, 1, 0)]);
+    }
+
+    #[test]
+    fn volume_rounding_is_not_a_change() {
+        let candle = aggregate::aggregate_trades("BTC_USDT", "1m", &[trade(T0, "1", 100.0)]).unwrap().remove(0);
+        let mut rounded = candle.clone();
+        rounded.volume_bs.buy_quote += 1e-12;
+        assert!(!candle_changed(&candle, &rounded));
+        rounded.trade_count += 1;
+        assert!(candle_changed(&candle, &rounded));
+    }
+
+    // Ряд-счётчик: закрытая граница берётся из отметки "test:closed", запись можно сломать
+    struct Counter {
+        fail: AtomicBool,
+        written: Mutex<Vec<(i64, i64)>>,
+    }
+
+    fn counter() -> Counter {
+        Counter {
+            fail: AtomicBool::new(false),
+            written: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[async_trait]
+    impl LiveSeries for Counter {
+        type State = u32;
+        type Row = (i64, i64);
+
+        fn label(&self) -> String {
+            "счётчика".to_string()
+        }
+
+        fn watermark_name(&self) -> String {
+            "test:counter".to_string()
+        }
+
+        async fn closed_until(&self, store: &dyn Storage) -> Result<Option<i64>, sqlx::Error> {
+            store.watermark("test:closed").await
+        }
+
+        async fn warm_up(&self, _store: &dyn Storage, _before_ts: i64) -> Result<Option<u32>, sqlx::Error> {
+            Ok(Some(0))
+        }
+
+        async fn push(&self, _: &dyn Storage, pushes: &mut u32, from: i64, until: i64)
+            -> Result<Vec<(i64, i64)>, sqlx::Error> {
+            *pushes += 1;
+            Ok(vec![(from, until)])
+        }
+
+        async fn write(&self, _store: &dyn Storage, rows: &[(i64, i64)]) -> Result<(), sqlx::Error> {
+            if self.fail.load(Ordering::SeqCst) {
+                return Err(sqlx::Error::PoolClosed);
+            }
+            self.written.lock().unwrap().extend_from_slice(rows);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn live_series_advance_only_after_write() {
+        let store = MemoryStore::default();
+        // Пока граница неизвестна, ряд не запускается
+        assert!(resume_live(&store, counter()).await.unwrap().is_none());
+
+        store.set_watermark("test:closed", T0).await.unwrap();
+        let mut run = resume_live(&store, counter()).await.unwrap().unwrap();
+        // Новый ряд начинает с текущей границы
+        assert_eq!((run.mark, store.watermark("test:counter").await.unwrap()), (T0, Some(T0)));
+        advance_live(&store, &mut run).await.unwrap();
+        assert_eq!(run.state, 0);
+
+        store.set_watermark("test:closed", T0 + MIN).await.unwrap();
+        run.series.fail.store(true, Ordering::SeqCst);
+        assert!(advance_live(&store, &mut run).await.is_err());
+        assert_eq!((run.state, run.mark), (0, T0));
+        assert_eq!(store.watermark("test:counter").await.unwrap(), Some(T0));
+
+        run.series.fail.store(false, Ordering::SeqCst);
+        advance_live(&store, &mut run).await.unwrap();
+        assert_eq!((run.state, run.mark), (1, T0 + MIN));
+        assert_eq!(store.watermark("test:counter").await.unwrap(), Some(T0 + MIN));
+        assert_eq!(*run.series.written.lock().unwrap(), vec![(T0, T0 + MIN)]);
+
+        // После перезапуска ряд продолжает с сохранённой отметки
+        assert_eq!(resume_live(&store, counter()).await.unwrap().unwrap().mark, T0 + MIN);
+    }
+}
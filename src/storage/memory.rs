@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use async_trait::async_trait;
+use crate::data_structs::{
+    Bar, BarSpec, CandleSource, CorrelationMatrix, IndicatorValue, Kline, OrderFlow, RecentTrade, Volatility,
+    VolumeProfile,
+};
+use super::{CandleStore, RetentionStore, SeriesStore, Storage, TradeStore, WatermarkStore};
+
+type CandleKey = (String, String, i64); // (pair, time_frame, utc_begin)
+type IndicatorKey = (String, String, String, String, i64); // (pair, time_frame, indicator, params, utc_begin)
+type ProfileKey = (String, String, String, i64); // (pair, period, bins, utc_begin)
+type VolatilityKey = (String, String, String, i64, i64); // (pair, time_frame, estimator, window, utc_begin)
+type CorrelationKey = (String, i64, i64); // (time_frame, window, utc_begin)
+
+// Хранилище в памяти процесса: для тестов и прогонов без БД. Покрывает всё, что идёт через трейты хранилища
+// (конвейер, расчёты, экспорт, импорт); аудит и запросы db.rs работают только с Postgres.
+// В корзине по одной свече на источник.
+#[derive(Default)]
+pub struct MemoryStore {
+    candles: Mutex<BTreeMap<CandleKey, Vec<Kline>>>,
+    trades: Mutex<HashMap<String, RecentTrade>>,
+    watermarks: Mutex<HashMap<String, i64>>,
+    bars: Mutex<Vec<Bar>>,
+    indicators: Mutex<BTreeMap<IndicatorKey, IndicatorValue>>,
+    order_flow: Mutex<BTreeMap<CandleKey, OrderFlow>>,
+    volume_profiles: Mutex<BTreeMap<ProfileKey, VolumeProfile>>,
+    volatility: Mutex<BTreeMap<VolatilityKey, Volatility>>,
+    correlations: Mutex<BTreeMap<CorrelationKey, CorrelationMatrix>>,
+    #[cfg(test)]
+    failing_trades: std::sync::atomic::AtomicBool, // запись трейдов падает, как при недоступной БД
+}
+
+impl MemoryStore {
+    fn candle_key(candle: &Kline) -> CandleKey {
+        (candle.pair.clone(), candle.time_frame.clone(), candle.utc_begin)
+    }
+
+    fn preferred(bucket: &[Kline], prefer: &[CandleSource]) -> Option<Kline> {
+        bucket.iter().min_by_key(|c| c.source.preference_key(prefer)).cloned()
+    }
+
+    #[cfg(test)]
+    pub fn fail_trade_writes(&self, fail: bool) {
+        self.failing_trades.store(fail, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl CandleStore for MemoryStore {
+    async fn insert_candles(&self, candles: Vec<Kline>) -> Result<u64, sqlx::Error> {
+        let mut stored = self.candles.lock().unwrap();
+        let mut inserted = 0;
+        for candle in candles {
+            let bucket = stored.entry(Self::candle_key(&candle)).or_default();
+            if !bucket.iter().any(|c| c.source == candle.source) {
+                bucket.push(candle);
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    async fn upsert_candles(&self, candles: Vec<Kline>) -> Result<(), sqlx::Error> {
+        let mut stored = self.candles.lock().unwrap();
+        for mut candle in candles {
+            let bucket = stored.entry(Self::candle_key(&candle)).or_default();
+            if let Some(pos) = bucket.iter().position(|c| c.source == candle.source) {
+                // Ревизия не откатывается при обычной перезаписи, как и в SQL-хранилищах
+                candle.revision = candle.revision.max(bucket.remove(pos).revision);
+            }
+            bucket.push(candle);
+        }
+        Ok(())
+    }
+
+    async fn candles_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+        prefer: &[CandleSource],
+    ) -> Result<Vec<Kline>, sqlx::Error> {
+        if start_ts > end_ts {
+            return Ok(Vec::new());
+        }
+        let stored = self.candles.lock().unwrap();
+        let from = (pair.to_string(), time_frame.to_string(), start_ts);
+        let to = (pair.to_string(), time_frame.to_string(), end_ts);
+        Ok(stored
+            .range(from..=to)
+            .filter_map(|(_, bucket)| Self::preferred(bucket, prefer))
+            .collect())
+    }
+
+    async fn latest_candle(&self, pair: &str, time_frame: &str) -> Result<Option<Kline>, sqlx::Error> {
+        let stored = self.candles.lock().unwrap();
+        let from = (pair.to_string(), time_frame.to_string(), i64::MIN);
+        let to = (pair.to_string(), time_frame.to_string(), i64::MAX);
+        Ok(stored
+            .range(from..=to)
+            .next_back()
+            .and_then(|(_, bucket)| Self::preferred(bucket, &CandleSource::DEFAULT_PREFERENCE)))
+    }
+}
+
+#[async_trait]
+impl TradeStore for MemoryStore {
+    async fn insert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error> {
+        #[cfg(test)]
+        if self.failing_trades.load(std::sync::atomic::Ordering::Relaxed) {
+            return Err(sqlx::Error::PoolClosed);
+        }
+        let mut stored = self.trades.lock().unwrap();
+        let mut inserted = 0;
+        for trade in trades {
+            if !stored.contains_key(&trade.tid) {
+                stored.insert(trade.tid.clone(), trade.clone());
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    async fn upsert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error> {
+        let mut stored = self.trades.lock().unwrap();
+        for trade in trades {
+            stored.insert(trade.tid.clone(), trade.clone());
+        }
+        Ok(trades.len() as u64)
+    }
+
+    async fn trades_range(&self, pair: &str, start_ts: i64, end_ts: i64) -> Result<Vec<RecentTrade>, sqlx::Error> {
+        let stored = self.trades.lock().unwrap();
+        let mut result: Vec<RecentTrade> = stored
+            .values()
+            .filter(|t| t.pair == pair && t.timestamp >= start_ts && t.timestamp <= end_ts)
+            .cloned()
+            .collect();
+        result.sort_by(crate::aggregate::trade_order);
+        Ok(result)
+    }
+
+    async fn latest_trade(&self, pair: &str) -> Result<Option<RecentTrade>, sqlx::Error> {
+        let stored = self.trades.lock().unwrap();
+        Ok(stored
+            .values()
+            .filter(|t| t.pair == pair)
+            .max_by(|a, b| crate::aggregate::trade_order(a, b))
+            .cloned())
+    }
+}
+
+#[async_trait]
+impl WatermarkStore for MemoryStore {
+    async fn watermark(&self, name: &str) -> Result<Option<i64>, sqlx::Error> {
+        Ok(self.watermarks.lock().unwrap().get(name).copied())
+    }
+
+    async fn set_watermark(&self, name: &str, value: i64) -> Result<(), sqlx::Error> {
+        self.watermarks.lock().unwrap().insert(name.to_string(), value);
+        Ok(())
+    }
+}
+
+fn same_series(bar: &Bar, pair: &str, spec: &BarSpec) -> bool {
+    bar.pair == pair && bar.spec == *spec
+}
+
+#[async_trait]
+impl SeriesStore for MemoryStore {
+    async fn upsert_bars(&self, bars: &[Bar]) -> Result<(), sqlx::Error> {
+        let mut stored = self.bars.lock().unwrap();
+        for bar in bars {
+            stored.retain(|b| {
+                !(same_series(b, &bar.pair, &bar.spec) && b.utc_begin == bar.utc_begin && b.first_tid == bar.first_tid)
+            });
+            stored.push(bar.clone());
+        }
+        Ok(())
+    }
+
+    async fn bars_range(&self, pair: &str, spec: &BarSpec, start_ts: i64, end_ts: i64) -> Result<Vec<Bar>, sqlx::Error> {
+        let stored = self.bars.lock().unwrap();
+        let mut result: Vec<Bar> = stored
+            .iter()
+            .filter(|b| same_series(b, pair, spec) && b.utc_begin >= start_ts && b.utc_begin <= end_ts)
+            .cloned()
+            .collect();
+        result.sort_by(crate::aggregate::bar_order);
+        Ok(result)
+    }
+
+    async fn latest_bar(&self, pair: &str, spec: &BarSpec) -> Result<Option<Bar>, sqlx::Error> {
+        let stored = self.bars.lock().unwrap();
+        Ok(stored
+            .iter()
+            .filter(|b| same_series(b, pair, spec))
+            .max_by(|a, b| {
+                crate::aggregate::trade_key_order((a.utc_end, &a.last_tid), (b.utc_end, &b.last_tid))
+            })
+            .cloned())
+    }
+
+    async fn delete_bars_from(&self, pair: &str, spec: &BarSpec, from_ts: i64) -> Result<u64, sqlx::Error> {
+        let mut stored = self.bars.lock().unwrap();
+        let before = stored.len();
+        stored.retain(|b| !(same_series(b, pair, spec) && b.utc_end >= from_ts));
+        Ok((before - stored.len()) as u64)
+    }
+
+    async fn upsert_indicator_values(&self, values: &[IndicatorValue]) -> Result<(), sqlx::Error> {
+        let mut stored = self.indicators.lock().unwrap();
+        for value in values {
+            let key = (
+                value.pair.clone(),
+                value.time_frame.clone(),
+                value.indicator.clone(),
+                value.params.clone(),
+                value.utc_begin,
+            );
+            stored.insert(key, value.clone());
+        }
+        Ok(())
+    }
+
+    async fn indicator_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        indicator: &str,
+        params: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<IndicatorValue>, sqlx::Error> {
+        if start_ts > end_ts {
+            return Ok(Vec::new());
+        }
+        let stored = self.indicators.lock().unwrap();
+        let key = |ts: i64| (pair.to_string(), time_frame.to_string(), indicator.to_string(), params.to_string(), ts);
+        Ok(stored.range(key(start_ts)..=key(end_ts)).map(|(_, v)| v.clone()).collect())
+    }
+
+    async fn upsert_order_flow(&self, rows: &[OrderFlow]) -> Result<(), sqlx::Error> {
+        let mut stored = self.order_flow.lock().unwrap();
+        for row in rows {
+            stored.insert((row.pair.clone(), row.time_frame.clone(), row.utc_begin), row.clone());
+        }
+        Ok(())
+    }
+
+    async fn order_flow_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<OrderFlow>, sqlx::Error> {
+        if start_ts > end_ts {
+            return Ok(Vec::new());
+        }
+        let stored = self.order_flow.lock().unwrap();
+        let from = (pair.to_string(), time_frame.to_string(), start_ts);
+        let to = (pair.to_string(), time_frame.to_string(), end_ts);
+        Ok(stored.range(from..=to).map(|(_, row)| row.clone()).collect())
+    }
+
+    async fn replace_volume_profiles(&self, profiles: &[VolumeProfile]) -> Result<(), sqlx::Error> {
+        let mut stored = self.volume_profiles.lock().unwrap();
+        for profile in profiles {
+            let key = (profile.pair.clone(), profile.period.clone(), profile.bins.clone(), profile.utc_begin);
+            stored.insert(key, profile.clone());
+        }
+        Ok(())
+    }
+
+    async fn volume_profiles_range(
+        &self,
+        pair: &str,
+        period: &str,
+        bins: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<VolumeProfile>, sqlx::Error> {
+        if start_ts > end_ts {
+            return Ok(Vec::new());
+        }
+        let stored = self.volume_profiles.lock().unwrap();
+        let key = |ts: i64| (pair.to_string(), period.to_string(), bins.to_string(), ts);
+        Ok(stored.range(key(start_ts)..=key(end_ts)).map(|(_, p)| p.clone()).collect())
+    }
+
+    async fn upsert_volatility(&self, rows: &[Volatility]) -> Result<(), sqlx::Error> {
+        let mut stored = self.volatility.lock().unwrap();
+        for row in rows {
+            let key = (row.pair.clone(), row.time_frame.clone(), row.estimator.clone(), row.window, row.utc_begin);
+            stored.insert(key, row.clone());
+        }
+        Ok(())
+    }
+
+    async fn volatility_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        estimator: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<Volatility>, sqlx::Error> {
+        if start_ts > end_ts {
+            return Ok(Vec::new());
+        }
+        let stored = self.volatility.lock().unwrap();
+        let key = |ts: i64| (pair.to_string(), time_frame.to_string(), estimator.to_string(), window, ts);
+        Ok(stored.range(key(start_ts)..=key(end_ts)).map(|(_, row)| row.clone()).collect())
+    }
+
+    async fn upsert_correlations(&self, matrices: &[CorrelationMatrix]) -> Result<(), sqlx::Error> {
+        let mut stored = self.correlations.lock().unwrap();
+        for matrix in matrices {
+            stored.insert((matrix.time_frame.clone(), matrix.window, matrix.utc_begin), matrix.clone());
+        }
+        Ok(())
+    }
+
+    async fn correlation_range(
+        &self,
+        time_frame: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<CorrelationMatrix>, sqlx::Error> {
+        if start_ts > end_ts {
+            return Ok(Vec::new());
+        }
+        let stored = self.correlations.lock().unwrap();
+        let from = (time_frame.to_string(), window, start_ts);
+        let to = (time_frame.to_string(), window, end_ts);
+        Ok(stored.range(from..=to).map(|(_, m)| m.clone()).collect())
+    }
+}
+
+#[async_trait]
+impl RetentionStore for MemoryStore {
+    async fn delete_trades_before(&self, cutoff: i64, guard: &[&str], limit: i64) -> Result<u64, sqlx::Error> {
+        let guard: Vec<(&str, i64)> = guard
+            .iter()
+            .map(|tf| crate::aggregate::time_frame_ms(tf).map(|ms| (*tf, ms)).ok_or_else(|| crate::aggregate::unknown_time_frame(tf)))
+            .collect::<Result<_, _>>()?;
+        let candles = self.candles.lock().unwrap();
+        let mut trades = self.trades.lock().unwrap();
+        let mut expired: Vec<(i64, String)> = trades
+            .values()
+            .filter(|t| t.timestamp < cutoff)
+            .filter(|t| {
+                guard.iter().all(|(tf, ms)| {
+                    let key = (t.pair.clone(), tf.to_string(), crate::aggregate::bucket_start(t.timestamp, *ms));
+                    candles.get(&key).is_some_and(|bucket| bucket.iter().any(|c| c.source == CandleSource::Aggregated))
+                })
+            })
+            .map(|t| (t.timestamp, t.tid.clone()))
+            .collect();
+        expired.sort();
+        expired.truncate(limit.max(0) as usize);
+        for (_, tid) in &expired {
+            trades.remove(tid);
+        }
+        Ok(expired.len() as u64)
+    }
+
+    async fn delete_candles_before(
+        &self,
+        pair: Option<&str>,
+        time_frame: &str,
+        cutoff: i64,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error> {
+        let mut stored = self.candles.lock().unwrap();
+        let mut expired: Vec<CandleKey> = stored
+            .keys()
+            .filter(|(p, tf, begin)| {
+                pair.is_none_or(|pair| pair == p) && tf == time_frame && *begin < cutoff
+            })
+            .cloned()
+            .collect();
+        expired.sort_by_key(|(_, _, begin)| *begin);
+        let mut deleted = 0;
+        for key in expired {
+            if deleted >= limit.max(0) as u64 {
+                break;
+            }
+            if let Some(bucket) = stored.get_mut(&key) {
+                let take = bucket.len().min((limit as u64 - deleted) as usize);
+                bucket.drain(..take);
+                deleted += take as u64;
+                if bucket.is_empty() {
+                    stored.remove(&key);
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
+    async fn oldest_candle_begin(&self, pair: &str, time_frame: &str) -> Result<Option<i64>, sqlx::Error> {
+        let stored = self.candles.lock().unwrap();
+        Ok(stored
+            .keys()
+            .filter(|(p, tf, _)| p == pair && tf == time_frame)
+            .map(|(_, _, begin)| *begin)
+            .min())
+    }
+}
+
+impl Storage for MemoryStore {}
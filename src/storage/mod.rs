@@ -0,0 +1,205 @@
+mod memory;
+mod postgres;
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+pub use sqlite::SqliteStore;
+
+use std::env;
+use std::sync::Arc;
+use async_trait::async_trait;
+use sqlx::PgPool;
+use crate::aggregate;
+use crate::data_structs::{
+    Bar, BarSpec, CandleSource, CorrelationMatrix, IndicatorValue, Kline, OrderFlow, RecentTrade, Volatility,
+    VolumeProfile,
+};
+
+#[async_trait]
+pub trait CandleStore: Send + Sync {
+    // Новые свечи; уже записанные свечи (pair, time_frame, utc_begin, source) остаются как есть.
+    // Возвращает число реально добавленных.
+    async fn insert_candles(&self, candles: Vec<Kline>) -> Result<u64, sqlx::Error>;
+    // Новые свечи с перезаписью свечей того же источника
+    async fn upsert_candles(&self, candles: Vec<Kline>) -> Result<(), sqlx::Error>;
+    // Свечи с utc_begin в [start_ts, end_ts], по возрастанию времени. Если в корзине свечи
+    // от нескольких источников, берётся первый по списку `prefer`, источники вне списка - в последнюю очередь.
+    async fn candles_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+        prefer: &[CandleSource],
+    ) -> Result<Vec<Kline>, sqlx::Error>;
+    // Последняя корзина ряда, источник - по CandleSource::DEFAULT_PREFERENCE
+    async fn latest_candle(&self, pair: &str, time_frame: &str) -> Result<Option<Kline>, sqlx::Error>;
+}
+
+#[async_trait]
+pub trait TradeStore: Send + Sync {
+    // Новые трейды с дедупликацией по tid; возвращает число реально добавленных
+    async fn insert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error>;
+    // Трейды с перезаписью уже записанных tid
+    async fn upsert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error>;
+    // Трейды с time_stamp в [start_ts, end_ts], по времени и id
+    async fn trades_range(&self, pair: &str, start_ts: i64, end_ts: i64) -> Result<Vec<RecentTrade>, sqlx::Error>;
+    async fn latest_trade(&self, pair: &str) -> Result<Option<RecentTrade>, sqlx::Error>;
+
+    // Свечи из трейдов за [start_ts, end_ts]. По умолчанию считается в памяти,
+    // бэкенды с собственным SQL могут переопределить.
+    async fn aggregate_candles(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<Kline>, sqlx::Error> {
+        let trades = self.trades_range(pair, start_ts, end_ts).await?;
+        aggregate::aggregate_trades(pair, time_frame, &trades).ok_or_else(|| aggregate::unknown_time_frame(time_frame))
+    }
+}
+
+#[async_trait]
+pub trait WatermarkStore: Send + Sync {
+    // До какого момента (мс) фоновая задача `name` уже обработала данные
+    async fn watermark(&self, name: &str) -> Result<Option<i64>, sqlx::Error>;
+    async fn set_watermark(&self, name: &str, value: i64) -> Result<(), sqlx::Error>;
+}
+
+// Ряды, рассчитанные из трейдов и свечей. Новый ряд добавляет сюда пару методов записи и чтения, а не свой трейт
+// в Storage; SQL-бэкенды пишут все ряды через общий upsert_rows
+#[async_trait]
+pub trait SeriesStore: Send + Sync {
+    // Бары с перезаписью бара того же ряда с тем же первым трейдом
+    async fn upsert_bars(&self, bars: &[Bar]) -> Result<(), sqlx::Error>;
+    // Бары ряда (pair, spec) с началом в [start_ts, end_ts], по первому трейду
+    async fn bars_range(&self, pair: &str, spec: &BarSpec, start_ts: i64, end_ts: i64) -> Result<Vec<Bar>, sqlx::Error>;
+    // Последний закрытый бар ряда: с него продолжается построение
+    async fn latest_bar(&self, pair: &str, spec: &BarSpec) -> Result<Option<Bar>, sqlx::Error>;
+    // Удаляет бары ряда, закончившиеся не раньше from_ts; возвращает число удалённых
+    async fn delete_bars_from(&self, pair: &str, spec: &BarSpec, from_ts: i64) -> Result<u64, sqlx::Error>;
+
+    // Значения с перезаписью значения того же индикатора с теми же параметрами на той же свече
+    async fn upsert_indicator_values(&self, values: &[IndicatorValue]) -> Result<(), sqlx::Error>;
+    // Значения индикатора со свечами в [start_ts, end_ts], по возрастанию времени
+    async fn indicator_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        indicator: &str,
+        params: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<IndicatorValue>, sqlx::Error>;
+
+    // Метрики с перезаписью строки той же свечи
+    async fn upsert_order_flow(&self, rows: &[OrderFlow]) -> Result<(), sqlx::Error>;
+    // Метрики ряда со свечами в [start_ts, end_ts], по возрастанию времени
+    async fn order_flow_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<OrderFlow>, sqlx::Error>;
+
+    // Профили целиком заменяют записанные (pair, period, utc_begin, bins)
+    async fn replace_volume_profiles(&self, profiles: &[VolumeProfile]) -> Result<(), sqlx::Error>;
+    // Профили с началом в [start_ts, end_ts], по возрастанию времени, уровни - по возрастанию цены
+    async fn volume_profiles_range(
+        &self,
+        pair: &str,
+        period: &str,
+        bins: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<VolumeProfile>, sqlx::Error>;
+
+    async fn upsert_volatility(&self, rows: &[Volatility]) -> Result<(), sqlx::Error>;
+    // Оценки одного ряда за [start_ts, end_ts] по возрастанию utc_begin
+    async fn volatility_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        estimator: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<Volatility>, sqlx::Error>;
+
+    async fn upsert_correlations(&self, matrices: &[CorrelationMatrix]) -> Result<(), sqlx::Error>;
+    // Матрицы таймфрейма и окна за [start_ts, end_ts] по возрастанию utc_begin
+    async fn correlation_range(
+        &self,
+        time_frame: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<CorrelationMatrix>, sqlx::Error>;
+}
+
+#[async_trait]
+pub trait RetentionStore: Send + Sync {
+    // Удаляет до `limit` трейдов с time_stamp < cutoff, у которых во всех таймфреймах `guard` уже есть
+    // собранная из трейдов (aggregated) свеча корзины трейда; возвращает число удалённых
+    async fn delete_trades_before(&self, cutoff: i64, guard: &[&str], limit: i64) -> Result<u64, sqlx::Error>;
+    // Удаляет до `limit` свечей таймфрейма (всех источников) с utc_begin < cutoff, по одной паре или по всем
+    async fn delete_candles_before(
+        &self,
+        pair: Option<&str>,
+        time_frame: &str,
+        cutoff: i64,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error>;
+    // Начало самой старой свечи пары в таймфрейме
+    async fn oldest_candle_begin(&self, pair: &str, time_frame: &str) -> Result<Option<i64>, sqlx::Error>;
+}
+
+pub trait Storage:
+    CandleStore
+    + TradeStore
+    + WatermarkStore
+    + SeriesStore
+    + RetentionStore
+{
+    // Пул Postgres для задач, которые работают только поверх Postgres: аудит и запросы db.rs
+    fn pg_pool(&self) -> Option<&PgPool> {
+        None
+    }
+
+    // Таймфрейм строится самой БД (непрерывный агрегат TimescaleDB), отдельная агрегация не нужна
+    fn has_continuous_aggregate(&self, _time_frame: &str) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageKind {
+    Postgres,
+    Sqlite,
+    Memory,
+}
+
+impl StorageKind {
+    // STORAGE=postgres|sqlite|memory, по умолчанию postgres
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("STORAGE").unwrap_or_default().to_lowercase().as_str() {
+            "" | "postgres" | "pg" => Ok(StorageKind::Postgres),
+            "sqlite" => Ok(StorageKind::Sqlite),
+            "memory" => Ok(StorageKind::Memory),
+            other => Err(format!("Неизвестное хранилище STORAGE={}", other)),
+        }
+    }
+}
+
+// Подключается к выбранному хранилищу и применяет миграции
+pub async fn connect(kind: StorageKind, database_url: &str) -> Result<Arc<dyn Storage>, Box<dyn std::error::Error>> {
+    let store: Arc<dyn Storage> = match kind {
+        StorageKind::Postgres => Arc::new(PgStore::connect(database_url).await?),
+        StorageKind::Sqlite => Arc::new(SqliteStore::connect(database_url).await?),
+        StorageKind::Memory => Arc::new(MemoryStore::default()),
+    };
+    Ok(store)
+}
@@ -0,0 +1,315 @@
+use async_trait::async_trait;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use crate::data_structs::{
+    Bar, BarSpec, CandleSource, CorrelationMatrix, IndicatorValue, Kline, OrderFlow, RecentTrade, Volatility,
+    VolumeProfile,
+};
+use crate::db;
+use super::{CandleStore, RetentionStore, SeriesStore, Storage, TradeStore, WatermarkStore};
+
+// Миграция TimescaleDB, пересоздающая непрерывные агрегаты с числом трейдов
+#[cfg(feature = "timescale")]
+const AGGREGATES_RECREATED: i64 = 20250525120000;
+
+pub struct PgStore {
+    pool: PgPool,
+    timescale: bool, // trades/candles - гипертаблицы, старшие таймфреймы - непрерывные агрегаты
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool, timescale: bool) -> Self {
+        PgStore { pool, timescale }
+    }
+
+    pub async fn connect(database_url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(database_url)
+            .await?;
+        println!("Подключение к Postgres установлено.");
+
+        // Миграции TimescaleDB лежат в отдельном каталоге, поэтому чужие версии не считаем ошибкой
+        let mut migrator = sqlx::migrate!("./migrations");
+        migrator.set_ignore_missing(true);
+        Self::prepare_compressed_candles(&pool, &migrator).await?;
+        migrator.run(&pool).await?;
+        println!("Миграции Postgres успешно выполнены.");
+
+        let timescale = Self::setup_timescale(&pool).await?;
+
+        Ok(PgStore::new(pool, timescale))
+    }
+
+    #[cfg(feature = "timescale")]
+    async fn setup_timescale(pool: &PgPool) -> Result<bool, Box<dyn std::error::Error>> {
+        if !db::timescale_available(pool).await? {
+            println!("TimescaleDB не установлен, используется обычная SQL-агрегация.");
+            return Ok(false);
+        }
+
+        let applied = db::applied_migrations(pool).await?;
+        let mut migrator = sqlx::migrate!("./migrations_timescale");
+        migrator.set_ignore_missing(true);
+        migrator.run(pool).await?;
+        println!("Миграции TimescaleDB успешно выполнены.");
+
+        // Сжатие candles могли выключить перед основными миграциями (или прервать запуск после этого)
+        if db::candles_hypertable(pool).await? == Some(false) {
+            db::compress_candles(pool).await?;
+            println!("Сжатие candles снова включено.");
+        }
+
+        // Пересозданные агрегаты пусты: историю считаем один раз, дальше работают политики
+        if !applied.contains(&AGGREGATES_RECREATED) {
+            println!("Пересчёт непрерывных агрегатов по всей истории трейдов...");
+            db::refresh_continuous_aggregates(pool).await?;
+        }
+        Ok(true)
+    }
+
+    #[cfg(not(feature = "timescale"))]
+    async fn setup_timescale(_pool: &PgPool) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(false)
+    }
+
+    // Миграции TimescaleDB датированы раньше части основных, но применяются после них отдельным
+    // мигратором. На уже установленной БД новые основные миграции меняют candles, которая к этому
+    // времени - гипертаблица со сжатием; перед ними чанки распаковываются, а сжатие
+    // включает обратно setup_timescale.
+    #[cfg(feature = "timescale")]
+    async fn prepare_compressed_candles(
+        pool: &PgPool,
+        migrator: &sqlx::migrate::Migrator,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if db::candles_hypertable(pool).await? != Some(true) {
+            return Ok(());
+        }
+        let applied = db::applied_migrations(pool).await?;
+        if migrator.iter().all(|m| applied.contains(&m.version)) {
+            return Ok(());
+        }
+        println!("Распаковка сжатых чанков candles перед миграциями...");
+        db::decompress_candles(pool).await?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "timescale"))]
+    async fn prepare_compressed_candles(
+        _pool: &PgPool,
+        _migrator: &sqlx::migrate::Migrator,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    // Откуда читать свечи таймфрейма: из таблицы или из непрерывного агрегата (вместе со свечами
+    // других источников из таблицы)
+    fn candles_relation(&self, time_frame: &str) -> &'static str {
+        if self.timescale {
+            if let Some(view) = db::continuous_aggregate(time_frame) {
+                return view;
+            }
+        }
+        "candles"
+    }
+}
+
+#[async_trait]
+impl CandleStore for PgStore {
+    async fn insert_candles(&self, candles: Vec<Kline>) -> Result<u64, sqlx::Error> {
+        db::insert_candles(&self.pool, candles).await
+    }
+
+    async fn upsert_candles(&self, candles: Vec<Kline>) -> Result<(), sqlx::Error> {
+        db::upsert_candles(&self.pool, candles).await
+    }
+
+    async fn candles_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+        prefer: &[CandleSource],
+    ) -> Result<Vec<Kline>, sqlx::Error> {
+        let relation = self.candles_relation(time_frame);
+        db::candles_range_from(&self.pool, relation, pair, time_frame, start_ts, end_ts, prefer).await
+    }
+
+    async fn latest_candle(&self, pair: &str, time_frame: &str) -> Result<Option<Kline>, sqlx::Error> {
+        let relation = self.candles_relation(time_frame);
+        db::latest_candle_from(&self.pool, relation, pair, time_frame).await
+    }
+}
+
+#[async_trait]
+impl TradeStore for PgStore {
+    async fn insert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error> {
+        db::insert_trades(&self.pool, trades).await
+    }
+
+    async fn upsert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error> {
+        let conflict = if self.timescale {
+            db::TRADES_CONFLICT_HYPERTABLE
+        } else {
+            db::TRADES_CONFLICT
+        };
+        db::upsert_trades(&self.pool, trades, conflict).await
+    }
+
+    async fn trades_range(&self, pair: &str, start_ts: i64, end_ts: i64) -> Result<Vec<RecentTrade>, sqlx::Error> {
+        db::trades_range(&self.pool, pair, start_ts, end_ts).await
+    }
+
+    async fn latest_trade(&self, pair: &str) -> Result<Option<RecentTrade>, sqlx::Error> {
+        db::latest_trade(&self.pool, pair).await
+    }
+
+    // Postgres агрегирует сам, не вытягивая трейды в процесс
+    async fn aggregate_candles(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<Kline>, sqlx::Error> {
+        db::aggregate_trades(&self.pool, pair, time_frame, start_ts, end_ts).await
+    }
+}
+
+#[async_trait]
+impl WatermarkStore for PgStore {
+    async fn watermark(&self, name: &str) -> Result<Option<i64>, sqlx::Error> {
+        db::get_watermark(&self.pool, name).await
+    }
+
+    async fn set_watermark(&self, name: &str, value: i64) -> Result<(), sqlx::Error> {
+        db::set_watermark(&self.pool, name, value).await
+    }
+}
+
+#[async_trait]
+impl SeriesStore for PgStore {
+    async fn upsert_bars(&self, bars: &[Bar]) -> Result<(), sqlx::Error> {
+        db::upsert_bars(&self.pool, bars).await
+    }
+
+    async fn bars_range(&self, pair: &str, spec: &BarSpec, start_ts: i64, end_ts: i64) -> Result<Vec<Bar>, sqlx::Error> {
+        db::bars_range(&self.pool, pair, spec, start_ts, end_ts).await
+    }
+
+    async fn latest_bar(&self, pair: &str, spec: &BarSpec) -> Result<Option<Bar>, sqlx::Error> {
+        db::latest_bar(&self.pool, pair, spec).await
+    }
+
+    async fn delete_bars_from(&self, pair: &str, spec: &BarSpec, from_ts: i64) -> Result<u64, sqlx::Error> {
+        db::delete_bars_from(&self.pool, pair, spec, from_ts).await
+    }
+
+    async fn upsert_indicator_values(&self, values: &[IndicatorValue]) -> Result<(), sqlx::Error> {
+        db::upsert_indicator_values(&self.pool, values).await
+    }
+
+    async fn indicator_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        indicator: &str,
+        params: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<IndicatorValue>, sqlx::Error> {
+        db::indicator_range(&self.pool, pair, time_frame, indicator, params, start_ts, end_ts).await
+    }
+
+    async fn upsert_order_flow(&self, rows: &[OrderFlow]) -> Result<(), sqlx::Error> {
+        db::upsert_order_flow(&self.pool, rows).await
+    }
+
+    async fn order_flow_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<OrderFlow>, sqlx::Error> {
+        db::order_flow_range(&self.pool, pair, time_frame, start_ts, end_ts).await
+    }
+
+    async fn replace_volume_profiles(&self, profiles: &[VolumeProfile]) -> Result<(), sqlx::Error> {
+        db::replace_volume_profiles(&self.pool, profiles).await
+    }
+
+    async fn volume_profiles_range(
+        &self,
+        pair: &str,
+        period: &str,
+        bins: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<VolumeProfile>, sqlx::Error> {
+        db::volume_profiles_range(&self.pool, pair, period, bins, start_ts, end_ts).await
+    }
+
+    async fn upsert_volatility(&self, rows: &[Volatility]) -> Result<(), sqlx::Error> {
+        db::upsert_volatility(&self.pool, rows).await
+    }
+
+    async fn volatility_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        estimator: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<Volatility>, sqlx::Error> {
+        db::volatility_range(&self.pool, pair, time_frame, estimator, window, start_ts, end_ts).await
+    }
+
+    async fn upsert_correlations(&self, matrices: &[CorrelationMatrix]) -> Result<(), sqlx::Error> {
+        db::upsert_correlations(&self.pool, matrices).await
+    }
+
+    async fn correlation_range(
+        &self,
+        time_frame: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<CorrelationMatrix>, sqlx::Error> {
+        db::correlation_range(&self.pool, time_frame, window, start_ts, end_ts).await
+    }
+}
+
+#[async_trait]
+impl RetentionStore for PgStore {
+    // Свечи старших таймфреймов на TimescaleDB проверяются в непрерывных агрегатах
+    async fn delete_trades_before(&self, cutoff: i64, guard: &[&str], limit: i64) -> Result<u64, sqlx::Error> {
+        let guard: Vec<(&str, &str)> = guard.iter().map(|tf| (*tf, self.candles_relation(tf))).collect();
+        db::delete_trades_before(&self.pool, cutoff, &guard, limit).await
+    }
+
+    async fn delete_candles_before(
+        &self,
+        pair: Option<&str>,
+        time_frame: &str,
+        cutoff: i64,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error> {
+        db::delete_candles_before(&self.pool, pair, time_frame, cutoff, limit).await
+    }
+
+    async fn oldest_candle_begin(&self, pair: &str, time_frame: &str) -> Result<Option<i64>, sqlx::Error> {
+        db::oldest_candle_begin(&self.pool, pair, time_frame).await
+    }
+}
+
+impl Storage for PgStore {
+    fn pg_pool(&self) -> Option<&PgPool> {
+        Some(&self.pool)
+    }
+
+    fn has_continuous_aggregate(&self, time_frame: &str) -> bool {
+        self.timescale && db::continuous_aggregate(time_frame).is_some()
+    }
+}
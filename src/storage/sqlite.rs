@@ -0,0 +1,788 @@
+use std::str::FromStr;
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteRow};
+use sqlx::query_builder::Separated;
+use sqlx::{QueryBuilder, Row, Sqlite, Transaction};
+use crate::aggregate;
+use crate::db;
+use crate::data_structs::{
+    Bar, BarKind, BarSpec, CandleSource, CorrelationCell, CorrelationMatrix, IndicatorValue, Kline, OrderFlow,
+    PriceLevel, RecentTrade, Volatility, VolumeProfile, VBS,
+};
+use super::{CandleStore, RetentionStore, SeriesStore, Storage, TradeStore, WatermarkStore};
+
+// SQLite ограничивает число параметров в запросе, поэтому пишем пачками
+const ROWS_PER_STATEMENT: usize = 500;
+
+const CANDLE_COLUMNS: &str =
+    "pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, utc_begin, \
+     close_time, trade_count, vwap, source, is_final, revision";
+
+const TRADE_COLUMNS: &str = "tid, pair, amount, side, quantity, create_time, price, time_stamp";
+
+// Порядок трейдов как aggregate::trade_key_order: по времени, затем числовые id по значению
+// (длина без ведущих нулей, затем сами цифры), затем остальные id побайтно
+const TRADE_ORDER_ASC: &str = "time_stamp, (tid = '' OR tid GLOB '*[^0-9]*'), \
+    CASE WHEN tid <> '' AND tid NOT GLOB '*[^0-9]*' THEN length(ltrim(tid, '0')) ELSE 0 END, \
+    CASE WHEN tid <> '' AND tid NOT GLOB '*[^0-9]*' THEN ltrim(tid, '0') ELSE '' END, tid";
+const TRADE_ORDER_DESC: &str = "time_stamp DESC, (tid = '' OR tid GLOB '*[^0-9]*') DESC, \
+    CASE WHEN tid <> '' AND tid NOT GLOB '*[^0-9]*' THEN length(ltrim(tid, '0')) ELSE 0 END DESC, \
+    CASE WHEN tid <> '' AND tid NOT GLOB '*[^0-9]*' THEN ltrim(tid, '0') ELSE '' END DESC, tid DESC";
+
+// Лёгкое хранилище в одном файле, например DATABASE_URL=sqlite://poloniex.db
+pub struct SqliteStore {
+    pool: SqlitePool,
+}
+
+impl SqliteStore {
+    pub async fn connect(database_url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let options = SqliteConnectOptions::from_str(database_url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        println!("Подключение к SQLite установлено.");
+
+        sqlx::migrate!("./migrations_sqlite").run(&pool).await?;
+        println!("Миграции SQLite успешно выполнены.");
+
+        Ok(SqliteStore { pool })
+    }
+
+    async fn write_candles(&self, candles: &[Kline], on_conflict: &str) -> Result<u64, sqlx::Error> {
+        write_rows(&self.pool, "candles", CANDLE_COLUMNS, on_conflict, candles, |mut b, candle| {
+            b.push_bind(candle.pair.clone())
+                .push_bind(candle.time_frame.clone())
+                .push_bind(candle.open)
+                .push_bind(candle.high)
+                .push_bind(candle.low)
+                .push_bind(candle.close)
+                .push_bind(candle.volume_bs.buy_base)
+                .push_bind(candle.volume_bs.sell_base)
+                .push_bind(candle.volume_bs.buy_quote)
+                .push_bind(candle.volume_bs.sell_quote)
+                .push_bind(candle.utc_begin)
+                .push_bind(candle.close_time)
+                .push_bind(candle.trade_count)
+                .push_bind(candle.vwap)
+                .push_bind(candle.source.as_str())
+                .push_bind(candle.is_final)
+                .push_bind(candle.revision);
+        })
+        .await
+    }
+
+    async fn write_trades(&self, trades: &[RecentTrade], on_conflict: &str) -> Result<u64, sqlx::Error> {
+        write_rows(&self.pool, "trades", TRADE_COLUMNS, on_conflict, trades, |mut b, trade| {
+            b.push_bind(trade.tid.clone())
+                .push_bind(trade.pair.clone())
+                .push_bind(trade.amount.clone())
+                .push_bind(trade.side.clone())
+                .push_bind(trade.quantity.clone())
+                .push_bind(trade.create_time)
+                .push_bind(trade.price.clone())
+                .push_bind(trade.timestamp);
+        })
+        .await
+    }
+}
+
+// Все строки пишутся одной транзакцией; suffix - хвост INSERT вроде ON CONFLICT. Возвращает число затронутых строк
+async fn write_rows<T: Sync>(
+    pool: &SqlitePool,
+    table: &str,
+    columns: &str,
+    suffix: &str,
+    rows: &[T],
+    bind: impl FnMut(Separated<'_, 'static, Sqlite, &'static str>, &T) + Send,
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let affected = insert_rows(&mut tx, table, columns, suffix, rows, bind).await?;
+    tx.commit().await?;
+    Ok(affected)
+}
+
+async fn insert_rows<T: Sync>(
+    tx: &mut Transaction<'_, Sqlite>,
+    table: &str,
+    columns: &str,
+    suffix: &str,
+    rows: &[T],
+    mut bind: impl FnMut(Separated<'_, 'static, Sqlite, &'static str>, &T) + Send,
+) -> Result<u64, sqlx::Error> {
+    let mut affected = 0;
+    for chunk in rows.chunks(ROWS_PER_STATEMENT) {
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!("INSERT INTO {} ({}) ", table, columns));
+        builder.push_values(chunk, &mut bind);
+        builder.push(" ");
+        builder.push(suffix);
+        affected += builder.build().execute(&mut *tx).await?.rows_affected();
+    }
+    Ok(affected)
+}
+
+// Строка с тем же ключом перезаписывается, как в db::upsert_rows
+async fn upsert_rows<T: Sync>(
+    pool: &SqlitePool,
+    table: &str,
+    columns: &str,
+    key: &str,
+    rows: &[T],
+    bind: impl FnMut(Separated<'_, 'static, Sqlite, &'static str>, &T) + Send,
+) -> Result<(), sqlx::Error> {
+    write_rows(pool, table, columns, &db::on_conflict_update(key, columns), rows, bind).await?;
+    Ok(())
+}
+
+#[async_trait]
+impl CandleStore for SqliteStore {
+    async fn insert_candles(&self, candles: Vec<Kline>) -> Result<u64, sqlx::Error> {
+        self.write_candles(&candles, "ON CONFLICT (pair, time_frame, utc_begin, source) DO NOTHING").await
+    }
+
+    async fn upsert_candles(&self, candles: Vec<Kline>) -> Result<(), sqlx::Error> {
+        let candles = db::dedup_candles(candles);
+        self.write_candles(
+            &candles,
+            "ON CONFLICT (pair, time_frame, utc_begin, source) DO UPDATE SET
+                open = excluded.open,
+                high = excluded.high,
+                low = excluded.low,
+                close = excluded.close,
+                buy_base = excluded.buy_base,
+                sell_base = excluded.sell_base,
+                buy_quote = excluded.buy_quote,
+                sell_quote = excluded.sell_quote,
+                close_time = excluded.close_time,
+                trade_count = excluded.trade_count,
+                vwap = excluded.vwap,
+                is_final = excluded.is_final,
+                revision = max(candles.revision, excluded.revision)",
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn candles_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+        prefer: &[CandleSource],
+    ) -> Result<Vec<Kline>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM candles
+            WHERE pair = ? AND time_frame = ? AND utc_begin BETWEEN ? AND ?
+            ORDER BY utc_begin",
+            CANDLE_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(time_frame)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        let candles = rows.iter().map(kline_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(preferred(&candles, prefer))
+    }
+
+    async fn latest_candle(&self, pair: &str, time_frame: &str) -> Result<Option<Kline>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM candles
+            WHERE pair = ?1 AND time_frame = ?2
+              AND utc_begin = (SELECT MAX(utc_begin) FROM candles WHERE pair = ?1 AND time_frame = ?2)",
+            CANDLE_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(time_frame)
+            .fetch_all(&self.pool)
+            .await?;
+        let candles = rows.iter().map(kline_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(preferred(&candles, &CandleSource::DEFAULT_PREFERENCE).pop())
+    }
+}
+
+#[async_trait]
+impl TradeStore for SqliteStore {
+    async fn insert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error> {
+        self.write_trades(trades, "ON CONFLICT (tid) DO NOTHING").await
+    }
+
+    async fn upsert_trades(&self, trades: &[RecentTrade]) -> Result<u64, sqlx::Error> {
+        self.write_trades(
+            trades,
+            "ON CONFLICT (tid) DO UPDATE SET
+                pair = excluded.pair,
+                amount = excluded.amount,
+                side = excluded.side,
+                quantity = excluded.quantity,
+                create_time = excluded.create_time,
+                price = excluded.price,
+                time_stamp = excluded.time_stamp",
+        )
+        .await
+    }
+
+    async fn trades_range(&self, pair: &str, start_ts: i64, end_ts: i64) -> Result<Vec<RecentTrade>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM trades
+            WHERE pair = ? AND time_stamp BETWEEN ? AND ?
+            ORDER BY {}",
+            TRADE_COLUMNS, TRADE_ORDER_ASC
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(trade_from_row).collect()
+    }
+
+    async fn latest_trade(&self, pair: &str) -> Result<Option<RecentTrade>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM trades
+            WHERE pair = ?
+            ORDER BY {}
+            LIMIT 1",
+            TRADE_COLUMNS, TRADE_ORDER_DESC
+        );
+        let row = sqlx::query(&query).bind(pair).fetch_optional(&self.pool).await?;
+        row.as_ref().map(trade_from_row).transpose()
+    }
+}
+
+#[async_trait]
+impl WatermarkStore for SqliteStore {
+    async fn watermark(&self, name: &str) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar("SELECT value FROM job_watermarks WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    async fn set_watermark(&self, name: &str, value: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO job_watermarks (name, value, updated_at)
+            VALUES (?1, ?2, CAST(strftime('%s', 'now') AS INTEGER) * 1000)
+            ON CONFLICT (name) DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at",
+        )
+        .bind(name)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SeriesStore for SqliteStore {
+    async fn upsert_bars(&self, bars: &[Bar]) -> Result<(), sqlx::Error> {
+        upsert_rows(&self.pool, "bars", db::BAR_COLUMNS, db::BAR_KEY, bars, |mut b, bar| {
+            b.push_bind(bar.pair.clone())
+                .push_bind(bar.spec.kind.as_str())
+                .push_bind(bar.spec.threshold)
+                .push_bind(bar.open)
+                .push_bind(bar.high)
+                .push_bind(bar.low)
+                .push_bind(bar.close)
+                .push_bind(bar.volume_bs.buy_base)
+                .push_bind(bar.volume_bs.sell_base)
+                .push_bind(bar.volume_bs.buy_quote)
+                .push_bind(bar.volume_bs.sell_quote)
+                .push_bind(bar.utc_begin)
+                .push_bind(bar.utc_end)
+                .push_bind(bar.first_tid.clone())
+                .push_bind(bar.last_tid.clone())
+                .push_bind(bar.trade_count)
+                .push_bind(bar.vwap)
+                .push_bind(bar.imbalance);
+        })
+        .await
+    }
+
+    async fn bars_range(&self, pair: &str, spec: &BarSpec, start_ts: i64, end_ts: i64) -> Result<Vec<Bar>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM bars
+            WHERE pair = ? AND bar_type = ? AND threshold = ? AND utc_begin BETWEEN ? AND ?",
+            db::BAR_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(spec.kind.as_str())
+            .bind(spec.threshold)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        let mut bars = rows.iter().map(bar_from_row).collect::<Result<Vec<_>, _>>()?;
+        bars.sort_by(crate::aggregate::bar_order);
+        Ok(bars)
+    }
+
+    async fn latest_bar(&self, pair: &str, spec: &BarSpec) -> Result<Option<Bar>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM bars
+            WHERE pair = ?1 AND bar_type = ?2 AND threshold = ?3
+              AND utc_end = (SELECT MAX(utc_end) FROM bars WHERE pair = ?1 AND bar_type = ?2 AND threshold = ?3)",
+            db::BAR_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(spec.kind.as_str())
+            .bind(spec.threshold)
+            .fetch_all(&self.pool)
+            .await?;
+        let bars = rows.iter().map(bar_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(bars.into_iter().max_by(|a, b| {
+            crate::aggregate::trade_key_order((a.utc_end, &a.last_tid), (b.utc_end, &b.last_tid))
+        }))
+    }
+
+    async fn delete_bars_from(&self, pair: &str, spec: &BarSpec, from_ts: i64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM bars WHERE pair = ? AND bar_type = ? AND threshold = ? AND utc_end >= ?")
+            .bind(pair)
+            .bind(spec.kind.as_str())
+            .bind(spec.threshold)
+            .bind(from_ts)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn upsert_indicator_values(&self, values: &[IndicatorValue]) -> Result<(), sqlx::Error> {
+        upsert_rows(&self.pool, "indicators", db::INDICATOR_COLUMNS, db::INDICATOR_KEY, values, |mut b, value| {
+            b.push_bind(value.pair.clone())
+                .push_bind(value.time_frame.clone())
+                .push_bind(value.utc_begin)
+                .push_bind(value.indicator.clone())
+                .push_bind(value.params.clone())
+                .push_bind(value.values.first().copied().unwrap_or(f64::NAN))
+                .push_bind(value.values.get(1).copied())
+                .push_bind(value.values.get(2).copied());
+        })
+        .await
+    }
+
+    async fn indicator_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        indicator: &str,
+        params: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<IndicatorValue>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM indicators
+            WHERE pair = ? AND time_frame = ? AND indicator = ? AND params = ? AND utc_begin BETWEEN ? AND ?
+            ORDER BY utc_begin",
+            db::INDICATOR_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(time_frame)
+            .bind(indicator)
+            .bind(params)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(indicator_from_row).collect()
+    }
+
+    async fn upsert_order_flow(&self, rows: &[OrderFlow]) -> Result<(), sqlx::Error> {
+        upsert_rows(&self.pool, "order_flow", db::ORDER_FLOW_COLUMNS, db::ORDER_FLOW_KEY, rows, |mut b, row| {
+            b.push_bind(row.pair.clone())
+                .push_bind(row.time_frame.clone())
+                .push_bind(row.utc_begin)
+                .push_bind(row.buy_volume)
+                .push_bind(row.sell_volume)
+                .push_bind(row.delta)
+                .push_bind(row.cvd)
+                .push_bind(row.imbalance)
+                .push_bind(row.divergence as i64)
+                .push_bind(row.buy_z)
+                .push_bind(row.sell_z)
+                .push_bind(row.window);
+        })
+        .await
+    }
+
+    async fn order_flow_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<OrderFlow>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM order_flow
+            WHERE pair = ? AND time_frame = ? AND utc_begin BETWEEN ? AND ?
+            ORDER BY utc_begin",
+            db::ORDER_FLOW_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(time_frame)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(order_flow_from_row).collect()
+    }
+
+    async fn replace_volume_profiles(&self, profiles: &[VolumeProfile]) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for profile in profiles {
+            sqlx::query("DELETE FROM volume_profiles WHERE pair = ? AND period = ? AND utc_begin = ? AND bins = ?")
+                .bind(&profile.pair)
+                .bind(&profile.period)
+                .bind(profile.utc_begin)
+                .bind(&profile.bins)
+                .execute(&mut tx)
+                .await?;
+            insert_rows(&mut tx, "volume_profiles", db::VOLUME_PROFILE_COLUMNS, "", &profile.levels, |mut b, level| {
+                b.push_bind(profile.pair.clone())
+                    .push_bind(profile.period.clone())
+                    .push_bind(profile.utc_begin)
+                    .push_bind(profile.utc_end)
+                    .push_bind(profile.bins.clone())
+                    .push_bind(profile.bin_width)
+                    .push_bind(level.price_low)
+                    .push_bind(level.buy_volume)
+                    .push_bind(level.sell_volume)
+                    .push_bind(level.trade_count)
+                    .push_bind(profile.poc)
+                    .push_bind(profile.value_area_high)
+                    .push_bind(profile.value_area_low);
+            })
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    async fn volume_profiles_range(
+        &self,
+        pair: &str,
+        period: &str,
+        bins: &str,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<VolumeProfile>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM volume_profiles
+            WHERE pair = ? AND period = ? AND bins = ? AND utc_begin BETWEEN ? AND ?
+            ORDER BY utc_begin, price_low",
+            db::VOLUME_PROFILE_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(period)
+            .bind(bins)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        let levels = rows.iter().map(profile_level_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(db::group_profile_levels(levels))
+    }
+
+    async fn upsert_volatility(&self, rows: &[Volatility]) -> Result<(), sqlx::Error> {
+        upsert_rows(&self.pool, "volatility", db::VOLATILITY_COLUMNS, db::VOLATILITY_KEY, rows, |mut b, row| {
+            b.push_bind(row.pair.clone())
+                .push_bind(row.time_frame.clone())
+                .push_bind(row.utc_begin)
+                .push_bind(row.estimator.clone())
+                .push_bind(row.window)
+                .push_bind(row.variance)
+                .push_bind(row.volatility);
+        })
+        .await
+    }
+
+    async fn volatility_range(
+        &self,
+        pair: &str,
+        time_frame: &str,
+        estimator: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<Volatility>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM volatility
+            WHERE pair = ? AND time_frame = ? AND estimator = ? AND window_size = ? AND utc_begin BETWEEN ? AND ?
+            ORDER BY utc_begin",
+            db::VOLATILITY_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(pair)
+            .bind(time_frame)
+            .bind(estimator)
+            .bind(window)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        rows.iter().map(volatility_from_row).collect()
+    }
+
+    async fn upsert_correlations(&self, matrices: &[CorrelationMatrix]) -> Result<(), sqlx::Error> {
+        let cells: Vec<(&CorrelationMatrix, &CorrelationCell)> =
+            matrices.iter().flat_map(|m| m.cells.iter().map(move |c| (m, c))).collect();
+        upsert_rows(&self.pool, "correlations", db::CORRELATION_COLUMNS, db::CORRELATION_KEY, &cells, |mut b, (matrix, cell)| {
+            b.push_bind(matrix.time_frame.clone())
+                .push_bind(matrix.utc_begin)
+                .push_bind(matrix.window)
+                .push_bind(cell.pair.clone())
+                .push_bind(cell.other_pair.clone())
+                .push_bind(cell.observations)
+                .push_bind(cell.correlation)
+                .push_bind(cell.beta);
+        })
+        .await
+    }
+
+    async fn correlation_range(
+        &self,
+        time_frame: &str,
+        window: i64,
+        start_ts: i64,
+        end_ts: i64,
+    ) -> Result<Vec<CorrelationMatrix>, sqlx::Error> {
+        let query = format!(
+            "SELECT {} FROM correlations
+            WHERE time_frame = ? AND window_size = ? AND utc_begin BETWEEN ? AND ?
+            ORDER BY utc_begin, pair, other_pair",
+            db::CORRELATION_COLUMNS
+        );
+        let rows = sqlx::query(&query)
+            .bind(time_frame)
+            .bind(window)
+            .bind(start_ts)
+            .bind(end_ts)
+            .fetch_all(&self.pool)
+            .await?;
+        let cells = rows.iter().map(correlation_cell_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(db::group_correlation_cells(cells))
+    }
+}
+
+#[async_trait]
+impl RetentionStore for SqliteStore {
+    async fn delete_trades_before(&self, cutoff: i64, guard: &[&str], limit: i64) -> Result<u64, sqlx::Error> {
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("DELETE FROM trades WHERE tid IN (SELECT t.tid FROM trades t WHERE t.time_stamp < ");
+        builder.push_bind(cutoff);
+        for time_frame in guard {
+            let bucket_ms = aggregate::time_frame_ms(time_frame).ok_or_else(|| aggregate::unknown_time_frame(time_frame))?;
+            builder
+                .push(" AND EXISTS (SELECT 1 FROM candles c WHERE c.source = 'aggregated' AND c.pair = t.pair AND c.time_frame = ")
+                .push_bind(time_frame.to_string())
+                .push(format!(
+                    " AND c.utc_begin = t.time_stamp - (t.time_stamp - {offset}) % {bucket_ms})",
+                    offset = aggregate::bucket_offset(bucket_ms),
+                    bucket_ms = bucket_ms
+                ));
+        }
+        builder.push(" LIMIT ").push_bind(limit).push(")");
+        Ok(builder.build().execute(&self.pool).await?.rows_affected())
+    }
+
+    async fn delete_candles_before(
+        &self,
+        pair: Option<&str>,
+        time_frame: &str,
+        cutoff: i64,
+        limit: i64,
+    ) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM candles WHERE rowid IN (
+                SELECT rowid FROM candles
+                WHERE (?1 IS NULL OR pair = ?1) AND time_frame = ?2 AND utc_begin < ?3
+                LIMIT ?4
+            )",
+        )
+        .bind(pair)
+        .bind(time_frame)
+        .bind(cutoff)
+        .bind(limit)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn oldest_candle_begin(&self, pair: &str, time_frame: &str) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar("SELECT min(utc_begin) FROM candles WHERE pair = ? AND time_frame = ?")
+            .bind(pair)
+            .bind(time_frame)
+            .fetch_one(&self.pool)
+            .await
+    }
+}
+
+impl Storage for SqliteStore {}
+
+// Свечи, упорядоченные по utc_begin, сводит к одной на корзину по списку предпочтения источников
+fn preferred(candles: &[Kline], prefer: &[CandleSource]) -> Vec<Kline> {
+    candles
+        .chunk_by(|a, b| a.utc_begin == b.utc_begin)
+        .filter_map(|bucket| bucket.iter().min_by_key(|c| c.source.preference_key(prefer)).cloned())
+        .collect()
+}
+
+fn kline_from_row(row: &SqliteRow) -> Result<Kline, sqlx::Error> {
+    let source: String = row.try_get("source")?;
+    Ok(Kline {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        open: row.try_get("open")?,
+        high: row.try_get("high")?,
+        low: row.try_get("low")?,
+        close: row.try_get("close")?,
+        volume_bs: VBS {
+            buy_base: row.try_get("buy_base")?,
+            sell_base: row.try_get("sell_base")?,
+            buy_quote: row.try_get("buy_quote")?,
+            sell_quote: row.try_get("sell_quote")?,
+        },
+        utc_begin: row.try_get("utc_begin")?,
+        close_time: row.try_get("close_time")?,
+        trade_count: row.try_get("trade_count")?,
+        vwap: row.try_get("vwap")?,
+        source: CandleSource::parse(&source)
+            .ok_or_else(|| sqlx::Error::Decode(format!("неизвестный источник свечи: {}", source).into()))?,
+        is_final: row.try_get("is_final")?,
+        revision: row.try_get("revision")?,
+    })
+}
+
+fn bar_from_row(row: &SqliteRow) -> Result<Bar, sqlx::Error> {
+    let bar_type: String = row.try_get("bar_type")?;
+    Ok(Bar {
+        pair: row.try_get("pair")?,
+        spec: BarSpec {
+            kind: BarKind::parse(&bar_type)
+                .ok_or_else(|| sqlx::Error::Decode(format!("неизвестный тип бара: {}", bar_type).into()))?,
+            threshold: row.try_get("threshold")?,
+        },
+        open: row.try_get("open")?,
+        high: row.try_get("high")?,
+        low: row.try_get("low")?,
+        close: row.try_get("close")?,
+        volume_bs: VBS {
+            buy_base: row.try_get("buy_base")?,
+            sell_base: row.try_get("sell_base")?,
+            buy_quote: row.try_get("buy_quote")?,
+            sell_quote: row.try_get("sell_quote")?,
+        },
+        utc_begin: row.try_get("utc_begin")?,
+        utc_end: row.try_get("utc_end")?,
+        first_tid: row.try_get("first_tid")?,
+        last_tid: row.try_get("last_tid")?,
+        trade_count: row.try_get("trade_count")?,
+        vwap: row.try_get("vwap")?,
+        imbalance: row.try_get("imbalance")?,
+    })
+}
+
+fn indicator_from_row(row: &SqliteRow) -> Result<IndicatorValue, sqlx::Error> {
+    let mut values = vec![row.try_get::<f64, _>("value_1")?];
+    for column in ["value_2", "value_3"] {
+        match row.try_get::<Option<f64>, _>(column)? {
+            Some(value) => values.push(value),
+            None => break,
+        }
+    }
+    Ok(IndicatorValue {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        indicator: row.try_get("indicator")?,
+        params: row.try_get("params")?,
+        values,
+    })
+}
+
+fn order_flow_from_row(row: &SqliteRow) -> Result<OrderFlow, sqlx::Error> {
+    Ok(OrderFlow {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        buy_volume: row.try_get("buy_volume")?,
+        sell_volume: row.try_get("sell_volume")?,
+        delta: row.try_get("delta")?,
+        cvd: row.try_get("cvd")?,
+        imbalance: row.try_get("imbalance")?,
+        divergence: row.try_get::<i64, _>("divergence")? as i8,
+        buy_z: row.try_get("buy_z")?,
+        sell_z: row.try_get("sell_z")?,
+        window: row.try_get("window_size")?,
+    })
+}
+
+fn profile_level_from_row(row: &SqliteRow) -> Result<VolumeProfile, sqlx::Error> {
+    Ok(VolumeProfile {
+        pair: row.try_get("pair")?,
+        period: row.try_get("period")?,
+        utc_begin: row.try_get("utc_begin")?,
+        utc_end: row.try_get("utc_end")?,
+        bins: row.try_get("bins")?,
+        bin_width: row.try_get("bin_width")?,
+        levels: vec![PriceLevel {
+            price_low: row.try_get("price_low")?,
+            buy_volume: row.try_get("buy_volume")?,
+            sell_volume: row.try_get("sell_volume")?,
+            trade_count: row.try_get("trade_count")?,
+        }],
+        poc: row.try_get("poc")?,
+        value_area_high: row.try_get("value_area_high")?,
+        value_area_low: row.try_get("value_area_low")?,
+    })
+}
+
+fn volatility_from_row(row: &SqliteRow) -> Result<Volatility, sqlx::Error> {
+    Ok(Volatility {
+        pair: row.try_get("pair")?,
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        estimator: row.try_get("estimator")?,
+        window: row.try_get("window_size")?,
+        variance: row.try_get("variance")?,
+        volatility: row.try_get("volatility")?,
+    })
+}
+
+fn correlation_cell_from_row(row: &SqliteRow) -> Result<CorrelationMatrix, sqlx::Error> {
+    let pair: String = row.try_get("pair")?;
+    Ok(CorrelationMatrix {
+        time_frame: row.try_get("time_frame")?,
+        utc_begin: row.try_get("utc_begin")?,
+        window: row.try_get("window_size")?,
+        pairs: vec![pair.clone()],
+        cells: vec![CorrelationCell {
+            pair,
+            other_pair: row.try_get("other_pair")?,
+            observations: row.try_get("observations")?,
+            correlation: row.try_get("correlation")?,
+            beta: row.try_get("beta")?,
+        }],
+    })
+}
+
+fn trade_from_row(row: &SqliteRow) -> Result<RecentTrade, sqlx::Error> {
+    Ok(RecentTrade {
+        tid: row.try_get("tid")?,
+        pair: row.try_get::<Option<String>, _>("pair")?.unwrap_or_default(),
+        price: row.try_get::<Option<String>, _>("price")?.unwrap_or_default(),
+        amount: row.try_get::<Option<String>, _>("amount")?.unwrap_or_default(),
+        quantity: row.try_get::<Option<String>, _>("quantity")?.unwrap_or_default(),
+        side: row.try_get::<Option<String>, _>("side")?.unwrap_or_default(),
+        create_time: row.try_get::<Option<i64>, _>("create_time")?.unwrap_or_default(),
+        timestamp: row.try_get::<Option<i64>, _>("time_stamp")?.unwrap_or_default(),
+    })
+}
@@ -0,0 +1,397 @@
+use std::collections::VecDeque;
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+use crate::data_structs::RecentTrade;
+use crate::storage::Storage;
+
+// Как часто писать метрики писателя в лог
+const METRICS_LOG_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct TradeWriterConfig {
+    pub capacity: usize,        // ёмкость канала между WS и писателем
+    pub spill_capacity: usize,  // запас трейдов в памяти, когда канал полон
+    pub batch_size: usize,      // сброс при накоплении стольких трейдов
+    pub flush_interval: Duration, // сброс не реже чем раз в этот интервал
+}
+
+impl Default for TradeWriterConfig {
+    fn default() -> Self {
+        TradeWriterConfig {
+            capacity: 10_000,
+            spill_capacity: 100_000,
+            batch_size: 500,
+            flush_interval: Duration::from_millis(1000),
+        }
+    }
+}
+
+impl TradeWriterConfig {
+    // TRADE_WRITER_CAPACITY, TRADE_WRITER_SPILL_CAPACITY, TRADE_WRITER_BATCH_SIZE, TRADE_WRITER_FLUSH_MS
+    pub fn from_env() -> Self {
+        let default = TradeWriterConfig::default();
+        let read = |name: &str, fallback: usize| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(fallback)
+        };
+        TradeWriterConfig {
+            capacity: read("TRADE_WRITER_CAPACITY", default.capacity),
+            spill_capacity: read("TRADE_WRITER_SPILL_CAPACITY", default.spill_capacity),
+            batch_size: read("TRADE_WRITER_BATCH_SIZE", default.batch_size),
+            flush_interval: Duration::from_millis(
+                read("TRADE_WRITER_FLUSH_MS", default.flush_interval.as_millis() as usize) as u64,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TradeWriterMetrics {
+    pub queue_depth: AtomicU64,           // трейдов в канале на момент последней выборки
+    pub received: AtomicU64,              // всего получено из канала
+    pub inserted: AtomicU64,              // реально вставлено (без дублей)
+    pub flushes: AtomicU64,               // успешных сбросов
+    pub failed_flushes: AtomicU64,        // неудачных сбросов
+    pub dropped: AtomicU64,               // трейдов выброшено после ошибок записи
+    pub spilled: AtomicU64,               // трейдов ушло в запас при полном канале
+    pub overflowed: AtomicU64,            // трейдов отброшено при полных канале и запасе
+    pub last_flush_latency_ms: AtomicU64, // длительность последнего сброса
+    pub max_flush_latency_ms: AtomicU64,  // максимальная длительность сброса
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TradeWriterSnapshot {
+    pub queue_depth: u64,
+    pub received: u64,
+    pub inserted: u64,
+    pub flushes: u64,
+    pub failed_flushes: u64,
+    pub dropped: u64,
+    pub spilled: u64,
+    pub overflowed: u64,
+    pub last_flush_latency_ms: u64,
+    pub max_flush_latency_ms: u64,
+}
+
+impl TradeWriterMetrics {
+    pub fn snapshot(&self) -> TradeWriterSnapshot {
+        TradeWriterSnapshot {
+            queue_depth: self.queue_depth.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            inserted: self.inserted.load(Ordering::Relaxed),
+            flushes: self.flushes.load(Ordering::Relaxed),
+            failed_flushes: self.failed_flushes.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            spilled: self.spilled.load(Ordering::Relaxed),
+            overflowed: self.overflowed.load(Ordering::Relaxed),
+            last_flush_latency_ms: self.last_flush_latency_ms.load(Ordering::Relaxed),
+            max_flush_latency_ms: self.max_flush_latency_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Фоновый писатель трейдов: WS-читатель только кладёт трейд в канал (или в запас, если канал полон),
+// а запись в БД идёт пачками по размеру или по времени.
+#[derive(Clone)]
+pub struct TradeWriter {
+    tx: mpsc::Sender<RecentTrade>,
+    spill: Arc<Mutex<VecDeque<RecentTrade>>>,
+    spill_capacity: usize,
+    metrics: Arc<TradeWriterMetrics>,
+}
+
+impl TradeWriter {
+    pub fn spawn(store: Arc<dyn Storage>, config: TradeWriterConfig) -> (TradeWriter, JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(config.capacity);
+        let metrics = Arc::new(TradeWriterMetrics::default());
+        let spill = Arc::new(Mutex::new(VecDeque::new()));
+        let writer = TradeWriter {
+            tx,
+            spill: Arc::clone(&spill),
+            spill_capacity: config.spill_capacity,
+            metrics: Arc::clone(&metrics),
+        };
+        let handle = tokio::spawn(run_writer(store, config, rx, spill, metrics));
+        (writer, handle)
+    }
+
+    // Не ждёт места в канале: WS-читатель не должен вставать, пока писатель догоняет БД.
+    // При полном канале трейд уходит в запас, который писатель забирает по таймеру; пока запас
+    // не разобран, новые трейды встают за ним. Отбрасывается трейд, только если полон и запас.
+    // Ошибка - только если писатель остановлен.
+    pub fn send(&self, trade: RecentTrade) -> Result<(), SendError<()>> {
+        let mut spill = self.spill.lock().unwrap();
+        let trade = if spill.is_empty() {
+            match self.tx.try_send(trade) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(trade)) => trade,
+                Err(TrySendError::Closed(_)) => return Err(SendError(())),
+            }
+        } else if self.tx.is_closed() {
+            return Err(SendError(()));
+        } else {
+            trade
+        };
+
+        if spill.len() < self.spill_capacity {
+            spill.push_back(trade);
+            self.metrics.spilled.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let overflowed = self.metrics.overflowed.fetch_add(1, Ordering::Relaxed) + 1;
+            if overflowed % 1000 == 1 {
+                eprintln!("Писатель трейдов не успевает: канал и запас полны, отброшено {} трейдов", overflowed);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn metrics(&self) -> Arc<TradeWriterMetrics> {
+        Arc::clone(&self.metrics)
+    }
+}
+
+async fn run_writer(
+    store: Arc<dyn Storage>,
+    config: TradeWriterConfig,
+    mut rx: mpsc::Receiver<RecentTrade>,
+    spill: Arc<Mutex<VecDeque<RecentTrade>>>,
+    metrics: Arc<TradeWriterMetrics>,
+) {
+    let mut buffer: Vec<RecentTrade> = Vec::with_capacity(config.batch_size);
+    let mut flush_tick = interval(config.flush_interval);
+    flush_tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_report = Instant::now();
+    // После ошибки записи сбрасываем только по таймеру, чтобы не долбить БД на каждом трейде
+    let mut failing = false;
+
+    loop {
+        tokio::select! {
+            msg = rx.recv() => {
+                match msg {
+                    Some(trade) => {
+                        metrics.received.fetch_add(1, Ordering::Relaxed);
+                        buffer.push(trade);
+                        if !failing && buffer.len() >= config.batch_size {
+                            failing = !flush(&*store, &config, &mut buffer, &metrics).await;
+                        }
+                    }
+                    None => {
+                        buffer.extend(spill.lock().unwrap().drain(..));
+                        flush(&*store, &config, &mut buffer, &metrics).await;
+                        println!("Писатель трейдов остановлен.");
+                        return;
+                    }
+                }
+            }
+            _ = flush_tick.tick() => {
+                buffer.extend(spill.lock().unwrap().drain(..));
+                failing = !flush(&*store, &config, &mut buffer, &metrics).await;
+            }
+        }
+
+        metrics.queue_depth.store(rx.len() as u64, Ordering::Relaxed);
+
+        if last_report.elapsed() >= METRICS_LOG_INTERVAL {
+            last_report = Instant::now();
+            let m = metrics.snapshot();
+            println!(
+                "Писатель трейдов: очередь={}, получено={}, вставлено={}, сбросов={}, ошибок={}, потеряно={}, в запасе={}, переполнение={}, задержка={}мс (макс {}мс)",
+                m.queue_depth, m.received, m.inserted, m.flushes, m.failed_flushes,
+                m.dropped, m.spilled, m.overflowed, m.last_flush_latency_ms, m.max_flush_latency_ms
+            );
+        }
+    }
+}
+
+async fn flush(
+    store: &dyn Storage,
+    config: &TradeWriterConfig,
+    buffer: &mut Vec<RecentTrade>,
+    metrics: &TradeWriterMetrics,
+) -> bool {
+    if buffer.is_empty() {
+        return true;
+    }
+
+    let started = Instant::now();
+    match store.insert_trades(buffer).await {
+        Ok(inserted) => {
+            let latency = started.elapsed().as_millis() as u64;
+            metrics.inserted.fetch_add(inserted, Ordering::Relaxed);
+            metrics.flushes.fetch_add(1, Ordering::Relaxed);
+            metrics.last_flush_latency_ms.store(latency, Ordering::Relaxed);
+            metrics.max_flush_latency_ms.fetch_max(latency, Ordering::Relaxed);
+            buffer.clear();
+            true
+        }
+        Err(e) => {
+            metrics.failed_flushes.fetch_add(1, Ordering::Relaxed);
+            eprintln!("Ошибка пакетной записи {} трейдов: {}", buffer.len(), e);
+            // Пачку оставляем до следующего сброса, но не даём ей расти бесконечно
+            if buffer.len() > config.capacity {
+                let excess = buffer.len() - config.capacity;
+                buffer.drain(..excess);
+                metrics.dropped.fetch_add(excess as u64, Ordering::Relaxed);
+                eprintln!("Выброшено {} самых старых трейдов из-за ошибок записи", excess);
+            }
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStore, TradeStore};
+
+    fn trade(i: i64) -> RecentTrade {
+        RecentTrade {
+            tid: i.to_string(),
+            pair: "BTC_USDT".to_string(),
+            price: "100".to_string(),
+            amount: "100".to_string(),
+            quantity: "1".to_string(),
+            side: "buy".to_string(),
+            create_time: i,
+            timestamp: i,
+        }
+    }
+
+    fn config(capacity: usize, batch_size: usize, flush_interval: Duration) -> TradeWriterConfig {
+        TradeWriterConfig {
+            capacity,
+            spill_capacity: 100,
+            batch_size,
+            flush_interval,
+        }
+    }
+
+    async fn tids(store: &MemoryStore) -> Vec<String> {
+        store.trades_range("BTC_USDT", 0, 100).await.unwrap().into_iter().map(|t| t.tid).collect()
+    }
+
+    async fn wait_for(metrics: &TradeWriterMetrics, done: impl Fn(&TradeWriterSnapshot) -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !done(&metrics.snapshot()) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("писатель не дошёл до ожидаемого состояния: {:?}", metrics.snapshot()));
+    }
+
+    // Первый тик таймера срабатывает сразу после запуска; ждём его, чтобы он не сбросил пачку раньше времени
+    async fn spawn(store: &Arc<MemoryStore>, config: TradeWriterConfig) -> (TradeWriter, JoinHandle<()>) {
+        let spawned = TradeWriter::spawn(store.clone(), config);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        spawned
+    }
+
+    #[tokio::test]
+    async fn flushes_when_batch_is_full() {
+        let store = Arc::new(MemoryStore::default());
+        let (writer, handle) = spawn(&store, config(10, 3, Duration::from_secs(3600))).await;
+        let metrics = writer.metrics();
+        for i in 0..3 {
+            writer.send(trade(i)).unwrap();
+        }
+        wait_for(&metrics, |m| m.inserted == 3).await;
+        assert_eq!(metrics.snapshot().flushes, 1);
+
+        // Неполная пачка ждёт таймера или закрытия канала
+        writer.send(trade(3)).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!((metrics.snapshot().received, metrics.snapshot().inserted), (4, 3));
+        drop(writer);
+        handle.await.unwrap();
+        assert_eq!(tids(&store).await, vec!["0", "1", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn timer_flush_drains_spill() {
+        let store = Arc::new(MemoryStore::default());
+        let (writer, handle) = spawn(&store, config(1, 100, Duration::from_millis(100))).await;
+        let metrics = writer.metrics();
+        // В канал помещается один трейд, остальные уходят в запас
+        for i in 0..4 {
+            writer.send(trade(i)).unwrap();
+        }
+        wait_for(&metrics, |m| m.inserted == 4).await;
+        let m = metrics.snapshot();
+        assert_eq!((m.spilled, m.flushes), (3, 1));
+        assert_eq!(tids(&store).await, vec!["0", "1", "2", "3"]);
+        drop(writer);
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_writes_keep_batch_up_to_capacity() {
+        let store = Arc::new(MemoryStore::default());
+        store.fail_trade_writes(true);
+        let (writer, handle) = spawn(&store, config(2, 2, Duration::from_millis(20))).await;
+        let metrics = writer.metrics();
+        for i in 0..5 {
+            writer.send(trade(i)).unwrap();
+        }
+        // Пачка копится между неудачными сбросами, но не больше ёмкости канала: лишние старые трейды выброшены
+        wait_for(&metrics, |m| m.received == 2 && m.dropped == 3).await;
+        assert!(metrics.snapshot().failed_flushes >= 1);
+        assert!(tids(&store).await.is_empty());
+
+        // Оставшаяся пачка записывается, когда БД снова доступна
+        store.fail_trade_writes(false);
+        wait_for(&metrics, |m| m.inserted == 2).await;
+        drop(writer);
+        handle.await.unwrap();
+        let m = metrics.snapshot();
+        assert_eq!((m.inserted, m.dropped), (2, 3));
+        assert_eq!(tids(&store).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn closing_channel_flushes_rest() {
+        let store = Arc::new(MemoryStore::default());
+        let (writer, handle) = spawn(&store, config(10, 100, Duration::from_secs(3600))).await;
+        let metrics = writer.metrics();
+        for i in 0..2 {
+            writer.send(trade(i)).unwrap();
+        }
+        drop(writer);
+        handle.await.unwrap();
+        let m = metrics.snapshot();
+        assert_eq!((m.received, m.inserted, m.flushes), (2, 2, 1));
+        assert_eq!(tids(&store).await, vec!["0", "1"]);
+    }
+
+    #[tokio::test]
+    async fn full_channel_spills_before_dropping() {
+        let store = Arc::new(MemoryStore::default());
+        let config = TradeWriterConfig {
+            capacity: 2,
+            spill_capacity: 3,
+            batch_size: 100,
+            flush_interval: Duration::from_secs(3600),
+        };
+        let (writer, handle) = TradeWriter::spawn(store.clone(), config);
+        // Писатель не запускается, пока тест не уступит управление: канал заполняется сразу
+        for i in 0..7 {
+            writer.send(trade(i)).unwrap();
+        }
+        let metrics = writer.metrics();
+        drop(writer);
+        handle.await.unwrap();
+
+        let m = metrics.snapshot();
+        assert_eq!((m.spilled, m.overflowed, m.inserted), (3, 2, 5));
+        let stored: Vec<String> = store.trades_range("BTC_USDT", 0, 10).await.unwrap().into_iter().map(|t| t.tid).collect();
+        assert_eq!(stored, vec!["0", "1", "2", "3", "4"]);
+    }
+}
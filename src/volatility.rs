@@ -0,0 +1,553 @@
+use std::collections::VecDeque;
+use std::env;
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::aggregate;
+use crate::data_structs::{CandleSource, Kline, Volatility, PAIRS};
+use crate::rollup::BASE_TIME_FRAME;
+use crate::scheduler::{self, run_live, LiveSeries, BATCH_BUCKETS};
+use crate::storage::Storage;
+
+// Окно скользящих оценок по умолчанию, в свечах
+pub const DEFAULT_WINDOW: usize = 30;
+// Имя реализованной дневной волатильности по минутным доходностям
+pub const REALIZED: &str = "realized";
+// Таймфрейм реализованной волатильности
+pub const REALIZED_TIME_FRAME: &str = "1d";
+// Крипторынок торгуется круглосуточно: в году 365 полных дней
+const YEAR_MS: i64 = 365 * 24 * 60 * 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Estimator {
+    CloseToClose,   // выборочная дисперсия доходностей закрытие-закрытие
+    Parkinson,      // по размаху high/low
+    GarmanKlass,    // размах и тело свечи
+    RogersSatchell, // устойчива к тренду
+    YangZhang,      // ночной гэп + тело + Роджерс-Сатчелл
+}
+
+pub const ESTIMATORS: [Estimator; 5] = [
+    Estimator::CloseToClose,
+    Estimator::Parkinson,
+    Estimator::GarmanKlass,
+    Estimator::RogersSatchell,
+    Estimator::YangZhang,
+];
+
+impl Estimator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Estimator::CloseToClose => "close_to_close",
+            Estimator::Parkinson => "parkinson",
+            Estimator::GarmanKlass => "garman_klass",
+            Estimator::RogersSatchell => "rogers_satchell",
+            Estimator::YangZhang => "yang_zhang",
+        }
+    }
+}
+
+// Сколько интервалов таймфрейма в году
+pub fn periods_per_year(time_frame: &str) -> Option<f64> {
+    aggregate::time_frame_ms(time_frame).map(|ms| YEAR_MS as f64 / ms as f64)
+}
+
+// Логарифмические слагаемые одной свечи относительно её открытия и предыдущего закрытия
+#[derive(Debug, Clone, Copy)]
+struct BarTerms {
+    overnight: f64, // ln(open / предыдущий close)
+    body: f64,      // ln(close / open)
+    range_sq: f64,  // ln(high / low)^2
+    rs: f64,        // ln(high/open) * ln(high/close) + ln(low/open) * ln(low/close)
+}
+
+impl BarTerms {
+    fn close_return(&self) -> f64 {
+        self.overnight + self.body
+    }
+}
+
+// Скользящие оценки волатильности одного ряда по закрытым свечам.
+// Первая свеча ряда даёт только предыдущее закрытие; свечи с неположительными ценами пропускаются.
+#[derive(Debug, Clone)]
+pub struct RollingVolatility {
+    window: usize,
+    periods_per_year: f64,
+    prev_close: Option<f64>,
+    terms: VecDeque<BarTerms>,
+}
+
+impl RollingVolatility {
+    pub fn new(window: usize, periods_per_year: f64) -> Self {
+        let window = window.max(2);
+        RollingVolatility {
+            window,
+            periods_per_year,
+            prev_close: None,
+            terms: VecDeque::with_capacity(window + 1),
+        }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // Оценки всех методов на свече; пусто, пока окно не заполнено
+    pub fn push(&mut self, candle: &Kline) -> Vec<Volatility> {
+        let (open, high, low, close) = (candle.open, candle.high, candle.low, candle.close);
+        if open <= 0.0 || high <= 0.0 || low <= 0.0 || close <= 0.0 {
+            return Vec::new();
+        }
+        let prev_close = self.prev_close.replace(close);
+        let prev_close = match prev_close {
+            Some(prev) => prev,
+            None => return Vec::new(),
+        };
+        self.terms.push_back(BarTerms {
+            overnight: (open / prev_close).ln(),
+            body: (close / open).ln(),
+            range_sq: (high / low).ln().powi(2),
+            rs: (high / open).ln() * (high / close).ln() + (low / open).ln() * (low / close).ln(),
+        });
+        if self.terms.len() > self.window {
+            self.terms.pop_front();
+        }
+        if self.terms.len() < self.window {
+            return Vec::new();
+        }
+
+        ESTIMATORS
+            .iter()
+            .map(|estimator| {
+                let variance = self.variance(*estimator).max(0.0);
+                Volatility {
+                    pair: candle.pair.clone(),
+                    time_frame: candle.time_frame.clone(),
+                    utc_begin: candle.utc_begin,
+                    estimator: estimator.name().to_string(),
+                    window: self.window as i64,
+                    variance,
+                    volatility: (variance * self.periods_per_year).sqrt(),
+                }
+            })
+            .collect()
+    }
+
+    // Дисперсия за один интервал по заполненному окну
+    fn variance(&self, estimator: Estimator) -> f64 {
+        let n = self.terms.len() as f64;
+        let mean = |f: fn(&BarTerms) -> f64| self.terms.iter().map(f).sum::<f64>() / n;
+        let sample_variance = |f: fn(&BarTerms) -> f64| {
+            let m = mean(f);
+            self.terms.iter().map(|t| (f(t) - m).powi(2)).sum::<f64>() / (n - 1.0)
+        };
+        match estimator {
+            Estimator::CloseToClose => sample_variance(BarTerms::close_return),
+            Estimator::Parkinson => mean(|t| t.range_sq) / (4.0 * std::f64::consts::LN_2),
+            Estimator::GarmanKlass => {
+                mean(|t| 0.5 * t.range_sq - (2.0 * std::f64::consts::LN_2 - 1.0) * t.body * t.body)
+            }
+            Estimator::RogersSatchell => mean(|t| t.rs),
+            Estimator::YangZhang => {
+                let k = 0.34 / (1.34 + (n + 1.0) / (n - 1.0));
+                sample_variance(|t| t.overnight) + k * sample_variance(|t| t.body) + (1.0 - k) * mean(|t| t.rs)
+            }
+        }
+    }
+}
+
+// Скользящие оценки по свечам одного ряда (по возрастанию utc_begin)
+pub fn volatility(candles: &[Kline], window: usize) -> Vec<Volatility> {
+    let periods = match candles.first().and_then(|c| periods_per_year(&c.time_frame)) {
+        Some(periods) => periods,
+        None => return Vec::new(),
+    };
+    let mut series = RollingVolatility::new(window, periods);
+    candles.iter().flat_map(|c| series.push(c)).collect()
+}
+
+// Реализованная дисперсия: сумма квадратов лог-доходностей между закрытиями соседних свечей.
+// prev_close - закрытие перед первой свечой, чтобы учесть доходность на стыке корзин.
+// None, если доходностей нет.
+pub fn realized_variance(candles: &[Kline], prev_close: Option<f64>) -> Option<f64> {
+    let mut prev = prev_close.filter(|p| *p > 0.0);
+    let mut sum = None;
+    for candle in candles.iter().filter(|c| c.close > 0.0) {
+        if let Some(p) = prev {
+            *sum.get_or_insert(0.0) += (candle.close / p).ln().powi(2);
+        }
+        prev = Some(candle.close);
+    }
+    sum
+}
+
+async fn read_candles(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<Vec<Kline>, sqlx::Error> {
+    if start_ts > end_ts {
+        return Ok(Vec::new());
+    }
+    store
+        .candles_range(pair, time_frame, start_ts, end_ts, &CandleSource::DEFAULT_PREFERENCE)
+        .await
+}
+
+// Ряд, прогретый на `window` корзинах и предыдущем закрытии до before_ts
+async fn warm_up(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    window: usize,
+    before_ts: i64,
+) -> Result<Option<RollingVolatility>, sqlx::Error> {
+    let (bucket_ms, periods) = match (aggregate::time_frame_ms(time_frame), periods_per_year(time_frame)) {
+        (Some(ms), Some(periods)) => (ms, periods),
+        _ => return Ok(None),
+    };
+    let mut series = RollingVolatility::new(window, periods);
+    let from = before_ts.saturating_sub(bucket_ms.saturating_mul(series.window() as i64 + 1));
+    for candle in read_candles(store, pair, time_frame, from, before_ts - 1).await? {
+        series.push(&candle);
+    }
+    Ok(Some(series))
+}
+
+// Считает скользящие оценки ряда за [start_ts, end_ts] с прогревом до start_ts и записывает;
+// возвращает число записанных строк
+pub async fn compute_range(
+    store: &dyn Storage,
+    pair: &str,
+    time_frame: &str,
+    window: usize,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<usize, sqlx::Error> {
+    let (bucket_ms, mut series) = match (
+        aggregate::time_frame_ms(time_frame),
+        warm_up(store, pair, time_frame, window, start_ts).await?,
+    ) {
+        (Some(ms), Some(series)) => (ms, series),
+        _ => return Ok(0),
+    };
+    let mut written = 0;
+    let mut from = start_ts;
+    while from <= end_ts {
+        let to = from.saturating_add(bucket_ms * BATCH_BUCKETS - 1).min(end_ts);
+        let candles = read_candles(store, pair, time_frame, from, to).await?;
+        let rows: Vec<Volatility> = candles.iter().flat_map(|c| series.push(c)).collect();
+        store.upsert_volatility(&rows).await?;
+        written += rows.len();
+        if to == end_ts {
+            break;
+        }
+        from = to + 1;
+    }
+    Ok(written)
+}
+
+// Реализованная волатильность дня по минутным свечам [utc_begin, utc_begin + 1d);
+// первая доходность - от закрытия последней минуты предыдущего дня, если она есть
+pub async fn realized_day(store: &dyn Storage, pair: &str, utc_begin: i64) -> Result<Option<Volatility>, sqlx::Error> {
+    let (base_ms, day_ms) = match (
+        aggregate::time_frame_ms(BASE_TIME_FRAME),
+        aggregate::time_frame_ms(REALIZED_TIME_FRAME),
+    ) {
+        (Some(base), Some(day)) => (base, day),
+        _ => return Ok(None),
+    };
+    let mut candles = read_candles(store, pair, BASE_TIME_FRAME, utc_begin - base_ms, utc_begin + day_ms - 1).await?;
+    let prev_close = match candles.first() {
+        Some(first) if first.utc_begin < utc_begin => Some(candles.remove(0).close),
+        _ => None,
+    };
+    let periods = YEAR_MS as f64 / day_ms as f64;
+    Ok(realized_variance(&candles, prev_close).map(|variance| Volatility {
+        pair: pair.to_string(),
+        time_frame: REALIZED_TIME_FRAME.to_string(),
+        utc_begin,
+        estimator: REALIZED.to_string(),
+        window: 1,
+        variance,
+        volatility: (variance * periods).sqrt(),
+    }))
+}
+
+// Реализованная волатильность закрытых дней, начинающихся в [start_ts, end_ts]; возвращает число дней
+pub async fn compute_realized_range(
+    store: &dyn Storage,
+    pair: &str,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<usize, sqlx::Error> {
+    let day_ms = match aggregate::time_frame_ms(REALIZED_TIME_FRAME) {
+        Some(ms) => ms,
+        None => return Ok(0),
+    };
+    let closed = match closed_days_until(store, pair, day_ms).await? {
+        Some(closed) => closed,
+        None => return Ok(0),
+    };
+    let mut rows = Vec::new();
+    let mut day = aggregate::bucket_start(start_ts, day_ms);
+    if day < start_ts {
+        day += day_ms;
+    }
+    while day <= end_ts && day + day_ms <= closed {
+        rows.extend(realized_day(store, pair, day).await?);
+        day += day_ms;
+    }
+    store.upsert_volatility(&rows).await?;
+    Ok(rows.len())
+}
+
+// День закрыт, когда агрегация минутных свечей прошла его конец
+async fn closed_days_until(store: &dyn Storage, pair: &str, day_ms: i64) -> Result<Option<i64>, sqlx::Error> {
+    Ok(scheduler::closed_until(store, pair, BASE_TIME_FRAME)
+        .await?
+        .map(|mark| aggregate::bucket_start(mark, day_ms)))
+}
+
+// VOLATILITY_WINDOW - окно живого расчёта в свечах; не задано - волатильность не считается
+pub fn window_from_env() -> Result<Option<usize>, String> {
+    match env::var("VOLATILITY_WINDOW") {
+        Ok(value) if !value.trim().is_empty() => value
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|w| *w >= 2)
+            .map(Some)
+            .ok_or_else(|| format!("Неверное окно VOLATILITY_WINDOW={}", value)),
+        _ => Ok(None),
+    }
+}
+
+pub fn watermark_name(time_frame: &str, window: usize, pair: &str) -> String {
+    format!("volatility:{}:{}:{}", window, time_frame, pair)
+}
+
+pub fn realized_watermark_name(pair: &str) -> String {
+    format!("volatility:{}:{}", REALIZED, pair)
+}
+
+// Скользящие оценки одного ряда в живом расчёте
+struct LiveVolatility {
+    pair: &'static str,
+    time_frame: &'static str,
+    window: usize,
+}
+
+#[async_trait]
+impl LiveSeries for LiveVolatility {
+    type State = RollingVolatility;
+    type Row = Volatility;
+
+    fn label(&self) -> String {
+        format!("волатильности {} {}", self.pair, self.time_frame)
+    }
+
+    fn watermark_name(&self) -> String {
+        watermark_name(self.time_frame, self.window, self.pair)
+    }
+
+    async fn closed_until(&self, store: &dyn Storage) -> Result<Option<i64>, sqlx::Error> {
+        scheduler::closed_until(store, self.pair, self.time_frame).await
+    }
+
+    async fn warm_up(&self, store: &dyn Storage, before_ts: i64) -> Result<Option<RollingVolatility>, sqlx::Error> {
+        warm_up(store, self.pair, self.time_frame, self.window, before_ts).await
+    }
+
+    async fn push(
+        &self,
+        store: &dyn Storage,
+        series: &mut RollingVolatility,
+        from: i64,
+        until: i64,
+    ) -> Result<Vec<Volatility>, sqlx::Error> {
+        let candles = read_candles(store, self.pair, self.time_frame, from, until - 1).await?;
+        Ok(candles.iter().flat_map(|c| series.push(c)).collect())
+    }
+
+    async fn write(&self, store: &dyn Storage, rows: &[Volatility]) -> Result<(), sqlx::Error> {
+        store.upsert_volatility(rows).await
+    }
+}
+
+// Реализованная волатильность пары; отметка - начало первого непосчитанного дня
+struct LiveRealized {
+    pair: &'static str,
+}
+
+#[async_trait]
+impl LiveSeries for LiveRealized {
+    type State = ();
+    type Row = Volatility;
+
+    fn label(&self) -> String {
+        format!("реализованной волатильности {}", self.pair)
+    }
+
+    fn watermark_name(&self) -> String {
+        realized_watermark_name(self.pair)
+    }
+
+    async fn closed_until(&self, store: &dyn Storage) -> Result<Option<i64>, sqlx::Error> {
+        match aggregate::time_frame_ms(REALIZED_TIME_FRAME) {
+            Some(day_ms) => closed_days_until(store, self.pair, day_ms).await,
+            None => Ok(None),
+        }
+    }
+
+    // Дни считаются независимо, прогревать нечего
+    async fn warm_up(&self, _store: &dyn Storage, _before_ts: i64) -> Result<Option<()>, sqlx::Error> {
+        Ok(Some(()))
+    }
+
+    async fn push(&self, store: &dyn Storage, _: &mut (), from: i64, until: i64) -> Result<Vec<Volatility>, sqlx::Error> {
+        let day_ms = match aggregate::time_frame_ms(REALIZED_TIME_FRAME) {
+            Some(ms) => ms,
+            None => return Ok(Vec::new()),
+        };
+        let mut rows = Vec::new();
+        let mut day = from;
+        while day + day_ms <= until {
+            rows.extend(realized_day(store, self.pair, day).await?);
+            day += day_ms;
+        }
+        Ok(rows)
+    }
+
+    async fn write(&self, store: &dyn Storage, rows: &[Volatility]) -> Result<(), sqlx::Error> {
+        store.upsert_volatility(rows).await
+    }
+}
+
+// Живой расчёт: скользящие оценки по свечам, закрытым агрегацией, и реализованная волатильность
+// каждого закрытого дня; история досчитывается командой volatility
+pub async fn run_volatility(store: Arc<dyn Storage>, time_frames: Vec<&'static str>, window: usize) {
+    let mut series = Vec::new();
+    for &time_frame in &time_frames {
+        for &pair in PAIRS.iter() {
+            series.push(LiveVolatility { pair, time_frame, window });
+        }
+    }
+    let realized = PAIRS.iter().map(|&pair| LiveRealized { pair }).collect();
+    let title = format!("волатильности, окно {}", window);
+    tokio::join!(
+        run_live(store.clone(), &title, series),
+        run_live(store, "реализованной волатильности", realized),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structs::testing::{self, MIN, T0};
+    use crate::storage::{CandleStore, MemoryStore};
+
+    const DAY: i64 = 24 * 60 * MIN;
+
+    // Свеча по логарифмам цен
+    fn candle(time_frame: &str, utc_begin: i64, open: f64, high: f64, low: f64, close: f64) -> Kline {
+        testing::candle(time_frame, utc_begin, close.exp()).with_ohlc(open.exp(), high.exp(), low.exp(), close.exp())
+    }
+
+    fn estimate(rows: &[Volatility], estimator: Estimator) -> &Volatility {
+        rows.iter().find(|r| r.estimator == estimator.name()).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn periods_per_year_by_time_frame() {
+        assert_eq!(periods_per_year("1m"), Some(525_600.0));
+        assert_eq!(periods_per_year("MINUTE_1"), Some(525_600.0));
+        assert_eq!(periods_per_year("1h"), Some(8_760.0));
+        assert_eq!(periods_per_year("1d"), Some(365.0));
+        assert_eq!(periods_per_year("7m"), None);
+    }
+
+    #[test]
+    fn constant_return_series() {
+        // Каждая свеча открывается на прошлом закрытии и растёт на r от минимума до максимума
+        let r = 0.01;
+        let candles: Vec<Kline> = (0..10)
+            .map(|i| {
+                let open = i as f64 * r;
+                candle("1m", T0 + i * MIN, open, open + r, open, open + r)
+            })
+            .collect();
+        let rows = volatility(&candles, 5);
+        // Первая свеча даёт только закрытие, окно из 5 доходностей заполняется на шестой
+        assert_eq!(rows.len(), 5 * ESTIMATORS.len());
+        let last = &rows[rows.len() - ESTIMATORS.len()..];
+        assert_eq!(last[0].utc_begin, T0 + 9 * MIN);
+
+        let ln2 = std::f64::consts::LN_2;
+        assert_close(estimate(last, Estimator::CloseToClose).variance, 0.0);
+        assert_close(estimate(last, Estimator::Parkinson).variance, r * r / (4.0 * ln2));
+        assert_close(estimate(last, Estimator::GarmanKlass).variance, 0.5 * r * r - (2.0 * ln2 - 1.0) * r * r);
+        assert_close(estimate(last, Estimator::RogersSatchell).variance, 0.0);
+        assert_close(estimate(last, Estimator::YangZhang).variance, 0.0);
+
+        let parkinson = estimate(last, Estimator::Parkinson);
+        assert_close(parkinson.volatility, (parkinson.variance * 525_600.0).sqrt());
+        assert_eq!(parkinson.window, 5);
+    }
+
+    #[test]
+    fn three_bar_window() {
+        // Логарифмы: гэпы 0.01, 0, -0.01; тела 0.01, -0.02, 0.02; размахи 0.04, 0.05, 0.04
+        let candles = vec![
+            candle("1d", T0, 0.0, 0.0, 0.0, 0.0),
+            candle("1d", T0 + DAY, 0.01, 0.03, -0.01, 0.02),
+            candle("1d", T0 + 2 * DAY, 0.02, 0.05, 0.0, 0.0),
+            candle("1d", T0 + 3 * DAY, -0.01, 0.02, -0.02, 0.01),
+        ];
+        let rows = volatility(&candles, 3);
+        assert_eq!(rows.len(), ESTIMATORS.len());
+
+        let ln2 = std::f64::consts::LN_2;
+        // Доходности 0.02, -0.02, 0.01: сумма квадратов 0.0009, среднее 1/300
+        let close_to_close = 13.0 / 30_000.0;
+        let rs = (0.0008 + 0.0015 + 0.0006) / 3.0;
+        let k = 0.34 / (1.34 + 4.0 / 2.0);
+        assert_close(estimate(&rows, Estimator::CloseToClose).variance, close_to_close);
+        assert_close(estimate(&rows, Estimator::Parkinson).variance, 0.0019 / (4.0 * ln2));
+        assert_close(estimate(&rows, Estimator::GarmanKlass).variance, 0.5 * 0.0019 - (2.0 * ln2 - 1.0) * 0.0003);
+        assert_close(estimate(&rows, Estimator::RogersSatchell).variance, rs);
+        assert_close(estimate(&rows, Estimator::YangZhang).variance, 0.0001 + k * close_to_close + (1.0 - k) * rs);
+
+        let daily = estimate(&rows, Estimator::CloseToClose);
+        assert_close(daily.volatility, (close_to_close * 365.0).sqrt());
+    }
+
+    #[tokio::test]
+    async fn realized_day_sums_squared_minute_returns() {
+        let store = MemoryStore::default();
+        let log_close = |i: i64| 0.002 * (i as f64 * 0.37).sin();
+        // Последняя минута прошлого дня, сам день и первая минута следующего с большим скачком
+        let candles: Vec<Kline> = (-1..=1440)
+            .map(|i| {
+                let close = if i == 1440 { 1.0 } else { log_close(i) };
+                candle("1m", T0 + i * MIN, close, close, close, close)
+            })
+            .collect();
+        store.upsert_candles(candles).await.unwrap();
+
+        let expected: f64 = (0..1440).map(|i| (log_close(i) - log_close(i - 1)).powi(2)).sum();
+        let row = realized_day(&store, "BTC_USDT", T0).await.unwrap().unwrap();
+        assert!((row.variance - expected).abs() < 1e-15, "{} != {}", row.variance, expected);
+        assert_close(row.volatility, (expected * 365.0).sqrt());
+        assert_eq!((row.estimator.as_str(), row.time_frame.as_str(), row.window), (REALIZED, "1d", 1));
+
+        // Без доходностей оценки нет
+        assert_eq!(realized_variance(&[], Some(1.0)), None);
+        assert!(realized_day(&store, "BTC_USDT", T0 + 10 * DAY).await.unwrap().is_none());
+    }
+}
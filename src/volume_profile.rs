@@ -0,0 +1,449 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::sync::Arc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use crate::aggregate;
+use crate::data_structs::{PriceLevel, RecentTrade, VolumeProfile, PAIRS};
+use crate::rollup::BASE_TIME_FRAME;
+use crate::scheduler;
+use crate::storage::Storage;
+
+// Периоды, профили которых хранятся и строятся живым расчётом
+pub const STORED_PERIODS: [&str; 2] = ["1d", "1w"];
+// Период профиля по произвольному диапазону
+pub const CUSTOM_PERIOD: &str = "custom";
+// Доля объёма в зоне стоимости по умолчанию
+pub const DEFAULT_VALUE_AREA: f64 = 0.7;
+// Сколько трейдов читается за один запрос: окно по времени
+const TRADE_WINDOW_MS: i64 = 6 * 60 * 60_000;
+// Как часто живой расчёт проверяет, не закрылся ли период
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+// Разбиение цены на уровни
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PriceBins {
+    Width(f64),   // уровни фиксированной ширины, выровненные от нуля
+    Count(usize), // столько уровней между минимальной и максимальной ценой периода
+}
+
+impl Default for PriceBins {
+    fn default() -> Self {
+        PriceBins::Count(100)
+    }
+}
+
+impl PriceBins {
+    // "10" - ширина уровня в котируемой валюте, "count:100" - число уровней
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let bins = match value.strip_prefix("count:") {
+            Some(count) => count.parse::<usize>().ok().filter(|c| *c > 0).map(PriceBins::Count),
+            None => value.parse::<f64>().ok().filter(|w| *w > 0.0 && w.is_finite()).map(PriceBins::Width),
+        };
+        bins.ok_or_else(|| format!("Неверное разбиение по цене: {}", value))
+    }
+
+    // Имя разбиения в БД: профили с разным разбиением хранятся рядом
+    pub fn name(&self) -> String {
+        match self {
+            PriceBins::Width(width) => width.to_string(),
+            PriceBins::Count(count) => format!("count:{}", count),
+        }
+    }
+}
+
+// Объём по точной цене; уровни нарезаются в конце, когда известен диапазон цен
+#[derive(Debug, Clone, Default)]
+pub struct ProfileBuilder {
+    prices: BTreeMap<u64, PriceLevel>, // ключ - биты цены, для положительных цен порядок тот же
+}
+
+impl ProfileBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Трейды с непарсящимися или неположительными числами пропускаются
+    pub fn push(&mut self, trade: &RecentTrade) {
+        let (price, quantity) = match (trade.price.parse::<f64>(), trade.quantity.parse::<f64>()) {
+            (Ok(p), Ok(q)) if p > 0.0 && q >= 0.0 => (p, q),
+            _ => return,
+        };
+        let level = self.prices.entry(price.to_bits()).or_insert(PriceLevel {
+            price_low: price,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            trade_count: 0,
+        });
+        match trade.side.as_str() {
+            "buy" => level.buy_volume += quantity,
+            "sell" => level.sell_volume += quantity,
+            _ => return,
+        }
+        level.trade_count += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prices.is_empty()
+    }
+
+    // Профиль за [utc_begin, utc_end]; None, если трейдов не было
+    pub fn finish(
+        &self,
+        pair: &str,
+        period: &str,
+        utc_begin: i64,
+        utc_end: i64,
+        bins: PriceBins,
+        value_area: f64,
+    ) -> Option<VolumeProfile> {
+        let min = self.prices.values().next()?.price_low;
+        let max = self.prices.values().next_back()?.price_low;
+        let (origin, bin_width, last) = match bins {
+            PriceBins::Width(width) => (0.0, width, i64::MAX),
+            PriceBins::Count(count) => (min, (max - min) / count as f64, count as i64 - 1),
+        };
+
+        let mut levels: BTreeMap<i64, PriceLevel> = BTreeMap::new();
+        for level in self.prices.values() {
+            // Все цены одинаковы - один уровень нулевой ширины; максимум попадает в последний уровень
+            let index = if bin_width > 0.0 {
+                (((level.price_low - origin) / bin_width).floor() as i64).min(last)
+            } else {
+                0
+            };
+            let bin = levels.entry(index).or_insert(PriceLevel {
+                price_low: origin + index as f64 * bin_width,
+                buy_volume: 0.0,
+                sell_volume: 0.0,
+                trade_count: 0,
+            });
+            bin.buy_volume += level.buy_volume;
+            bin.sell_volume += level.sell_volume;
+            bin.trade_count += level.trade_count;
+        }
+        let levels: Vec<PriceLevel> = levels.into_values().collect();
+        let (poc_index, low_index, high_index) = value_area_bounds(&levels, value_area);
+
+        Some(VolumeProfile {
+            pair: pair.to_string(),
+            period: period.to_string(),
+            utc_begin,
+            utc_end,
+            bins: bins.name(),
+            bin_width,
+            poc: levels[poc_index].price_low + bin_width / 2.0,
+            value_area_high: levels[high_index].price_low + bin_width,
+            value_area_low: levels[low_index].price_low,
+            levels,
+        })
+    }
+}
+
+fn level_volume(level: &PriceLevel) -> f64 {
+    level.buy_volume + level.sell_volume
+}
+
+// (POC, нижний и верхний уровни зоны стоимости). POC - уровень с наибольшим объёмом (при равенстве - нижний);
+// зона растёт от POC к соседнему уровню с большим объёмом (при равенстве - вверх), пока не наберёт долю объёма
+fn value_area_bounds(levels: &[PriceLevel], value_area: f64) -> (usize, usize, usize) {
+    let mut poc = 0;
+    for (i, level) in levels.iter().enumerate() {
+        if level_volume(level) > level_volume(&levels[poc]) {
+            poc = i;
+        }
+    }
+    let target = levels.iter().map(level_volume).sum::<f64>() * value_area;
+    let (mut low, mut high) = (poc, poc);
+    let mut covered = level_volume(&levels[poc]);
+    while covered < target && (low > 0 || high + 1 < levels.len()) {
+        let above = levels.get(high + 1).map(level_volume);
+        let below = low.checked_sub(1).map(|i| level_volume(&levels[i]));
+        match (above, below) {
+            (Some(a), Some(b)) if b > a => {
+                low -= 1;
+                covered += b;
+            }
+            (Some(a), _) => {
+                high += 1;
+                covered += a;
+            }
+            (None, Some(b)) => {
+                low -= 1;
+                covered += b;
+            }
+            (None, None) => break,
+        }
+    }
+    (poc, low, high)
+}
+
+// Профиль по трейдам одной пары за [utc_begin, utc_end]; трейды других пар и вне диапазона пропускаются
+pub fn volume_profile(
+    pair: &str,
+    period: &str,
+    trades: &[RecentTrade],
+    utc_begin: i64,
+    utc_end: i64,
+    bins: PriceBins,
+    value_area: f64,
+) -> Option<VolumeProfile> {
+    let mut builder = ProfileBuilder::new();
+    for trade in trades {
+        if trade.pair == pair && trade.timestamp >= utc_begin && trade.timestamp <= utc_end {
+            builder.push(trade);
+        }
+    }
+    builder.finish(pair, period, utc_begin, utc_end, bins, value_area)
+}
+
+// Профиль по трейдам из хранилища; трейды читаются окнами, в памяти - только объёмы по ценам
+pub async fn build_profile(
+    store: &dyn Storage,
+    pair: &str,
+    period: &str,
+    utc_begin: i64,
+    utc_end: i64,
+    bins: PriceBins,
+    value_area: f64,
+) -> Result<Option<VolumeProfile>, sqlx::Error> {
+    let mut builder = ProfileBuilder::new();
+    let mut from = utc_begin;
+    while from <= utc_end {
+        let to = from.saturating_add(TRADE_WINDOW_MS - 1).min(utc_end);
+        for trade in store.trades_range(pair, from, to).await? {
+            builder.push(&trade);
+        }
+        if to == utc_end {
+            break;
+        }
+        from = to + 1;
+    }
+    Ok(builder.finish(pair, period, utc_begin, utc_end, bins, value_area))
+}
+
+// Строит и сохраняет профили закрытых периодов, начинающихся в [start_ts, end_ts].
+// Периоды выровнены как свечи того же таймфрейма (недели - с понедельника). Возвращает число профилей.
+pub async fn compute_range(
+    store: &dyn Storage,
+    pair: &str,
+    period: &str,
+    bins: PriceBins,
+    value_area: f64,
+    start_ts: i64,
+    end_ts: i64,
+) -> Result<usize, sqlx::Error> {
+    let period_ms = match aggregate::time_frame_ms(period) {
+        Some(ms) => ms,
+        None => return Ok(0),
+    };
+    let closed = match closed_periods_until(store, pair, period_ms).await? {
+        Some(closed) => closed,
+        None => return Ok(0),
+    };
+    let mut written = 0;
+    let mut begin = aggregate::bucket_start(start_ts, period_ms);
+    if begin < start_ts {
+        begin += period_ms;
+    }
+    while begin <= end_ts && begin + period_ms <= closed {
+        if let Some(profile) = build_profile(store, pair, period, begin, begin + period_ms - 1, bins, value_area).await? {
+            store.replace_volume_profiles(&[profile]).await?;
+            written += 1;
+        }
+        begin += period_ms;
+    }
+    Ok(written)
+}
+
+// Граница закрытых периодов: трейды окончательны до отметки агрегации минутных свечей
+async fn closed_periods_until(store: &dyn Storage, pair: &str, period_ms: i64) -> Result<Option<i64>, sqlx::Error> {
+    Ok(scheduler::closed_until(store, pair, BASE_TIME_FRAME)
+        .await?
+        .map(|mark| aggregate::bucket_start(mark, period_ms)))
+}
+
+// Разбиение для живого расчёта из VOLUME_PROFILE_BINS; без переменной профили не строятся
+pub fn bins_from_env() -> Result<Option<PriceBins>, String> {
+    match env::var("VOLUME_PROFILE_BINS") {
+        Ok(value) if !value.trim().is_empty() => PriceBins::parse(&value).map(Some),
+        _ => Ok(None),
+    }
+}
+
+pub fn watermark_name(period: &str, bins: PriceBins, pair: &str) -> String {
+    format!("volume_profile:{}:{}:{}", period, bins.name(), pair)
+}
+
+// Живой расчёт: после закрытия каждого дня и недели строит их профили по всем парам.
+// Отметка - начало первого непостроенного периода; новый ряд начинает с текущего периода,
+// история строится командой volume-profile.
+pub async fn run_volume_profiles(store: Arc<dyn Storage>, bins: PriceBins) {
+    println!("Живой расчёт профилей объёма: периоды {:?}, разбиение {}", STORED_PERIODS, bins.name());
+    let mut tick = interval(POLL_INTERVAL);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        for period in STORED_PERIODS {
+            for &pair in PAIRS.iter() {
+                if let Err(e) = advance(&*store, pair, period, bins).await {
+                    eprintln!("Ошибка построения профиля объёма {} {}: {}", pair, period, e);
+                }
+            }
+        }
+    }
+}
+
+async fn advance(store: &dyn Storage, pair: &str, period: &str, bins: PriceBins) -> Result<(), sqlx::Error> {
+    let period_ms = match aggregate::time_frame_ms(period) {
+        Some(ms) => ms,
+        None => return Ok(()),
+    };
+    let closed = match closed_periods_until(store, pair, period_ms).await? {
+        Some(closed) => closed,
+        None => return Ok(()),
+    };
+    let name = watermark_name(period, bins, pair);
+    let mut mark = match store.watermark(&name).await? {
+        Some(mark) => mark,
+        None => {
+            store.set_watermark(&name, closed).await?;
+            return Ok(());
+        }
+    };
+    while mark + period_ms <= closed {
+        if let Some(profile) =
+            build_profile(store, pair, period, mark, mark + period_ms - 1, bins, DEFAULT_VALUE_AREA).await?
+        {
+            store.replace_volume_profiles(&[profile]).await?;
+        }
+        mark += period_ms;
+        store.set_watermark(&name, mark).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStore, TradeStore};
+
+    // 2024-01-01 00:00 UTC
+    const T0: i64 = 1_704_067_200_000;
+    const HOUR: i64 = 3_600_000;
+
+    fn trade(pair: &str, ts: i64, price: &str, quantity: f64, side: &str) -> RecentTrade {
+        RecentTrade {
+            tid: format!("{}-{}", ts, price),
+            pair: pair.to_string(),
+            price: price.to_string(),
+            amount: String::new(),
+            quantity: quantity.to_string(),
+            side: side.to_string(),
+            create_time: ts,
+            timestamp: ts,
+        }
+    }
+
+    fn sample() -> Vec<RecentTrade> {
+        vec![
+            trade("BTC_USDT", T0, "101", 1.0, "buy"),
+            trade("BTC_USDT", T0 + HOUR, "105", 2.0, "sell"),
+            trade("BTC_USDT", T0 + 7 * HOUR, "115", 5.0, "buy"),
+            trade("BTC_USDT", T0 + 13 * HOUR, "129.99", 1.0, "sell"),
+            trade("BTC_USDT", T0 + 20 * HOUR, "99", 1.0, "buy"),
+            // Не попадают в профиль: другая пара, вне периода, неразборная цена
+            trade("ETH_USDT", T0, "115", 100.0, "buy"),
+            trade("BTC_USDT", T0 + 24 * HOUR, "115", 100.0, "buy"),
+            trade("BTC_USDT", T0, "bad", 100.0, "buy"),
+        ]
+    }
+
+    // (price_low, buy_volume, sell_volume, trade_count)
+    fn levels(profile: &VolumeProfile) -> Vec<(f64, f64, f64, i64)> {
+        profile
+            .levels
+            .iter()
+            .map(|l| (l.price_low, l.buy_volume, l.sell_volume, l.trade_count))
+            .collect()
+    }
+
+    #[test]
+    fn fixed_width_levels_poc_and_value_area() {
+        let profile =
+            volume_profile("BTC_USDT", "1d", &sample(), T0, T0 + 24 * HOUR - 1, PriceBins::Width(10.0), 0.7).unwrap();
+        // Уровни выровнены от нуля: [90, 100), [100, 110), ...
+        assert_eq!(
+            levels(&profile),
+            vec![(90.0, 1.0, 0.0, 1), (100.0, 1.0, 2.0, 2), (110.0, 5.0, 0.0, 1), (120.0, 0.0, 1.0, 1)]
+        );
+        assert_eq!((profile.bins.as_str(), profile.bin_width), ("10", 10.0));
+        assert_eq!(profile.poc, 115.0);
+        // От POC (5 из 10) зона идёт к большему соседу 100 (3) и набирает 8 >= 7
+        assert_eq!((profile.value_area_low, profile.value_area_high), (100.0, 120.0));
+    }
+
+    #[test]
+    fn level_count_spans_price_range() {
+        let trades = vec![
+            trade("BTC_USDT", T0, "100", 1.0, "buy"),
+            trade("BTC_USDT", T0, "101", 1.0, "buy"),
+            trade("BTC_USDT", T0, "104", 3.0, "sell"),
+            trade("BTC_USDT", T0, "108", 1.0, "sell"),
+        ];
+        let profile = volume_profile("BTC_USDT", CUSTOM_PERIOD, &trades, T0, T0, PriceBins::Count(4), 0.7).unwrap();
+        // Ширина (108 - 100) / 4, максимум попадает в последний уровень, пустые уровни не хранятся
+        assert_eq!(profile.bin_width, 2.0);
+        assert_eq!(levels(&profile), vec![(100.0, 2.0, 0.0, 2), (104.0, 0.0, 3.0, 1), (106.0, 0.0, 1.0, 1)]);
+        assert_eq!(profile.poc, 105.0);
+        // Соседи по списку уровней - 100 (2) и 106 (1): с большим набрано 5 из 6 >= 4.2
+        assert_eq!((profile.value_area_low, profile.value_area_high), (100.0, 106.0));
+
+        // Одна цена - один уровень нулевой ширины
+        let single = volume_profile("BTC_USDT", CUSTOM_PERIOD, &trades[..1], T0, T0, PriceBins::Count(4), 0.7).unwrap();
+        assert_eq!((single.bin_width, single.poc, single.value_area_low, single.value_area_high), (0.0, 100.0, 100.0, 100.0));
+        assert!(volume_profile("BTC_USDT", CUSTOM_PERIOD, &[], T0, T0, PriceBins::Count(4), 0.7).is_none());
+    }
+
+    #[test]
+    fn ties_prefer_lower_poc_and_grow_upwards() {
+        let trades = vec![
+            trade("BTC_USDT", T0, "95", 1.0, "buy"),
+            trade("BTC_USDT", T0, "105", 2.0, "buy"),
+            trade("BTC_USDT", T0, "115", 2.0, "sell"),
+        ];
+        let profile = volume_profile("BTC_USDT", CUSTOM_PERIOD, &trades, T0, T0, PriceBins::Width(10.0), 0.9).unwrap();
+        // Уровни 100 и 110 равны - POC нижний; дальше зона идёт к большему соседу 110, затем вниз
+        assert_eq!(profile.poc, 105.0);
+        assert_eq!((profile.value_area_low, profile.value_area_high), (90.0, 120.0));
+
+        let trades = vec![
+            trade("BTC_USDT", T0, "95", 1.0, "buy"),
+            trade("BTC_USDT", T0, "105", 3.0, "buy"),
+            trade("BTC_USDT", T0, "115", 1.0, "sell"),
+        ];
+        let profile = volume_profile("BTC_USDT", CUSTOM_PERIOD, &trades, T0, T0, PriceBins::Width(10.0), 0.7).unwrap();
+        // Равные соседи - зона растёт вверх и набирает 4 из 5 >= 3.5
+        assert_eq!((profile.value_area_low, profile.value_area_high), (100.0, 120.0));
+    }
+
+    #[test]
+    fn parses_bins() {
+        assert_eq!(PriceBins::parse("10"), Ok(PriceBins::Width(10.0)));
+        assert_eq!(PriceBins::parse("count:100"), Ok(PriceBins::Count(100)));
+        for value in ["0", "-1", "inf", "count:0", "count:x"] {
+            assert!(PriceBins::parse(value).is_err(), "{}", value);
+        }
+        assert_eq!(PriceBins::Count(100).name(), "count:100");
+    }
+
+    #[tokio::test]
+    async fn stored_trades_are_read_in_windows() {
+        let store = MemoryStore::default();
+        store.insert_trades(&sample()).await.unwrap();
+        let bins = PriceBins::Width(10.0);
+        let stored = build_profile(&store, "BTC_USDT", "1d", T0, T0 + 24 * HOUR - 1, bins, 0.7).await.unwrap();
+        let expected = volume_profile("BTC_USDT", "1d", &sample(), T0, T0 + 24 * HOUR - 1, bins, 0.7);
+        assert_eq!(stored, expected);
+    }
+}
@@ -1,23 +1,31 @@
-use futures_util::stream::All;
-use tokio::task;
 use tokio_tungstenite::connect_async;
-use futures_util::{join, SinkExt, StreamExt};
+use futures_util::{SinkExt, StreamExt};
 use tokio::time::{sleep, interval, Duration};
 use tokio_tungstenite::tungstenite::protocol::Message;
 use serde_json::{json, Value};
 use url::Url;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use crate::data_structs::{Kline, VBS, PAIRS};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use crate::data_structs::{CandleSource, Kline, VBS, AGG_TIME_FRAMES};
+use crate::aggregate;
+use crate::bar_builder;
+use crate::candle_builder;
+use crate::correlation;
+use crate::indicators;
+use crate::order_flow;
+use crate::scheduler::{self, AggSchedulerConfig};
 use crate::data_structs::RecentTrade;
-use crate::{db};
-use sqlx::{PgPool, Row};
-use chrono::{Utc, Timelike};
+use crate::storage::Storage;
+use crate::trade_writer::{TradeWriter, TradeWriterConfig};
+use crate::volatility;
+use crate::volume_profile;
 
 const WS_URL: &str = "wss://ws.poloniex.com/ws/public";
 
 // метод не используется
-pub async fn start_ws(pool: Arc<PgPool>) {
+#[allow(dead_code)]
+pub async fn start_ws(store: Arc<dyn Storage>) {
     loop {
         match connect_async(Url::parse(WS_URL).unwrap()).await {
             Ok((ws_stream, _)) => {
@@ -59,14 +67,14 @@ pub async fn start_ws(pool: Arc<PgPool>) {
                 });
 
                 // Фоновая задача для обработки сообщений и записи в БД
-                let pool_clone = Arc::clone(&pool);
+                let store_clone = Arc::clone(&store);
                 tokio::spawn(async move {
                     while let Some(msg) = read.next().await {
                         match msg {
                             Ok(Message::Text(text)) => {
                                 println!("Получено сообщение: {}", text);
                                 if let Some(kline) = parse_candle_message(&text) {
-                                    if let Err(e) = db::insert_candles(&pool_clone, vec![kline]).await {
+                                    if let Err(e) = store_clone.upsert_candles(vec![kline]).await {
                                         eprintln!("Ошибка записи в БД: {}", e);
                                     } else {
                                         println!("Данные записаны в БД.");
@@ -92,10 +100,11 @@ pub async fn start_ws(pool: Arc<PgPool>) {
 }
 
 // метод не используется
+#[allow(dead_code)]
 fn parse_candle_message(text: &str) -> Option<Kline> {
     let parsed: Value = serde_json::from_str(text).ok()?;
     let data_array = parsed.get("data")?.as_array()?;
-    let row = data_array.get(0)?;
+    let row = data_array.first()?;
     
     // Извлекаем поля из объекта
     let symbol = row.get("symbol")?.as_str()?.to_string();
@@ -106,6 +115,8 @@ fn parse_candle_message(text: &str) -> Option<Kline> {
     let quantity = row.get("quantity")?.as_str()?.parse::<f64>().ok()?;
     let amount = row.get("amount")?.as_str()?.parse::<f64>().ok()?;
     let start_time = row.get("startTime")?.as_i64()?;
+    let close_time = row.get("closeTime")?.as_i64()?;
+    let trade_count = row.get("tradeCount")?.as_i64()?;
     
     let volume_bs = VBS {
         buy_base: 0.0,
@@ -116,13 +127,11 @@ fn parse_candle_message(text: &str) -> Option<Kline> {
     
     // время интервала
     let channel = parsed.get("channel")?.as_str()?;
-    let time_frame = channel
-        .split('_')
-        .skip(1)
-        .collect::<Vec<&str>>()
-        .join("_")
-        .to_uppercase();
+    let time_frame = aggregate::canonical_time_frame(
+        &channel.split('_').skip(1).collect::<Vec<&str>>().join("_").to_uppercase(),
+    );
     
+    let is_final = aggregate::bucket_closed(start_time, &time_frame);
     Some(Kline {
         pair: symbol,
         time_frame,
@@ -132,31 +141,85 @@ fn parse_candle_message(text: &str) -> Option<Kline> {
         close,
         volume_bs,
         utc_begin: start_time,
+        close_time,
+        trade_count,
+        vwap: if quantity > 0.0 { amount / quantity } else { 0.0 },
+        source: CandleSource::Ws,
+        is_final,
+        revision: 0,
     })
 }
 
-async fn agg_candles(pool: Arc<PgPool>, text: &str, duration: i64) {
-    loop {
-        sleep(Duration::from_secs((60 * duration) as u64)).await;
-        let now = Utc::now();
-        let start = now.date_naive().and_hms(now.hour(), now.minute(), 0).timestamp_millis();
-        let end = start + 60000 * duration;
-        println!("Агрегация свечей {}: {} - {}", text, start, end);
-        for &pair in PAIRS.iter() {
-            if let Err(e) = aggregate_trades_to_candles(
-                Arc::clone(&pool),
-                pair,
-                &text,
-                start,
-                end,
-            ).await {
-                eprintln!("Ошибка агрегации для пары {}: {}", pair, e);
-            }
+pub async fn start_ws_trades(store: Arc<dyn Storage>) {
+    // Писатель живёт дольше отдельных подключений: трейды, принятые до обрыва, всё равно дойдут до БД
+    let (writer, _writer_handle) = TradeWriter::spawn(Arc::clone(&store), TradeWriterConfig::from_env());
+    // Свечи по живому потоку; таймфреймы с непрерывным агрегатом строит TimescaleDB
+    let live_time_frames: Vec<&'static str> = AGG_TIME_FRAMES
+        .into_iter()
+        .filter(|tf| !store.has_continuous_aggregate(tf))
+        .collect();
+    let (builder, _builder_handle) = candle_builder::spawn(Arc::clone(&store), &live_time_frames);
+    // Бары по потоку трейдов, если заданы в BAR_SPECS
+    let bars = match bar_builder::bar_specs_from_env() {
+        Ok(specs) if !specs.is_empty() => Some(bar_builder::spawn(Arc::clone(&store), specs).0),
+        Ok(_) => None,
+        Err(e) => {
+            eprintln!("Бары не строятся: {}", e);
+            None
         }
+    };
+    // Пересчёт закрытых корзин по трейдам из БД, по границам интервалов
+    let scheduler_config = AggSchedulerConfig::from_env();
+    for time_frame in AGG_TIME_FRAMES {
+        if store.has_continuous_aggregate(time_frame) {
+            println!("Свечи {} строит TimescaleDB, агрегация не запускается", time_frame);
+            continue;
+        }
+        tokio::spawn(scheduler::run_aggregation(Arc::clone(&store), time_frame, scheduler_config.clone()));
+    }
+    // Пересчёт недавно закрытых свечей, в которые попали опоздавшие трейды
+    tokio::spawn(scheduler::run_revisions(Arc::clone(&store), live_time_frames.clone(), scheduler_config));
+    // Индикаторы по закрытым свечам, если заданы в INDICATORS
+    match indicators::indicator_specs_from_env() {
+        Ok(specs) if !specs.is_empty() => {
+            tokio::spawn(indicators::run_indicators(Arc::clone(&store), live_time_frames.clone(), specs));
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Индикаторы не считаются: {}", e),
+    }
+    // Поток ордеров по закрытым свечам, если задано окно ORDER_FLOW_WINDOW
+    match order_flow::window_from_env() {
+        Ok(Some(window)) => {
+            tokio::spawn(order_flow::run_order_flow(Arc::clone(&store), live_time_frames.clone(), window));
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Поток ордеров не считается: {}", e),
+    }
+    // Дневные и недельные профили объёма, если задано разбиение VOLUME_PROFILE_BINS
+    match volume_profile::bins_from_env() {
+        Ok(Some(bins)) => {
+            tokio::spawn(volume_profile::run_volume_profiles(Arc::clone(&store), bins));
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Профили объёма не строятся: {}", e),
+    }
+    // Оценки волатильности по закрытым свечам и дневная реализованная, если задано окно VOLATILITY_WINDOW
+    match volatility::window_from_env() {
+        Ok(Some(window)) => {
+            tokio::spawn(volatility::run_volatility(Arc::clone(&store), live_time_frames.clone(), window));
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Волатильность не считается: {}", e),
+    }
+    // Корреляции и беты пар к BTC_USDT по закрытым свечам, если задано окно CORRELATION_WINDOW
+    match correlation::window_from_env() {
+        Ok(Some(window)) => {
+            tokio::spawn(correlation::run_correlations(Arc::clone(&store), live_time_frames.clone(), window));
+        }
+        Ok(None) => {}
+        Err(e) => eprintln!("Корреляции не считаются: {}", e),
     }
-}
 
-pub async fn start_ws_trades(pool: Arc<PgPool>) {
     loop {
         let mut tasks = vec![]; 
         match connect_async(Url::parse(WS_URL).unwrap()).await {
@@ -200,17 +263,23 @@ pub async fn start_ws_trades(pool: Arc<PgPool>) {
                 }));
 
                 // Фоновая задача для обработки входящих трейд-сообщений
-                let pool_clone = Arc::clone(&pool);
+                let writer_clone = writer.clone();
+                let builder_clone = builder.clone();
+                let bars_clone = bars.clone();
                 tasks.push(tokio::spawn(async move {
+                    let (mut dropped_candles, mut dropped_bars) = (0u64, 0u64);
                     while let Some(msg) = read.next().await {
                         match msg {
                             Ok(Message::Text(text)) => {
                                 //println!("Трейд-сообщение получено: {}", text);
                                 if let Some(trade) = parse_trade_message(&text) {
-                                    if let Err(e) = db::insert_trade(&pool_clone, trade).await {
-                                        eprintln!("Ошибка записи трейда в БД: {}", e);
-                                    } else {
-                                        //println!("Трейд записан в БД.");
+                                    offer(&builder_clone, &trade, &mut dropped_candles, "свечей");
+                                    if let Some(bars) = &bars_clone {
+                                        offer(bars, &trade, &mut dropped_bars, "баров");
+                                    }
+                                    if let Err(e) = writer_clone.send(trade) {
+                                        eprintln!("Писатель трейдов недоступен: {}", e);
+                                        break;
                                     }
                                 }
                             }
@@ -225,26 +294,6 @@ pub async fn start_ws_trades(pool: Arc<PgPool>) {
                         }
                     }
                 }));
-                tasks.push(tokio::spawn(agg_candles(
-                    Arc::clone(&pool),
-                    "1m",
-                    1,
-                )));
-                tasks.push(tokio::spawn(agg_candles(
-                    Arc::clone(&pool),
-                    "15m",
-                    15,
-                )));
-                tasks.push(tokio::spawn(agg_candles(
-                    Arc::clone(&pool),
-                    "1h",
-                    60,
-                )));
-                tasks.push(tokio::spawn(agg_candles(
-                    Arc::clone(&pool),
-                    "1d",
-                    24*60,
-                )));
                 futures_util::future::join_all(tasks).await;
             }
             Err(e) => eprintln!("Ошибка подключения к WS для трейдов: {}", e),
@@ -255,11 +304,34 @@ pub async fn start_ws_trades(pool: Arc<PgPool>) {
     }
 }
 
+// Передаёт трейд построителю без ожидания, чтобы полный канал не останавливал чтение сокета.
+// Отброшенный здесь трейд не попадёт в свечу построителя. Его учтёт агрегация закрытых корзин по таблице
+// трейдов (планировщик или непрерывный агрегат TimescaleDB), но только если трейд сохранил писатель;
+// бары можно перестроить командой bars.
+fn offer(tx: &mpsc::Sender<RecentTrade>, trade: &RecentTrade, dropped: &mut u64, what: &str) {
+    match tx.try_send(trade.clone()) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            *dropped += 1;
+            if *dropped % 1000 == 1 {
+                eprintln!("Построитель {} не успевает: отброшено {} трейдов", what, dropped);
+            }
+        }
+        Err(TrySendError::Closed(_)) => {
+            eprintln!("Построитель {} недоступен, трейд {} не учтён", what, trade.tid);
+        }
+    }
+}
+
 fn parse_trade_message(text: &str) -> Option<RecentTrade> {
     let parsed: Value = serde_json::from_str(text).ok()?;
     let data_array = parsed.get("data")?.as_array()?;
-    let row = data_array.get(0)?;
-    
+    let row = data_array.first()?;
+    trade_from_json(row)
+}
+
+// Трейд из объекта в формате канала `trades` (symbol, price, amount, quantity, takerSide, createTime, id, ts)
+pub fn trade_from_json(row: &Value) -> Option<RecentTrade> {
     let pair = row.get("symbol")?.as_str()?.to_string();
     let price = row.get("price")?.as_str()?.to_string();
     let amount = row.get("amount")?.as_str()?.to_string();
@@ -293,7 +365,7 @@ fn parse_trade_message(text: &str) -> Option<RecentTrade> {
 }
 
 pub async fn aggregate_trades_to_candles(
-    pool: Arc<PgPool>,
+    store: Arc<dyn Storage>,
     pair: &str,
     time_frame: &str,
     start_ts: i64,
@@ -303,151 +375,9 @@ pub async fn aggregate_trades_to_candles(
         "Выполняем агрегацию для пары '{}' с start_ts={} и end_ts={}",
         pair, start_ts, end_ts
     );
-    
-    let query = r#"
-    WITH grouped AS (
-      SELECT
-        pair,
-        date_trunc('minute', to_timestamp(time_stamp/1000.0)) AS utc_begin,
-        MIN(time_stamp) AS first_ts,
-        MAX(time_stamp) AS last_ts,
-        MIN(price::numeric)::float8 AS low,
-        MAX(price::numeric)::float8 AS high,
-        SUM(CASE WHEN side = 'buy' THEN quantity::numeric ELSE 0 END)::float8 AS buy_base,
-        SUM(CASE WHEN side = 'sell' THEN quantity::numeric ELSE 0 END)::float8 AS sell_base,
-        SUM(CASE WHEN side = 'buy' THEN amount::numeric ELSE 0 END)::float8 AS buy_quote,
-        SUM(CASE WHEN side = 'sell' THEN amount::numeric ELSE 0 END)::float8 AS sell_quote
-      FROM trades
-      WHERE pair = $1
-        AND time_stamp BETWEEN $2 AND $3
-      GROUP BY pair, date_trunc('minute', to_timestamp(time_stamp/1000.0))
-    )
-    SELECT
-        g.pair,
-        (SELECT price::numeric::float8
-            FROM trades
-            WHERE time_stamp = g.first_ts
-            LIMIT 1
-        ) AS o,
-        g.high AS h,
-        g.low AS l,
-        (SELECT price::numeric::float8
-            FROM trades
-            WHERE time_stamp = g.last_ts
-            LIMIT 1
-        ) AS c,
-        CAST(EXTRACT(EPOCH FROM g.utc_begin)*1000 AS float8) AS utc_begin_ms,
-        g.buy_base,
-        g.sell_base,
-        g.buy_quote,
-        g.sell_quote
-    FROM grouped g;
-    "#;
-    
-    let rows = sqlx::query(query)
-        .bind(pair)
-        .bind(start_ts)
-        .bind(end_ts)
-        .fetch_all(&*pool)
-        .await?;
-    
-    println!("Получено {} строк", rows.len());
-    for (i, row) in rows.iter().enumerate() {
-        let o: Result<f64, _> = row.try_get("o");
-        let h: Result<f64, _> = row.try_get("h");
-        println!("Строка {}: o={:?}, h={:?}", i, o, h);
-    }
-    
-    let mut candles = Vec::new();
-    for row in rows {
-        let pair_val: String = pair.to_string();
-        let open: f64 = match row.try_get("o") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'o' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let high: f64 = match row.try_get("h") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'h' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let low: f64 = match row.try_get("l") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'l' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let close: f64 = match row.try_get("c") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'c' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let utc_begin_ms: f64 = match row.try_get("utc_begin_ms") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'utc_begin_ms' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let buy_base: f64 = match row.try_get("buy_base") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'buy_base' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let sell_base: f64 = match row.try_get("sell_base") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'sell_base' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let buy_quote: f64 = match row.try_get("buy_quote") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'buy_quote' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        let sell_quote: f64 = match row.try_get("sell_quote") {
-            Ok(val) => val,
-            Err(e) => {
-                eprintln!("Ошибка получения 'sell_quote' для пары {}: {}", pair, e);
-                return Err(e);
-            }
-        };
-        
-        let utc_begin = utc_begin_ms as i64;
-        
-        let volume_bs = VBS {
-            buy_base,
-            sell_base,
-            buy_quote,
-            sell_quote,
-        };
-        
-        let candle = Kline {
-            pair: pair_val,
-            time_frame: time_frame.to_string(),
-            open,
-            high,
-            low,
-            close,
-            volume_bs,
-            utc_begin,
-        };
-        
-        candles.push(candle);
-    }
-    
+
+    let candles = store.aggregate_candles(pair, time_frame, start_ts, end_ts).await?;
+
     if candles.is_empty() {
         println!(
             "Нет данных для агрегации: pair={}, time_frame={}, start_ts={}, end_ts={}",
@@ -456,7 +386,7 @@ pub async fn aggregate_trades_to_candles(
         return Ok(());
     }
     
-    db::insert_candles(&pool, candles).await?;
+    store.upsert_candles(candles).await?;
     println!(
         "Добавлено свечей для pair={}, time_frame={}, start_ts={}, end_ts={}",
         pair, time_frame, start_ts, end_ts
@@ -0,0 +1,108 @@
+// Аудит ряда в Postgres: пропуски по сетке, по трейдам и в ряду биржи, дубли одного источника,
+// сломанные цены и расхождение объёма свечи с трейдами
+mod common;
+
+use common::{pg_store, trade, CandleExt, HOUR, MIN, T0};
+use poloniex::audit::{self, IssueKind};
+use poloniex::data_structs::{CandleSource, Kline};
+use poloniex::storage::{CandleStore, Storage, TradeStore};
+
+fn candle(time_frame: &str, utc_begin: i64, high: f64, low: f64, buy_base: f64) -> Kline {
+    common::candle(time_frame, utc_begin, 10.0)
+        .with_pair("AUD_USDT")
+        .with_ohlc(10.0, high, low, 10.0)
+        .with_volume(buy_base, 0.0)
+}
+
+fn kinds(report: &audit::AuditReport) -> Vec<(i64, IssueKind)> {
+    report.issues.iter().map(|i| (i.utc_begin, i.kind.clone())).collect()
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn finds_gaps_duplicates_broken_prices_and_volume_mismatches() {
+    let store = pg_store("test_audit").await;
+    let pool = store.pg_pool().unwrap();
+
+    // 4h строится не из трейдов: пропуски ищутся по сетке между свечами
+    store
+        .upsert_candles(vec![
+            candle("4h", T0, 11.0, 9.0, 1.0),
+            candle("4h", T0 + 4 * HOUR, 9.0, 11.0, 1.0), // high < low
+            candle("4h", T0 + 16 * HOUR, 11.0, 9.0, 1.0),
+        ])
+        .await
+        .unwrap();
+    let report = audit::audit_series(pool, "AUD_USDT", "4h", Some(T0), Some(T0 + 20 * HOUR)).await.unwrap();
+    assert_eq!(report.candles_checked, 3);
+    assert_eq!(
+        kinds(&report),
+        vec![
+            (T0 + 4 * HOUR, IssueKind::HighBelowLow),
+            (T0 + 4 * HOUR, IssueKind::OpenCloseOutOfRange),
+            (T0 + 8 * HOUR, IssueKind::Missing),
+            (T0 + 12 * HOUR, IssueKind::Missing),
+        ]
+    );
+
+    // 1m из трейдов: пропуск - корзина с трейдами без свечи, объём сверяется с трейдами
+    store
+        .insert_trades(&[
+            trade("AUD_USDT", T0 + 10, "1", 10.0, 1.0, "buy"),
+            trade("AUD_USDT", T0 + MIN + 5, "2", 10.0, 2.0, "buy"),
+            trade("AUD_USDT", T0 + 3 * MIN, "3", 10.0, 1.0, "buy"),
+        ])
+        .await
+        .unwrap();
+    store
+        .upsert_candles(vec![candle("1m", T0, 10.0, 10.0, 1.0), candle("1m", T0 + MIN, 10.0, 10.0, 5.0)])
+        .await
+        .unwrap();
+    // Дубль одного источника мог остаться от данных до уникального индекса
+    sqlx::query("DROP INDEX candles_pair_time_frame_utc_begin_source_idx").execute(pool).await.unwrap();
+    sqlx::query("INSERT INTO candles (pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, \
+                 sell_quote, utc_begin, close_time, trade_count, vwap, source, is_final, revision) \
+                 SELECT pair, time_frame, open, high, low, close, buy_base, sell_base, buy_quote, sell_quote, \
+                 utc_begin, close_time, trade_count, vwap, source, is_final, revision \
+                 FROM candles WHERE pair = 'AUD_USDT' AND time_frame = '1m' AND utc_begin = $1")
+        .bind(T0)
+        .execute(pool)
+        .await
+        .unwrap();
+
+    let report = audit::audit_series(pool, "AUD_USDT", "1m", Some(T0), Some(T0 + 10 * MIN)).await.unwrap();
+    assert_eq!(
+        kinds(&report),
+        vec![
+            (T0, IssueKind::Duplicate { count: 2 }),
+            (T0 + MIN, IssueKind::VolumeMismatch { candle: 5.0, trades: 2.0 }),
+            (T0 + 3 * MIN, IssueKind::Missing),
+        ]
+    );
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn finds_missing_exchange_candles() {
+    let store = pg_store("test_audit_rest").await;
+    let pool = store.pg_pool().unwrap();
+
+    let rest = |utc_begin: i64| candle("1h", utc_begin, 11.0, 9.0, 1.0).with_source(CandleSource::Rest);
+    // Свеча из трейдов в T0 + 2h не закрывает пропуск свечи биржи
+    store
+        .upsert_candles(vec![
+            rest(T0),
+            rest(T0 + HOUR),
+            candle("1h", T0 + 2 * HOUR, 11.0, 9.0, 1.0),
+            rest(T0 + 3 * HOUR),
+            rest(T0 + 5 * HOUR),
+        ])
+        .await
+        .unwrap();
+
+    let report = audit::audit_series(pool, "AUD_USDT", "1h", Some(T0), Some(T0 + 6 * HOUR)).await.unwrap();
+    assert_eq!(
+        kinds(&report),
+        vec![(T0 + 2 * HOUR, IssueKind::Missing), (T0 + 4 * HOUR, IssueKind::Missing)]
+    );
+}
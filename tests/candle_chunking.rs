@@ -0,0 +1,80 @@
+// Запись пачки свечей больше лимита параметров одного запроса: режется на несколько INSERT в одной транзакции
+mod common;
+
+use common::pg_store;
+use poloniex::data_structs::{CandleSource, Kline, VBS};
+use poloniex::storage::Storage;
+
+const COUNT: usize = 100_000;
+const T0: i64 = 1_704_067_200_000;
+const MIN: i64 = 60_000;
+
+fn candles(close: f64) -> Vec<Kline> {
+    (0..COUNT as i64)
+        .map(|i| Kline {
+            pair: "BULK_USDT".to_string(),
+            time_frame: "1m".to_string(),
+            open: 1.0,
+            high: close.max(1.0),
+            low: close.min(1.0),
+            close,
+            volume_bs: VBS {
+                buy_base: 1.0,
+                sell_base: 1.0,
+                buy_quote: 1.0,
+                sell_quote: 1.0,
+            },
+            utc_begin: T0 + i * MIN,
+            close_time: T0 + (i + 1) * MIN - 1,
+            trade_count: 2,
+            vwap: 1.0,
+            source: CandleSource::Aggregated,
+            is_final: true,
+            revision: 0,
+        })
+        .collect()
+}
+
+async fn stored(store: &dyn Storage) -> Vec<Kline> {
+    store
+        .candles_range("BULK_USDT", "1m", T0, T0 + COUNT as i64 * MIN, &CandleSource::DEFAULT_PREFERENCE)
+        .await
+        .unwrap()
+}
+
+async fn check(store: &dyn Storage) {
+    store.insert_candles(candles(2.0)).await.unwrap();
+    let rows = stored(store).await;
+    assert_eq!(rows.len(), COUNT);
+    assert!(rows.iter().all(|c| c.close == 2.0));
+
+    // Повторная запись тех же корзин: без дублей, upsert перезаписывает значения
+    store.upsert_candles(candles(3.0)).await.unwrap();
+    let rows = stored(store).await;
+    assert_eq!(rows.len(), COUNT);
+    assert!(rows.iter().all(|c| c.close == 3.0));
+
+    // insert не трогает уже записанные свечи
+    store.insert_candles(candles(4.0)).await.unwrap();
+    let rows = stored(store).await;
+    assert_eq!(rows.len(), COUNT);
+    assert!(rows.iter().all(|c| c.close == 3.0));
+}
+
+#[tokio::test]
+async fn sqlite_writes_100k_candles_in_one_call() {
+    check(&common::sqlite_store("candle_chunking").await).await;
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn postgres_writes_100k_candles_in_one_call() {
+    let store = pg_store("test_candle_chunking").await;
+    check(&store).await;
+
+    let total: i64 = sqlx::query_scalar("SELECT count(*) FROM candles WHERE pair = 'BULK_USDT'")
+        .fetch_one(store.pg_pool().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(total, COUNT as i64);
+}
@@ -0,0 +1,56 @@
+// Чтение свечей при непрерывных агрегатах TimescaleDB: свечи агрегата и других источников из candles
+// сводятся по предпочтению. Агрегат подменяется обычной таблицей с теми же колонками.
+mod common;
+
+use common::{pg_store, CandleExt, MIN, T0};
+use poloniex::data_structs::{CandleSource, Kline};
+use poloniex::storage::{CandleStore, PgStore, Storage};
+
+const QUARTER: i64 = 15 * MIN;
+
+fn candle(utc_begin: i64, close: f64, source: CandleSource) -> Kline {
+    common::candle("15m", utc_begin, close)
+        .with_ohlc(100.0, 110.0, 90.0, close)
+        .with_vwap(100.0)
+        .with_source(source)
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn aggregate_reads_respect_source_preference() {
+    let store = pg_store("test_continuous_aggregates").await;
+    let pool = store.pg_pool().unwrap().clone();
+    for sql in [
+        "CREATE FUNCTION unix_now_ms() RETURNS BIGINT LANGUAGE SQL STABLE AS $$
+            SELECT (extract(epoch FROM now()) * 1000)::BIGINT $$",
+        "CREATE TABLE candles_15m (
+            pair TEXT, time_frame TEXT, utc_begin BIGINT, open FLOAT8, high FLOAT8, low FLOAT8, close FLOAT8,
+            buy_base FLOAT8, sell_base FLOAT8, buy_quote FLOAT8, sell_quote FLOAT8, trade_count BIGINT)",
+        "INSERT INTO candles_15m VALUES
+            ('BTC_USDT', '15m', 1704067200000, 100, 110, 90, 103, 1, 0, 100, 0, 1),
+            ('BTC_USDT', '15m', 1704068100000, 100, 110, 90, 104, 1, 0, 100, 0, 1)",
+    ] {
+        sqlx::query(sql).execute(&pool).await.unwrap();
+    }
+    // Свеча биржи за первую корзину и устаревшая своя свеча в таблице: агрегат её заменяет
+    store
+        .upsert_candles(vec![
+            candle(T0, 101.0, CandleSource::Rest),
+            candle(T0 + QUARTER, 102.0, CandleSource::Aggregated),
+        ])
+        .await
+        .unwrap();
+    let timescale = PgStore::new(pool, true);
+
+    let closes = |candles: Vec<Kline>| candles.iter().map(|c| (c.close, c.source)).collect::<Vec<_>>();
+    let end = T0 + 2 * QUARTER - 1;
+    let rest_first = timescale.candles_range("BTC_USDT", "15m", T0, end, &CandleSource::DEFAULT_PREFERENCE).await.unwrap();
+    assert_eq!(closes(rest_first), vec![(101.0, CandleSource::Rest), (104.0, CandleSource::Aggregated)]);
+    let own_first = timescale.candles_range("BTC_USDT", "15m", T0, end, &[CandleSource::Aggregated]).await.unwrap();
+    assert_eq!(closes(own_first), vec![(103.0, CandleSource::Aggregated), (104.0, CandleSource::Aggregated)]);
+    let only_rest = timescale.candles_range("BTC_USDT", "15m", T0, T0, &[CandleSource::Rest]).await.unwrap();
+    assert_eq!(closes(only_rest), vec![(101.0, CandleSource::Rest)]);
+
+    let latest = timescale.latest_candle("BTC_USDT", "15m").await.unwrap().unwrap();
+    assert_eq!((latest.utc_begin, latest.close, latest.source), (T0 + QUARTER, 104.0, CandleSource::Aggregated));
+}
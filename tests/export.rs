@@ -0,0 +1,163 @@
+// Экспорт идёт через трейты хранилища и работает без Postgres
+mod common;
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use flate2::read::GzDecoder;
+use common::{trade, HOUR, T0};
+use poloniex::data_structs::Kline;
+use poloniex::export::{self, ExportFormat};
+use poloniex::storage::{CandleStore, MemoryStore, TradeStore};
+
+fn candle(utc_begin: i64, close: f64) -> Kline {
+    common::candle("1h", utc_begin, close)
+}
+
+// Строки данных gzip-CSV без заголовка
+fn csv_rows(path: &Path) -> Vec<String> {
+    let mut text = String::new();
+    GzDecoder::new(fs::File::open(path).unwrap()).read_to_string(&mut text).unwrap();
+    text.lines().skip(1).map(str::to_string).collect()
+}
+
+#[tokio::test]
+async fn exports_candles_and_trades_from_memory_store() {
+    let out = std::env::temp_dir().join("poloniex_test_export_memory");
+    let _ = fs::remove_dir_all(&out);
+    let store = MemoryStore::default();
+
+    // Двое суток часовых свечей: окна чтения и разбиение по датам
+    store.upsert_candles((0..48).map(|i| candle(T0 + i * HOUR, 100.0 + i as f64)).collect()).await.unwrap();
+    store
+        .insert_trades(&[
+            trade("BTC_USDT", T0 + 10, "1", 100.0, 1.0, "buy"),
+            trade("BTC_USDT", T0 + 2 * HOUR, "2", 101.0, 1.0, "sell"),
+            trade("BTC_USDT", T0 + 25 * HOUR, "3", 102.0, 1.0, "buy"),
+        ])
+        .await
+        .unwrap();
+
+    let pairs = vec!["BTC_USDT".to_string()];
+    let end = T0 + 48 * HOUR - 1;
+    let candles = export::export_candles(&store, &pairs, "1h", T0, end, ExportFormat::Csv, &out, false).await.unwrap();
+    assert_eq!((candles.files, candles.rows), (2, 48));
+    let trades = export::export_trades(&store, &pairs, T0, end, ExportFormat::Csv, &out, false).await.unwrap();
+    assert_eq!((trades.files, trades.rows), (2, 3));
+
+    let day = out.join("candles/pair=BTC_USDT/date=2024-01-01/candles_1h.csv.gz");
+    assert_eq!(csv_rows(&day).len(), 24);
+    let day = out.join("trades/pair=BTC_USDT/date=2024-01-02/trades.csv.gz");
+    assert_eq!(csv_rows(&day).len(), 1);
+    fs::remove_dir_all(&out).unwrap();
+}
+
+// Размер кирпича по ATR считается только по прошлым свечам: продление диапазона не меняет уже сложенные кирпичи
+#[tokio::test]
+async fn renko_atr_bricks_do_not_depend_on_range_end() {
+    let out = std::env::temp_dir().join("poloniex_test_export_renko");
+    let _ = fs::remove_dir_all(&out);
+    let store = MemoryStore::default();
+
+    // Спокойные первые сутки и резкие колебания во вторые
+    let candles = (0..48)
+        .map(|i| {
+            let (close, range) = if i < 24 {
+                (100.0 + 2.0 * i as f64, 1.0)
+            } else {
+                (150.0 + 40.0 * (i % 2) as f64, 30.0)
+            };
+            let mut c = candle(T0 + i * HOUR, close);
+            c.high = close + range;
+            c.low = close - range;
+            c
+        })
+        .collect();
+    store.upsert_candles(candles).await.unwrap();
+
+    let pairs = vec!["BTC_USDT".to_string()];
+    let brick = poloniex::derived::BrickSize::Atr(3);
+    let day = "renko/pair=BTC_USDT/date=2024-01-01/renko_1h.csv.gz";
+    let short = out.join("short");
+    let long = out.join("long");
+    let start = T0 + 5 * HOUR;
+    export::export_renko(&store, &pairs, "1h", brick, start, T0 + 20 * HOUR, ExportFormat::Csv, &short, false).await.unwrap();
+    export::export_renko(&store, &pairs, "1h", brick, start, T0 + 48 * HOUR - 1, ExportFormat::Csv, &long, false)
+        .await
+        .unwrap();
+
+    let short_rows = csv_rows(&short.join(day));
+    let long_rows = csv_rows(&long.join(day));
+    assert!(!short_rows.is_empty());
+    assert_eq!(short_rows[..], long_rows[..short_rows.len()]);
+    // Свечи перед диапазоном разогревают ATR: отсчёт идёт с первой свечи диапазона, кирпич - через две
+    assert!(short_rows[0].starts_with(&format!("BTC_USDT,1h,{},", T0 + 7 * HOUR)));
+    fs::remove_dir_all(&out).unwrap();
+}
+
+// Повторный заход в уже записанную партицию - ошибка, а не молчаливая перезапись файла
+#[tokio::test]
+async fn repeated_partition_is_an_error() {
+    let out = std::env::temp_dir().join("poloniex_test_export_repeat");
+    let _ = fs::remove_dir_all(&out);
+    let store = MemoryStore::default();
+    // Ряд переходит через полночь: второй проход по паре возвращается к уже закрытым суткам
+    store.upsert_candles((22..26).map(|i| candle(T0 + i * HOUR, 100.0)).collect()).await.unwrap();
+
+    let pairs = vec!["BTC_USDT".to_string(), "BTC_USDT".to_string()];
+    let result = export::export_candles(&store, &pairs, "1h", T0, T0 + 26 * HOUR - 1, ExportFormat::Csv, &out, false).await;
+    let Err(err) = result else {
+        panic!("партиция записана дважды");
+    };
+    assert!(err.to_string().contains("pair=BTC_USDT date=2024-01-01"), "{}", err);
+    // Первый проход по партиции остался в файле целиком
+    let day = out.join("candles/pair=BTC_USDT/date=2024-01-01/candles_1h.csv.gz");
+    assert_eq!(csv_rows(&day).len(), 2);
+    fs::remove_dir_all(&out).unwrap();
+}
+
+// Файлы прошлого запуска не перезаписываются без overwrite; с ним заменяются целиком, без временных файлов
+#[tokio::test]
+async fn existing_partitions_need_overwrite() {
+    let out = std::env::temp_dir().join("poloniex_test_export_overwrite");
+    let _ = fs::remove_dir_all(&out);
+    let store = MemoryStore::default();
+    store.upsert_candles((0..4).map(|i| candle(T0 + i * HOUR, 100.0)).collect()).await.unwrap();
+
+    let pairs = vec!["BTC_USDT".to_string()];
+    let export = |end: i64, overwrite: bool| export::export_candles(&store, &pairs, "1h", T0, end, ExportFormat::Csv, &out, overwrite);
+    export(T0 + 4 * HOUR - 1, false).await.unwrap();
+    let day_dir = out.join("candles/pair=BTC_USDT/date=2024-01-01");
+    let day = day_dir.join("candles_1h.csv.gz");
+
+    let err = export(T0 + 2 * HOUR - 1, false).await.unwrap_err();
+    assert!(err.to_string().contains("--overwrite"), "{}", err);
+    assert_eq!(csv_rows(&day).len(), 4);
+
+    let summary = export(T0 + 2 * HOUR - 1, true).await.unwrap();
+    assert_eq!((summary.files, summary.rows), (1, 2));
+    assert_eq!(csv_rows(&day).len(), 2);
+    let files: Vec<_> = fs::read_dir(&day_dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(files, vec!["candles_1h.csv.gz"]);
+    fs::remove_dir_all(&out).unwrap();
+}
+
+// Партиция, которую не удалось закрыть, не оставляет временного файла
+#[tokio::test]
+async fn failed_partition_leaves_no_partial_file() {
+    let out = std::env::temp_dir().join("poloniex_test_export_failed");
+    let _ = fs::remove_dir_all(&out);
+    let store = MemoryStore::default();
+    store.upsert_candles((0..4).map(|i| candle(T0 + i * HOUR, 100.0)).collect()).await.unwrap();
+
+    // На месте итогового файла непустой каталог: временный файл не встаёт на место
+    let day_dir = out.join("candles/pair=BTC_USDT/date=2024-01-01");
+    fs::create_dir_all(day_dir.join("candles_1h.csv.gz/blocker")).unwrap();
+    let pairs = vec!["BTC_USDT".to_string()];
+    export::export_candles(&store, &pairs, "1h", T0, T0 + 4 * HOUR - 1, ExportFormat::Csv, &out, true)
+        .await
+        .unwrap_err();
+    let files: Vec<_> = fs::read_dir(&day_dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(files, vec!["candles_1h.csv.gz"]);
+    fs::remove_dir_all(&out).unwrap();
+}
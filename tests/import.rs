@@ -0,0 +1,113 @@
+// Импорт файлов в хранилище: разбор колонок Parquet и учёт уже имеющихся строк
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::sync::Arc;
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+use parquet::arrow::ArrowWriter;
+use poloniex::data_structs::CandleSource;
+use poloniex::import::{self, ImportFormat, ImportMode, ImportOptions, ImportTable};
+use poloniex::storage::{CandleStore, MemoryStore, TradeStore};
+
+// 2024-01-01 00:00 UTC
+const T0: i64 = 1_704_067_200_000;
+
+fn options(table: ImportTable, format: ImportFormat) -> ImportOptions {
+    ImportOptions {
+        table,
+        format,
+        mode: ImportMode::Insert,
+        mapping: HashMap::new(),
+        pair: None,
+        time_frame: None,
+        batch_size: 1000,
+    }
+}
+
+// Колонка неподдерживаемого типа, которая не участвует в импорте, не отклоняет строки
+#[tokio::test]
+async fn parquet_ignores_unused_columns_of_unsupported_type() {
+    let path = std::env::temp_dir().join("poloniex_test_import_trades.parquet");
+    let strings = |values: [&str; 2]| -> ArrayRef { Arc::new(StringArray::from(values.to_vec())) };
+    let batch = RecordBatch::try_from_iter(vec![
+        ("id", strings(["1", "2"])),
+        ("symbol", strings(["BTC_USDT", "BTC_USDT"])),
+        ("price", strings(["100", "101"])),
+        ("amount", strings(["100", "202"])),
+        ("quantity", strings(["1", "2"])),
+        ("takerSide", strings(["buy", "sell"])),
+        ("ts", Arc::new(Int64Array::from(vec![T0, T0 + 1])) as ArrayRef),
+        ("received_at", Arc::new(TimestampMillisecondArray::from(vec![T0, T0 + 1])) as ArrayRef),
+    ])
+    .unwrap();
+    let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let store = MemoryStore::default();
+    let report = import::import_file(&store, &path, &options(ImportTable::Trades, ImportFormat::Parquet))
+        .await
+        .unwrap();
+    assert_eq!((report.imported, report.skipped, report.rejected), (2, 0, 0));
+    assert_eq!(store.trades_range("BTC_USDT", T0, T0 + 1).await.unwrap().len(), 2);
+
+    // Та же колонка, указанная в --map, разобрана быть не может - строки отклоняются
+    let mut mapped = options(ImportTable::Trades, ImportFormat::Parquet);
+    mapped.mapping.insert("ts".to_string(), "received_at".to_string());
+    let report = import::import_file(&store, &path, &mapped).await.unwrap();
+    assert_eq!(report.rejected, 2);
+    fs::remove_file(&path).unwrap();
+}
+
+// Повторный импорт тех же свечей ничего не записывает и считает строки пропущенными
+#[tokio::test]
+async fn reimported_candles_are_skipped() {
+    let path = std::env::temp_dir().join("poloniex_test_import_candles.csv");
+    let mut csv = String::from("symbol,interval,low,high,open,close,amount,quantity,buyTakerAmount,buyTakerQuantity,startTime\n");
+    for i in 0..3 {
+        csv.push_str(&format!("BTC_USDT,1h,99,101,100,100,200,2,100,1,{}\n", T0 + i * 3_600_000));
+    }
+    fs::write(&path, csv).unwrap();
+
+    let store = MemoryStore::default();
+    let options = options(ImportTable::Candles, ImportFormat::Csv);
+    let first = import::import_file(&store, &path, &options).await.unwrap();
+    assert_eq!((first.imported, first.skipped, first.rejected), (3, 0, 0));
+    let second = import::import_file(&store, &path, &options).await.unwrap();
+    assert_eq!((second.imported, second.skipped, second.rejected), (0, 3, 0));
+    fs::remove_file(&path).unwrap();
+}
+
+// В режиме upsert повторный импорт исправляет уже записанные свечи; повторы в файле - последняя строка
+#[tokio::test]
+async fn upsert_mode_overwrites_candles() {
+    let path = std::env::temp_dir().join("poloniex_test_import_upsert.csv");
+    let header = "symbol,interval,low,high,open,close,amount,quantity,buyTakerAmount,buyTakerQuantity,startTime\n";
+    let row = |close: f64, begin: i64| format!("BTC_USDT,1h,90,110,100,{},200,2,100,1,{}\n", close, begin);
+    fs::write(&path, format!("{}{}{}", header, row(100.0, T0), row(101.0, T0 + 3_600_000))).unwrap();
+
+    let store = MemoryStore::default();
+    let mut options = options(ImportTable::Candles, ImportFormat::Csv);
+    options.batch_size = 1;
+    import::import_file(&store, &path, &options).await.unwrap();
+
+    // Исправленный файл: при вставке ничего не меняется
+    fs::write(&path, format!("{}{}{}{}", header, row(105.0, T0), row(106.0, T0 + 3_600_000), row(107.0, T0 + 3_600_000))).unwrap();
+    let closes = || async {
+        let candles = store.candles_range("BTC_USDT", "1h", T0, T0 + 3_600_000, &[CandleSource::Imported]).await.unwrap();
+        candles.iter().map(|c| c.close).collect::<Vec<f64>>()
+    };
+    let report = import::import_file(&store, &path, &options).await.unwrap();
+    assert_eq!((report.imported, report.skipped), (0, 3));
+    assert_eq!(closes().await, vec![100.0, 101.0]);
+
+    // Перезапись: повтор из другой пачки (batch 1) перезаписывает предыдущую строку, из той же - заменяет её
+    options.mode = ImportMode::Upsert;
+    let report = import::import_file(&store, &path, &options).await.unwrap();
+    assert_eq!((report.imported, report.skipped, report.rejected), (3, 0, 0));
+    assert_eq!(closes().await, vec![105.0, 107.0]);
+    options.batch_size = 1000;
+    let report = import::import_file(&store, &path, &options).await.unwrap();
+    assert_eq!((report.imported, report.skipped), (2, 1));
+    assert_eq!(closes().await, vec![105.0, 107.0]);
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,131 @@
+// Правила хранения на всех бэкендах: трейды удаляются только при готовых свечах из трейдов,
+// минутные свечи (и свои, и REST) перед удалением собираются в часовые
+mod common;
+
+use common::{pg_store, trade, CandleExt, HOUR, MIN, T0};
+use poloniex::aggregate;
+use poloniex::data_structs::{CandleSource, Kline, AGG_TIME_FRAMES};
+use poloniex::retention::{self, RetentionConfig};
+use poloniex::storage::{MemoryStore, Storage};
+use tokio::time::Duration;
+
+const DAY: i64 = 24 * HOUR;
+
+fn candle(time_frame: &str, utc_begin: i64, price: f64, source: CandleSource) -> Kline {
+    common::candle(time_frame, utc_begin, price + 0.5)
+        .with_ohlc(price, price + 1.0, price - 1.0, price + 0.5)
+        .with_vwap(price)
+        .with_volume(1.0, 1.0)
+        .with_source(source)
+}
+
+async fn check(store: &dyn Storage) {
+    // Три часа своих минутных свечей и ещё час минутных из REST
+    let mut candles: Vec<Kline> = (0..180)
+        .map(|i| candle("1m", T0 + i * MIN, 100.0 + i as f64, CandleSource::Aggregated))
+        .collect();
+    candles.extend((180..240).map(|i| candle("1m", T0 + i * MIN, 100.0 + i as f64, CandleSource::Rest)));
+    // Часовая корзина T0 уже есть и не должна перезаписаться; 15m и 1d есть только для T0
+    candles.push(candle("1h", T0, 999.0, CandleSource::Aggregated));
+    candles.push(candle("15m", T0, 100.0, CandleSource::Aggregated));
+    candles.push(candle("1d", T0, 100.0, CandleSource::Aggregated));
+    store.upsert_candles(candles).await.unwrap();
+
+    store
+        .insert_trades(&[
+            trade("BTC_USDT", T0 + 10, "1", 100.0, 1.0, "buy"),          // все корзины есть - удаляется
+            trade("BTC_USDT", T0 + 20 * MIN, "2", 100.0, 1.0, "buy"),    // нет свечи 15m - остаётся
+            trade("BTC_USDT", T0 + 2 * DAY, "3", 100.0, 1.0, "buy"),     // моложе срока - остаётся
+        ])
+        .await
+        .unwrap();
+
+    let config = RetentionConfig {
+        rules: retention::parse_rules("trades=1d,candles:1m=1d>1h,candles:1d=forever").unwrap(),
+        batch_size: 7,
+        interval: Duration::from_secs(3600),
+    };
+    retention::apply_rules(store, &config, T0 + 2 * DAY + 30 * MIN).await;
+
+    let end = T0 + 3 * DAY;
+    let prefer = CandleSource::DEFAULT_PREFERENCE;
+    assert!(store.candles_range("BTC_USDT", "1m", T0, end, &prefer).await.unwrap().is_empty());
+
+    let hours = store.candles_range("BTC_USDT", "1h", T0, end, &prefer).await.unwrap();
+    let begins: Vec<i64> = hours.iter().map(|c| c.utc_begin).collect();
+    assert_eq!(begins, vec![T0, T0 + HOUR, T0 + 2 * HOUR, T0 + 3 * HOUR]);
+    assert_eq!(hours[0].open, 999.0);
+    let h1 = &hours[1];
+    assert_eq!((h1.open, h1.close, h1.high, h1.low), (160.0, 219.5, 220.0, 159.0));
+    assert_eq!((h1.trade_count, h1.volume_bs.buy_base), (60, 60.0));
+    // Корзина из REST-минуток
+    assert_eq!((hours[3].open, hours[3].close), (280.0, 339.5));
+
+    assert_eq!(store.candles_range("BTC_USDT", "15m", T0, end, &prefer).await.unwrap().len(), 1);
+    assert_eq!(store.candles_range("BTC_USDT", "1d", T0, end, &prefer).await.unwrap().len(), 1);
+
+    let trades = store.trades_range("BTC_USDT", T0, end).await.unwrap();
+    assert_eq!(trades.iter().map(|t| t.tid.as_str()).collect::<Vec<_>>(), vec!["2", "3"]);
+}
+
+// Свечи биржи в корзинах трейда не заменяют собранные из трейдов: без них трейд не удаляется
+async fn check_rest_only(store: &dyn Storage) {
+    let in_bucket = |source: CandleSource| -> Vec<Kline> {
+        AGG_TIME_FRAMES
+            .iter()
+            .map(|tf| {
+                let len = aggregate::time_frame_ms(tf).unwrap();
+                candle(tf, aggregate::bucket_start(T0 + 10, len), 100.0, source)
+            })
+            .collect()
+    };
+    store.upsert_candles(in_bucket(CandleSource::Rest)).await.unwrap();
+    store.insert_trades(&[trade("BTC_USDT", T0 + 10, "1", 100.0, 1.0, "buy")]).await.unwrap();
+
+    let config = RetentionConfig {
+        rules: retention::parse_rules("trades=1d").unwrap(),
+        batch_size: 7,
+        interval: Duration::from_secs(3600),
+    };
+    let now = T0 + 2 * DAY;
+    retention::apply_rules(store, &config, now).await;
+    assert_eq!(store.trades_range("BTC_USDT", T0, now).await.unwrap().len(), 1);
+
+    // Планировщик собрал корзины - трейд можно удалять
+    store.upsert_candles(in_bucket(CandleSource::Aggregated)).await.unwrap();
+    retention::apply_rules(store, &config, now).await;
+    assert!(store.trades_range("BTC_USDT", T0, now).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn retention_on_memory_store() {
+    check(&MemoryStore::default()).await;
+}
+
+#[tokio::test]
+async fn rest_candles_keep_trades_on_memory_store() {
+    check_rest_only(&MemoryStore::default()).await;
+}
+
+#[tokio::test]
+async fn rest_candles_keep_trades_on_sqlite() {
+    check_rest_only(&common::sqlite_store("retention_rest").await).await;
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn rest_candles_keep_trades_on_postgres() {
+    check_rest_only(&pg_store("test_retention_rest").await).await;
+}
+
+#[tokio::test]
+async fn retention_on_sqlite() {
+    check(&common::sqlite_store("retention").await).await;
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn retention_on_postgres() {
+    let store = pg_store("test_retention").await;
+    check(&store).await;
+}
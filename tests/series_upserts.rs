@@ -0,0 +1,164 @@
+// Повторная запись рядов, рассчитанных из трейдов и свечей: строка с тем же ключом перезаписывается, а не дублируется
+mod common;
+
+use common::pg_store;
+use poloniex::data_structs::{
+    Bar, BarKind, BarSpec, CorrelationCell, CorrelationMatrix, IndicatorValue, OrderFlow, PriceLevel, Volatility,
+    VolumeProfile, VBS,
+};
+use poloniex::storage::Storage;
+
+const T0: i64 = 1_704_067_200_000;
+const HOUR: i64 = 3_600_000;
+const SPEC: BarSpec = BarSpec { kind: BarKind::Tick, threshold: 10.0 };
+
+fn bar(close: f64) -> Bar {
+    Bar {
+        pair: "BTC_USDT".to_string(),
+        spec: SPEC,
+        open: 1.0,
+        high: close.max(1.0),
+        low: 1.0,
+        close,
+        volume_bs: VBS {
+            buy_base: 1.0,
+            sell_base: 1.0,
+            buy_quote: 1.0,
+            sell_quote: 1.0,
+        },
+        utc_begin: T0,
+        utc_end: T0 + 1000,
+        first_tid: "1".to_string(),
+        last_tid: "10".to_string(),
+        trade_count: 10,
+        vwap: 1.0,
+        imbalance: 0.0,
+    }
+}
+
+fn indicator(value: f64) -> IndicatorValue {
+    IndicatorValue {
+        pair: "BTC_USDT".to_string(),
+        time_frame: "1h".to_string(),
+        utc_begin: T0,
+        indicator: "macd".to_string(),
+        params: "12,26,9".to_string(),
+        values: vec![value, value / 2.0, value / 4.0],
+    }
+}
+
+fn order_flow(delta: f64) -> OrderFlow {
+    OrderFlow {
+        pair: "BTC_USDT".to_string(),
+        time_frame: "1h".to_string(),
+        utc_begin: T0,
+        buy_volume: 1.0 + delta,
+        sell_volume: 1.0,
+        delta,
+        cvd: delta,
+        imbalance: 0.5,
+        divergence: -1,
+        buy_z: Some(delta),
+        sell_z: None,
+        window: 20,
+    }
+}
+
+fn profile(levels: usize) -> VolumeProfile {
+    VolumeProfile {
+        pair: "BTC_USDT".to_string(),
+        period: "1d".to_string(),
+        utc_begin: T0,
+        utc_end: T0 + 24 * HOUR - 1,
+        bins: "10".to_string(),
+        bin_width: 10.0,
+        levels: (0..levels)
+            .map(|i| PriceLevel {
+                price_low: 100.0 + i as f64 * 10.0,
+                buy_volume: 1.0,
+                sell_volume: 2.0,
+                trade_count: 3,
+            })
+            .collect(),
+        poc: 105.0,
+        value_area_high: 110.0,
+        value_area_low: 100.0,
+    }
+}
+
+fn volatility(variance: f64) -> Volatility {
+    Volatility {
+        pair: "BTC_USDT".to_string(),
+        time_frame: "1h".to_string(),
+        utc_begin: T0,
+        estimator: "parkinson".to_string(),
+        window: 24,
+        variance,
+        volatility: variance.sqrt(),
+    }
+}
+
+fn correlations(correlation: f64) -> CorrelationMatrix {
+    CorrelationMatrix {
+        time_frame: "1h".to_string(),
+        utc_begin: T0,
+        window: 24,
+        pairs: vec!["BTC_USDT".to_string(), "ETH_USDT".to_string()],
+        cells: vec![
+            CorrelationCell {
+                pair: "BTC_USDT".to_string(),
+                other_pair: "ETH_USDT".to_string(),
+                observations: 24,
+                correlation: Some(correlation),
+                beta: Some(correlation * 2.0),
+            },
+            CorrelationCell {
+                pair: "ETH_USDT".to_string(),
+                other_pair: "BTC_USDT".to_string(),
+                observations: 24,
+                correlation: Some(correlation),
+                beta: None,
+            },
+        ],
+    }
+}
+
+async fn check(store: &dyn Storage) {
+    for close in [2.0, 3.0] {
+        store.upsert_bars(&[bar(close)]).await.unwrap();
+        store.upsert_indicator_values(&[indicator(close)]).await.unwrap();
+        store.upsert_order_flow(&[order_flow(close)]).await.unwrap();
+        store.upsert_volatility(&[volatility(close)]).await.unwrap();
+        store.upsert_correlations(&[correlations(close / 10.0)]).await.unwrap();
+    }
+
+    let bars = store.bars_range("BTC_USDT", &SPEC, T0, T0).await.unwrap();
+    assert_eq!(bars.len(), 1);
+    assert_eq!(bars[0].close, 3.0);
+    assert_eq!(
+        store.indicator_range("BTC_USDT", "1h", "macd", "12,26,9", T0, T0).await.unwrap(),
+        vec![indicator(3.0)]
+    );
+    assert_eq!(store.order_flow_range("BTC_USDT", "1h", T0, T0).await.unwrap(), vec![order_flow(3.0)]);
+    assert_eq!(
+        store.volatility_range("BTC_USDT", "1h", "parkinson", 24, T0, T0).await.unwrap(),
+        vec![volatility(3.0)]
+    );
+    assert_eq!(store.correlation_range("1h", 24, T0, T0).await.unwrap(), vec![correlations(0.3)]);
+
+    // Профиль заменяется целиком: уровни, которых больше нет, удаляются
+    store.replace_volume_profiles(&[profile(3)]).await.unwrap();
+    store.replace_volume_profiles(&[profile(2)]).await.unwrap();
+    assert_eq!(store.volume_profiles_range("BTC_USDT", "1d", "10", T0, T0).await.unwrap(), vec![profile(2)]);
+}
+
+#[tokio::test]
+async fn sqlite_overwrites_series_rows() {
+    check(&common::sqlite_store("series_upserts").await).await;
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn postgres_overwrites_series_rows() {
+    check(&pg_store("test_series_upserts").await).await;
+}
@@ -0,0 +1,98 @@
+// Агрегация трейдов в Postgres (db::aggregate_trades): свечи 15m/1h/1d/1w по посчитанным вручную
+// значениям и совпадение со сборкой в памяти (aggregate::aggregate_trades)
+mod common;
+
+use common::{close, pg_store, trade};
+use poloniex::aggregate;
+use poloniex::data_structs::{CandleSource, Kline};
+use poloniex::storage::TradeStore;
+
+// 2024-01-01 00:00 UTC, понедельник
+const T0: i64 = 1_704_067_200_000;
+const MIN: i64 = 60_000;
+
+// (utc_begin, open, high, low, close, trade_count, buy_base, sell_base, buy_quote, sell_quote)
+type Expected = (i64, f64, f64, f64, f64, i64, f64, f64, f64, f64);
+
+fn summary(candles: &[Kline]) -> Vec<Expected> {
+    let mut rows: Vec<Expected> = candles
+        .iter()
+        .map(|c| {
+            let v = &c.volume_bs;
+            (c.utc_begin, c.open, c.high, c.low, c.close, c.trade_count, v.buy_base, v.sell_base, v.buy_quote, v.sell_quote)
+        })
+        .collect();
+    rows.sort_by_key(|r| r.0);
+    rows
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn sql_aggregation_matches_reference_candles() {
+    let store = pg_store("test_sql_aggregation").await;
+    // Намеренно не по порядку; в T0 + 14m два трейда одной миллисекунды: "10" после "9" по значению id
+    let trades = vec![
+        trade("BTC_USDT", T0 + 70 * MIN, "50", 130.0, 1.0, "sell"),
+        trade("BTC_USDT", T0 + 5 * MIN, "2", 110.0, 2.0, "sell"),
+        trade("BTC_USDT", T0 + 1000, "1", 100.0, 1.0, "buy"),
+        trade("ETH_USDT", T0 + 1000, "e1", 5.0, 1.0, "buy"),
+        trade("BTC_USDT", T0 + 14 * MIN, "10", 90.0, 1.0, "buy"),
+        trade("BTC_USDT", T0 + 14 * MIN, "9", 95.0, 1.0, "buy"),
+        trade("BTC_USDT", T0 + 20 * MIN, "40", 120.0, 0.5, "buy"),
+        trade("BTC_USDT", T0 - 1, "0", 80.0, 1.0, "sell"), // воскресенье: другие сутки и другая неделя
+    ];
+    store.insert_trades(&trades).await.unwrap();
+
+    let cases: [(&str, Vec<Expected>); 4] = [
+        (
+            "15m",
+            vec![
+                (T0 - 15 * MIN, 80.0, 80.0, 80.0, 80.0, 1, 0.0, 1.0, 0.0, 80.0),
+                (T0, 100.0, 110.0, 90.0, 90.0, 4, 3.0, 2.0, 285.0, 220.0),
+                (T0 + 15 * MIN, 120.0, 120.0, 120.0, 120.0, 1, 0.5, 0.0, 60.0, 0.0),
+                (T0 + 60 * MIN, 130.0, 130.0, 130.0, 130.0, 1, 0.0, 1.0, 0.0, 130.0),
+            ],
+        ),
+        (
+            "1h",
+            vec![
+                (T0 - 60 * MIN, 80.0, 80.0, 80.0, 80.0, 1, 0.0, 1.0, 0.0, 80.0),
+                (T0, 100.0, 120.0, 90.0, 120.0, 5, 3.5, 2.0, 345.0, 220.0),
+                (T0 + 60 * MIN, 130.0, 130.0, 130.0, 130.0, 1, 0.0, 1.0, 0.0, 130.0),
+            ],
+        ),
+        (
+            "1d",
+            vec![
+                (T0 - 24 * 60 * MIN, 80.0, 80.0, 80.0, 80.0, 1, 0.0, 1.0, 0.0, 80.0),
+                (T0, 100.0, 130.0, 90.0, 130.0, 6, 3.5, 3.0, 345.0, 350.0),
+            ],
+        ),
+        (
+            "1w",
+            vec![
+                (T0 - 7 * 24 * 60 * MIN, 80.0, 80.0, 80.0, 80.0, 1, 0.0, 1.0, 0.0, 80.0),
+                (T0, 100.0, 130.0, 90.0, 130.0, 6, 3.5, 3.0, 345.0, 350.0),
+            ],
+        ),
+    ];
+
+    let (start, end) = (T0 - 7 * 24 * 60 * MIN, T0 + 24 * 60 * MIN - 1);
+    for (time_frame, expected) in cases {
+        let candles = store.aggregate_candles("BTC_USDT", time_frame, start, end).await.unwrap();
+        let actual = summary(&candles);
+        assert_eq!(actual.len(), expected.len(), "{}", time_frame);
+        for (a, e) in actual.iter().zip(&expected) {
+            assert_eq!((a.0, a.1, a.2, a.3, a.4, a.5), (e.0, e.1, e.2, e.3, e.4, e.5), "{}", time_frame);
+            for (x, y) in [(a.6, e.6), (a.7, e.7), (a.8, e.8), (a.9, e.9)] {
+                assert!(close(x, y), "{} {}: {} != {}", time_frame, a.0, x, y);
+            }
+        }
+        let ms = aggregate::time_frame_ms(time_frame).unwrap();
+        for c in &candles {
+            assert_eq!((c.pair.as_str(), c.time_frame.as_str()), ("BTC_USDT", time_frame));
+            assert_eq!((c.close_time, c.source, c.is_final), (c.utc_begin + ms - 1, CandleSource::Aggregated, true));
+        }
+        assert_eq!(actual, summary(&aggregate::aggregate_trades("BTC_USDT", time_frame, &trades).unwrap()), "{}", time_frame);
+    }
+}
@@ -0,0 +1,142 @@
+// Свечи из SQL-агрегации Postgres совпадают с aggregate::aggregate_trades:
+// open/close по (time_stamp, tid_num NULLS LAST, tid), трейды разных пар не смешиваются
+mod common;
+
+use common::{close, pg_store, trade};
+use poloniex::aggregate;
+use poloniex::data_structs::{Kline, RecentTrade};
+use poloniex::db;
+use poloniex::storage::{MemoryStore, Storage, TradeStore};
+
+// 2024-01-01 00:00 UTC
+const T0: i64 = 1_704_067_200_000;
+
+fn assert_same(sql: &[Kline], rust: &[Kline]) {
+    assert_eq!(sql.len(), rust.len());
+    for (s, r) in sql.iter().zip(rust) {
+        assert_eq!((s.pair.as_str(), s.utc_begin), (r.pair.as_str(), r.utc_begin));
+        assert_eq!((s.open, s.close), (r.open, r.close), "open/close {} {}", s.pair, s.utc_begin);
+        assert_eq!((s.high, s.low, s.trade_count), (r.high, r.low, r.trade_count));
+        let (sv, rv) = (&s.volume_bs, &r.volume_bs);
+        assert!(close(sv.buy_base, rv.buy_base) && close(sv.sell_base, rv.sell_base));
+        assert!(close(sv.buy_quote, rv.buy_quote) && close(sv.sell_quote, rv.sell_quote));
+    }
+}
+
+async fn compare(store: &dyn Storage, trades: &[RecentTrade], pair: &str, time_frame: &str) -> Vec<Kline> {
+    let sql = store.aggregate_candles(pair, time_frame, T0, T0 + 86_400_000).await.unwrap();
+    let rust = aggregate::aggregate_trades(pair, time_frame, trades).unwrap();
+    assert_same(&sql, &rust);
+    // И из трейдов, прочитанных обратно из хранилища
+    let stored = store.trades_range(pair, T0, T0 + 86_400_000).await.unwrap();
+    assert_same(&sql, &aggregate::aggregate_trades(pair, time_frame, &stored).unwrap());
+    sql
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn pairs_sharing_a_timestamp_are_aggregated_separately() {
+    let store = pg_store("test_pairs_shared_ts").await;
+    let trades = vec![
+        trade("AAA_USDT", T0 + 10, "101", 1.0, 1.0, "buy"),
+        trade("BBB_USDT", T0 + 10, "102", 50.0, 2.0, "sell"),
+        trade("AAA_USDT", T0 + 10, "103", 2.0, 1.0, "sell"),
+        trade("BBB_USDT", T0 + 10, "100", 40.0, 1.0, "buy"),
+        trade("AAA_USDT", T0 + 20, "99", 3.0, 1.0, "buy"),
+        trade("BBB_USDT", T0 + 20, "98", 60.0, 1.0, "buy"),
+    ];
+    store.insert_trades(&trades).await.unwrap();
+
+    for time_frame in ["1m", "15m", "1h", "1d", "1w"] {
+        let a = compare(&store, &trades, "AAA_USDT", time_frame).await;
+        let b = compare(&store, &trades, "BBB_USDT", time_frame).await;
+        assert_eq!((a[0].open, a[0].close, a[0].trade_count), (1.0, 3.0, 3));
+        assert_eq!((b[0].open, b[0].close, b[0].trade_count), (40.0, 60.0, 3));
+    }
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn numeric_tids_come_before_non_numeric_in_one_bucket() {
+    let store = pg_store("test_mixed_tids").await;
+    // Одна миллисекунда: порядок задают только id. Числовые по значению ("9" < "010" < "10" < "100"),
+    // затем остальные побайтно ("B" < "a1", независимо от collation базы)
+    let trades = vec![
+        trade("MIX_USDT", T0 + 5, "a1", 6.0, 1.0, "buy"),
+        trade("MIX_USDT", T0 + 5, "10", 3.0, 1.0, "sell"),
+        trade("MIX_USDT", T0 + 5, "B", 5.0, 1.0, "buy"),
+        trade("MIX_USDT", T0 + 5, "9", 1.0, 1.0, "buy"),
+        trade("MIX_USDT", T0 + 5, "100", 4.0, 1.0, "sell"),
+        trade("MIX_USDT", T0 + 5, "010", 2.0, 1.0, "buy"),
+    ];
+    store.insert_trades(&trades).await.unwrap();
+
+    let candles = compare(&store, &trades, "MIX_USDT", "15m").await;
+    assert_eq!((candles[0].open, candles[0].close), (1.0, 6.0));
+}
+
+fn mixed_tids() -> Vec<RecentTrade> {
+    let mut trades = Vec::new();
+    for (i, tid) in ["a1", "10", "B", "9", "100", "010", "", "2x", "99999999999999999999"].iter().enumerate() {
+        trade_into(&mut trades, T0 + 5, tid, i as f64 + 1.0);
+    }
+    trade_into(&mut trades, T0 + 4, "500", 0.5);
+    trades
+}
+
+fn trade_into(trades: &mut Vec<RecentTrade>, ts: i64, tid: &str, price: f64) {
+    trades.push(trade("ORD_USDT", ts, tid, price, 1.0, "buy"));
+}
+
+fn expected_order(trades: &[RecentTrade]) -> Vec<String> {
+    let mut sorted = trades.to_vec();
+    sorted.sort_by(aggregate::trade_order);
+    sorted.into_iter().map(|t| t.tid).collect()
+}
+
+async fn check_store_order(store: &dyn Storage) {
+    let trades = mixed_tids();
+    store.insert_trades(&trades).await.unwrap();
+    let expected = expected_order(&trades);
+    assert_eq!(
+        expected,
+        vec!["500", "9", "010", "10", "100", "99999999999999999999", "", "2x", "B", "a1"]
+    );
+
+    let stored = store.trades_range("ORD_USDT", T0, T0 + 10).await.unwrap();
+    assert_eq!(stored.into_iter().map(|t| t.tid).collect::<Vec<_>>(), expected);
+    let latest = store.latest_trade("ORD_USDT").await.unwrap().unwrap();
+    assert_eq!(&latest.tid, expected.last().unwrap());
+}
+
+#[tokio::test]
+async fn stores_return_trades_in_numeric_first_order() {
+    check_store_order(&MemoryStore::default()).await;
+    check_store_order(&common::sqlite_store("trade_order").await).await;
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn postgres_returns_trades_in_numeric_first_order() {
+    check_store_order(&pg_store("test_trade_order").await).await;
+}
+
+#[tokio::test]
+#[ignore = "нужен Postgres: TEST_DATABASE_URL и cargo test -- --ignored"]
+async fn trade_pages_follow_numeric_first_order() {
+    let store = pg_store("test_trade_pages").await;
+    let trades = mixed_tids();
+    store.insert_trades(&trades).await.unwrap();
+    let pool = store.pg_pool().unwrap();
+
+    // По одному трейду на страницу: каждый курсор должен продолжать ровно с соседа
+    let mut seen = Vec::new();
+    let mut cursor: Option<db::TradeCursor> = None;
+    loop {
+        let page = db::get_trades(pool, "ORD_USDT", None, None, cursor.as_ref(), 1).await.unwrap();
+        let Some(last) = page.last() else { break };
+        cursor = Some(db::TradeCursor::from(last));
+        seen.push(last.tid.clone());
+    }
+    assert_eq!(seen, expected_order(&trades));
+}